@@ -0,0 +1,910 @@
+// Schema, settings and row-level helpers for the index database. Commands in
+// main.rs lock the connection and call into these.
+
+use std::path::is_separator;
+
+use rusqlite::{Connection, OptionalExtension, Result};
+
+use crate::{parent_dir, path_depth, FileMeta};
+
+// A row stores its own name and the id of its directory in `dirs`, not its
+// full path: a big index is mostly the same directory prefixes over and
+// over, and `dirs` keeps each one once. Every name there is one path
+// segment with its trailing separator ("C:\", "Users\"), so following
+// parent_id up and joining the names gives back the prefix byte for byte,
+// whichever separators it was written with. A root has parent_id 0.
+//
+// Full paths are put back together per connection: dir_paths holds each
+// directory's prefix and the `entries` view joins it onto main_table, so
+// queries read `path` as they always have (see load_dir_paths).
+const MAIN_TABLE_SQL: &str = "CREATE TABLE IF NOT EXISTS main_table (
+            dir_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            extension TEXT,
+            size INTEGER NOT NULL,
+            modified INTEGER NOT NULL,
+            accessed INTEGER,
+            is_dir INTEGER NOT NULL DEFAULT 0,
+            phash TEXT,
+            phash_mtime INTEGER,
+            created INTEGER,
+            is_symlink INTEGER NOT NULL DEFAULT 0,
+            link_count INTEGER,
+            volume_id INTEGER,
+            file_id INTEGER,
+            entropy REAL,
+            entropy_mtime INTEGER,
+            doc_title TEXT,
+            doc_author TEXT,
+            doc_meta_mtime INTEGER
+        )";
+
+pub(crate) fn create_table(conn: &Connection) -> Result<()> {
+    conn.execute(MAIN_TABLE_SQL, [])?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS dirs (
+            id INTEGER PRIMARY KEY,
+            parent_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            UNIQUE (parent_id, name)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS index_runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            root TEXT NOT NULL,
+            source TEXT NOT NULL,
+            started_at INTEGER NOT NULL,
+            finished_at INTEGER,
+            status TEXT NOT NULL,
+            processed INTEGER NOT NULL DEFAULT 0,
+            created INTEGER NOT NULL DEFAULT 0,
+            updated INTEGER NOT NULL DEFAULT 0,
+            error TEXT
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS index_collisions (
+            run_id INTEGER NOT NULL,
+            path TEXT NOT NULL,
+            kept_size INTEGER NOT NULL,
+            skipped_size INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS dir_sizes (
+            path TEXT PRIMARY KEY,
+            size INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            label TEXT PRIMARY KEY,
+            state TEXT NOT NULL,
+            updated_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS location_history (
+            path TEXT PRIMARY KEY,
+            visits INTEGER NOT NULL,
+            last_visited INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS search_history (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            term TEXT NOT NULL,
+            searched_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS operation_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            kind TEXT NOT NULL,
+            original TEXT NOT NULL,
+            current TEXT NOT NULL,
+            performed_at INTEGER NOT NULL,
+            run_id INTEGER
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS rules (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            watch_dir TEXT NOT NULL,
+            priority INTEGER NOT NULL DEFAULT 0,
+            enabled INTEGER NOT NULL DEFAULT 1,
+            criteria TEXT NOT NULL,
+            action TEXT NOT NULL,
+            destination TEXT
+        )",
+        [],
+    )?;
+    // Tags are keyed by path; renames, moves and deletes made through the
+    // app carry them along.
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS file_tags (
+            path TEXT NOT NULL,
+            tag TEXT NOT NULL,
+            tagged_at INTEGER NOT NULL,
+            PRIMARY KEY (path, tag)
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS stats_snapshots (
+            run_id INTEGER NOT NULL,
+            root TEXT NOT NULL,
+            taken_at INTEGER NOT NULL,
+            dimension TEXT NOT NULL,
+            key TEXT NOT NULL,
+            bytes INTEGER NOT NULL,
+            files INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS remotes (
+            name TEXT PRIMARY KEY,
+            user TEXT NOT NULL,
+            host TEXT NOT NULL,
+            port INTEGER NOT NULL,
+            base_path TEXT NOT NULL,
+            auth_kind TEXT NOT NULL,
+            key_path TEXT,
+            host_key TEXT NOT NULL
+        )",
+        [],
+    )?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS file_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            occurred_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+pub(crate) fn get_setting(conn: &Connection, key: &str) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT value FROM settings WHERE key = ?1",
+        rusqlite::params![key],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+pub(crate) fn set_setting(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        rusqlite::params![key, value],
+    )?;
+    Ok(())
+}
+
+// Columns added after the first release; CREATE TABLE IF NOT EXISTS leaves
+// older databases without them.
+pub(crate) fn migrate_schema(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "main_table", "accessed", "INTEGER")?;
+    add_column_if_missing(conn, "main_table", "is_dir", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "main_table", "phash", "TEXT")?;
+    add_column_if_missing(conn, "main_table", "phash_mtime", "INTEGER")?;
+    add_column_if_missing(conn, "main_table", "created", "INTEGER")?;
+    add_column_if_missing(conn, "main_table", "is_symlink", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "main_table", "link_count", "INTEGER")?;
+    add_column_if_missing(conn, "main_table", "volume_id", "INTEGER")?;
+    add_column_if_missing(conn, "main_table", "file_id", "INTEGER")?;
+    add_column_if_missing(conn, "main_table", "entropy", "REAL")?;
+    add_column_if_missing(conn, "main_table", "entropy_mtime", "INTEGER")?;
+    add_column_if_missing(conn, "main_table", "doc_title", "TEXT")?;
+    add_column_if_missing(conn, "main_table", "doc_author", "TEXT")?;
+    add_column_if_missing(conn, "main_table", "doc_meta_mtime", "INTEGER")?;
+    intern_flat_paths(conn)?;
+    add_column_if_missing(conn, "index_runs", "created", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "index_runs", "updated", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(conn, "index_runs", "avg_rate", "REAL")?;
+    add_column_if_missing(conn, "operation_log", "run_id", "INTEGER")?;
+    add_column_if_missing(conn, "rules", "tag", "TEXT")?;
+    Ok(())
+}
+
+// Databases from before dirs kept every row's full path in main_table. Their
+// rows move to the interned layout once, in rowid order, and the flat table
+// goes with its indexes; the VACUUM after it may renumber the rowids. A row
+// whose name is not the end of its path, which only a database
+// normalize_legacy_paths has yet to audit can hold, takes the end of its path
+// as its name so the path survives unchanged.
+fn intern_flat_paths(conn: &Connection) -> Result<()> {
+    let flat = table_columns(conn, "main", "main_table")?;
+    if !flat.iter().any(|c| c == "path") {
+        return Ok(());
+    }
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("ALTER TABLE main_table RENAME TO flat_main_table", [])?;
+    tx.execute(MAIN_TABLE_SQL, [])?;
+    load_dir_paths(&tx)?;
+    // with whether each is NOT NULL, which the oldest tables left size and
+    // modified without
+    let kept: Vec<(String, bool)> = tx
+        .prepare("SELECT name, \"notnull\" FROM pragma_table_info('main_table')")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<Vec<(String, bool)>>>()?
+        .into_iter()
+        .filter(|(c, _)| c != "dir_id" && c != "name" && flat.contains(c))
+        .collect();
+    let selected = kept.iter().map(|(c, not_null)| {
+        if *not_null {
+            format!(", COALESCE({}, 0)", c)
+        } else {
+            format!(", {}", c)
+        }
+    });
+    let mut select = tx.prepare(&format!(
+        "SELECT rowid, path{} FROM flat_main_table WHERE path IS NOT NULL",
+        selected.collect::<String>()
+    ))?;
+    let mut insert = tx.prepare(&format!(
+        "INSERT INTO main_table (rowid, dir_id, name{}) VALUES (?1, ?2, ?3{})",
+        kept.iter().map(|(c, _)| format!(", {}", c)).collect::<String>(),
+        (0..kept.len()).map(|n| format!(", ?{}", n + 4)).collect::<String>()
+    ))?;
+    let mut rows = select.query([])?;
+    while let Some(row) = rows.next()? {
+        let rowid: i64 = row.get(0)?;
+        let path: String = row.get(1)?;
+        let (prefix, name) = split_path(&path);
+        let mut values = vec![
+            rusqlite::types::Value::Integer(rowid),
+            rusqlite::types::Value::Integer(intern_dir(&tx, prefix)?),
+            rusqlite::types::Value::Text(name.to_string()),
+        ];
+        for n in 0..kept.len() {
+            values.push(row.get(n + 2)?);
+        }
+        insert.execute(rusqlite::params_from_iter(values))?;
+    }
+    drop(rows);
+    drop((select, insert));
+    tx.execute("DROP TABLE flat_main_table", [])?;
+    tx.commit()?;
+    // hand the pages the flat paths took back to the file system, and drop
+    // the rebuild's pages from the cache, which otherwise slow this whole
+    // session's reads down
+    conn.execute_batch("VACUUM; PRAGMA shrink_memory;")
+}
+
+pub(crate) fn table_columns(conn: &Connection, schema: &str, table: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA {}.table_info({})", schema, table))?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(columns)
+}
+
+pub(crate) fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists = table_columns(conn, "main", table)?.iter().any(|name| name == column);
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl), [])?;
+    }
+    Ok(())
+}
+
+// How a listed directory is matched to the prefixes of its rows: ignoring
+// case on Windows, whose filesystems do, and byte for byte elsewhere, where
+// "Foo/" and "foo/" are two directories.
+#[cfg(target_os = "windows")]
+pub(crate) const PATH_COLLATION: &str = "NOCASE";
+#[cfg(not(target_os = "windows"))]
+pub(crate) const PATH_COLLATION: &str = "BINARY";
+
+// `dir` without its trailing separators, "C:" for "C:\".
+pub(crate) fn parent_key(dir: &str) -> &str {
+    dir.trim_end_matches(['\\', '/'])
+}
+
+// Where `path` splits into the prefix `dirs` interns and the name main_table
+// keeps: just past the last separator, so "/a/b.txt" is "/a/" and "b.txt",
+// and a root like "C:\" is all prefix with an empty name.
+pub(crate) fn split_path(path: &str) -> (&str, &str) {
+    path.split_at(path.rfind(is_separator).map_or(0, |cut| cut + 1))
+}
+
+// The full prefix of every directory in dirs, rebuilt from its chain of
+// segments; `schema` is "main" or an attached database.
+fn dir_chain_sql(schema: &str) -> String {
+    format!(
+        "WITH RECURSIVE chain(id, path) AS (
+             SELECT 0, ''
+             UNION ALL
+             SELECT d.id, chain.path || d.name FROM {}.dirs d JOIN chain ON d.parent_id = chain.id
+         )
+         SELECT id, path FROM chain",
+        schema
+    )
+}
+
+// Sets up what reads need to see full paths again on `conn`: dir_paths, one
+// row per directory prefix including the empty one, kept in step with dirs
+// by triggers, and the `entries` view of main_table with `path` and
+// `dir_path` put back. rowid in the view is main_table's own. Like the other
+// temp objects they live only as long as the connection, so the prefixes
+// cost memory while the index is open rather than space in the file. dirs
+// rows are only ever inserted and deleted; a rename moves a subtree's rows
+// to newly interned directories instead of editing the old ones.
+pub(crate) fn load_dir_paths(conn: &Connection) -> Result<()> {
+    conn.execute_batch(&format!(
+        "CREATE TEMP TABLE IF NOT EXISTS dir_paths (
+             id INTEGER PRIMARY KEY,
+             path TEXT NOT NULL UNIQUE
+         );
+         CREATE INDEX IF NOT EXISTS temp.idx_dir_paths_listing
+             ON dir_paths(path COLLATE {collation});
+         DELETE FROM dir_paths;
+         INSERT INTO dir_paths (id, path) {chain};
+         CREATE TEMP TRIGGER IF NOT EXISTS dirs_inserted AFTER INSERT ON main.dirs
+         BEGIN
+             INSERT INTO dir_paths (id, path)
+             SELECT NEW.id, path || NEW.name FROM dir_paths WHERE id = NEW.parent_id;
+         END;
+         CREATE TEMP TRIGGER IF NOT EXISTS dirs_deleted AFTER DELETE ON main.dirs
+         BEGIN
+             DELETE FROM dir_paths WHERE id = OLD.id;
+         END;
+         CREATE TEMP VIEW IF NOT EXISTS entries AS
+             SELECT m.rowid AS rowid, d.path || m.name AS path, d.path AS dir_path, m.*
+             FROM main.main_table m JOIN dir_paths d ON d.id = m.dir_id;",
+        collation = PATH_COLLATION,
+        chain = dir_chain_sql("main")
+    ))
+}
+
+// Interns every directory of the attached database `schema` here, as (their
+// id, our id) pairs for copying its rows across.
+pub(crate) fn intern_attached_dirs(conn: &Connection, schema: &str) -> Result<Vec<(i64, i64)>> {
+    let theirs: Vec<(i64, String)> = conn
+        .prepare(&dir_chain_sql(schema))?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    theirs.into_iter().map(|(id, prefix)| Ok((id, intern_dir(conn, &prefix)?))).collect()
+}
+
+fn dir_id(conn: &Connection, prefix: &str) -> Result<Option<i64>> {
+    conn.prepare_cached("SELECT id FROM dir_paths WHERE path = ?1")?
+        .query_row(rusqlite::params![prefix], |row| row.get(0))
+        .optional()
+}
+
+// The id of the directory `prefix` (as split_path gives it), interning it
+// and whichever of its ancestors are new.
+pub(crate) fn intern_dir(conn: &Connection, prefix: &str) -> Result<i64> {
+    if let Some(id) = dir_id(conn, prefix)? {
+        return Ok(id);
+    }
+    let (parent, _) = split_path(&prefix[..prefix.len() - 1]);
+    let parent_id = intern_dir(conn, parent)?;
+    conn.prepare_cached("INSERT INTO dirs (parent_id, name) VALUES (?1, ?2)")?
+        .execute(rusqlite::params![parent_id, &prefix[parent.len()..]])?;
+    Ok(conn.last_insert_rowid())
+}
+
+// `path = <param>` over main_table or entries, spelled so idx_path answers
+// it: the prefix through dir_paths' index, then the name.
+pub(crate) fn path_is(param: &str) -> String {
+    format!(
+        "(dir_id = (SELECT id FROM dir_paths WHERE path = path_prefix({0})) \
+         AND name = path_name({0}))",
+        param
+    )
+}
+
+// `path >= <start> AND path < <end>` over main_table or entries, for the
+// bounds subtree_range gives: a row is below a directory exactly when its
+// prefix is, and the prefixes are found through dir_paths' index.
+pub(crate) fn dir_in_range(start: &str, end: &str) -> String {
+    format!("dir_id IN (SELECT id FROM dir_paths WHERE path >= {} AND path < {})", start, end)
+}
+
+// The directories whose rows list_children shows for `dir`: those whose
+// prefix is `dir` and one separator, compared under PATH_COLLATION. Mostly
+// just one; on Windows two spellings of a folder can both be indexed.
+pub(crate) fn child_dir_ids(conn: &Connection, dir: &str) -> Result<Vec<i64>> {
+    let key = parent_key(dir);
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT id FROM dir_paths WHERE path COLLATE {} IN (?1, ?2) ORDER BY id",
+        PATH_COLLATION
+    ))?;
+    let prefixes = rusqlite::params![format!("{}/", key), format!("{}\\", key)];
+    let ids = stmt.query_map(prefixes, |row| row.get(0))?;
+    Ok(ids.filter_map(Result::ok).collect())
+}
+
+// Deletes directories nothing is indexed under any more, as deletes leave
+// them behind. Each pass takes the current leaves.
+pub(crate) fn prune_dirs(conn: &Connection) -> Result<u64> {
+    let mut pruned = 0;
+    loop {
+        let deleted = conn.execute(
+            "DELETE FROM dirs
+             WHERE NOT EXISTS (SELECT 1 FROM main_table WHERE dir_id = dirs.id)
+               AND NOT EXISTS (SELECT 1 FROM dirs child WHERE child.parent_id = dirs.id)",
+            [],
+        )?;
+        if deleted == 0 {
+            return Ok(pruned);
+        }
+        pruned += deleted as u64;
+    }
+}
+
+// Creates `name` from `sql`, rebuilding it when an older release left an
+// index of that name with a different definition.
+fn ensure_index(conn: &Connection, name: &str, sql: &str) -> Result<()> {
+    let existing: Option<String> = conn
+        .query_row(
+            "SELECT sql FROM sqlite_master WHERE type = 'index' AND name = ?1",
+            rusqlite::params![name],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if existing.as_deref() != Some(sql) {
+        conn.execute(&format!("DROP INDEX IF EXISTS {}", name), [])?;
+        conn.execute(sql, [])?;
+    }
+    Ok(())
+}
+
+pub(crate) fn create_indexes(conn: &Connection) -> Result<()> {
+    // idx_path, idx_name and idx_extension keep the names list_db_indexes
+    // has always reported. A path is its directory and its name, so
+    // idx_path is what keeps paths unique and answers lookups by path.
+    ensure_index(
+        conn,
+        "idx_path",
+        "CREATE UNIQUE INDEX idx_path ON main_table(dir_id, name)",
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_name ON main_table(name)",
+        [],
+    )?;
+    // search_files filters on extension and name together; the name column
+    // still serves extension-only lookups.
+    conn.execute("DROP INDEX IF EXISTS idx_extension_name", [])?;
+    ensure_index(
+        conn,
+        "idx_extension",
+        "CREATE INDEX idx_extension ON main_table(extension, name)",
+    )?;
+    // list_children and its pages: one directory's rows, already in name order
+    ensure_index(
+        conn,
+        "idx_parent_name",
+        "CREATE INDEX idx_parent_name ON main_table(dir_id, name COLLATE NOCASE)",
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_file_id ON main_table(volume_id, file_id)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_file_events_path ON file_events(path, kind, occurred_at)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_file_events_time ON file_events(occurred_at)",
+        [],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS idx_stats_snapshots_run ON stats_snapshots(run_id)",
+        [],
+    )?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UpsertOutcome {
+    Created,
+    Updated,
+    // The path held a different file, told apart by size and file key, as
+    // when a followed link or a remount puts something new under an old path.
+    Replaced { previous_size: u64 },
+}
+
+// Refreshes the row in place when there is one, so columns the walk does not
+// produce (cached hashes) survive; the update's change count tells new rows
+// from refreshed ones without looking the path up first. Only a row that
+// turns out to describe another file costs an extra read. The row's name is
+// the end of its path, as split_path cuts it.
+pub(crate) fn insert_file_meta(conn: &Connection, file: &FileMeta) -> Result<UpsertOutcome> {
+    let (prefix, name) = split_path(&file.path);
+    let dir_id = intern_dir(conn, prefix)?;
+    let params = rusqlite::params![
+        name,
+        dir_id,
+        file.extension,
+        file.size,
+        file.modified,
+        file.accessed,
+        file.is_dir,
+        file.created,
+        file.is_symlink,
+        file.link_count,
+        file.file_key.map(|k| k.volume),
+        file.file_key.map(|k| k.index)
+    ];
+    const UPDATE: &str = "UPDATE main_table SET
+            extension = ?3, size = ?4, modified = ?5, accessed = ?6, is_dir = ?7, created = ?8,
+            is_symlink = ?9, link_count = ?10, volume_id = ?11, file_id = ?12
+         WHERE dir_id = ?2 AND name = ?1";
+    // a missing file key on either side never counts as a different file
+    let same_file = conn.execute(
+        &format!(
+            "{} AND NOT COALESCE(size != ?4 AND (volume_id != ?11 OR file_id != ?12), 0)",
+            UPDATE
+        ),
+        params,
+    )?;
+    if same_file > 0 {
+        return Ok(UpsertOutcome::Updated);
+    }
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO main_table (name, dir_id, extension, size, modified, accessed,
+                                           is_dir, created, is_symlink, link_count, volume_id,
+                                           file_id)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params,
+    )?;
+    if inserted > 0 {
+        return Ok(UpsertOutcome::Created);
+    }
+    let previous_size = conn.query_row(
+        "SELECT size FROM main_table WHERE dir_id = ?1 AND name = ?2",
+        rusqlite::params![dir_id, name],
+        |row| row.get(0),
+    )?;
+    conn.execute(UPDATE, params)?;
+    Ok(UpsertOutcome::Replaced { previous_size })
+}
+
+// Lets queries filter on path_depth(path) instead of counting separators in
+// SQL; parent_dir feeds the change-tracking triggers, and path_prefix and
+// path_name split a path the way main_table stores it (see path_is).
+pub(crate) fn register_sql_functions(conn: &Connection) -> Result<()> {
+    use rusqlite::functions::FunctionFlags;
+
+    conn.create_scalar_function(
+        "path_depth",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(path_depth(&ctx.get::<String>(0)?) as i64),
+    )?;
+    conn.create_scalar_function(
+        "parent_dir",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(parent_dir(&ctx.get::<String>(0)?)),
+    )?;
+    conn.create_scalar_function(
+        "path_prefix",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get_raw(0).as_str().map(|path| split_path(path).0.to_string())?),
+    )?;
+    conn.create_scalar_function(
+        "path_name",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(ctx.get_raw(0).as_str().map(|path| split_path(path).1.to_string())?),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileKey;
+
+    fn memory_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_sql_functions(&conn).unwrap();
+        create_table(&conn).unwrap();
+        migrate_schema(&conn).unwrap();
+        create_indexes(&conn).unwrap();
+        load_dir_paths(&conn).unwrap();
+        conn
+    }
+
+    fn file(path: &str, size: u64) -> FileMeta {
+        FileMeta {
+            name: path.rsplit('/').next().unwrap().to_string(),
+            path: path.to_string(),
+            extension: None,
+            size,
+            modified: 1_000,
+            accessed: None,
+            is_dir: false,
+            created: None,
+            is_symlink: false,
+            link_count: Some(1),
+            file_key: Some(FileKey { volume: 1, index: 7 }),
+            kind: None,
+        }
+    }
+
+    #[test]
+    fn inserting_a_path_twice_counts_the_second_as_an_update() {
+        let conn = memory_db();
+        assert_eq!(insert_file_meta(&conn, &file("/a/b.txt", 1)).unwrap(), UpsertOutcome::Created);
+        assert_eq!(insert_file_meta(&conn, &file("/a/b.txt", 2)).unwrap(), UpsertOutcome::Updated);
+        assert_eq!(insert_file_meta(&conn, &file("/a/c.txt", 3)).unwrap(), UpsertOutcome::Created);
+
+        let rows: (u64, u64) = conn
+            .query_row(
+                "SELECT COUNT(*), SUM(size) FROM entries WHERE path = '/a/b.txt'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(rows, (1, 2));
+    }
+
+    #[test]
+    fn an_update_keeps_columns_the_walk_does_not_produce() {
+        let conn = memory_db();
+        insert_file_meta(&conn, &file("/a/b.jpg", 1)).unwrap();
+        conn.execute("UPDATE main_table SET phash = 'abc', entropy = 7.5", []).unwrap();
+        insert_file_meta(&conn, &file("/a/b.jpg", 2)).unwrap();
+
+        let kept: (String, f64) = conn
+            .query_row("SELECT phash, entropy FROM main_table", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(kept, ("abc".to_string(), 7.5));
+    }
+
+    fn query_plan(conn: &Connection, sql: &str) -> String {
+        let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {}", sql)).unwrap();
+        let details: Vec<String> =
+            stmt.query_map([], |row| row.get(3)).unwrap().filter_map(Result::ok).collect();
+        details.join("\n")
+    }
+
+    #[test]
+    fn children_in_name_order_come_straight_from_idx_parent_name() {
+        let conn = memory_db();
+        let plan = query_plan(
+            &conn,
+            "SELECT path FROM entries WHERE dir_id IN (1) AND name <> ''
+             ORDER BY name COLLATE NOCASE, rowid",
+        );
+        assert!(plan.contains("INDEX idx_parent_name (dir_id=?)"), "{}", plan);
+        assert!(!plan.contains("TEMP B-TREE"), "{}", plan);
+    }
+
+    #[test]
+    fn lookups_and_ranges_resolve_prefixes_through_indexes() {
+        let conn = memory_db();
+        let lookup =
+            query_plan(&conn, &format!("SELECT size FROM entries WHERE {}", path_is("'/a/b'")));
+        assert!(lookup.contains("USING INDEX idx_path (dir_id=? AND name=?)"), "{}", lookup);
+        assert!(!lookup.contains("SCAN"), "{}", lookup);
+        let range = query_plan(
+            &conn,
+            &format!("SELECT COUNT(*) FROM main_table WHERE {}", dir_in_range("'/a/'", "'/a0'")),
+        );
+        assert!(range.contains("(dir_id=?)"), "{}", range);
+        assert!(!range.contains("SCAN"), "{}", range);
+    }
+
+    #[test]
+    fn child_dir_ids_are_the_directories_just_below() {
+        let conn = memory_db();
+        for path in ["/a/b.txt", "/a/sub", "/a/sub/c.txt", "/ab/d.txt"] {
+            insert_file_meta(&conn, &file(path, 1)).unwrap();
+        }
+        let children = |dir: &str| -> Vec<String> {
+            let ids = child_dir_ids(&conn, dir).unwrap();
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT path FROM entries WHERE dir_id IN ({}) ORDER BY path",
+                    vec!["?"; ids.len()].join(", ")
+                ))
+                .unwrap();
+            let rows = stmt.query_map(rusqlite::params_from_iter(ids), |row| row.get(0)).unwrap();
+            rows.filter_map(Result::ok).collect()
+        };
+        assert_eq!(children("/a/"), ["/a/b.txt", "/a/sub"]);
+        if cfg!(target_os = "windows") {
+            assert_eq!(children("/A/SUB"), ["/a/sub/c.txt"]);
+        } else {
+            assert_eq!(children("/A/SUB"), Vec::<String>::new());
+        }
+        assert_eq!(children("/"), Vec::<String>::new());
+        assert_eq!(parent_key("C:\\"), "C:");
+    }
+
+    #[test]
+    fn interned_paths_come_back_byte_for_byte() {
+        let conn = memory_db();
+        let paths = [
+            "/",
+            "/a/b.txt",
+            "/a/sub/c d.txt",
+            "C:\\",
+            "C:\\Users\\me\\notes.md",
+            "C:\\Users/me/mixed.txt",
+            "C:/Users\\me\\notes.md",
+            "\\\\server\\share\\x.bin",
+            "relative.txt",
+        ];
+        for path in paths {
+            insert_file_meta(&conn, &file(path, 1)).unwrap();
+        }
+        for path in paths {
+            let (prefix, name) = split_path(path);
+            assert_eq!(format!("{}{}", prefix, name), path);
+            let back: String = conn
+                .query_row(
+                    &format!("SELECT path FROM entries WHERE {}", path_is("?1")),
+                    [path],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(back, path);
+        }
+        // each spelling of a folder is a directory of its own, and only
+        // Windows splits on a backslash
+        let dirs: i64 = conn
+            .query_row("SELECT COUNT(*) FROM dir_paths WHERE path LIKE 'C:%me%'", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(dirs, if cfg!(target_os = "windows") { 3 } else { 1 });
+        // a reopened connection rebuilds the same prefixes from dirs
+        load_dir_paths(&conn).unwrap();
+        let count: i64 =
+            conn.query_row("SELECT COUNT(*) FROM entries", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, paths.len() as i64);
+    }
+
+    #[test]
+    fn pruning_drops_only_directories_nothing_is_under() {
+        let conn = memory_db();
+        for path in ["/a/b/c.txt", "/a/d.txt", "/e/f/g.txt"] {
+            insert_file_meta(&conn, &file(path, 1)).unwrap();
+        }
+        conn.execute(&format!("DELETE FROM main_table WHERE {}", path_is("?1")), ["/e/f/g.txt"])
+            .unwrap();
+        assert_eq!(prune_dirs(&conn).unwrap(), 2);
+        let mut stmt = conn.prepare("SELECT path FROM dir_paths ORDER BY path").unwrap();
+        let left: Vec<String> =
+            stmt.query_map([], |row| row.get(0)).unwrap().filter_map(Result::ok).collect();
+        assert_eq!(left, ["", "/", "/a/", "/a/b/"]);
+        assert_eq!(prune_dirs(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn a_different_file_at_a_known_path_is_a_replacement() {
+        let conn = memory_db();
+        insert_file_meta(&conn, &file("/a/b.txt", 10)).unwrap();
+        let mut other = file("/a/b.txt", 25);
+        other.file_key = Some(FileKey { volume: 1, index: 8 });
+        let outcome = insert_file_meta(&conn, &other).unwrap();
+        assert_eq!(outcome, UpsertOutcome::Replaced { previous_size: 10 });
+        let size: u64 = conn
+            .query_row("SELECT size FROM entries WHERE path = '/a/b.txt'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(size, 25);
+
+        // the same size under a new key is a file saved by replacement
+        let mut resaved = file("/a/b.txt", 25);
+        resaved.file_key = Some(FileKey { volume: 1, index: 9 });
+        assert_eq!(insert_file_meta(&conn, &resaved).unwrap(), UpsertOutcome::Updated);
+        // without a key to compare there is nothing to tell the files apart
+        let mut keyless = file("/a/b.txt", 40);
+        keyless.file_key = None;
+        assert_eq!(insert_file_meta(&conn, &keyless).unwrap(), UpsertOutcome::Updated);
+    }
+
+    #[test]
+    fn settings_read_back_what_was_last_set() {
+        let conn = memory_db();
+        assert_eq!(get_setting(&conn, "theme").unwrap(), None);
+        set_setting(&conn, "theme", "dark").unwrap();
+        set_setting(&conn, "theme", "light").unwrap();
+        assert_eq!(get_setting(&conn, "theme").unwrap().as_deref(), Some("light"));
+    }
+
+    #[test]
+    fn migrating_an_old_database_adds_the_missing_columns_once() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE main_table (
+                id INTEGER PRIMARY KEY, name TEXT, path TEXT UNIQUE,
+                extension TEXT, size INTEGER, modified INTEGER
+            )",
+            [],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO main_table (id, name, path) VALUES (4, 'a', '/a')", []).unwrap();
+        conn.execute(
+            "INSERT INTO main_table (id, name, path, extension, size, modified)
+             VALUES (9, 'c.TXT', '/b/c.TXT', 'TXT', 12, 34)",
+            [],
+        )
+        .unwrap();
+        create_table(&conn).unwrap();
+        migrate_schema(&conn).unwrap();
+        // a second start finds nothing left to add
+        migrate_schema(&conn).unwrap();
+
+        let columns = table_columns(&conn, "main", "main_table").unwrap();
+        for column in ["accessed", "is_dir", "file_id", "doc_meta_mtime", "dir_id"] {
+            assert_eq!(columns.iter().filter(|c| *c == column).count(), 1, "{}", column);
+        }
+        // the flat path and the old key went with the table they were in
+        assert!(!columns.iter().any(|c| c == "path" || c == "id"));
+        load_dir_paths(&conn).unwrap();
+        let mut stmt = conn
+            .prepare(
+                "SELECT rowid, path, name, extension, size, modified, is_dir FROM entries
+                 ORDER BY rowid",
+            )
+            .unwrap();
+        type Row = (i64, String, String, Option<String>, i64, i64, i64);
+        let rows: Vec<Row> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                ))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let c = ("c.TXT".to_string(), Some("TXT".to_string()));
+        assert_eq!(
+            rows,
+            [
+                (1, "/a".to_string(), "a".to_string(), None, 0, 0, 0),
+                (2, "/b/c.TXT".to_string(), c.0, c.1, 12, 34, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn sql_functions_match_their_rust_counterparts() {
+        let conn = memory_db();
+        let (depth, parent): (i64, Option<String>) = conn
+            .query_row("SELECT path_depth('C:\\a\\b'), parent_dir('C:\\a\\b')", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((depth, parent.as_deref()), (2, Some("C:\\a")));
+        let root: Option<String> =
+            conn.query_row("SELECT parent_dir('/')", [], |row| row.get(0)).unwrap();
+        assert_eq!(root, None);
+    }
+}
@@ -0,0 +1,356 @@
+// Document properties: the core and app parts of Office Open XML packages
+// (docx, xlsx, pptx) and the Info dictionary of PDFs. Anything unreadable in
+// the document itself is Error::MalformedDocument so a pass over many files
+// can record it and move on.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use crate::{utc_timestamp, Error};
+
+pub(crate) const DOCUMENT_EXTENSIONS: &[&str] = &["docx", "xlsx", "pptx", "pdf"];
+
+// lopdf parses the whole file; past this a PDF is not worth the memory
+const PDF_MAX_BYTES: u64 = 256 * 1024 * 1024;
+// a zip member bigger than this is not a real docProps part
+const PART_MAX_BYTES: u64 = 4 * 1024 * 1024;
+
+#[derive(Debug, Default, serde::Serialize)]
+pub(crate) struct DocumentMetadata {
+    pub(crate) title: Option<String>,
+    pub(crate) author: Option<String>,
+    // unix seconds, as the document records them
+    pub(crate) created: Option<u64>,
+    pub(crate) modified: Option<u64>,
+    // pages of a docx or PDF, slides of a pptx
+    pub(crate) pages: Option<u64>,
+    pub(crate) words: Option<u64>,
+}
+
+pub(crate) fn read_document_metadata(path: &Path) -> Result<DocumentMetadata, Error> {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "docx" | "xlsx" | "pptx" => read_ooxml(path),
+        "pdf" => read_pdf(path),
+        _ => Err(Error::InvalidInput(format!(
+            "{} is not a docx, xlsx, pptx or pdf file",
+            path.display()
+        ))),
+    }
+}
+
+fn malformed(path: &Path, e: impl std::fmt::Display) -> Error {
+    Error::MalformedDocument(path.to_string_lossy().to_string(), e.to_string())
+}
+
+fn read_ooxml(path: &Path) -> Result<DocumentMetadata, Error> {
+    let file = std::fs::File::open(path)?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| malformed(path, e))?;
+    // a package without docProps/core.xml simply has no properties
+    let core = read_part(&mut archive, path, "docProps/core.xml")?.unwrap_or_default();
+    let app = read_part(&mut archive, path, "docProps/app.xml")?.unwrap_or_default();
+    let core = xml_fields(&core).map_err(|e| malformed(path, e))?;
+    let app = xml_fields(&app).map_err(|e| malformed(path, e))?;
+    let count = |name: &str| app.get(name).and_then(|v| v.trim().parse().ok());
+    Ok(DocumentMetadata {
+        title: core.get("title").cloned(),
+        author: core.get("creator").cloned(),
+        created: core.get("created").and_then(|v| parse_w3c_date(v)),
+        modified: core.get("modified").and_then(|v| parse_w3c_date(v)),
+        pages: count("Pages").or_else(|| count("Slides")),
+        words: count("Words"),
+    })
+}
+
+fn read_part(
+    archive: &mut zip::ZipArchive<std::fs::File>,
+    path: &Path,
+    name: &str,
+) -> Result<Option<String>, Error> {
+    let part = match archive.by_name(name) {
+        Ok(part) => part,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(malformed(path, e)),
+    };
+    if part.size() > PART_MAX_BYTES {
+        return Err(malformed(path, format!("{} is implausibly large", name)));
+    }
+    let mut xml = String::new();
+    part.take(PART_MAX_BYTES).read_to_string(&mut xml).map_err(|e| malformed(path, e))?;
+    Ok(Some(xml))
+}
+
+// Text of every element that holds only text, keyed by local name, so
+// dc:title and cp:lastModifiedBy come out as "title" and "lastModifiedBy".
+fn xml_fields(xml: &str) -> Result<HashMap<String, String>, quick_xml::Error> {
+    use quick_xml::events::Event;
+
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.config_mut().trim_text(true);
+    let mut fields = HashMap::new();
+    let mut open: Option<String> = None;
+    loop {
+        match reader.read_event()? {
+            Event::Start(e) => {
+                open = Some(String::from_utf8_lossy(e.local_name().as_ref()).to_string());
+            }
+            Event::Text(text) => {
+                if let Some(name) = open.take() {
+                    let value = text.unescape()?.to_string();
+                    if !value.is_empty() {
+                        fields.entry(name).or_insert(value);
+                    }
+                }
+            }
+            Event::End(_) => open = None,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(fields)
+}
+
+// 2023-01-15T10:30:00Z, with optional fraction and numeric offset; a bare
+// date is taken as midnight UTC.
+fn parse_w3c_date(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let (date, time) = value.split_once('T').unwrap_or((value, "00:00:00Z"));
+    let mut ymd = date.splitn(3, '-').map(|p| p.parse::<i64>().ok());
+    let (year, month, day) = (ymd.next()??, ymd.next()??, ymd.next()??);
+
+    let (clock, offset) = match time.find(['Z', '+', '-']) {
+        Some(i) => (&time[..i], parse_utc_offset(&time[i..])?),
+        None => (time, 0),
+    };
+    let clock = clock.split('.').next()?;
+    let mut hms = clock.splitn(3, ':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute) = (hms.next()??, hms.next()??);
+    let second = hms.next().unwrap_or(Some(0))?;
+    let local = utc_timestamp(year, month, day, hour, minute, second)?;
+    u64::try_from(local as i64 - offset).ok()
+}
+
+// "Z", "+01:00" or "-0530" to seconds east of UTC.
+fn parse_utc_offset(offset: &str) -> Option<i64> {
+    let (sign, digits) = match offset.chars().next()? {
+        'Z' => return Some(0),
+        '+' => (1, &offset[1..]),
+        '-' => (-1, &offset[1..]),
+        _ => return None,
+    };
+    let digits: String = digits.chars().filter(|c| c.is_ascii_digit()).collect();
+    if digits.len() < 2 {
+        return None;
+    }
+    let hours: i64 = digits[..2].parse().ok()?;
+    let minutes: i64 = digits.get(2..4).map_or(Some(0), |m| m.parse().ok())?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+fn read_pdf(path: &Path) -> Result<DocumentMetadata, Error> {
+    if std::fs::metadata(path)?.len() > PDF_MAX_BYTES {
+        return Err(malformed(path, "too large to read the properties of"));
+    }
+    let document = lopdf::Document::load(path).map_err(|e| match e {
+        lopdf::Error::IO(e) => Error::Io(e),
+        e => malformed(path, e),
+    })?;
+    let pages = Some(document.get_pages().len() as u64);
+    let info = match document.trailer.get(b"Info") {
+        Ok(info) => document
+            .dereference(info)
+            .and_then(|(_, info)| info.as_dict())
+            .map_err(|e| malformed(path, e))?,
+        // no Info dictionary: only the page count is known
+        Err(_) => return Ok(DocumentMetadata { pages, ..Default::default() }),
+    };
+    let text = |key: &[u8]| match info.get(key).and_then(|o| document.dereference(o)) {
+        Ok((_, lopdf::Object::String(bytes, _))) => Some(pdf_text(bytes)).filter(|s| !s.is_empty()),
+        _ => None,
+    };
+    Ok(DocumentMetadata {
+        title: text(b"Title"),
+        author: text(b"Author"),
+        created: text(b"CreationDate").and_then(|d| parse_pdf_date(&d)),
+        modified: text(b"ModDate").and_then(|d| parse_pdf_date(&d)),
+        pages,
+        words: None,
+    })
+}
+
+// PDF text strings are UTF-16BE behind a byte order mark, otherwise
+// PDFDocEncoding, which matches Latin-1 for everything a title needs.
+fn pdf_text(bytes: &[u8]) -> String {
+    let text = match bytes.strip_prefix(&[0xfe, 0xff]) {
+        Some(utf16) => {
+            let units: Vec<u16> = utf16
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        None => bytes.iter().map(|&b| char::from(b)).collect(),
+    };
+    text.trim().to_string()
+}
+
+// D:YYYYMMDDHHmmSSOHH'mm', where everything after the year is optional.
+fn parse_pdf_date(value: &str) -> Option<u64> {
+    let value = value.strip_prefix("D:").unwrap_or(value);
+    let digits = value.bytes().take_while(u8::is_ascii_digit).count();
+    let field = |from: usize, len: usize, default: i64| -> Option<i64> {
+        match value.get(from..from + len) {
+            Some(part) if from + len <= digits => part.parse().ok(),
+            _ => Some(default),
+        }
+    };
+    if digits < 4 {
+        return None;
+    }
+    let local = utc_timestamp(
+        field(0, 4, 0)?,
+        field(4, 2, 1)?,
+        field(6, 2, 1)?,
+        field(8, 2, 0)?,
+        field(10, 2, 0)?,
+        field(12, 2, 0)?,
+    )?;
+    let offset = match &value[digits..] {
+        "" => 0,
+        rest => parse_utc_offset(rest).unwrap_or(0),
+    };
+    u64::try_from(local as i64 - offset).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // 2023-01-15T10:30:00Z
+    const MID_JANUARY: u64 = 1_673_778_600;
+
+    fn write_package(path: &Path, parts: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, content) in parts {
+            zip.start_file(*name, zip::write::FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn w3c_dates_honour_fractions_offsets_and_bare_dates() {
+        assert_eq!(parse_w3c_date("2023-01-15T10:30:00Z"), Some(MID_JANUARY));
+        assert_eq!(parse_w3c_date(" 2023-01-15T10:30:00.250Z "), Some(MID_JANUARY));
+        assert_eq!(parse_w3c_date("2023-01-15T12:30:00+02:00"), Some(MID_JANUARY));
+        assert_eq!(parse_w3c_date("2023-01-15T05:00:00-0530"), Some(MID_JANUARY));
+        assert_eq!(parse_w3c_date("2023-01-15T10:30Z"), Some(MID_JANUARY));
+        assert_eq!(parse_w3c_date("2023-01-15"), Some(MID_JANUARY - 37_800));
+        assert_eq!(parse_w3c_date("last tuesday"), None);
+        assert_eq!(parse_w3c_date("2023-01-15T10:30:00+1"), None);
+    }
+
+    #[test]
+    fn pdf_dates_default_missing_fields() {
+        assert_eq!(parse_pdf_date("D:20230115103000Z"), Some(MID_JANUARY));
+        assert_eq!(parse_pdf_date("D:20230115123000+02'00'"), Some(MID_JANUARY));
+        assert_eq!(parse_pdf_date("20230115103000"), Some(MID_JANUARY));
+        assert_eq!(parse_pdf_date("D:2023"), parse_w3c_date("2023-01-01"));
+        assert_eq!(parse_pdf_date("D:20"), None);
+    }
+
+    #[test]
+    fn pdf_text_decodes_utf16_and_latin1() {
+        assert_eq!(pdf_text(&[0xfe, 0xff, 0x00, 0x51, 0x00, 0x33, 0x20, 0xac]), "Q3€");
+        assert_eq!(pdf_text(b" Caf\xe9 "), "Café");
+    }
+
+    #[test]
+    fn ooxml_properties_come_from_core_and_app_parts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("budget.docx");
+        let core = r#"<?xml version="1.0"?>
+            <cp:coreProperties xmlns:cp="cp" xmlns:dc="dc" xmlns:dcterms="dcterms">
+              <dc:title>Q3 budget &amp; plan</dc:title>
+              <dc:creator>Finance</dc:creator>
+              <dcterms:created>2023-01-15T10:30:00Z</dcterms:created>
+            </cp:coreProperties>"#;
+        let app = "<Properties><Pages>4</Pages><Words> 1200 </Words></Properties>";
+        write_package(&path, &[("docProps/core.xml", core), ("docProps/app.xml", app)]);
+
+        let meta = read_document_metadata(&path).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("Q3 budget & plan"));
+        assert_eq!(meta.author.as_deref(), Some("Finance"));
+        assert_eq!(meta.created, Some(MID_JANUARY));
+        assert_eq!(meta.modified, None);
+        assert_eq!((meta.pages, meta.words), (Some(4), Some(1200)));
+
+        // a package without properties is fine; slides stand in for pages
+        let deck = dir.path().join("deck.pptx");
+        let app = "<Properties><Slides>7</Slides></Properties>";
+        write_package(&deck, &[("docProps/app.xml", app)]);
+        let meta = read_document_metadata(&deck).unwrap();
+        assert_eq!((meta.title, meta.pages), (None, Some(7)));
+    }
+
+    #[test]
+    fn unreadable_documents_are_malformed_and_others_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let not_zip = dir.path().join("broken.xlsx");
+        std::fs::write(&not_zip, b"not a zip").unwrap();
+        assert!(matches!(read_document_metadata(&not_zip), Err(Error::MalformedDocument(..))));
+
+        let bad_xml = dir.path().join("bad.docx");
+        write_package(&bad_xml, &[("docProps/core.xml", "<a><b></a>")]);
+        assert!(matches!(read_document_metadata(&bad_xml), Err(Error::MalformedDocument(..))));
+
+        let not_pdf = dir.path().join("fake.pdf");
+        std::fs::write(&not_pdf, b"%PDF-nothing").unwrap();
+        assert!(matches!(read_document_metadata(&not_pdf), Err(Error::MalformedDocument(..))));
+
+        let text = dir.path().join("notes.txt");
+        std::fs::write(&text, b"x").unwrap();
+        assert!(matches!(read_document_metadata(&text), Err(Error::InvalidInput(_))));
+        let missing = dir.path().join("missing.docx");
+        assert!(matches!(read_document_metadata(&missing), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn pdf_properties_come_from_the_info_dictionary() {
+        use lopdf::{dictionary, Object};
+
+        let mut doc = lopdf::Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let page = |doc: &mut lopdf::Document| {
+            doc.add_object(dictionary! { "Type" => "Page", "Parent" => pages_id })
+        };
+        let kids: Vec<Object> = vec![page(&mut doc).into(), page(&mut doc).into()];
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! { "Type" => "Pages", "Kids" => kids, "Count" => 2 }),
+        );
+        let catalog = doc.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        let info = doc.add_object(dictionary! {
+            "Title" => Object::string_literal("Q3 budget"),
+            "Author" => Object::string_literal(""),
+            "CreationDate" => Object::string_literal("D:20230115103000Z"),
+        });
+        doc.trailer.set("Root", catalog);
+        doc.trailer.set("Info", info);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("budget.pdf");
+        doc.save(&path).unwrap();
+
+        let meta = read_document_metadata(&path).unwrap();
+        assert_eq!(meta.title.as_deref(), Some("Q3 budget"));
+        // an empty string is no author at all
+        assert_eq!(meta.author, None);
+        assert_eq!(meta.created, Some(MID_JANUARY));
+        assert_eq!(meta.pages, Some(2));
+    }
+}
@@ -0,0 +1,372 @@
+// Reading entries off the disk: FileMeta construction, the folders indexing
+// skips and the walk itself. get_file_meta goes through MetadataSource so the
+// conversion can be driven by something other than the real filesystem.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use walkdir::WalkDir;
+
+use crate::{FileKey, FileMeta};
+
+pub(crate) const SKIP_KEYWORDS: &[&str] = &["CloudStore", "OneDrive", "System Volume Information"];
+
+pub(crate) fn is_excluded(path: &str) -> bool {
+    contains_any(path, SKIP_KEYWORDS)
+}
+
+fn contains_any<S: AsRef<str>>(path: &str, keywords: &[S]) -> bool {
+    keywords.iter().any(|k| path.contains(k.as_ref()))
+}
+
+// What a walk leaves out and whether it descends through symlinks. The
+// default is what indexing uses; tests and one-off walks make their own.
+#[derive(Debug, Clone)]
+pub(crate) struct WalkConfig {
+    // paths containing any of these are left out
+    pub(crate) skip_keywords: Vec<String>,
+    pub(crate) follow_links: bool,
+}
+
+impl Default for WalkConfig {
+    fn default() -> Self {
+        WalkConfig {
+            skip_keywords: SKIP_KEYWORDS.iter().map(|k| k.to_string()).collect(),
+            follow_links: false,
+        }
+    }
+}
+
+impl WalkConfig {
+    pub(crate) fn excludes(&self, path: &str) -> bool {
+        contains_any(path, &self.skip_keywords)
+    }
+
+    pub(crate) fn walk(&self, root: &str) -> IndexWalk {
+        IndexWalk {
+            entries: WalkDir::new(root).follow_links(self.follow_links).into_iter(),
+            root: PathBuf::from(root),
+            config: self.clone(),
+            unreadable: Vec::new(),
+        }
+    }
+}
+
+// Symlinks are indexed as entries of their own but never descended into.
+pub(crate) fn index_walk(root: &str) -> IndexWalk {
+    WalkConfig::default().walk(root)
+}
+
+// Entries the walk fails to read are left out. Where that happened goes to
+// `unreadable`, so a pruning run does not take what lies there for deleted.
+pub(crate) struct IndexWalk {
+    entries: walkdir::IntoIter,
+    root: PathBuf,
+    config: WalkConfig,
+    pub(crate) unreadable: Vec<PathBuf>,
+}
+
+impl Iterator for IndexWalk {
+    type Item = walkdir::DirEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.entries.next()? {
+                Ok(entry) if self.config.excludes(&entry.path().display().to_string()) => {}
+                Ok(entry) => return Some(entry),
+                Err(err) => {
+                    let at = err.path().unwrap_or(&self.root).to_path_buf();
+                    self.unreadable.push(at);
+                }
+            }
+        }
+    }
+}
+
+// What FileMeta is built from, stripped of fs::Metadata so it can be made up.
+pub(crate) struct EntryStat {
+    pub(crate) is_symlink: bool,
+    pub(crate) is_dir: bool,
+    pub(crate) len: u64,
+    pub(crate) modified: std::io::Result<SystemTime>,
+    pub(crate) accessed: Option<SystemTime>,
+    // not every filesystem records a birth time
+    pub(crate) created: Option<SystemTime>,
+    // link count and file key; None for directories
+    pub(crate) identity: Option<(u64, FileKey)>,
+}
+
+pub(crate) trait MetadataSource {
+    fn stat(&self, path: &Path) -> std::io::Result<EntryStat>;
+}
+
+pub(crate) struct DiskMetadata;
+
+impl MetadataSource for DiskMetadata {
+    // Symlinks are described by their target when it resolves and by the link
+    // itself when it does not, so dangling links still get a row.
+    fn stat(&self, path: &Path) -> std::io::Result<EntryStat> {
+        let link_metadata = fs::symlink_metadata(path)?;
+        let is_symlink = link_metadata.file_type().is_symlink();
+        let metadata = if is_symlink {
+            fs::metadata(path).unwrap_or(link_metadata)
+        } else {
+            link_metadata
+        };
+        let identity = if metadata.is_dir() {
+            None
+        } else {
+            file_identity(path, &metadata)
+        };
+        Ok(EntryStat {
+            is_symlink,
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified(),
+            accessed: metadata.accessed().ok(),
+            created: metadata.created().ok(),
+            identity,
+        })
+    }
+}
+
+pub(crate) fn get_file_meta(path: &Path) -> std::io::Result<FileMeta> {
+    read_file_meta(&DiskMetadata, path)
+}
+
+pub(crate) fn read_file_meta(source: &impl MetadataSource, path: &Path) -> std::io::Result<FileMeta> {
+    let stat = source.stat(path)?;
+    let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+
+    let modified = stat.modified?.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|s| s.to_string());
+    let (link_count, file_key) = match stat.identity {
+        Some((links, key)) => (Some(links), Some(key)),
+        None => (None, None),
+    };
+
+    Ok(FileMeta {
+        name: path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string(),
+        path: path.to_string_lossy().to_string(),
+        extension,
+        size: stat.len,
+        modified,
+        accessed: stat.accessed.and_then(secs),
+        is_dir: stat.is_dir,
+        created: stat.created.and_then(secs),
+        is_symlink: stat.is_symlink,
+        link_count,
+        file_key,
+        kind: None,
+    })
+}
+
+pub(crate) fn metadata_mtime(metadata: &fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+// Windows only hands out link counts and file indexes through an open handle,
+// so this costs one extra open per file while indexing. The handle is opened
+// without read access, which also works on files other processes hold locked.
+#[cfg(target_os = "windows")]
+pub(crate) fn file_identity(path: &Path, metadata: &fs::Metadata) -> Option<(u64, FileKey)> {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsRawHandle;
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION,
+    };
+
+    // Opening a cloud placeholder can start downloading it.
+    if is_cloud_placeholder(metadata) {
+        return None;
+    }
+    let file = fs::OpenOptions::new().access_mode(0).open(path).ok()?;
+    // SAFETY: the handle stays open for the duration of the call and the
+    // struct is plain data filled in by the API.
+    let info = unsafe {
+        let mut info: BY_HANDLE_FILE_INFORMATION = std::mem::zeroed();
+        if GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) == 0 {
+            return None;
+        }
+        info
+    };
+    let index = ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64;
+    Some((
+        info.nNumberOfLinks as u64,
+        FileKey {
+            volume: info.dwVolumeSerialNumber as i64,
+            index: index as i64,
+        },
+    ))
+}
+
+// OneDrive-style files whose contents live in the cloud until opened.
+#[cfg(target_os = "windows")]
+pub(crate) fn is_cloud_placeholder(metadata: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_OFFLINE: u32 = 0x1000;
+    const FILE_ATTRIBUTE_RECALL_ON_OPEN: u32 = 0x40000;
+    const FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS: u32 = 0x400000;
+    metadata.file_attributes()
+        & (FILE_ATTRIBUTE_OFFLINE | FILE_ATTRIBUTE_RECALL_ON_OPEN | FILE_ATTRIBUTE_RECALL_ON_DATA_ACCESS)
+        != 0
+}
+
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn is_cloud_placeholder(_metadata: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(unix)]
+pub(crate) fn file_identity(_path: &Path, metadata: &fs::Metadata) -> Option<(u64, FileKey)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((
+        metadata.nlink(),
+        FileKey {
+            volume: metadata.dev() as i64,
+            index: metadata.ino() as i64,
+        },
+    ))
+}
+
+#[cfg(not(any(unix, target_os = "windows")))]
+pub(crate) fn file_identity(_path: &Path, _metadata: &fs::Metadata) -> Option<(u64, FileKey)> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Hands out the same made-up stat for every path, or an error.
+    struct FakeMetadata(fn() -> std::io::Result<EntryStat>);
+
+    impl MetadataSource for FakeMetadata {
+        fn stat(&self, _path: &Path) -> std::io::Result<EntryStat> {
+            (self.0)()
+        }
+    }
+
+    fn plain_file() -> std::io::Result<EntryStat> {
+        Ok(EntryStat {
+            is_symlink: false,
+            is_dir: false,
+            len: 42,
+            modified: Ok(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            accessed: Some(UNIX_EPOCH + Duration::from_secs(1_700_000_100)),
+            created: None,
+            identity: Some((2, FileKey { volume: 3, index: 4 })),
+        })
+    }
+
+    #[test]
+    fn file_meta_is_built_from_the_source() {
+        let path = Path::new("/data/report.PDF");
+        let meta = read_file_meta(&FakeMetadata(plain_file), path).unwrap();
+        assert_eq!((meta.name.as_str(), meta.extension.as_deref()), ("report.PDF", Some("PDF")));
+        assert_eq!((meta.size, meta.modified), (42, 1_700_000_000));
+        assert_eq!(meta.accessed, Some(1_700_000_100));
+        assert_eq!(meta.created, None);
+        assert_eq!(meta.link_count, Some(2));
+        assert!(matches!(meta.file_key, Some(FileKey { volume: 3, index: 4 })));
+    }
+
+    #[test]
+    fn errors_from_the_source_come_back_unchanged() {
+        let denied = || Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+        let err = read_file_meta(&FakeMetadata(denied), Path::new("/data/locked")).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::PermissionDenied);
+
+        let no_mtime = || {
+            let mut stat = plain_file()?;
+            stat.modified = Err(std::io::Error::from(std::io::ErrorKind::Unsupported));
+            Ok(stat)
+        };
+        assert!(read_file_meta(&FakeMetadata(no_mtime), Path::new("/data/a")).is_err());
+    }
+
+    #[test]
+    fn timestamps_before_the_epoch_do_not_fail_the_entry() {
+        let ancient = || {
+            let mut stat = plain_file()?;
+            stat.modified = Ok(UNIX_EPOCH - Duration::from_secs(60));
+            stat.accessed = Some(UNIX_EPOCH - Duration::from_secs(60));
+            stat.is_dir = true;
+            stat.identity = None;
+            Ok(stat)
+        };
+        let meta = read_file_meta(&FakeMetadata(ancient), Path::new("/data/old")).unwrap();
+        assert_eq!((meta.modified, meta.accessed), (0, None));
+        assert!(meta.is_dir);
+        assert_eq!(meta.link_count, None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_kept_lossily() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(b"/data/caf\xe9.txt"));
+        let meta = read_file_meta(&FakeMetadata(plain_file), path).unwrap();
+        assert_eq!(meta.name, "caf\u{fffd}.txt");
+        assert_eq!(meta.extension.as_deref(), Some("txt"));
+    }
+
+    #[test]
+    fn exclusions_come_from_the_walk_config() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["keep/a.txt", "OneDrive/b.txt", "scratch/c.txt"] {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "x").unwrap();
+        }
+        let names = |walk: IndexWalk| {
+            let mut names: Vec<String> = walk
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.file_name().to_string_lossy().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+        let root = dir.path().to_string_lossy().to_string();
+        assert_eq!(names(index_walk(&root)), ["a.txt", "c.txt"]);
+        let walk = WalkConfig { skip_keywords: vec!["scratch".to_string()], follow_links: false };
+        assert!(walk.excludes("/x/scratch/y") && !walk.excludes("/x/OneDrive/y"));
+        assert_eq!(names(walk.walk(&root)), ["a.txt", "b.txt"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn following_links_is_up_to_the_walk_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        fs::create_dir(&target).unwrap();
+        fs::write(target.join("inside.txt"), "x").unwrap();
+        let tree = dir.path().join("tree");
+        fs::create_dir(&tree).unwrap();
+        std::os::unix::fs::symlink(&target, tree.join("link")).unwrap();
+
+        let root = tree.to_string_lossy().to_string();
+        let count = |follow_links| {
+            let walk = WalkConfig { follow_links, ..WalkConfig::default() };
+            walk.walk(&root).count()
+        };
+        // the root and the link itself, then also what the link points at
+        assert_eq!(count(false), 2);
+        assert_eq!(count(true), 3);
+    }
+}
@@ -1,358 +1,15286 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 use std::{fs, path::Path, sync::Mutex};
-use rusqlite::{Connection, Result};
+use rusqlite::{Connection, OptionalExtension, Result};
 use tauri::State;
 use walkdir::WalkDir;
 use std::time::UNIX_EPOCH;
 use tauri::Manager;
 use std::error::Error as StdError;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+mod db;
+mod documents;
+mod fs_meta;
+mod remote;
+
+use db::*;
+use fs_meta::{
+    file_identity, get_file_meta, index_walk, is_cloud_placeholder, is_excluded, metadata_mtime,
+    read_file_meta, DiskMetadata, MetadataSource, WalkConfig,
+};
 
 struct DbConnection(Mutex<Connection>);
 
-#[derive(Debug, serde::Serialize)]
-struct FileMeta {
-    name: String,
-    path: String,
-    extension: Option<String>,
-    size: u64,
-    modified: u64,
+// Where the index lives. Launched with --ephemeral (or FILE_EXPLORER_EPHEMERAL
+// set) it is kept in memory and nothing is written under the app data dir.
+enum DbLocation {
+    File(std::path::PathBuf),
+    Memory,
 }
 
-#[derive(Debug)]
-enum Error {
-    Io(std::io::Error),
-    Rusqlite(rusqlite::Error),
-    MutexPoison,
+fn ephemeral_requested() -> bool {
+    std::env::args().any(|arg| arg == "--ephemeral")
+        || std::env::var_os("FILE_EXPLORER_EPHEMERAL").is_some()
 }
 
-impl From<std::io::Error> for Error {
-    fn from(err: std::io::Error) -> Self {
-        Error::Io(err)
+// Commands that store settings, and what each stores. Saved in an in-memory
+// index they would vanish on exit, so the invoke handler refuses them there.
+const PERSISTENT_COMMANDS: &[(&str, &str)] = &[
+    ("set_result_limits", "result limits"),
+    ("set_custom_file_kind", "file kinds"),
+    ("set_index_throttle", "index throttle"),
+    ("set_activity_retention", "activity retention"),
+    ("set_stats_retention", "stats retention"),
+    ("set_schedule", "schedules"),
+    ("set_integrity_schedule", "the integrity schedule"),
+    ("set_drive_settings", "drive settings"),
+    ("set_tray_settings", "tray settings"),
+    ("set_open_files_confirm_above", "the open files threshold"),
+    ("protect_root", "protected roots"),
+    ("unprotect_root", "protected roots"),
+    ("set_restricted_mode", "restricted mode"),
+    ("set_directory_approved", "approved directories"),
+    ("set_copy_verify_retries", "the copy verification retries"),
+    ("add_remote", "remote locations"),
+    ("add_rule", "rules"),
+    ("update_rule", "rules"),
+    ("delete_rule", "rules"),
+];
+
+impl DbLocation {
+    fn require_persistent(&self, what: &'static str) -> Result<(), Error> {
+        match self {
+            DbLocation::File(_) => Ok(()),
+            DbLocation::Memory => Err(Error::Ephemeral(what)),
+        }
     }
-}
 
-impl From<rusqlite::Error> for Error {
-    fn from(err: rusqlite::Error) -> Self {
-        Error::Rusqlite(err)
+    // Error::Ephemeral for a PERSISTENT_COMMANDS entry while in memory.
+    fn admit(&self, command: &str) -> Result<(), Error> {
+        match PERSISTENT_COMMANDS.iter().find(|(name, _)| *name == command) {
+            Some((_, what)) => self.require_persistent(what),
+            None => Ok(()),
+        }
     }
 }
 
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Error::Io(e) => write!(f, "IO error: {}", e),
-            Error::Rusqlite(e) => write!(f, "Database error: {}", e),
-            Error::MutexPoison => write!(f, "Mutex poisoned"),
+// Cancellation for long-running commands, keyed by an operation token. A
+// command takes a client-generated token or makes one and announces it in
+// "operation-started"; cancel_operation flips the flag and the work checks it
+// between units. Tokens are dropped when the operation ends, and closing a
+// window cancels whatever it started.
+#[derive(Default)]
+struct TokenRegistry {
+    next_id: std::sync::atomic::AtomicU64,
+    tokens: Mutex<std::collections::HashMap<String, (String, std::sync::Arc<AtomicBool>)>>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct OperationStarted {
+    token: String,
+    command: &'static str,
+}
+
+impl TokenRegistry {
+    fn begin(
+        &self,
+        window: &tauri::Window,
+        token: Option<String>,
+        command: &'static str,
+    ) -> Result<OperationToken<'_>, Error> {
+        let flag = std::sync::Arc::new(AtomicBool::new(false));
+        self.begin_with_flag(window, token, command, flag)
+    }
+
+    // For operations that already own a flag other code can also set.
+    fn begin_with_flag(
+        &self,
+        window: &tauri::Window,
+        token: Option<String>,
+        command: &'static str,
+        flag: std::sync::Arc<AtomicBool>,
+    ) -> Result<OperationToken<'_>, Error> {
+        let token = token.unwrap_or_else(|| {
+            format!("{}-{}", command, self.next_id.fetch_add(1, Ordering::SeqCst))
+        });
+        {
+            let mut tokens = self.tokens.lock().map_err(|_| Error::MutexPoison)?;
+            if tokens.contains_key(&token) {
+                return Err(Error::InvalidInput(format!("operation token '{}' is in use", token)));
+            }
+            tokens.insert(token.clone(), (window.label().to_string(), flag.clone()));
+        }
+        let _ = window.emit("operation-started", OperationStarted { token: token.clone(), command });
+        Ok(OperationToken { registry: self, token, flag })
+    }
+
+    fn cancel(&self, token: &str) {
+        if let Ok(tokens) = self.tokens.lock() {
+            if let Some((_, flag)) = tokens.get(token) {
+                flag.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+
+    fn cancel_window(&self, label: &str) {
+        if let Ok(tokens) = self.tokens.lock() {
+            for (_, flag) in tokens.values().filter(|(owner, _)| owner == label) {
+                flag.store(true, Ordering::SeqCst);
+            }
         }
     }
 }
 
-impl serde::Serialize for Error {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::ser::Serializer,
-    {
-        serializer.serialize_str(&self.to_string())
+struct OperationToken<'a> {
+    registry: &'a TokenRegistry,
+    token: String,
+    flag: std::sync::Arc<AtomicBool>,
+}
+
+impl OperationToken<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
     }
 }
 
-impl StdError for Error {}
+impl Drop for OperationToken<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut tokens) = self.registry.tokens.lock() {
+            tokens.remove(&self.token);
+        }
+    }
+}
 
-fn main() {
-    tauri::Builder::default()
-        .setup(|app| {
-            // Initialize database
-            let app_dir = app.path_resolver().app_data_dir().unwrap();
-            std::fs::create_dir_all(&app_dir)?;
-            let db_path = app_dir.join("file_explorer.sqlite3");
-            println!("Database path: {:?}", db_path);
-            
-            let mut conn = Connection::open(&db_path)?;
-            println!("Database connection established");
-            create_table(&conn)?;
-            create_indexes(&conn)?;
-            println!("Database tables and indexes created");
-            
-            // Check if database is empty and populate if needed
-            let count: i64 = conn.query_row("SELECT COUNT(*) FROM main_table", [], |row| row.get(0))?;
-            if count == 0 {
-                println!("Database is empty, populating with initial data...");
-                let path = "C:\\".to_string();
-                let tx = conn.transaction()?;
-                let skip_keywords = ["CloudStore", "OneDrive", "System Volume Information"];
-
-                for entry in WalkDir::new(&path)
-                    .follow_links(false)
-                    .into_iter()
-                    .filter_map(Result::ok)
-                    .filter(|e| !e.file_type().is_symlink())
-                    .filter(|e| {
-                        let path_str = e.path().display().to_string();
-                        !skip_keywords.iter().any(|k| path_str.contains(k))
-                    })
-                {
-                    if let Ok(file_meta) = get_file_meta(entry.path()) {
-                        if let Err(err) = insert_file_meta(&tx, &file_meta) {
-                            eprintln!("DB insert error for {:?}: {:?}", entry.path(), err);
-                        }
-                    }
-                }
+// What a cancellable command hands back: the full result, or whatever was
+// done before cancel_operation stopped it.
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "status", content = "result", rename_all = "lowercase")]
+enum Outcome<T> {
+    Completed(T),
+    Cancelled(T),
+}
 
-                tx.commit()?;
-                println!("Initial data population complete");
-            }
-            
-            app.manage(DbConnection(Mutex::new(conn)));
-            Ok(())
-        })
-        .invoke_handler(tauri::generate_handler![
-            list_children,
-            search_files,
-            get_file_meta_command,
-            transfer_to_sqlite,
-            get_directory_size,
-            database_has_files,
-            list_directory_contents,
-            open_file
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+impl<T> Outcome<T> {
+    fn new(result: T, cancelled: bool) -> Self {
+        if cancelled {
+            Outcome::Cancelled(result)
+        } else {
+            Outcome::Completed(result)
+        }
+    }
 }
 
+// Unknown and already finished tokens are ignored, so a late cancel is harmless.
 #[tauri::command]
-fn list_children(db: State<DbConnection>, dir: String) -> Result<Vec<FileMeta>, Error> {
-    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
-    let norm_dir = dir.trim_end_matches('\\');
-    let slash_count = norm_dir.matches('\\').count();
-    let target_slash_count = slash_count + 1;
+fn cancel_operation(registry: State<TokenRegistry>, token: String) {
+    registry.cancel(&token);
+}
 
-    let like_pattern = format!("{}\\%", norm_dir.replace("\\", "\\\\"));
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IndexSource {
+    Manual,
+    Scheduled,
+    // a removable volume was plugged in with auto_index_removable on
+    Removable,
+}
 
-    let mut stmt = conn.prepare(
-        "SELECT name, path, extension, size, modified
-         FROM main_table
-         WHERE path LIKE ?1 ESCAPE '\\'
-         AND (LENGTH(path) - LENGTH(REPLACE(path, '\\', ''))) = ?2",
-    )?;
+impl IndexSource {
+    fn as_str(self) -> &'static str {
+        match self {
+            IndexSource::Manual => "manual",
+            IndexSource::Scheduled => "scheduled",
+            IndexSource::Removable => "removable",
+        }
+    }
+}
 
-    let rows = stmt.query_map(rusqlite::params![like_pattern, target_slash_count], |row| {
-        Ok(FileMeta {
-            name: row.get(0)?,
-            path: row.get(1)?,
-            extension: row.get(2)?,
-            size: row.get(3)?,
-            modified: row.get(4)?,
-        })
-    })?;
+#[derive(Debug, Clone, Default, serde::Serialize)]
+struct IndexingStatus {
+    running: bool,
+    current_root: Option<String>,
+    source: Option<&'static str>,
+    processed: u64,
+    // entries per second since the run started, pauses included
+    entries_per_sec: f64,
+    throttled: bool,
+    paused: bool,
+    // a scheduled run is due but held back, or a running one is on hold,
+    // until the user has been away long enough
+    waiting_for_idle: bool,
+    // entries per second over the last RATE_WINDOW
+    rate: Option<f64>,
+    // only when the previous completed run of this root gives a total
+    eta_seconds: Option<u64>,
+    percent: Option<f64>,
+}
 
-    Ok(rows.filter_map(Result::ok).collect())
+const RATE_WINDOW: std::time::Duration = std::time::Duration::from_secs(10);
+const RATE_SAMPLE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+// Rolling rate over the last RATE_WINDOW. Until a second of samples is in,
+// it falls back to `seed`, the previous run's average.
+#[derive(Debug, Default)]
+struct RateTracker {
+    samples: std::collections::VecDeque<(std::time::Instant, u64)>,
+    seed: Option<f64>,
 }
 
-#[tauri::command]
-fn search_files(
-    db: State<DbConnection>,
-    name: String,
-    extension: String,
-) -> Result<Vec<FileMeta>, Error> {
-    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+impl RateTracker {
+    fn new(seed: Option<f64>) -> Self {
+        RateTracker { samples: std::collections::VecDeque::new(), seed }
+    }
 
-    fn map_row(row: &rusqlite::Row) -> Result<FileMeta, rusqlite::Error> {
-        Ok(FileMeta {
-            name: row.get(0)?,
-            path: row.get(1)?,
-            extension: row.get(2)?,
-            size: row.get(3)?,
-            modified: row.get(4)?,
-        })
+    fn record(&mut self, at: std::time::Instant, processed: u64) {
+        if let Some(&(last, _)) = self.samples.back() {
+            if at.duration_since(last) < RATE_SAMPLE_INTERVAL {
+                return;
+            }
+        }
+        self.samples.push_back((at, processed));
+        while let Some(&(first, _)) = self.samples.front() {
+            if at.duration_since(first) <= RATE_WINDOW {
+                break;
+            }
+            self.samples.pop_front();
+        }
     }
 
-    let result = if !extension.is_empty() {
-        let mut stmt = conn.prepare(
-            "SELECT name, path, extension, size, modified
-             FROM main_table
-             WHERE name LIKE ?1 AND extension = ?2",
-        )?;
-        let rows = stmt.query_map(rusqlite::params![&format!("%{}%", name), &extension], map_row)?;
-        rows.filter_map(Result::ok).collect::<Vec<_>>()
-    } else {
-        let mut stmt = conn.prepare(
-            "SELECT name, path, extension, size, modified
-             FROM main_table
-             WHERE name LIKE ?1",
-        )?;
-        let rows = stmt.query_map(rusqlite::params![&format!("%{}%", name)], map_row)?;
-        rows.filter_map(Result::ok).collect::<Vec<_>>()
-    };
-    Ok(result)
+    fn rate(&self) -> Option<f64> {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(&(t0, p0)), Some(&(t1, p1))) if t1.duration_since(t0).as_secs() >= 1 => {
+                Some(p1.saturating_sub(p0) as f64 / t1.duration_since(t0).as_secs_f64())
+            }
+            _ => self.seed,
+        }
+    }
+
+    // (eta_seconds, percent) once `processed` is below a known total; a run
+    // that outgrows the estimate has none.
+    fn estimate(&self, processed: u64, total: Option<u64>) -> (Option<u64>, Option<f64>) {
+        match (total, self.rate()) {
+            (Some(total), Some(rate)) if processed < total && rate > 0.0 => (
+                Some(((total - processed) as f64 / rate).ceil() as u64),
+                Some(processed as f64 * 100.0 / total as f64),
+            ),
+            _ => (None, None),
+        }
+    }
 }
 
-#[tauri::command]
-fn get_file_meta_command(path: String) -> Result<FileMeta, Error> {
-    get_file_meta(Path::new(&path)).map_err(Into::into)
+#[derive(Debug, Clone, Copy, Default)]
+struct IndexRunOptions {
+    // remove rows under the root that the walk no longer saw
+    prune: bool,
+    // None uses the saved index_throttle setting
+    throttle: Option<IndexThrottle>,
+    // hold the walk whenever the user is back at the machine
+    only_when_idle: bool,
 }
 
-#[tauri::command]
-fn database_has_files(db: State<DbConnection>) -> Result<bool, Error> {
-    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
-    let count: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM main_table",
-        [],
-        |row| row.get(0),
-    )?;
-    Ok(count > 0)
+#[derive(Debug, Clone, Copy, Default, serde::Serialize, serde::Deserialize)]
+struct IndexThrottle {
+    // None walks at full speed
+    max_entries_per_sec: Option<u32>,
+    // lower the indexing thread's CPU and I/O priority (Windows only)
+    background_priority: bool,
 }
 
-#[tauri::command]
-fn transfer_to_sqlite(db: State<DbConnection>, path: String) -> Result<(), Error> {
-    let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
-    let tx = conn.transaction()?;
-    let skip_keywords = ["CloudStore", "OneDrive", "System Volume Information"];
+impl IndexThrottle {
+    fn is_active(&self) -> bool {
+        self.max_entries_per_sec.is_some() || self.background_priority
+    }
+}
 
-    for entry in WalkDir::new(&path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(Result::ok)
-        .filter(|e| !e.file_type().is_symlink())
-        .filter(|e| {
-            let path_str = e.path().display().to_string();
-            !skip_keywords.iter().any(|k| path_str.contains(k))
-        })
-    {
-        match get_file_meta(entry.path()) {
-            Ok(file_meta) => {
-                if let Err(err) = insert_file_meta(&tx, &file_meta) {
-                    eprintln!("DB insert error for {:?}: {:?}", entry.path(), err);
+// At most one index run at a time; `begin` hands out a guard that resets the
+// state when the run ends, however it ends.
+#[derive(Default)]
+struct IndexingState {
+    current: Mutex<Option<(String, IndexSource, std::time::Instant)>>,
+    processed: std::sync::atomic::AtomicU64,
+    // shared with the registry when a run is started under an operation token
+    cancel: std::sync::Arc<AtomicBool>,
+    throttled: AtomicBool,
+    // a throttled run waiting out BackgroundPaused
+    paused: AtomicBool,
+    // the running run is on hold until the user is idle again
+    idle_hold: AtomicBool,
+    // set by the scheduler while a due only_when_idle run cannot start yet
+    deferred_for_idle: AtomicBool,
+    // set alongside `cancel` when the volume being indexed disappears
+    device_removed: AtomicBool,
+    // rate so far and the expected entry count, if a previous run gives one
+    progress: Mutex<(RateTracker, Option<u64>)>,
+}
+
+struct IndexingGuard<'a>(&'a IndexingState);
+
+impl Drop for IndexingGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut current) = self.0.current.lock() {
+            *current = None;
+        }
+    }
+}
+
+impl IndexingState {
+    fn begin(&self, root: &str, source: IndexSource) -> Result<IndexingGuard<'_>, Error> {
+        let mut current = self.current.lock().map_err(|_| Error::MutexPoison)?;
+        if current.is_some() {
+            return Err(Error::AlreadyIndexing);
+        }
+        *current = Some((root.to_string(), source, std::time::Instant::now()));
+        self.processed.store(0, Ordering::SeqCst);
+        self.cancel.store(false, Ordering::SeqCst);
+        self.throttled.store(false, Ordering::SeqCst);
+        self.paused.store(false, Ordering::SeqCst);
+        self.idle_hold.store(false, Ordering::SeqCst);
+        self.device_removed.store(false, Ordering::SeqCst);
+        if let Ok(mut progress) = self.progress.lock() {
+            *progress = Default::default();
+        }
+        Ok(IndexingGuard(self))
+    }
+
+    fn is_running(&self) -> bool {
+        self.current.lock().map(|c| c.is_some()).unwrap_or(true)
+    }
+
+    fn snapshot(&self) -> Result<IndexingStatus, Error> {
+        let current = self.current.lock().map_err(|_| Error::MutexPoison)?;
+        Ok(match &*current {
+            Some((root, source, started)) => {
+                let processed = self.processed.load(Ordering::SeqCst);
+                let elapsed = started.elapsed().as_secs_f64();
+                let progress = self.progress.lock().map_err(|_| Error::MutexPoison)?;
+                let (eta_seconds, percent) = progress.0.estimate(processed, progress.1);
+                IndexingStatus {
+                    running: true,
+                    current_root: Some(root.clone()),
+                    source: Some(source.as_str()),
+                    processed,
+                    entries_per_sec: if elapsed > 0.0 { processed as f64 / elapsed } else { 0.0 },
+                    throttled: self.throttled.load(Ordering::SeqCst),
+                    paused: self.paused.load(Ordering::SeqCst),
+                    waiting_for_idle: self.idle_hold.load(Ordering::SeqCst),
+                    rate: progress.0.rate(),
+                    eta_seconds,
+                    percent,
                 }
             }
-            Err(_) => {
-                // silently skip
-            }
-        }
+            None => IndexingStatus {
+                waiting_for_idle: self.deferred_for_idle.load(Ordering::SeqCst),
+                ..IndexingStatus::default()
+            },
+        })
     }
+}
 
-    tx.commit()?;
-    Ok(())
+#[derive(Debug, Clone, serde::Serialize)]
+struct IndexRunSummary {
+    run_id: i64,
+    root: String,
+    source: &'static str,
+    status: &'static str,
+    processed: u64,
+    created: u64,
+    updated: u64,
+    // rows a different file replaced at the same path; listed by
+    // list_index_collisions
+    collisions: u64,
 }
 
-#[tauri::command]
-fn get_directory_size(db: State<DbConnection>, path: String) -> Result<u64, Error> {
-    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
-    let mut stmt = conn.prepare(
-        "SELECT COALESCE(SUM(size), 0) FROM main_table WHERE path LIKE ?1 || '%'",
-    )?;
-    
-    let size: u64 = stmt.query_row(rusqlite::params![&path], |row| row.get(0))?;
-    Ok(size)
+#[derive(Debug, serde::Serialize)]
+struct PathCollision {
+    run_id: i64,
+    path: String,
+    // the file the index now holds for the path, and the one it replaced
+    kept_size: u64,
+    skipped_size: u64,
 }
 
-#[tauri::command]
-fn list_directory_contents(path: String) -> Result<Vec<FileMeta>, Error> {
-    let dir = Path::new(&path);
-    let mut contents = Vec::new();
-    
-    for entry in fs::read_dir(dir).map_err(Error::Io)? {
-        match entry {
-            Ok(entry) => {
-                if let Ok(meta) = get_file_meta(entry.path().as_ref()) {
-                    contents.push(meta);
-                }
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct ScheduleConfig {
+    interval_hours: u32,
+    #[serde(default)]
+    only_when_idle: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct DriveSettings {
+    auto_index_removable: bool,
+}
+
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+struct WindowGeometry {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+    #[serde(default)]
+    maximized: bool,
+}
+
+// One per window label. Fields this side doesn't interpret (sidebar width,
+// column layout, ...) are kept verbatim in `extra`.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+struct SessionState {
+    #[serde(default)]
+    tabs: Vec<String>,
+    #[serde(default)]
+    active_tab: usize,
+    #[serde(default)]
+    window: Option<WindowGeometry>,
+    #[serde(default)]
+    history: Option<NavigationHistory>,
+    #[serde(flatten)]
+    extra: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+struct NavigationHistory {
+    entries: Vec<String>,
+    // index into `entries` of the directory currently shown
+    position: usize,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct NavigationResult {
+    // None when there was nothing (still existing) to go to
+    path: Option<String>,
+    can_go_back: bool,
+    can_go_forward: bool,
+}
+
+const LISTING_CACHE_CAPACITY: usize = 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ListingKey {
+    dir: String,
+    sort: Option<String>,
+    filter: Option<String>,
+}
+
+// Recent list_children results, most recent last. `generation` is bumped by
+// an update hook on every row written to main_table, and an entry is only
+// served while the generation it was read at is still current, so a write
+// anywhere in the index can never leave a stale listing behind.
+#[derive(Default)]
+struct ListingCache {
+    generation: std::sync::Arc<std::sync::atomic::AtomicU64>,
+    entries: Mutex<std::collections::VecDeque<CachedListing>>,
+}
+
+struct CachedListing {
+    key: ListingKey,
+    // the generation the listing was read at
+    read_at: u64,
+    listing: std::sync::Arc<Vec<FileMeta>>,
+}
+
+impl ListingCache {
+    fn get(&self, key: &ListingKey) -> Option<std::sync::Arc<Vec<FileMeta>>> {
+        let generation = self.generation.load(Ordering::SeqCst);
+        let mut entries = self.entries.lock().ok()?;
+        let index = entries.iter().position(|e| &e.key == key)?;
+        let entry = entries.remove(index)?;
+        if entry.read_at != generation {
+            return None;
+        }
+        let listing = entry.listing.clone();
+        entries.push_back(entry);
+        Some(listing)
+    }
+
+    fn insert(&self, key: ListingKey, read_at: u64, listing: std::sync::Arc<Vec<FileMeta>>) {
+        // A write landed while the listing was read; it may already be stale.
+        if read_at != self.generation.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|e| e.key != key);
+            entries.push_back(CachedListing { key, read_at, listing });
+            while entries.len() > LISTING_CACHE_CAPACITY {
+                entries.pop_front();
             }
-            Err(e) => eprintln!("Error reading directory entry: {}", e),
         }
     }
-    
-    Ok(contents)
-}
 
-#[tauri::command]
-fn open_file(path: String) -> Result<(), Error> {
-    #[cfg(target_os = "windows")]
-    {
-        use std::os::windows::process::CommandExt;
-        std::process::Command::new("cmd")
-            .args(&["/C", "start", "", &path])
-            .creation_flags(0x08000000)
-            .spawn()
-            .map_err(|e| Error::Io(e))?;
-    }
-    
-    #[cfg(not(target_os = "windows"))]
-    {
-        opener::open(path).map_err(|e| Error::Io(std::io::Error::new(std::io::ErrorKind::Other, e)))?;
+    // Bumps the generation on every row `conn` writes to main_table.
+    fn watch(&self, conn: &Connection) {
+        let generation = self.generation.clone();
+        conn.update_hook(Some(move |_: rusqlite::hooks::Action, _: &str, table: &str, _: i64| {
+            if table == "main_table" {
+                generation.fetch_add(1, Ordering::SeqCst);
+            }
+        }));
     }
-    
-    Ok(())
-}
 
-fn get_file_meta(path: &Path) -> std::io::Result<FileMeta> {
-    let metadata = fs::metadata(path)?;
+    // For changes seen on disk before they reach the index.
+    fn invalidate_dir(&self, dir: &str) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|e| e.key.dir != dir);
+        }
+    }
+}
 
-    let modified = metadata
-        .modified()?
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
+// Indexed directories for quick_jump, loaded on first use and reloaded after
+// each index run so a query never has to hit the database for them.
+#[derive(Default)]
+struct JumpCandidates(Mutex<Option<std::sync::Arc<Vec<JumpCandidate>>>>);
 
-    let extension = path
-        .extension()
-        .and_then(|ext| ext.to_str())
-        .map(|s| s.to_string());
+struct JumpCandidate {
+    path: String,
+    name: String,
+    // `name` lowercased char by char, so indices line up with `name`
+    lower: Vec<char>,
+    depth: usize,
+}
 
-    Ok(FileMeta {
-        name: path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string(),
-        path: path.to_string_lossy().to_string(),
-        extension,
-        size: metadata.len(),
-        modified,
-    })
+#[derive(Debug, serde::Serialize)]
+struct JumpMatch {
+    path: String,
+    name: String,
+    score: f64,
+    // [start, end) char offsets into `name` of the matched runs
+    ranges: Vec<(usize, usize)>,
 }
 
-fn create_table(conn: &Connection) -> Result<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS main_table (
-            name TEXT NOT NULL,
-            path TEXT UNIQUE NOT NULL,
-            extension TEXT,
-            size INTEGER NOT NULL,
-            modified INTEGER NOT NULL
-        )",
-        [],
-    )?;
-    Ok(())
+#[derive(Debug, serde::Serialize)]
+struct Schedule {
+    root: String,
+    #[serde(flatten)]
+    config: ScheduleConfig,
+    last_run: Option<u64>,
+    next_run: Option<u64>,
 }
 
-fn create_indexes(conn: &Connection) -> Result<()> {
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_path ON main_table(path)",
-        [],
-    )?;
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_name ON main_table(name)",
-        [],
-    )?;
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_extension ON main_table(extension)",
-        [],
-    )?;
-    Ok(())
+// Set from the tray menu; the scheduler and drive poller idle while it is on,
+// and throttled index runs wait.
+struct BackgroundPaused(AtomicBool);
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct TraySettings {
+    minimize_to_tray: bool,
+    quick_search_shortcut: String,
 }
 
-fn insert_file_meta(conn: &Connection, file: &FileMeta) -> Result<()> {
-    conn.execute(
-        "INSERT OR REPLACE INTO main_table (name, path, extension, size, modified)
-         VALUES (?1, ?2, ?3, ?4, ?5)",
-        rusqlite::params![
-            file.name,
-            file.path,
-            file.extension,
-            file.size,
-            file.modified
-        ],
-    )?;
+// Directory shown in each window, by label, kept current by the frontend; OS
+// file drops land in the directory of the window they were dropped on.
+#[derive(Default)]
+struct ActiveDirectory(Mutex<std::collections::HashMap<String, String>>);
+
+#[derive(Clone, serde::Serialize)]
+struct DropCompleted {
+    dest: String,
+    moved: bool,
+    report: Option<Outcome<CopyReport>>,
+    error: Option<String>,
+}
+
+// Stop flag of the running drive poller, if any.
+struct DriveWatcher(Mutex<Option<std::sync::Arc<AtomicBool>>>);
+
+// One watcher per (window label, file path); dropping it stops the watch.
+#[derive(Default)]
+struct FileWatchers(
+    Mutex<std::collections::HashMap<(String, String), notify::RecommendedWatcher>>,
+);
+
+#[derive(Clone, serde::Serialize)]
+struct FileChanged {
+    path: String,
+    size: u64,
+    modified: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct FileMeta {
+    name: String,
+    path: String,
+    extension: Option<String>,
+    size: u64,
+    modified: u64,
+    accessed: Option<u64>,
+    is_dir: bool,
+    created: Option<u64>,
+    is_symlink: bool,
+    // None for directories and where the platform gave no answer
+    link_count: Option<u64>,
+    #[serde(skip)]
+    file_key: Option<FileKey>,
+    // filled in by listings so the frontend needs no mapping of its own
+    #[serde(skip_serializing_if = "Option::is_none")]
+    kind: Option<FileKind>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+struct FileKind {
+    kind: String,
+    label: String,
+    category: String,
+}
+
+// Identifies the file behind a path: (volume serial, file index) on Windows,
+// (device, inode) elsewhere. Stored bit-for-bit as i64 since SQLite has no
+// unsigned integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct FileKey {
+    volume: i64,
+    index: i64,
+}
+
+const FILE_META_COLUMNS: &str = "name, path, extension, size, modified, accessed, is_dir, created, \
+     is_symlink, link_count, volume_id, file_id";
+// Queries selecting extra columns after FILE_META_COLUMNS start reading here.
+const FILE_META_COLUMN_COUNT: usize = 12;
+
+fn file_meta_from_row(row: &rusqlite::Row) -> Result<FileMeta, rusqlite::Error> {
+    Ok(FileMeta {
+        name: row.get(0)?,
+        path: row.get(1)?,
+        extension: row.get(2)?,
+        size: row.get(3)?,
+        modified: row.get(4)?,
+        accessed: row.get(5)?,
+        is_dir: row.get(6)?,
+        created: row.get(7)?,
+        is_symlink: row.get(8)?,
+        link_count: row.get(9)?,
+        file_key: match (row.get(10)?, row.get(11)?) {
+            (Some(volume), Some(index)) => Some(FileKey { volume, index }),
+            _ => None,
+        },
+        kind: None,
+    })
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SimilarImage {
+    file: FileMeta,
+    // hamming distance to the first member of the cluster
+    distance: u32,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SkippedImage {
+    path: String,
+    reason: String,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SimilarImagesReport {
+    clusters: Vec<Vec<SimilarImage>>,
+    skipped: Vec<SkippedImage>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+struct DriveInfo {
+    name: String,
+    mount_point: String,
+    file_system: String,
+    total_space: u64,
+    available_space: u64,
+    is_removable: bool,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct ProgressPayload {
+    processed: u64,
+    total: u64,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct DirectoryComparison {
+    // paths relative to the compared roots
+    only_in_left: Vec<String>,
+    only_in_right: Vec<String>,
+    changed: Vec<String>,
+    identical: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct FailedEntry {
+    path: String,
+    error: String,
+}
+
+impl FailedEntry {
+    fn new(path: &str, error: &str) -> Self {
+        FailedEntry {
+            path: path.to_string(),
+            error: error.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+struct CopyReport {
+    copied: u64,
+    failed: Vec<FailedEntry>,
+    // one per file when the copy was verified
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    verification: Vec<VerifiedCopy>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct VerifiedCopy {
+    path: String,
+    // of the source as read during the copy
+    sha256: String,
+    attempts: u32,
+    verified: bool,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct RenamedEntry {
+    from: String,
+    to: String,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct FlattenReport {
+    moved: u64,
+    // files that took a numbered name because `dest` already had the name
+    collisions: Vec<RenamedEntry>,
+    removed_dirs: Vec<String>,
+    failed: Vec<FailedEntry>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SyncAction {
+    action: &'static str,
+    path: String,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct SyncReport {
+    actions: Vec<SyncAction>,
+    copied: u64,
+    skipped: u64,
+    deleted: u64,
+    failed: Vec<FailedEntry>,
+    dry_run: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    verification: Vec<VerifiedCopy>,
+}
+
+#[derive(Clone, serde::Serialize)]
+struct CopyProgressPayload {
+    processed: u64,
+    total: u64,
+    current: String,
+    // "copying", or "verifying" while a written file is read back
+    phase: &'static str,
+}
+
+// Progress goes only to the window that started the operation.
+struct CopyProgress<'a> {
+    window: &'a tauri::Window,
+    cancel: &'a AtomicBool,
+    processed: u64,
+    total: u64,
+}
+
+impl<'a> CopyProgress<'a> {
+    fn new(window: &'a tauri::Window, cancel: &'a AtomicBool, total: u64) -> Self {
+        CopyProgress {
+            window,
+            cancel,
+            processed: 0,
+            total,
+        }
+    }
+
+    fn step(&mut self, current: &Path) -> Result<(), Error> {
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(Error::Cancelled);
+        }
+        self.processed += 1;
+        self.emit(current, "copying");
+        Ok(())
+    }
+
+    // Does not advance the count; the file was already counted when copied.
+    fn verifying(&mut self, current: &Path) -> Result<(), Error> {
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(Error::Cancelled);
+        }
+        self.emit(current, "verifying");
+        Ok(())
+    }
+
+    fn emit(&self, current: &Path, phase: &'static str) {
+        let _ = self.window.emit(
+            "copy-progress",
+            CopyProgressPayload {
+                processed: self.processed,
+                total: self.total,
+                current: current.to_string_lossy().to_string(),
+                phase,
+            },
+        );
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DayBucket {
+    // YYYY-MM-DD
+    day: String,
+    count: u64,
+    total_size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ShellVerb {
+    verb: String,
+    label: String,
+    dangerous: bool,
+    // "registry" or "static"
+    source: &'static str,
+}
+
+impl ShellVerb {
+    fn new(verb: &str, label: &str, source: &'static str) -> Self {
+        ShellVerb {
+            verb: verb.to_string(),
+            label: label.to_string(),
+            dangerous: DANGEROUS_VERBS.iter().any(|d| d.eq_ignore_ascii_case(verb)),
+            source,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct BrokenSymlink {
+    link: FileMeta,
+    // None when the link itself has disappeared since indexing
+    target: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct RiskFlags {
+    executable: bool,
+    // e.g. "invoice.pdf.exe"
+    masquerading_extension: bool,
+    // a Zone.Identifier stream marks the file as downloaded
+    mark_of_the_web: bool,
+    hidden_executable: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct RiskAssessment {
+    path: String,
+    flags: RiskFlags,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct RiskReport {
+    assessed: Vec<RiskAssessment>,
+    failed: Vec<FailedEntry>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct AlternateStream {
+    name: String,
+    size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct StreamContents {
+    // lossily decoded, as UTF-8 unless another encoding was asked for; most
+    // streams, like Zone.Identifier, are text
+    data: String,
+    size: u64,
+    truncated: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum RefreshedEntry {
+    Updated(FileMeta),
+    Removed { path: String },
+    // cloud placeholder, left alone so it isn't downloaded
+    Skipped { path: String },
+    Failed { path: String, error: String },
+}
+
+#[derive(Debug, serde::Serialize)]
+struct SubtreeStats {
+    file_count: u64,
+    dir_count: u64,
+    total_size: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Coverage {
+    on_disk: u64,
+    indexed: u64,
+    coverage_pct: f64,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct IndexPathsReport {
+    created: u64,
+    updated: u64,
+    failed: Vec<FailedEntry>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct FileOwner {
+    // account names, None when they could not be resolved
+    owner: Option<String>,
+    group: Option<String>,
+    // uid on Unix, SID string on Windows
+    owner_id: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct LockingProcess {
+    pid: u32,
+    // executable name, e.g. "WINWORD.EXE"
+    name: String,
+    // friendly name reported by Restart Manager, e.g. "Microsoft Word"
+    app_name: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct TextStats {
+    lines: u64,
+    words: u64,
+    chars: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct ModifiedUpdate {
+    path: String,
+    timestamp: u64,
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct TimestampReport {
+    updated: u64,
+    failed: Vec<FailedEntry>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct CaseCollision {
+    directory: String,
+    entries: Vec<FileMeta>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct LocateResult {
+    parent: String,
+    children: Vec<FileMeta>,
+    exists: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct EntryName {
+    name: String,
+    path: String,
+    is_dir: bool,
+}
+
+// Listings return everything unless a page was asked for, or unless
+// everything would not fit the result limits; then the part that fits comes
+// back as a truncated page.
+#[derive(Debug, serde::Serialize)]
+#[serde(untagged)]
+enum Listing {
+    All(Entries),
+    Page {
+        entries: Entries,
+        // None on the last page
+        next_cursor: Option<String>,
+        // fewer rows than asked for, to stay within the result limits
+        truncated: bool,
+    },
+}
+
+impl Listing {
+    // An unpaged listing that `limits` cut short comes back as the first page
+    // of the same listing in `sort` order instead, read by `first_page`, so
+    // its cursor continues right after the last row kept.
+    fn all(
+        entries: Vec<FileMeta>,
+        format: ListingFormat,
+        sort: Option<String>,
+        limits: ResultLimits,
+        first_page: impl FnOnce(&PageQuery) -> Result<ListingPage, Error>,
+    ) -> Result<Self, Error> {
+        let fits = limits.fitting(entries.iter());
+        if fits == entries.len() {
+            return Ok(Listing::All(Entries::new(entries, format)));
+        }
+        let fits = u32::try_from(fits).unwrap_or(u32::MAX);
+        let mut page = first_page(&PageQuery::new(sort, None, Some(fits), limits)?)?;
+        // there is more even when the page itself came back whole
+        page.truncated = true;
+        Ok(Listing::page(page, format))
+    }
+
+    fn page(page: ListingPage, format: ListingFormat) -> Self {
+        Listing::Page {
+            entries: Entries::new(page.entries, format),
+            next_cursor: page.next_cursor,
+            truncated: page.truncated,
+        }
+    }
+}
+
+struct ListingPage {
+    entries: Vec<FileMeta>,
+    next_cursor: Option<String>,
+    truncated: bool,
+}
+
+const RESULT_LIMITS_SETTING: &str = "result_limits";
+
+// Upper bounds on what one command hands the webview, whatever it asked
+// for: rows, and bytes of the entries as JSON.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+struct ResultLimits {
+    max_rows: usize,
+    max_bytes: usize,
+}
+
+impl Default for ResultLimits {
+    fn default() -> Self {
+        ResultLimits {
+            max_rows: 5000,
+            max_bytes: 8 * 1024 * 1024,
+        }
+    }
+}
+
+impl ResultLimits {
+    fn load(conn: &Connection) -> Result<Self, Error> {
+        match get_setting(conn, RESULT_LIMITS_SETTING)? {
+            Some(value) => serde_json::from_str(&value).map_err(|e| Error::InvalidInput(e.to_string())),
+            None => Ok(Self::default()),
+        }
+    }
+
+    // How many leading entries fit. At least one always does, so paging
+    // past an oversized row still makes progress.
+    fn fitting<'a>(&self, entries: impl Iterator<Item = &'a FileMeta>) -> usize {
+        let (mut count, mut bytes) = (0, 0);
+        for entry in entries.take(self.max_rows) {
+            bytes += serde_json::to_vec(entry).map_or(0, |json| json.len()) + 1;
+            if bytes > self.max_bytes {
+                return count.max(1);
+            }
+            count += 1;
+        }
+        count
+    }
+
+    // For commands returning a bare list, which have no flag to set.
+    fn truncate(&self, entries: &mut Vec<FileMeta>) {
+        let fits = self.fitting(entries.iter());
+        entries.truncate(fits);
+    }
+}
+
+#[tauri::command]
+fn get_result_limits(db: State<DbConnection>) -> Result<ResultLimits, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    ResultLimits::load(&conn)
+}
+
+#[tauri::command]
+fn set_result_limits(db: State<DbConnection>, limits: ResultLimits) -> Result<(), Error> {
+    if limits.max_rows == 0 || limits.max_bytes == 0 {
+        return Err(Error::InvalidInput("result limits must be above zero".to_string()));
+    }
+    let value = serde_json::to_string(&limits).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    set_setting(&conn, RESULT_LIMITS_SETTING, &value)?;
     Ok(())
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ListingFormat {
+    Objects,
+    Columnar,
+}
+
+impl std::str::FromStr for ListingFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "objects" => Ok(ListingFormat::Objects),
+            "columnar" => Ok(ListingFormat::Columnar),
+            other => Err(Error::InvalidInput(format!("unknown listing format '{}'", other))),
+        }
+    }
+}
+
+fn listing_format(format: Option<String>) -> Result<ListingFormat, Error> {
+    format.map_or(Ok(ListingFormat::Objects), |f| f.parse())
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(untagged)]
+enum Entries {
+    Objects(Vec<FileMeta>),
+    Columns(Box<FileColumns>),
+}
+
+impl Entries {
+    fn new(entries: Vec<FileMeta>, format: ListingFormat) -> Self {
+        match format {
+            ListingFormat::Objects => Entries::Objects(entries),
+            ListingFormat::Columnar => Entries::Columns(Box::new(FileColumns::from(entries))),
+        }
+    }
+}
+
+const COLUMN_FLAG_DIR: u8 = 1;
+const COLUMN_FLAG_SYMLINK: u8 = 2;
+
+// The same listing as parallel arrays, one slot per entry. Field names are
+// sent once instead of once per entry, and repeated kinds once per listing,
+// which roughly halves what big listings cost to send and parse.
+#[derive(Debug, Default, serde::Serialize)]
+struct FileColumns {
+    names: Vec<String>,
+    paths: Vec<String>,
+    // null for entries without an extension
+    extensions: Vec<Option<String>>,
+    sizes: Vec<u64>,
+    modifieds: Vec<u64>,
+    accessed: Vec<Option<u64>>,
+    created: Vec<Option<u64>>,
+    link_counts: Vec<Option<u64>>,
+    // COLUMN_FLAG_DIR | COLUMN_FLAG_SYMLINK
+    flags: Vec<u8>,
+    // index into `kind_table`, null when no kind was resolved
+    kinds: Vec<Option<usize>>,
+    kind_table: Vec<FileKind>,
+}
+
+impl From<Vec<FileMeta>> for FileColumns {
+    fn from(entries: Vec<FileMeta>) -> Self {
+        let mut columns = FileColumns::default();
+        let mut kind_index = std::collections::HashMap::new();
+        for entry in entries {
+            let mut flags = 0;
+            if entry.is_dir {
+                flags |= COLUMN_FLAG_DIR;
+            }
+            if entry.is_symlink {
+                flags |= COLUMN_FLAG_SYMLINK;
+            }
+            let kind = entry.kind.map(|kind| {
+                *kind_index.entry(kind.clone()).or_insert_with(|| {
+                    columns.kind_table.push(kind);
+                    columns.kind_table.len() - 1
+                })
+            });
+            columns.names.push(entry.name);
+            columns.paths.push(entry.path);
+            columns.extensions.push(entry.extension);
+            columns.sizes.push(entry.size);
+            columns.modifieds.push(entry.modified);
+            columns.accessed.push(entry.accessed);
+            columns.created.push(entry.created);
+            columns.link_counts.push(entry.link_count);
+            columns.flags.push(flags);
+            columns.kinds.push(kind);
+        }
+        columns
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct PageCursor {
+    sort: String,
+    // sort key of the last row handed out
+    key: serde_json::Value,
+    rowid: i64,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(untagged)]
+enum DirectoryListing {
+    Full(Vec<FileMeta>),
+    NamesOnly(Vec<EntryName>),
+}
+
+#[derive(Debug, serde::Serialize)]
+struct StaleFilesReport {
+    files: Vec<FileMeta>,
+    // false when the OS does not keep last-access times up to date, in which
+    // case only `modified` was used to judge staleness
+    access_times_reliable: bool,
+}
+
+#[derive(Debug)]
+enum Error {
+    Io(std::io::Error),
+    Rusqlite(rusqlite::Error),
+    MutexPoison,
+    InvalidInput(String),
+    AlreadyExists(String),
+    Cancelled,
+    Background(String),
+    AlreadyIndexing,
+    NotExecutable(String),
+    SchemaMismatch(String),
+    DeviceRemoved(String),
+    // the cursor was issued for another sort order; start from the first page
+    CursorMismatch,
+    // a setting was changed while the index is in memory only
+    Ephemeral(&'static str),
+    // the index is encrypted and unlock_index has not succeeded yet
+    Locked,
+    #[cfg_attr(not(feature = "encrypted-index"), allow(dead_code))]
+    WrongPassphrase,
+    // the path, and the protected root it falls under
+    Protected(String, String),
+    // restricted mode is on and the path is outside every allowed root
+    Forbidden(String),
+    // only produced on platforms lacking a native implementation
+    #[cfg_attr(target_os = "windows", allow(dead_code))]
+    Unsupported(&'static str),
+    // the remote's name, then what went wrong
+    RemoteAuth(String, String),
+    RemoteNetwork(String, String),
+    RemoteHostKey(String, String),
+    Keyring(String),
+    // the document's path, and what could not be parsed
+    MalformedDocument(String, String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Self {
+        Error::Rusqlite(err)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "IO error: {}", e),
+            Error::Rusqlite(e) => write!(f, "Database error: {}", e),
+            Error::MutexPoison => write!(f, "Mutex poisoned"),
+            Error::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            Error::AlreadyExists(path) => write!(f, "Destination already exists: {}", path),
+            Error::Cancelled => write!(f, "Operation cancelled"),
+            Error::Background(msg) => write!(f, "Background task failed: {}", msg),
+            Error::AlreadyIndexing => write!(f, "An index run is already in progress"),
+            Error::NotExecutable(path) => write!(f, "Not an executable file: {}", path),
+            Error::SchemaMismatch(msg) => write!(f, "Incompatible index database: {}", msg),
+            Error::DeviceRemoved(root) => write!(f, "Volume was removed: {}", root),
+            Error::CursorMismatch => write!(f, "Cursor does not match the requested sort order"),
+            Error::Ephemeral(what) => write!(f, "Cannot save {} in ephemeral mode", what),
+            Error::Locked => write!(f, "The index is locked; unlock it with its passphrase first"),
+            Error::WrongPassphrase => write!(f, "Wrong passphrase for the index"),
+            Error::Protected(path, root) => {
+                write!(f, "'{}' is under protected root '{}'; unprotect it first", path, root)
+            }
+            Error::Forbidden(path) => {
+                write!(f, "'{}' is outside the indexed and approved roots (restricted mode)", path)
+            }
+            Error::Unsupported(what) => write!(f, "Not supported on this platform: {}", what),
+            Error::RemoteAuth(name, msg) => {
+                write!(f, "Authentication to remote '{}' failed: {}", name, msg)
+            }
+            Error::RemoteNetwork(name, msg) => write!(f, "Cannot reach remote '{}': {}", name, msg),
+            Error::RemoteHostKey(name, msg) => {
+                write!(f, "Host key check for remote '{}' failed: {}", name, msg)
+            }
+            Error::Keyring(msg) => write!(f, "Credential store error: {}", msg),
+            Error::MalformedDocument(path, msg) => {
+                write!(f, "Cannot read the properties of '{}': {}", path, msg)
+            }
+        }
+    }
+}
+
+impl serde::Serialize for Error {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::ser::Serializer,
+    {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl StdError for Error {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConflictPolicy {
+    Error,
+    Overwrite,
+    Rename,
+}
+
+impl std::str::FromStr for ConflictPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(ConflictPolicy::Error),
+            "overwrite" => Ok(ConflictPolicy::Overwrite),
+            "rename" => Ok(ConflictPolicy::Rename),
+            other => Err(Error::InvalidInput(format!("unknown conflict policy '{}'", other))),
+        }
+    }
+}
+
+fn main() {
+    tauri::Builder::default()
+        .manage(TokenRegistry::default())
+        .manage(DriveWatcher(Mutex::new(None)))
+        .manage(IndexingState::default())
+        .manage(BackgroundPaused(AtomicBool::new(false)))
+        .manage(ActiveDirectory::default())
+        .manage(FileWatchers::default())
+        .manage(RuleWatchers::default())
+        .manage(JumpCandidates::default())
+        .manage(ListingCache::default())
+        .system_tray(build_tray())
+        .on_system_tray_event(handle_tray_event)
+        .on_window_event(handle_window_event)
+        .setup(|app| {
+            note_command_invoked();
+            // Initialize database
+            let location = if ephemeral_requested() {
+                println!("Database kept in memory (ephemeral mode)");
+                DbLocation::Memory
+            } else {
+                let app_dir = app.path_resolver().app_data_dir().unwrap();
+                std::fs::create_dir_all(&app_dir)?;
+                let db_path = app_dir.join("file_explorer.sqlite3");
+                println!("Database path: {:?}", db_path);
+                DbLocation::File(db_path)
+            };
+            let (mut conn, locked) = open_index(&location)?;
+            println!("Database connection established");
+            if locked {
+                println!("Database is encrypted, waiting for unlock_index");
+            } else {
+                println!("Database tables and indexes created");
+            }
+            
+            // Check if database is empty and populate if needed
+            let count: i64 = if locked {
+                -1
+            } else {
+                conn.query_row("SELECT COUNT(*) FROM main_table", [], |row| row.get(0))?
+            };
+            // A memory index starts empty on every launch; it is filled in the
+            // background below rather than holding the window back each time.
+            let ephemeral = matches!(location, DbLocation::Memory);
+            if count == 0 && !ephemeral {
+                println!("Database is empty, populating with initial data...");
+                let path = "C:\\".to_string();
+                let tx = conn.transaction()?;
+
+                for entry in index_walk(&path) {
+                    if let Ok(file_meta) = get_file_meta(entry.path()) {
+                        if let Err(err) = insert_file_meta(&tx, &file_meta) {
+                            eprintln!("DB insert error for {:?}: {:?}", entry.path(), err);
+                        }
+                    }
+                }
+
+                recompute_dir_sizes(&tx)?;
+                tx.commit()?;
+                refresh_query_stats(&conn, true)?;
+                println!("Initial data population complete");
+            }
+            
+            if !locked {
+                watch_index_changes(&conn, &app.handle())?;
+            }
+            app.manage(DbConnection(Mutex::new(conn)));
+            app.manage(location);
+            app.manage(IndexLock(AtomicBool::new(locked)));
+            spawn_scheduler(app.handle());
+            spawn_index_update_notifier(app.handle());
+            spawn_index_health_report(app.handle());
+            spawn_rule_evaluator(app.handle());
+            spawn_activity_watcher(app.handle());
+            if ephemeral {
+                let app = app.handle();
+                std::thread::spawn(move || {
+                    let options = IndexRunOptions::default();
+                    if let Err(err) = index_root(&app, "C:\\", IndexSource::Manual, options) {
+                        eprintln!("Initial index of C:\\ failed: {}", err);
+                    }
+                });
+            }
+            if setting_enabled(&app.handle(), AUTO_INDEX_REMOVABLE_SETTING) {
+                start_drive_watcher(&app.handle())?;
+            }
+
+            let shortcut = {
+                let db = app.state::<DbConnection>();
+                let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+                // a locked index cannot be read yet, so the default applies
+                if locked {
+                    None
+                } else {
+                    get_setting(&conn, QUICK_SEARCH_SHORTCUT_SETTING)?
+                }
+                .unwrap_or_else(|| DEFAULT_QUICK_SEARCH_SHORTCUT.to_string())
+            };
+            if let Err(err) = register_quick_search_shortcut(&app.handle(), &shortcut) {
+                eprintln!("{}", err);
+            }
+            if let Some(window) = app.get_window("main") {
+                restore_window_geometry(&window);
+            }
+            Ok(())
+        })
+        .invoke_handler({
+            let commands: Box<dyn Fn(tauri::Invoke) + Send + Sync> = Box::new(tauri::generate_handler![
+                list_children,
+                list_descendants,
+                browse,
+                search_files,
+                get_file_meta_command,
+                get_file_meta_many,
+                changed_since_index,
+                transfer_to_sqlite,
+                get_directory_size,
+                database_has_files,
+                get_index_health,
+                list_directory_contents,
+                open_file,
+                open_files,
+                open_all_in_dir,
+                get_open_files_confirm_above,
+                set_open_files_confirm_above,
+                move_entry,
+                protect_root,
+                unprotect_root,
+                list_protected_roots,
+                list_rules,
+                add_rule,
+                update_rule,
+                delete_rule,
+                get_file_tags,
+                find_tagged,
+                remove_file_tag,
+                run_rules_now,
+                get_restricted_mode,
+                set_restricted_mode,
+                set_directory_approved,
+                stale_large_files,
+                get_cached_directory_size,
+                directory_size_excluding,
+                type_composition,
+                export_tree_text,
+                export_tree_report,
+                find_similar_images,
+                find_duplicate_directories,
+                list_drives,
+                subscribe_drive_changes,
+                unsubscribe_drive_changes,
+                compare_directories,
+                copy_entries,
+                sync_folders,
+                get_copy_verify_retries,
+                set_copy_verify_retries,
+                add_remote,
+                list_remotes,
+                remove_remote,
+                list_remote_directory,
+                download_remote,
+                upload_remote,
+                rename_remote,
+                delete_remote,
+                is_indexing,
+                cancel_indexing,
+                get_schedules,
+                set_schedule,
+                get_tray_settings,
+                set_tray_settings,
+                hide_quick_search,
+                move_entries,
+                set_active_directory,
+                locate,
+                start_native_drag,
+                creation_histogram,
+                get_shell_verbs,
+                invoke_shell_verb,
+                find_broken_symlinks,
+                open_file_elevated,
+                assess_risk,
+                remove_mark_of_the_web,
+                list_alternate_streams,
+                read_alternate_stream,
+                delete_alternate_stream,
+                merge_database,
+                find_hard_links,
+                refresh_metadata,
+                relative_path,
+                list_search_history,
+                get_drive_settings,
+                set_drive_settings,
+                watch_file,
+                unwatch_file,
+                who_locks_file,
+                text_stats,
+                save_session,
+                load_session,
+                set_modified,
+                set_modified_batch,
+                create_new_window,
+                find_case_collisions,
+                push_history,
+                navigate_history,
+                show_os_properties,
+                resolve_shortcut,
+                clipboard_copy_files,
+                quick_jump,
+                suggest,
+                get_file_kind,
+                get_file_kinds,
+                set_custom_file_kind,
+                prefetch_children,
+                path_depth_command,
+                max_depth,
+                directory_activity,
+                get_extension,
+                index_paths,
+                find_first,
+                optimize_database,
+                list_db_indexes,
+                coverage,
+                flatten_directory,
+                get_owner,
+                get_index_throttle,
+                set_index_throttle,
+                subtree_stats,
+                get_idle_seconds,
+                rename_entry,
+                rename_template,
+                undo_operation,
+                cancel_operation,
+                detect_encoding,
+                files_over_size,
+                check_index_integrity,
+                get_integrity_schedule,
+                set_integrity_schedule,
+                get_db_location,
+                list_index_collisions,
+                list_normalization_changes,
+                get_activity,
+                pause_activity_recording,
+                get_activity_retention,
+                set_activity_retention,
+                get_stats_timeline,
+                get_stats_retention,
+                set_stats_retention,
+                index_locked,
+                unlock_index,
+                set_index_passphrase,
+                search_advanced,
+                file_checksum,
+                file_entropy,
+                get_document_metadata,
+                index_document_metadata,
+                get_result_limits,
+                set_result_limits
+            ]);
+            move |invoke| {
+                note_command_invoked();
+                let window = invoke.message.window_ref();
+                if refused_while_locked(&window.state::<IndexLock>(), invoke.message.command()) {
+                    invoke.resolver.reject(Error::Locked);
+                    return;
+                }
+                if let Err(err) = window.state::<DbLocation>().admit(invoke.message.command()) {
+                    invoke.resolver.reject(err);
+                    return;
+                }
+                commands(invoke)
+            }
+        })
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Quitting from the tray never sends CloseRequested, so this is
+            // the last chance to remember where the windows were.
+            if let tauri::RunEvent::Exit = event {
+                for window in app.windows().values() {
+                    save_window_geometry(window);
+                }
+            }
+        });
+}
+
+// `sort` is "name", "size" or "modified", prefixed with '-' for descending;
+// `filter` keeps names containing it, ignoring case. Passing `page_size` or
+// `after_cursor` returns one page at a time instead of the whole directory.
+// `format` is "objects" (the default) or "columnar".
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn list_children(
+    db: State<DbConnection>,
+    cache: State<ListingCache>,
+    dir: String,
+    sort: Option<String>,
+    filter: Option<String>,
+    after_cursor: Option<String>,
+    page_size: Option<u32>,
+    format: Option<String>,
+) -> Result<Listing, Error> {
+    let format = listing_format(format)?;
+    let key = ListingKey {
+        dir: normalize_dir_input(&dir),
+        sort: sort.filter(|s| !s.is_empty()),
+        filter: filter.filter(|f| !f.is_empty()),
+    };
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    children_listing(&conn, &cache, key, after_cursor, page_size, format)
+}
+
+fn children_listing(
+    conn: &Connection,
+    cache: &ListingCache,
+    key: ListingKey,
+    after_cursor: Option<String>,
+    page_size: Option<u32>,
+    format: ListingFormat,
+) -> Result<Listing, Error> {
+    let kinds = FileKinds::load(conn)?;
+    let limits = ResultLimits::load(conn)?;
+    let read_page = |page: &PageQuery| {
+        let mut listing = page_children(conn, &key, page)?;
+        kinds.annotate(&mut listing.entries);
+        Ok(listing)
+    };
+    if after_cursor.is_some() || page_size.is_some() {
+        let page = PageQuery::new(key.sort.clone(), after_cursor, page_size, limits)?;
+        return Ok(Listing::page(read_page(&page)?, format));
+    }
+    let listing = cached_children(conn, cache, key.clone())?;
+    let mut children = listing.as_ref().clone();
+    kinds.annotate(&mut children);
+    Listing::all(children, format, key.sort.clone(), limits, read_page)
+}
+
+fn page_children(conn: &Connection, key: &ListingKey, page: &PageQuery) -> Result<ListingPage, Error> {
+    let dirs = child_dir_ids(conn, &key.dir)?;
+    let mut params: Vec<_> = dirs.iter().map(|&id| rusqlite::types::Value::Integer(id)).collect();
+    let mut sql = format!(
+        "SELECT {}, rowid FROM entries WHERE dir_id IN ({}) AND name <> ''",
+        FILE_META_COLUMNS,
+        vec!["?"; dirs.len()].join(", ")
+    );
+    if let Some(filter) = &key.filter {
+        sql.push_str(" AND name LIKE ? ESCAPE '\\'");
+        params.push(rusqlite::types::Value::Text(format!("%{}%", escape_like(filter))));
+    }
+    sql.push_str(&page.seek_and_order(&mut params));
+    page.run(conn, &sql, &params)
+}
+
+// Keyset paging: the cursor holds the sort key and rowid of the last row
+// handed out, so each page seeks straight past it instead of skipping rows
+// with OFFSET, and stays as cheap on page 600 as on page 1.
+struct PageQuery {
+    sort: String,
+    field: &'static str,
+    descending: bool,
+    after: Option<PageCursor>,
+    limit: u32,
+    limits: ResultLimits,
+    // the page size asked for was above limits.max_rows
+    capped: bool,
+}
+
+const DEFAULT_PAGE_SIZE: u32 = 500;
+
+impl PageQuery {
+    fn new(
+        sort: Option<String>,
+        after_cursor: Option<String>,
+        limit: Option<u32>,
+        limits: ResultLimits,
+    ) -> Result<Self, Error> {
+        let sort = sort.unwrap_or_else(|| "name".to_string());
+        let (field, descending) = match sort.strip_prefix('-') {
+            Some(field) => (field, true),
+            None => (sort.as_str(), false),
+        };
+        let field = match field {
+            "name" => "name",
+            "size" => "size",
+            "modified" => "modified",
+            other => return Err(Error::InvalidInput(format!("unknown sort '{}'", other))),
+        };
+        let after = after_cursor.map(|c| decode_cursor(&c)).transpose()?;
+        if after.as_ref().map_or(false, |cursor| cursor.sort != sort) {
+            return Err(Error::CursorMismatch);
+        }
+        let requested = limit.unwrap_or(DEFAULT_PAGE_SIZE).max(1);
+        let max_rows = u32::try_from(limits.max_rows).unwrap_or(u32::MAX);
+        Ok(PageQuery {
+            sort,
+            field,
+            descending,
+            after,
+            limit: requested.min(max_rows),
+            limits,
+            capped: requested > max_rows,
+        })
+    }
+
+    // The whole listing in `sort` order, one row past limits.max_rows so
+    // Listing::all can tell when it was cut short.
+    fn unpaged(sort: Option<String>, limits: ResultLimits) -> Result<Self, Error> {
+        let mut all = PageQuery::new(sort, None, None, limits)?;
+        all.limit = u32::try_from(limits.max_rows.saturating_add(1)).unwrap_or(u32::MAX);
+        all.capped = false;
+        Ok(all)
+    }
+
+    fn column(&self) -> &'static str {
+        match self.field {
+            "name" => "name COLLATE NOCASE",
+            other => other,
+        }
+    }
+
+    // Continues a WHERE clause with the seek past the cursor, then orders and
+    // limits; the matching parameters are appended to `params`.
+    fn seek_and_order(&self, params: &mut Vec<rusqlite::types::Value>) -> String {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        let mut sql = String::new();
+        if let Some(cursor) = &self.after {
+            let key = match &cursor.key {
+                serde_json::Value::String(s) => rusqlite::types::Value::Text(s.clone()),
+                other => rusqlite::types::Value::Integer(other.as_i64().unwrap_or(0)),
+            };
+            params.push(key);
+            params.push(rusqlite::types::Value::Integer(cursor.rowid));
+            sql.push_str(&format!(
+                " AND ({}, rowid) {} (?, ?)",
+                self.column(),
+                if self.descending { "<" } else { ">" }
+            ));
+        }
+        params.push(rusqlite::types::Value::Integer(i64::from(self.limit)));
+        sql.push_str(&format!(
+            " ORDER BY {0} {1}, rowid {1} LIMIT ?",
+            self.column(),
+            direction
+        ));
+        sql
+    }
+
+    // `sql` selects FILE_META_COLUMNS followed by rowid. Every paged command
+    // ends here, so this is where the byte budget cuts a page short; the
+    // cursor then continues right after the last row kept.
+    fn run(
+        &self,
+        conn: &Connection,
+        sql: &str,
+        params: &[rusqlite::types::Value],
+    ) -> Result<ListingPage, Error> {
+        let mut stmt = conn.prepare(sql)?;
+        let mut rows: Vec<(FileMeta, i64)> = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                Ok((file_meta_from_row(row)?, row.get(FILE_META_COLUMN_COUNT)?))
+            })?
+            .filter_map(Result::ok)
+            .collect();
+        let full = rows.len() == self.limit as usize;
+        let fits = self.limits.fitting(rows.iter().map(|(meta, _)| meta));
+        let cut = fits < rows.len();
+        rows.truncate(fits);
+        let next_cursor = match rows.last() {
+            Some((last, rowid)) if full || cut => {
+                let key = match self.field {
+                    "name" => serde_json::Value::from(last.name.clone()),
+                    "size" => serde_json::Value::from(last.size),
+                    _ => serde_json::Value::from(last.modified),
+                };
+                let cursor = PageCursor { sort: self.sort.clone(), key, rowid: *rowid };
+                Some(encode_cursor(&cursor)?)
+            }
+            _ => None,
+        };
+        Ok(ListingPage {
+            entries: rows.into_iter().map(|(meta, _)| meta).collect(),
+            next_cursor,
+            truncated: cut || (full && self.capped),
+        })
+    }
+}
+
+// Cursors are opaque to the frontend: hex-encoded JSON.
+fn encode_cursor(cursor: &PageCursor) -> Result<String, Error> {
+    let json = serde_json::to_string(cursor).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    Ok(json.bytes().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn decode_cursor(cursor: &str) -> Result<PageCursor, Error> {
+    let malformed = || Error::InvalidInput("malformed cursor".to_string());
+    if cursor.len() % 2 != 0 || !cursor.is_ascii() {
+        return Err(malformed());
+    }
+    let bytes = (0..cursor.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&cursor[i..i + 2], 16))
+        .collect::<std::result::Result<Vec<u8>, _>>()
+        .map_err(|_| malformed())?;
+    serde_json::from_slice(&bytes).map_err(|_| malformed())
+}
+
+// Warms the cache for directories the user is likely to open next, such as
+// the siblings of the current one.
+#[tauri::command]
+async fn prefetch_children(app: tauri::AppHandle, dirs: Vec<String>) -> Result<(), Error> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = app.state::<DbConnection>();
+        let cache = app.state::<ListingCache>();
+        for dir in dirs.iter().take(LISTING_CACHE_CAPACITY) {
+            let key = ListingKey {
+                dir: normalize_dir_input(dir),
+                sort: None,
+                filter: None,
+            };
+            // Lock per directory so a click is never stuck behind the batch.
+            let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+            cached_children(&conn, &cache, key)?;
+        }
+        Ok(())
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// The caller holds the connection, so no write can land between reading the
+// generation and running the query.
+fn cached_children(
+    conn: &Connection,
+    cache: &ListingCache,
+    key: ListingKey,
+) -> Result<std::sync::Arc<Vec<FileMeta>>, Error> {
+    if let Some(listing) = cache.get(&key) {
+        return Ok(listing);
+    }
+    let read_at = cache.generation.load(Ordering::SeqCst);
+    let mut children = query_children(conn, &key.dir)?;
+    if let Some(filter) = &key.filter {
+        let filter = filter.to_lowercase();
+        children.retain(|c| c.name.to_lowercase().contains(&filter));
+    }
+    if let Some(sort) = &key.sort {
+        sort_children(&mut children, sort)?;
+    }
+    let listing = std::sync::Arc::new(children);
+    cache.insert(key, read_at, listing.clone());
+    Ok(listing)
+}
+
+fn sort_children(children: &mut [FileMeta], sort: &str) -> Result<(), Error> {
+    let (field, descending) = match sort.strip_prefix('-') {
+        Some(field) => (field, true),
+        None => (sort, false),
+    };
+    match field {
+        "name" => children.sort_by_key(|c| c.name.to_lowercase()),
+        "size" => children.sort_by_key(|c| c.size),
+        "modified" => children.sort_by_key(|c| c.modified),
+        other => return Err(Error::InvalidInput(format!("unknown sort '{}'", other))),
+    }
+    if descending {
+        children.reverse();
+    }
+    Ok(())
+}
+
+fn query_children(conn: &Connection, dir: &str) -> Result<Vec<FileMeta>> {
+    let dirs = child_dir_ids(conn, dir)?;
+    // The root row has no name, and would otherwise list itself.
+    let mut stmt = conn.prepare_cached(&format!(
+        "SELECT {}
+         FROM entries
+         WHERE dir_id IN ({}) AND name <> ''",
+        FILE_META_COLUMNS,
+        vec!["?"; dirs.len()].join(", ")
+    ))?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(dirs), file_meta_from_row)?;
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+// extension, kind, label, category
+const FILE_KINDS: &[(&str, &str, &str, &str)] = &[
+    ("jpg", "jpeg", "JPEG image", "image"),
+    ("jpeg", "jpeg", "JPEG image", "image"),
+    ("png", "png", "PNG image", "image"),
+    ("gif", "gif", "GIF image", "image"),
+    ("bmp", "bmp", "Bitmap image", "image"),
+    ("tif", "tiff", "TIFF image", "image"),
+    ("tiff", "tiff", "TIFF image", "image"),
+    ("webp", "webp", "WebP image", "image"),
+    ("svg", "svg", "SVG image", "image"),
+    ("ico", "icon", "Icon", "image"),
+    ("mp4", "mp4", "MP4 video", "video"),
+    ("mkv", "mkv", "Matroska video", "video"),
+    ("avi", "avi", "AVI video", "video"),
+    ("mov", "mov", "QuickTime video", "video"),
+    ("wmv", "wmv", "Windows Media video", "video"),
+    ("webm", "webm", "WebM video", "video"),
+    ("mp3", "mp3", "MP3 audio", "audio"),
+    ("wav", "wav", "WAV audio", "audio"),
+    ("flac", "flac", "FLAC audio", "audio"),
+    ("ogg", "ogg", "Ogg audio", "audio"),
+    ("m4a", "m4a", "MPEG-4 audio", "audio"),
+    ("pdf", "pdf", "PDF document", "document"),
+    ("doc", "word", "Word document", "document"),
+    ("docx", "word", "Word document", "document"),
+    ("xls", "excel", "Excel workbook", "document"),
+    ("xlsx", "excel", "Excel workbook", "document"),
+    ("ppt", "powerpoint", "PowerPoint presentation", "document"),
+    ("pptx", "powerpoint", "PowerPoint presentation", "document"),
+    ("odt", "opendocument", "OpenDocument text", "document"),
+    ("rtf", "rtf", "Rich text document", "document"),
+    ("txt", "text", "Text file", "text"),
+    ("md", "markdown", "Markdown file", "text"),
+    ("log", "log", "Log file", "text"),
+    ("csv", "csv", "CSV file", "text"),
+    ("json", "json", "JSON file", "code"),
+    ("xml", "xml", "XML file", "code"),
+    ("yaml", "yaml", "YAML file", "code"),
+    ("yml", "yaml", "YAML file", "code"),
+    ("toml", "toml", "TOML file", "code"),
+    ("html", "html", "HTML document", "code"),
+    ("htm", "html", "HTML document", "code"),
+    ("css", "css", "CSS stylesheet", "code"),
+    ("js", "javascript", "JavaScript file", "code"),
+    ("jsx", "javascript", "JavaScript file", "code"),
+    ("ts", "typescript", "TypeScript file", "code"),
+    ("tsx", "typescript", "TypeScript file", "code"),
+    ("rs", "rust", "Rust source", "code"),
+    ("py", "python", "Python script", "code"),
+    ("c", "c", "C source", "code"),
+    ("h", "c-header", "C header", "code"),
+    ("cpp", "cpp", "C++ source", "code"),
+    ("cs", "csharp", "C# source", "code"),
+    ("java", "java", "Java source", "code"),
+    ("go", "go", "Go source", "code"),
+    ("ps1", "powershell", "PowerShell script", "code"),
+    ("sh", "shell", "Shell script", "code"),
+    ("zip", "zip", "ZIP archive", "archive"),
+    ("7z", "7z", "7-Zip archive", "archive"),
+    ("rar", "rar", "RAR archive", "archive"),
+    ("tar", "tar", "Tar archive", "archive"),
+    ("gz", "gzip", "Gzip archive", "archive"),
+    ("iso", "disk-image", "Disc image", "archive"),
+    ("exe", "exe", "Application", "executable"),
+    ("msi", "msi", "Windows Installer package", "executable"),
+    ("bat", "batch", "Batch file", "executable"),
+    ("cmd", "batch", "Batch file", "executable"),
+    ("com", "com", "DOS application", "executable"),
+    ("dll", "dll", "Application extension", "system"),
+    ("sys", "driver", "System file", "system"),
+    ("lnk", "shortcut", "Shortcut", "system"),
+];
+
+// JSON object of lowercase extension -> FileKind, layered over FILE_KINDS.
+const FILE_KINDS_SETTING: &str = "file_kinds";
+
+// The built-in table plus the user's additions from the settings table.
+struct FileKinds(std::collections::HashMap<String, FileKind>);
+
+impl FileKinds {
+    fn load(conn: &Connection) -> Result<Self, Error> {
+        Ok(FileKinds(load_custom_file_kinds(conn)?))
+    }
+
+    fn resolve(&self, extension: Option<&str>, is_dir: bool) -> FileKind {
+        let owned = |kind: &str, label: &str, category: &str| FileKind {
+            kind: kind.to_string(),
+            label: label.to_string(),
+            category: category.to_string(),
+        };
+        if is_dir {
+            return owned("folder", "Folder", "folder");
+        }
+        let extension = match extension.filter(|e| !e.is_empty()) {
+            Some(extension) => extension.to_lowercase(),
+            None => return owned("file", "File", "other"),
+        };
+        if let Some(custom) = self.0.get(&extension) {
+            return custom.clone();
+        }
+        match FILE_KINDS.iter().find(|(ext, ..)| *ext == extension) {
+            Some((_, kind, label, category)) => owned(kind, label, category),
+            None => FileKind {
+                kind: extension.clone(),
+                label: format!("{} file", extension.to_uppercase()),
+                category: "other".to_string(),
+            },
+        }
+    }
+
+    fn annotate(&self, entries: &mut [FileMeta]) {
+        for entry in entries {
+            entry.kind = Some(self.resolve(entry.extension.as_deref(), entry.is_dir));
+        }
+    }
+}
+
+fn load_custom_file_kinds(
+    conn: &Connection,
+) -> Result<std::collections::HashMap<String, FileKind>, Error> {
+    match get_setting(conn, FILE_KINDS_SETTING)? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| Error::InvalidInput(e.to_string())),
+        None => Ok(Default::default()),
+    }
+}
+
+#[tauri::command]
+fn get_file_kind(
+    db: State<DbConnection>,
+    extension: Option<String>,
+    is_dir: bool,
+) -> Result<FileKind, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(FileKinds::load(&conn)?.resolve(extension.as_deref(), is_dir))
+}
+
+#[derive(Debug, serde::Deserialize)]
+struct FileKindQuery {
+    extension: Option<String>,
+    is_dir: bool,
+}
+
+// One answer per query, in order.
+#[tauri::command]
+fn get_file_kinds(
+    db: State<DbConnection>,
+    queries: Vec<FileKindQuery>,
+) -> Result<Vec<FileKind>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let kinds = FileKinds::load(&conn)?;
+    Ok(queries
+        .iter()
+        .map(|q| kinds.resolve(q.extension.as_deref(), q.is_dir))
+        .collect())
+}
+
+// Passing None drops the user's mapping, falling back to the built-in one.
+#[tauri::command]
+fn set_custom_file_kind(
+    db: State<DbConnection>,
+    extension: String,
+    kind: Option<FileKind>,
+) -> Result<(), Error> {
+    let extension = extension.trim_start_matches('.').to_lowercase();
+    if extension.is_empty() {
+        return Err(Error::InvalidInput("extension is empty".to_string()));
+    }
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut custom = load_custom_file_kinds(&conn)?;
+    match kind {
+        Some(kind) => {
+            custom.insert(extension, kind);
+        }
+        None => {
+            custom.remove(&extension);
+        }
+    }
+    let value = serde_json::to_string(&custom).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    set_setting(&conn, FILE_KINDS_SETTING, &value)?;
+    Ok(())
+}
+
+// Everything "reveal in app" needs in one round trip: where the file lives and
+// that directory's listing. A file that vanished from disk is dropped from
+// the index and reported with exists = false.
+#[tauri::command]
+fn locate(db: State<DbConnection>, path: String) -> Result<LocateResult, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    locate_in(&conn, &normalize_dir_input(&path))
+}
+
+fn locate_in(conn: &Connection, path: &str) -> Result<LocateResult, Error> {
+    let parent = Path::new(path)
+        .parent()
+        .map(|p| p.to_string_lossy().to_string())
+        .ok_or_else(|| Error::InvalidInput(format!("'{}' has no parent directory", path)))?;
+
+    let exists = Path::new(path).exists();
+    if !exists {
+        remove_subtree_from_index(conn, path)?;
+    }
+
+    Ok(LocateResult {
+        children: query_children(conn, &parent)?,
+        parent,
+        exists,
+    })
+}
+
+const MAX_QUERY_TERMS: usize = 64;
+
+// A structured query, e.g.
+//   {"and": [{"field": "name", "op": "contains", "value": "invoice"},
+//            {"field": "extension", "op": "in", "value": ["pdf", "docx"]}]}
+// Groups nest freely. Fields and ops come from fixed lists and values are
+// always bound, so nothing from the query is spliced into the SQL.
+#[derive(Debug, serde::Deserialize)]
+#[serde(untagged)]
+enum QueryNode {
+    And { and: Vec<QueryNode> },
+    Or { or: Vec<QueryNode> },
+    Term { field: String, op: String, value: serde_json::Value },
+}
+
+enum QueryField {
+    Text(&'static str),
+    Number(&'static str),
+    Flag(&'static str),
+}
+
+impl QueryField {
+    fn parse(field: &str) -> Result<Self, Error> {
+        Ok(match field {
+            "name" => QueryField::Text("name"),
+            "path" => QueryField::Text("path"),
+            "extension" | "ext" => QueryField::Text("extension"),
+            "doc_title" => QueryField::Text("doc_title"),
+            "doc_author" => QueryField::Text("doc_author"),
+            "size" => QueryField::Number("size"),
+            "modified" => QueryField::Number("modified"),
+            "accessed" => QueryField::Number("accessed"),
+            "created" => QueryField::Number("created"),
+            "is_dir" => QueryField::Flag("is_dir"),
+            "is_symlink" => QueryField::Flag("is_symlink"),
+            other => return Err(Error::InvalidInput(format!("unknown query field '{}'", other))),
+        })
+    }
+}
+
+impl QueryNode {
+    fn compile(
+        &self,
+        sql: &mut String,
+        params: &mut Vec<rusqlite::types::Value>,
+        terms: &mut usize,
+    ) -> Result<(), Error> {
+        let (children, joiner) = match self {
+            QueryNode::And { and } => (and, " AND "),
+            QueryNode::Or { or } => (or, " OR "),
+            QueryNode::Term { field, op, value } => {
+                *terms += 1;
+                if *terms > MAX_QUERY_TERMS {
+                    return Err(Error::InvalidInput(format!(
+                        "queries are limited to {} terms",
+                        MAX_QUERY_TERMS
+                    )));
+                }
+                return compile_term(field, op, value, sql, params);
+            }
+        };
+        if children.is_empty() {
+            return Err(Error::InvalidInput("empty query group".to_string()));
+        }
+        sql.push('(');
+        for (i, child) in children.iter().enumerate() {
+            if i > 0 {
+                sql.push_str(joiner);
+            }
+            child.compile(sql, params, terms)?;
+        }
+        sql.push(')');
+        Ok(())
+    }
+}
+
+fn compile_term(
+    field: &str,
+    op: &str,
+    value: &serde_json::Value,
+    sql: &mut String,
+    params: &mut Vec<rusqlite::types::Value>,
+) -> Result<(), Error> {
+    use rusqlite::types::Value;
+
+    let bad_value = || Error::InvalidInput(format!("bad value for '{}' {}", field, op));
+    let text = |v: &serde_json::Value| v.as_str().map(str::to_string).ok_or_else(bad_value);
+    let unknown_op = || Error::InvalidInput(format!("unknown op '{}' for field '{}'", op, field));
+
+    match QueryField::parse(field)? {
+        QueryField::Text(column) => {
+            let pattern = match op {
+                "eq" => None,
+                "contains" => Some(format!("%{}%", escape_like(&text(value)?))),
+                "starts_with" => Some(format!("{}%", escape_like(&text(value)?))),
+                "ends_with" => Some(format!("%{}", escape_like(&text(value)?))),
+                "in" => {
+                    let values = value.as_array().filter(|v| !v.is_empty()).ok_or_else(bad_value)?;
+                    let placeholders = vec!["?"; values.len()].join(", ");
+                    sql.push_str(&format!("{} COLLATE NOCASE IN ({})", column, placeholders));
+                    for v in values {
+                        params.push(Value::Text(text(v)?));
+                    }
+                    return Ok(());
+                }
+                _ => return Err(unknown_op()),
+            };
+            match pattern {
+                Some(pattern) => {
+                    sql.push_str(&format!("{} LIKE ? ESCAPE '\\'", column));
+                    params.push(Value::Text(pattern));
+                }
+                None => {
+                    sql.push_str(&format!("{} = ? COLLATE NOCASE", column));
+                    params.push(Value::Text(text(value)?));
+                }
+            }
+        }
+        QueryField::Number(column) => {
+            let comparison = match op {
+                "eq" => "=",
+                "ne" => "<>",
+                "lt" => "<",
+                "le" => "<=",
+                "gt" => ">",
+                "ge" => ">=",
+                _ => return Err(unknown_op()),
+            };
+            sql.push_str(&format!("{} {} ?", column, comparison));
+            params.push(Value::Integer(value.as_i64().ok_or_else(bad_value)?));
+        }
+        QueryField::Flag(column) => {
+            if op != "eq" {
+                return Err(unknown_op());
+            }
+            sql.push_str(&format!("{} = ?", column));
+            params.push(Value::Integer(i64::from(value.as_bool().ok_or_else(bad_value)?)));
+        }
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn search_advanced(db: State<DbConnection>, query_json: String) -> Result<Vec<FileMeta>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    advanced_matches(&conn, &query_json)
+}
+
+fn advanced_matches(conn: &Connection, query_json: &str) -> Result<Vec<FileMeta>, Error> {
+    let query: QueryNode =
+        serde_json::from_str(query_json).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    let mut sql = format!("SELECT {} FROM entries WHERE ", FILE_META_COLUMNS);
+    let mut params = Vec::new();
+    query.compile(&mut sql, &mut params, &mut 0)?;
+    sql.push_str(" ORDER BY name, path");
+
+    let limits = ResultLimits::load(conn)?;
+    sql.push_str(&format!(" LIMIT {}", limits.max_rows));
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(params), file_meta_from_row)?;
+    let mut found = rows.filter_map(Result::ok).collect();
+    limits.truncate(&mut found);
+    Ok(found)
+}
+
+// Paged like list_children when `page_size` or `after_cursor` is passed, and
+// takes the same `format`. `doc_title` and `doc_author` match substrings of
+// the properties index_document_metadata stored.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn search_files(
+    db: State<DbConnection>,
+    name: String,
+    extension: String,
+    sort: Option<String>,
+    after_cursor: Option<String>,
+    page_size: Option<u32>,
+    format: Option<String>,
+    doc_title: Option<String>,
+    doc_author: Option<String>,
+) -> Result<Listing, Error> {
+    let format = listing_format(format)?;
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    if after_cursor.is_none() {
+        record_search(&conn, &name)?;
+    }
+    let filters = SearchFilters { name, extension, doc_title, doc_author };
+    search_listing(&conn, &filters, sort, after_cursor, page_size, format)
+}
+
+struct SearchFilters {
+    name: String,
+    extension: String,
+    doc_title: Option<String>,
+    doc_author: Option<String>,
+}
+
+// Unpaged, the matches come back in `sort` order like a first page would,
+// reading at most one row past limits.max_rows.
+fn search_listing(
+    conn: &Connection,
+    search: &SearchFilters,
+    sort: Option<String>,
+    after_cursor: Option<String>,
+    page_size: Option<u32>,
+    format: ListingFormat,
+) -> Result<Listing, Error> {
+    let limits = ResultLimits::load(conn)?;
+
+    let mut params = vec![rusqlite::types::Value::Text(format!("%{}%", search.name))];
+    let mut filters = String::from("name LIKE ?");
+    if !search.extension.is_empty() {
+        filters.push_str(" AND extension = ?");
+        params.push(rusqlite::types::Value::Text(search.extension.clone()));
+    }
+    for (column, wanted) in [("doc_title", &search.doc_title), ("doc_author", &search.doc_author)] {
+        if let Some(wanted) = wanted.as_ref().filter(|w| !w.is_empty()) {
+            filters.push_str(&format!(" AND {} LIKE ? ESCAPE '\\'", column));
+            params.push(rusqlite::types::Value::Text(format!("%{}%", escape_like(wanted))));
+        }
+    }
+
+    let sort = sort.filter(|s| !s.is_empty());
+    let read_page = |page: &PageQuery| {
+        let mut params = params.clone();
+        let mut sql =
+            format!("SELECT {}, rowid FROM entries WHERE {}", FILE_META_COLUMNS, filters);
+        sql.push_str(&page.seek_and_order(&mut params));
+        page.run(conn, &sql, &params)
+    };
+    if after_cursor.is_some() || page_size.is_some() {
+        let page = PageQuery::new(sort, after_cursor, page_size, limits)?;
+        return Ok(Listing::page(read_page(&page)?, format));
+    }
+
+    let everything = PageQuery::unpaged(sort.clone(), limits)?;
+    let mut unpaged_params = params.clone();
+    let mut sql = format!("SELECT {} FROM entries WHERE {}", FILE_META_COLUMNS, filters);
+    sql.push_str(&everything.seek_and_order(&mut unpaged_params));
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(&unpaged_params), file_meta_from_row)?;
+    let result = rows.filter_map(Result::ok).collect::<Vec<_>>();
+    Listing::all(result, format, sort, limits, read_page)
+}
+
+#[derive(serde::Serialize)]
+struct Descendants {
+    entries: Entries,
+    // None on the last page
+    next_cursor: Option<String>,
+    // as in Listing::Page
+    truncated: bool,
+    // files under the directory passing the extension filter, across all pages
+    total: u64,
+}
+
+// The half-open range [start, end) of paths below `dir`. Being a plain
+// comparison on path, it is served by the index on path where LIKE and
+// substr() would scan the table.
+fn subtree_range(dir: &str) -> (String, String) {
+    let start = subtree_prefix(dir);
+    let mut end = start.clone();
+    let separator = end.pop().unwrap_or(std::path::MAIN_SEPARATOR);
+    end.push(char::from(separator as u8 + 1));
+    (start, end)
+}
+
+// subtree_range of an optional scope; with none the bounds are NULL and a
+// `(?1 IS NULL OR (path >= ?1 AND path < ?2))` filter keeps every row.
+fn optional_subtree_range(dir: Option<&str>) -> (Option<String>, Option<String>) {
+    match dir.map(subtree_range) {
+        Some((start, end)) => (Some(start), Some(end)),
+        None => (None, None),
+    }
+}
+
+// Every indexed file below `dir` however deep, always paged. Sorting, cursors
+// and `format` work as in list_children; `extension` keeps one extension.
+#[tauri::command]
+fn list_descendants(
+    db: State<DbConnection>,
+    dir: String,
+    sort: Option<String>,
+    extension: Option<String>,
+    after_cursor: Option<String>,
+    page_size: Option<u32>,
+    format: Option<String>,
+) -> Result<Descendants, Error> {
+    use rusqlite::types::Value;
+
+    let format = listing_format(format)?;
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let limits = ResultLimits::load(&conn)?;
+    let page = PageQuery::new(sort.filter(|s| !s.is_empty()), after_cursor, page_size, limits)?;
+    let (start, end) = subtree_range(&normalize_dir_input(&dir));
+    let mut filter = format!("{} AND is_dir = 0", dir_in_range("?", "?"));
+    let mut params = vec![Value::Text(start), Value::Text(end)];
+    let extension = extension.map(|e| e.trim_start_matches('.').to_string());
+    if let Some(extension) = extension.filter(|e| !e.is_empty()) {
+        filter.push_str(" AND extension = ? COLLATE NOCASE");
+        params.push(Value::Text(extension));
+    }
+
+    let total = conn.query_row(
+        &format!("SELECT COUNT(*) FROM main_table WHERE {}", filter),
+        rusqlite::params_from_iter(&params),
+        |row| row.get(0),
+    )?;
+    let mut sql = format!("SELECT {}, rowid FROM entries WHERE {}", FILE_META_COLUMNS, filter);
+    sql.push_str(&page.seek_and_order(&mut params));
+    let mut listing = page.run(&conn, &sql, &params)?;
+    FileKinds::load(&conn)?.annotate(&mut listing.entries);
+    Ok(Descendants {
+        entries: Entries::new(listing.entries, format),
+        next_cursor: listing.next_cursor,
+        truncated: listing.truncated,
+        total,
+    })
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default)]
+struct BrowseOptions {
+    // list this directory, or with `recursive` everything below it
+    dir: Option<String>,
+    recursive: bool,
+    // names containing this, ignoring case; on its own it searches the index
+    query: Option<String>,
+    extensions: Vec<String>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    modified_after: Option<u64>,
+    modified_before: Option<u64>,
+    files_only: bool,
+    // as in list_children
+    sort: Option<String>,
+    after_cursor: Option<String>,
+    page_size: Option<u32>,
+    format: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct BrowseResult {
+    items: Entries,
+    // None on the last page
+    next_cursor: Option<String>,
+    // as in Listing::Page
+    truncated: bool,
+    // everything matching the options, across all pages
+    total: u64,
+}
+
+// The listing entry point for the UI: a directory, subtree or search with
+// its filters, sort and page in one call. list_children, search_files and
+// list_descendants remain for existing callers.
+#[tauri::command]
+fn browse(db: State<DbConnection>, options: BrowseOptions) -> Result<BrowseResult, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    browse_index(&conn, options)
+}
+
+fn browse_index(conn: &Connection, options: BrowseOptions) -> Result<BrowseResult, Error> {
+    use rusqlite::types::Value;
+
+    let format = listing_format(options.format)?;
+    let page = PageQuery::new(
+        options.sort.filter(|s| !s.is_empty()),
+        options.after_cursor,
+        options.page_size,
+        ResultLimits::load(conn)?,
+    )?;
+    let query = options.query.filter(|q| !q.is_empty());
+    let mut clauses = Vec::new();
+    let mut params = Vec::new();
+    let below = dir_in_range("?", "?");
+    match options.dir.map(|d| normalize_dir_input(&d)) {
+        Some(dir) => {
+            let (start, end) = subtree_range(&dir);
+            if options.recursive {
+                clauses.push(below.as_str());
+                params.extend([Value::Text(start), Value::Text(end)]);
+            } else {
+                // a root's own row shares its children's prefix
+                clauses.push("dir_id = (SELECT id FROM dir_paths WHERE path = ?) AND name <> ''");
+                params.push(Value::Text(start));
+            }
+        }
+        None if query.is_none() => {
+            return Err(Error::InvalidInput("browse needs a dir or a query".to_string()));
+        }
+        None => {}
+    }
+    if let Some(query) = query {
+        clauses.push("name LIKE ? ESCAPE '\\'");
+        params.push(Value::Text(format!("%{}%", escape_like(&query))));
+    }
+    let extensions: Vec<String> = options
+        .extensions
+        .iter()
+        .map(|e| e.trim_start_matches('.').to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect();
+    let placeholders = vec!["?"; extensions.len()].join(", ");
+    let extension_clause = format!("lower(extension) IN ({})", placeholders);
+    if !extensions.is_empty() {
+        clauses.push(&extension_clause);
+        params.extend(extensions.into_iter().map(Value::Text));
+    }
+    let bounds = [
+        ("size >= ?", options.min_size),
+        ("size <= ?", options.max_size),
+        ("modified >= ?", options.modified_after),
+        ("modified <= ?", options.modified_before),
+    ];
+    for (clause, bound) in bounds {
+        if let Some(bound) = bound {
+            clauses.push(clause);
+            params.push(Value::Integer(bound as i64));
+        }
+    }
+    if options.files_only {
+        clauses.push("is_dir = 0");
+    }
+    let filter = if clauses.is_empty() {
+        "1".to_string()
+    } else {
+        clauses.join(" AND ")
+    };
+
+    let total = conn.query_row(
+        &format!("SELECT COUNT(*) FROM main_table WHERE {}", filter),
+        rusqlite::params_from_iter(&params),
+        |row| row.get(0),
+    )?;
+    let mut sql = format!("SELECT {}, rowid FROM entries WHERE {}", FILE_META_COLUMNS, filter);
+    sql.push_str(&page.seek_and_order(&mut params));
+    let mut listing = page.run(conn, &sql, &params)?;
+    FileKinds::load(conn)?.annotate(&mut listing.entries);
+    Ok(BrowseResult {
+        items: Entries::new(listing.entries, format),
+        next_cursor: listing.next_cursor,
+        truncated: listing.truncated,
+        total,
+    })
+}
+
+// The one entry a "jump to" needs, matched like search_files. Exact names
+// (ignoring case) win, then the shallowest path, then path order, so the same
+// index always yields the same answer.
+#[tauri::command]
+fn find_first(db: State<DbConnection>, name: String) -> Result<Option<FileMeta>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    first_match(&conn, &name)
+}
+
+fn first_match(conn: &Connection, name: &str) -> Result<Option<FileMeta>, Error> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+    let found = conn
+        .query_row(
+            &format!(
+                "SELECT {}
+                 FROM entries
+                 WHERE name LIKE ?1 ESCAPE '\\'
+                 ORDER BY name = ?2 COLLATE NOCASE DESC, path_depth(path), path
+                 LIMIT 1",
+                FILE_META_COLUMNS
+            ),
+            rusqlite::params![format!("%{}%", escape_like(name)), name],
+            file_meta_from_row,
+        )
+        .optional()?;
+    Ok(found)
+}
+
+// Files bigger than `threshold` bytes, largest first; directories never
+// count, even with their rolled-up sizes.
+#[tauri::command]
+fn files_over_size(
+    db: State<DbConnection>,
+    threshold: u64,
+    limit: Option<u32>,
+) -> Result<Vec<FileMeta>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    largest_files(&conn, threshold, limit.unwrap_or(100))
+}
+
+fn largest_files(conn: &Connection, threshold: u64, limit: u32) -> Result<Vec<FileMeta>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+         FROM entries
+         WHERE is_dir = 0 AND size > ?1
+         ORDER BY size DESC, path
+         LIMIT ?2",
+        FILE_META_COLUMNS
+    ))?;
+    let rows = stmt.query_map(
+        rusqlite::params![threshold, limit],
+        file_meta_from_row,
+    )?;
+    let mut found = rows.filter_map(Result::ok).collect();
+    ResultLimits::load(conn)?.truncate(&mut found);
+    Ok(found)
+}
+
+// Distinct names starting with `prefix`, most common first, for type-ahead.
+#[tauri::command]
+fn suggest(db: State<DbConnection>, prefix: String, limit: u32) -> Result<Vec<String>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    suggestions(&conn, &prefix, limit)
+}
+
+fn suggestions(conn: &Connection, prefix: &str, limit: u32) -> Result<Vec<String>, Error> {
+    if prefix.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn.prepare(
+        "SELECT name FROM main_table
+         WHERE name LIKE ?1 || '%' ESCAPE '\\'
+         GROUP BY name
+         ORDER BY COUNT(*) DESC, name
+         LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![escape_like(prefix), limit], |row| row.get(0))?;
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+// For LIKE ... ESCAPE '\', so user input matches literally.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+const SEARCH_HISTORY_LIMIT: u32 = 200;
+
+// Search-as-you-type fires for every pause in typing; a term that refines the
+// previous one within this window replaces it instead of adding an entry.
+const SEARCH_REFINE_WINDOW_SECS: u64 = 10;
+
+#[derive(Debug, serde::Serialize)]
+struct SearchHistoryEntry {
+    term: String,
+    searched_at: u64,
+}
+
+fn record_search(conn: &Connection, term: &str) -> Result<()> {
+    let term = term.trim();
+    if term.is_empty() {
+        return Ok(());
+    }
+    let now = unix_now();
+    let last: Option<(i64, String, u64)> = conn
+        .query_row(
+            "SELECT id, term, searched_at FROM search_history ORDER BY id DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    if let Some((id, last_term, searched_at)) = last {
+        let refines = now.saturating_sub(searched_at) <= SEARCH_REFINE_WINDOW_SECS
+            && (term.starts_with(&last_term) || last_term.starts_with(term));
+        if last_term == term || refines {
+            conn.execute(
+                "UPDATE search_history SET term = ?1, searched_at = ?2 WHERE id = ?3",
+                rusqlite::params![term, now, id],
+            )?;
+            return Ok(());
+        }
+    }
+    conn.execute(
+        "INSERT INTO search_history (term, searched_at) VALUES (?1, ?2)",
+        rusqlite::params![term, now],
+    )?;
+    conn.execute(
+        "DELETE FROM search_history WHERE id NOT IN
+            (SELECT id FROM search_history ORDER BY id DESC LIMIT ?1)",
+        rusqlite::params![SEARCH_HISTORY_LIMIT],
+    )?;
+    Ok(())
+}
+
+// Most recent first.
+#[tauri::command]
+fn list_search_history(
+    db: State<DbConnection>,
+    limit: Option<u32>,
+) -> Result<Vec<SearchHistoryEntry>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    recent_searches(&conn, limit.unwrap_or(20))
+}
+
+fn recent_searches(conn: &Connection, limit: u32) -> Result<Vec<SearchHistoryEntry>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT term, searched_at FROM search_history ORDER BY id DESC LIMIT ?1",
+    )?;
+    let rows = stmt.query_map(
+        rusqlite::params![limit.min(SEARCH_HISTORY_LIMIT)],
+        |row| {
+            Ok(SearchHistoryEntry {
+                term: row.get(0)?,
+                searched_at: row.get(1)?,
+            })
+        },
+    )?;
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+// Counts are taken over raw bytes so chunk boundaries never split a
+// character: chars are UTF-8 lead bytes, words are runs of non-whitespace and
+// a final line without a trailing newline still counts. Files containing a
+// NUL byte are treated as binary.
+#[tauri::command]
+fn text_stats(path: String, max_bytes: u64) -> Result<TextStats, Error> {
+    use std::io::Read;
+
+    let path = normalize_dir_input(&path);
+    let mut file = fs::File::open(&path)?;
+    let size = file.metadata()?.len();
+    if size > max_bytes {
+        return Err(Error::InvalidInput(format!(
+            "'{}' is {} bytes, over the {} byte limit",
+            path, size, max_bytes
+        )));
+    }
+
+    let mut stats = TextStats::default();
+    let mut in_word = false;
+    let mut last_byte = None;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        for &byte in &buf[..read] {
+            if byte == 0 {
+                return Err(Error::InvalidInput(format!("'{}' looks like a binary file", path)));
+            }
+            if byte & 0xC0 != 0x80 {
+                stats.chars += 1;
+            }
+            if byte == b'\n' {
+                stats.lines += 1;
+            }
+            if byte.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                stats.words += 1;
+            }
+        }
+        last_byte = Some(buf[read - 1]);
+    }
+    if last_byte.map_or(false, |b| b != b'\n') {
+        stats.lines += 1;
+    }
+    Ok(stats)
+}
+
+// FAT, the most limited filesystem still in use, cannot store anything older.
+const EARLIEST_MTIME: u64 = 315_532_800; // 1980-01-01
+
+#[tauri::command]
+fn set_modified(db: State<DbConnection>, path: String, timestamp: u64) -> Result<(), Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    apply_modified(&conn, &normalize_dir_input(&path), timestamp)
+}
+
+#[tauri::command]
+fn set_modified_batch(
+    db: State<DbConnection>,
+    updates: Vec<ModifiedUpdate>,
+) -> Result<TimestampReport, Error> {
+    let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    apply_modified_batch(&mut conn, updates)
+}
+
+fn apply_modified_batch(
+    conn: &mut Connection,
+    updates: Vec<ModifiedUpdate>,
+) -> Result<TimestampReport, Error> {
+    let tx = conn.transaction()?;
+    let mut report = TimestampReport::default();
+    for update in updates {
+        let path = normalize_dir_input(&update.path);
+        match apply_modified(&tx, &path, update.timestamp) {
+            Ok(()) => report.updated += 1,
+            Err(e) => report.failed.push(FailedEntry::new(&path, &e.to_string())),
+        }
+    }
+    tx.commit()?;
+    Ok(report)
+}
+
+fn apply_modified(conn: &Connection, path: &str, timestamp: u64) -> Result<(), Error> {
+    // A day of slack for clocks that disagree about time zones.
+    let latest = unix_now() + 24 * 60 * 60;
+    if !(EARLIEST_MTIME..=latest).contains(&timestamp) {
+        return Err(Error::InvalidInput(format!(
+            "timestamp {} is outside 1980-01-01 .. now",
+            timestamp
+        )));
+    }
+    ensure_writable(conn, &[path])?;
+    filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(timestamp as i64, 0))?;
+    conn.execute(
+        &format!("UPDATE main_table SET modified = ?1 WHERE {}", path_is("?2")),
+        rusqlite::params![timestamp, path],
+    )?;
+    Ok(())
+}
+
+// Keeps a refresh of the rows on screen within a predictable latency.
+const REFRESH_BATCH_LIMIT: usize = 500;
+
+// Re-stats `paths` and writes the results back to the index, returning
+// entries in the order given.
+#[tauri::command]
+async fn refresh_metadata(
+    app: tauri::AppHandle,
+    paths: Vec<String>,
+) -> Result<Vec<RefreshedEntry>, Error> {
+    if paths.len() > REFRESH_BATCH_LIMIT {
+        return Err(Error::InvalidInput(format!(
+            "at most {} paths can be refreshed at once, got {}",
+            REFRESH_BATCH_LIMIT,
+            paths.len()
+        )));
+    }
+    tauri::async_runtime::spawn_blocking(move || refresh_metadata_blocking(&app, &paths))
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn refresh_metadata_blocking(
+    app: &tauri::AppHandle,
+    paths: &[String],
+) -> Result<Vec<RefreshedEntry>, Error> {
+    // Stat everything before taking the lock so slow disks don't stall other
+    // commands.
+    let mut entries = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = normalize_dir_input(path);
+        let entry = match fs::symlink_metadata(&path) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => RefreshedEntry::Removed { path },
+            Err(e) => RefreshedEntry::Failed { path, error: e.to_string() },
+            Ok(metadata) if is_cloud_placeholder(&metadata) => RefreshedEntry::Skipped { path },
+            Ok(_) => match get_file_meta(Path::new(&path)) {
+                Ok(meta) => RefreshedEntry::Updated(meta),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    RefreshedEntry::Removed { path }
+                }
+                Err(e) => RefreshedEntry::Failed { path, error: e.to_string() },
+            },
+        };
+        entries.push(entry);
+    }
+
+    let db = app.state::<DbConnection>();
+    let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let tx = conn.transaction()?;
+    for entry in &entries {
+        match entry {
+            RefreshedEntry::Updated(meta) => {
+                insert_file_meta(&tx, meta)?;
+                invalidate_dir_sizes(&tx, &meta.path)?;
+            }
+            RefreshedEntry::Removed { path } => remove_subtree_from_index(&tx, path)?,
+            RefreshedEntry::Skipped { .. } | RefreshedEntry::Failed { .. } => {}
+        }
+    }
+    tx.commit()?;
+    Ok(entries)
+}
+
+// Adds or updates just these paths, walking into any directories among them,
+// for files the UI created or downloaded and wants searchable right away.
+#[tauri::command]
+async fn index_paths(app: tauri::AppHandle, paths: Vec<String>) -> Result<IndexPathsReport, Error> {
+    tauri::async_runtime::spawn_blocking(move || index_paths_blocking(&app, &paths))
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn index_paths_blocking(app: &tauri::AppHandle, paths: &[String]) -> Result<IndexPathsReport, Error> {
+    let mut report = IndexPathsReport::default();
+    let metas = stat_paths(paths, &mut report);
+    let db = app.state::<DbConnection>();
+    let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    upsert_metas(&mut conn, &metas, &mut report)?;
+    emit_directories_invalidated(app, reindexed_dirs(paths));
+    Ok(report)
+}
+
+// A re-walked directory's own listing changed too, not just its parent's.
+fn reindexed_dirs(paths: &[String]) -> std::collections::BTreeSet<String> {
+    let requested: Vec<String> = paths.iter().map(|p| normalize_dir_input(p)).collect();
+    let walked = requested.iter().filter(|p| Path::new(p).is_dir()).cloned();
+    invalidated_parents(&requested).into_iter().chain(walked).collect()
+}
+
+// Stats before the caller takes the lock so slow disks don't stall other
+// commands.
+fn stat_paths(paths: &[String], report: &mut IndexPathsReport) -> Vec<FileMeta> {
+    stat_paths_with(&WalkConfig::default(), &DiskMetadata, paths, report)
+}
+
+fn stat_paths_with(
+    walk: &WalkConfig,
+    source: &impl MetadataSource,
+    paths: &[String],
+    report: &mut IndexPathsReport,
+) -> Vec<FileMeta> {
+    let mut metas = Vec::new();
+    for path in paths {
+        let path = normalize_dir_input(path);
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.is_dir() => {
+                metas.extend(
+                    walk.walk(&path).filter_map(|e| read_file_meta(source, e.path()).ok()),
+                );
+            }
+            Ok(_) => match read_file_meta(source, Path::new(&path)) {
+                Ok(meta) => metas.push(meta),
+                Err(e) => report.failed.push(FailedEntry::new(&path, &e.to_string())),
+            },
+            Err(e) => report.failed.push(FailedEntry::new(&path, &e.to_string())),
+        }
+    }
+    metas
+}
+
+fn upsert_metas(
+    conn: &mut Connection,
+    metas: &[FileMeta],
+    report: &mut IndexPathsReport,
+) -> Result<(), Error> {
+    let tx = conn.transaction()?;
+    for meta in metas {
+        match insert_file_meta(&tx, meta)? {
+            UpsertOutcome::Created => report.created += 1,
+            UpsertOutcome::Updated | UpsertOutcome::Replaced { .. } => report.updated += 1,
+        }
+        invalidate_dir_sizes(&tx, &meta.path)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// Returns "" for the root itself. Components are compared case-insensitively
+// on Windows, so "c:\users" is a root of "C:\Users\me".
+#[tauri::command]
+fn relative_path(root: String, path: String) -> Result<String, Error> {
+    let root_norm = normalize_dir_input(&root);
+    let path_norm = normalize_dir_input(&path);
+    let mut remaining = Path::new(&path_norm).components();
+    for root_part in Path::new(&root_norm).components() {
+        match remaining.next() {
+            Some(part) if same_component(root_part.as_os_str(), part.as_os_str()) => {}
+            _ => return Err(Error::InvalidInput(format!("'{}' is not under '{}'", path, root))),
+        }
+    }
+    let parts: Vec<_> = remaining.map(|c| c.as_os_str().to_string_lossy().to_string()).collect();
+    Ok(parts.join(&std::path::MAIN_SEPARATOR.to_string()))
+}
+
+#[cfg(target_os = "windows")]
+fn same_component(a: &std::ffi::OsStr, b: &std::ffi::OsStr) -> bool {
+    a.to_string_lossy().to_lowercase() == b.to_string_lossy().to_lowercase()
+}
+
+#[cfg(not(target_os = "windows"))]
+fn same_component(a: &std::ffi::OsStr, b: &std::ffi::OsStr) -> bool {
+    a == b
+}
+
+#[tauri::command]
+fn get_file_meta_command(path: String) -> Result<FileMeta, Error> {
+    get_file_meta(Path::new(&normalize_dir_input(&path))).map_err(Into::into)
+}
+
+// Below this many paths one thread stats them faster than several would.
+const META_CHUNK: usize = 64;
+
+// Applies `f` to every item across a few threads, keeping the order. Meant
+// for stat calls, where the threads spend their time waiting on the disk.
+fn parallel_map<T, R>(items: Vec<T>, f: fn(&T) -> R) -> Vec<R>
+where
+    T: Clone + Send + 'static,
+    R: Send + 'static,
+{
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    if items.len() <= META_CHUNK || threads == 1 {
+        return items.iter().map(f).collect();
+    }
+    let chunk = ((items.len() + threads - 1) / threads).max(META_CHUNK);
+    let workers: Vec<_> = items
+        .chunks(chunk)
+        .map(|part| {
+            let part = part.to_vec();
+            std::thread::spawn(move || part.iter().map(f).collect::<Vec<R>>())
+        })
+        .collect();
+    workers
+        .into_iter()
+        .flat_map(|worker| worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+        .collect()
+}
+
+// get_file_meta_command for many paths in one call. Results line up with
+// `paths`; a path that cannot be read carries its error instead.
+#[tauri::command]
+async fn get_file_meta_many(paths: Vec<String>) -> Result<Vec<Result<FileMeta, String>>, Error> {
+    tauri::async_runtime::spawn_blocking(move || file_metas(paths))
+        .await
+        .map_err(|e| Error::Background(e.to_string()))
+}
+
+fn file_metas(paths: Vec<String>) -> Vec<Result<FileMeta, String>> {
+    parallel_map(paths, |p| {
+        get_file_meta(Path::new(&normalize_dir_input(p))).map_err(|e| e.to_string())
+    })
+}
+
+const CHANGED_SINCE_INDEX_LIMIT: usize = 5000;
+
+#[derive(Debug, serde::Serialize)]
+struct IndexDrift {
+    path: String,
+    // "modified" or "deleted"
+    status: &'static str,
+    indexed_size: u64,
+    indexed_modified: u64,
+    // what the disk holds now; None once deleted
+    size: Option<u64>,
+    modified: Option<u64>,
+}
+
+// Indexed rows, under `within_path` or everywhere, whose file no longer
+// matches the disk: gone, or with another size or mtime. Directories only
+// count when deleted, as their mtime moves with every child. Read-only; at
+// most CHANGED_SINCE_INDEX_LIMIT rows come back, in path order.
+#[tauri::command]
+async fn changed_since_index(
+    app: tauri::AppHandle,
+    within_path: Option<String>,
+) -> Result<Vec<IndexDrift>, Error> {
+    tauri::async_runtime::spawn_blocking(move || {
+        index_drift(&app.state::<DbConnection>(), within_path)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn index_drift(db: &DbConnection, within_path: Option<String>) -> Result<Vec<IndexDrift>, Error> {
+    let rows: Vec<(String, u64, u64, bool)> = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        let (start, end) = match within_path.map(|p| normalize_dir_input(&p)) {
+            Some(dir) => subtree_range(&dir),
+            None => (String::new(), char::MAX.to_string()),
+        };
+        let mut stmt = conn.prepare(&format!(
+            "SELECT path, size, modified, is_dir FROM entries WHERE {} ORDER BY path",
+            dir_in_range("?1", "?2")
+        ))?;
+        let rows = stmt.query_map(rusqlite::params![start, end], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        rows.filter_map(Result::ok).collect()
+    };
+
+    let drift = parallel_map(rows, |(path, indexed_size, indexed_modified, is_dir)| {
+        let (indexed_size, indexed_modified) = (*indexed_size, *indexed_modified);
+        let on_disk = fs::metadata(path).or_else(|_| fs::symlink_metadata(path));
+        let (status, size, modified) = match on_disk {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ("deleted", None, None),
+            Err(_) => return None,
+            Ok(_) if *is_dir => return None,
+            Ok(metadata) => {
+                let (size, modified) = (metadata.len(), metadata_mtime(&metadata));
+                if (size, modified) == (indexed_size, indexed_modified) {
+                    return None;
+                }
+                ("modified", Some(size), Some(modified))
+            }
+        };
+        Some(IndexDrift {
+            path: path.clone(),
+            status,
+            indexed_size,
+            indexed_modified,
+            size,
+            modified,
+        })
+    });
+    Ok(drift.into_iter().flatten().take(CHANGED_SINCE_INDEX_LIMIT).collect())
+}
+
+#[tauri::command]
+fn database_has_files(db: State<DbConnection>) -> Result<bool, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM main_table",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+// Rows already present in this index (by path) are kept as they are.
+// Returns the number of rows added.
+#[tauri::command]
+fn merge_database(
+    db: State<DbConnection>,
+    jump: State<JumpCandidates>,
+    other_path: String,
+) -> Result<u64, Error> {
+    if !Path::new(&other_path).is_file() {
+        return Err(Error::InvalidInput(format!("'{}' is not a database file", other_path)));
+    }
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let merged = merge_database_file(&conn, &other_path);
+    if let Ok(mut candidates) = jump.0.lock() {
+        *candidates = None;
+    }
+    merged
+}
+
+fn merge_database_file(conn: &Connection, other_path: &str) -> Result<u64, Error> {
+    conn.execute("ATTACH DATABASE ?1 AS other", rusqlite::params![other_path])?;
+    let merged = merge_attached(conn);
+    // Detach even when the merge failed so the next attempt can attach again.
+    conn.execute("DETACH DATABASE other", [])?;
+    merged
+}
+
+fn merge_attached(conn: &Connection) -> Result<u64, Error> {
+    let ours = table_columns(conn, "main", "main_table")?;
+    let mut theirs = table_columns(conn, "other", "main_table")?;
+    if theirs.is_empty() {
+        return Err(Error::SchemaMismatch("it has no file table".to_string()));
+    }
+    let mut sorted_ours = ours.clone();
+    sorted_ours.sort();
+    theirs.sort();
+    if sorted_ours != theirs {
+        let missing: Vec<_> = sorted_ours.iter().filter(|c| !theirs.contains(c)).cloned().collect();
+        let extra: Vec<_> = theirs.iter().filter(|c| !sorted_ours.contains(c)).cloned().collect();
+        return Err(Error::SchemaMismatch(format!(
+            "missing columns [{}], unknown columns [{}]; open it with a matching version first",
+            missing.join(", "),
+            extra.join(", ")
+        )));
+    }
+
+    // their rows point into their dirs; each becomes the same prefix in ours
+    conn.execute(
+        "CREATE TEMP TABLE merged_dirs (theirs INTEGER PRIMARY KEY, ours INTEGER NOT NULL)",
+        [],
+    )?;
+    let copied = copy_attached_rows(conn, &ours);
+    conn.execute("DROP TABLE merged_dirs", [])?;
+    let added = copied?;
+    prune_dirs(conn)?;
+    if added > 0 {
+        // Cached sizes are recomputed on their next read.
+        conn.execute("DELETE FROM main.dir_sizes", [])?;
+    }
+    Ok(added as u64)
+}
+
+fn copy_attached_rows(conn: &Connection, columns: &[String]) -> Result<u64, Error> {
+    let mut mapped = conn.prepare("INSERT INTO merged_dirs (theirs, ours) VALUES (?1, ?2)")?;
+    for (theirs, ours) in intern_attached_dirs(conn, "other")? {
+        mapped.execute(rusqlite::params![theirs, ours])?;
+    }
+    let selected: Vec<String> = columns
+        .iter()
+        .map(|c| if c == "dir_id" { "m.ours".to_string() } else { format!("o.{}", c) })
+        .collect();
+    let added = conn.execute(
+        &format!(
+            "INSERT OR IGNORE INTO main.main_table ({}) SELECT {}
+             FROM other.main_table o JOIN merged_dirs m ON m.theirs = o.dir_id",
+            columns.join(", "),
+            selected.join(", ")
+        ),
+        [],
+    )?;
+    Ok(added as u64)
+}
+
+// `throttle` overrides the saved index_throttle setting for this run only.
+// Both cancel_operation and cancel_indexing stop the run.
+#[tauri::command]
+async fn transfer_to_sqlite(
+    app: tauri::AppHandle,
+    window: tauri::Window,
+    path: String,
+    throttle: Option<IndexThrottle>,
+    token: Option<String>,
+) -> Result<Outcome<IndexRunSummary>, Error> {
+    let path = normalize_dir_input(&path);
+    tauri::async_runtime::spawn_blocking(move || {
+        let indexing = app.state::<IndexingState>();
+        let registry = app.state::<TokenRegistry>();
+        let _op = registry.begin_with_flag(&window, token, "index", indexing.cancel.clone())?;
+        let options = IndexRunOptions { throttle, ..IndexRunOptions::default() };
+        let summary = index_root(&app, &path, IndexSource::Manual, options)?;
+        let cancelled = summary.status == "cancelled";
+        Ok(Outcome::new(summary, cancelled))
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+const INDEX_THROTTLE_SETTING: &str = "index_throttle";
+
+#[tauri::command]
+fn get_index_throttle(db: State<DbConnection>) -> Result<IndexThrottle, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    load_index_throttle(&conn)
+}
+
+#[tauri::command]
+fn set_index_throttle(db: State<DbConnection>, throttle: IndexThrottle) -> Result<(), Error> {
+    if throttle.max_entries_per_sec == Some(0) {
+        return Err(Error::InvalidInput("max_entries_per_sec must be positive".to_string()));
+    }
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let value = serde_json::to_string(&throttle).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    set_setting(&conn, INDEX_THROTTLE_SETTING, &value)?;
+    Ok(())
+}
+
+fn load_index_throttle(conn: &Connection) -> Result<IndexThrottle, Error> {
+    match get_setting(conn, INDEX_THROTTLE_SETTING)? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| Error::InvalidInput(e.to_string())),
+        None => Ok(IndexThrottle::default()),
+    }
+}
+
+const IDLE_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Spaces a throttled walk out to the configured rate, and holds it while the
+// tray's pause is on or, for only_when_idle runs, while the user is active.
+// Time lost to a slow disk is not made up with a burst.
+struct Pacer {
+    interval: Option<std::time::Duration>,
+    next_due: std::time::Instant,
+    wait_for_idle: bool,
+    last_idle_check: std::time::Instant,
+    // seconds since the user's last input, and how often to ask again
+    idle_seconds: Box<dyn Fn() -> u64>,
+    idle_poll: std::time::Duration,
+}
+
+impl Pacer {
+    fn new(throttle: &IndexThrottle, wait_for_idle: bool) -> Self {
+        Pacer::with_idle_source(throttle, wait_for_idle, user_idle_seconds, IDLE_CHECK_INTERVAL)
+    }
+
+    fn with_idle_source(
+        throttle: &IndexThrottle,
+        wait_for_idle: bool,
+        idle_seconds: impl Fn() -> u64 + 'static,
+        idle_poll: std::time::Duration,
+    ) -> Self {
+        let now = std::time::Instant::now();
+        Pacer {
+            interval: throttle
+                .max_entries_per_sec
+                .filter(|&rate| rate > 0)
+                .map(|rate| std::time::Duration::from_secs_f64(1.0 / f64::from(rate))),
+            next_due: now,
+            wait_for_idle,
+            last_idle_check: now,
+            idle_seconds: Box::new(idle_seconds),
+            idle_poll,
+        }
+    }
+
+    fn step(&mut self, indexing: &IndexingState, paused: &AtomicBool) {
+        if self.wait_for_idle && self.last_idle_check.elapsed() >= self.idle_poll {
+            self.hold_until_idle(indexing);
+            self.last_idle_check = std::time::Instant::now();
+        }
+        if paused.load(Ordering::SeqCst) {
+            indexing.paused.store(true, Ordering::SeqCst);
+            while paused.load(Ordering::SeqCst) && !indexing.cancel.load(Ordering::SeqCst) {
+                std::thread::sleep(std::time::Duration::from_millis(250));
+            }
+            indexing.paused.store(false, Ordering::SeqCst);
+            self.next_due = std::time::Instant::now();
+        }
+        let Some(interval) = self.interval else { return };
+        let now = std::time::Instant::now();
+        if self.next_due > now {
+            std::thread::sleep(self.next_due - now);
+        } else {
+            self.next_due = now;
+        }
+        self.next_due += interval;
+    }
+
+    // Paused rather than cancelled, so the run picks up where it stopped.
+    fn hold_until_idle(&mut self, indexing: &IndexingState) {
+        if (self.idle_seconds)() >= IDLE_THRESHOLD_SECS {
+            return;
+        }
+        indexing.idle_hold.store(true, Ordering::SeqCst);
+        while (self.idle_seconds)() < IDLE_THRESHOLD_SECS && !indexing.cancel.load(Ordering::SeqCst)
+        {
+            std::thread::sleep(self.idle_poll);
+        }
+        indexing.idle_hold.store(false, Ordering::SeqCst);
+        self.next_due = std::time::Instant::now();
+    }
+}
+
+// Background mode lowers both CPU and I/O priority of the calling thread; the
+// guard ends it again since the thread goes back to a shared pool.
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+struct BackgroundPriority;
+
+impl BackgroundPriority {
+    #[cfg(target_os = "windows")]
+    fn enter() -> Option<Self> {
+        use windows_sys::Win32::System::Threading::{
+            GetCurrentThread, SetThreadPriority, THREAD_MODE_BACKGROUND_BEGIN,
+        };
+        // SAFETY: the pseudo-handle of the current thread needs no closing.
+        let ok = unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_BEGIN) };
+        (ok != 0).then(|| BackgroundPriority)
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    fn enter() -> Option<Self> {
+        None
+    }
+}
+
+impl Drop for BackgroundPriority {
+    fn drop(&mut self) {
+        #[cfg(target_os = "windows")]
+        {
+            use windows_sys::Win32::System::Threading::{
+                GetCurrentThread, SetThreadPriority, THREAD_MODE_BACKGROUND_END,
+            };
+            // SAFETY: as in `enter`.
+            unsafe { SetThreadPriority(GetCurrentThread(), THREAD_MODE_BACKGROUND_END) };
+        }
+    }
+}
+
+#[tauri::command]
+fn is_indexing(indexing: State<IndexingState>) -> Result<IndexingStatus, Error> {
+    indexing.snapshot()
+}
+
+#[tauri::command]
+fn cancel_indexing(indexing: State<IndexingState>) {
+    indexing.cancel.store(true, Ordering::SeqCst);
+}
+
+const INDEX_BATCH_SIZE: usize = 2000;
+const INDEX_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Walks `root` into the index, committing in batches so other commands are not
+// locked out for the whole walk. With `prune`, rows under the root that the
+// walk no longer saw are removed afterwards (skipped when cancelled). A
+// cancelled run still returns its summary, with status "cancelled".
+fn index_root(
+    app: &tauri::AppHandle,
+    root: &str,
+    source: IndexSource,
+    options: IndexRunOptions,
+) -> Result<IndexRunSummary, Error> {
+    let db = app.state::<DbConnection>();
+    let indexing = app.state::<IndexingState>();
+    let paused = &app.state::<BackgroundPaused>().0;
+    index_root_with(&db, &indexing, paused, app, root, source, options)
+}
+
+// What a run reports while it goes and once it ends, failed runs included.
+trait IndexEvents {
+    fn progress(&self, status: &IndexingStatus);
+    fn completed(&self, summary: &IndexRunSummary);
+}
+
+impl IndexEvents for tauri::AppHandle {
+    fn progress(&self, status: &IndexingStatus) {
+        let _ = self.emit_all("index-progress", status);
+    }
+
+    fn completed(&self, summary: &IndexRunSummary) {
+        reload_jump_candidates(self);
+        let _ = self.emit_all("index-completed", summary);
+    }
+}
+
+// index_root with its state passed in rather than taken from the app.
+fn index_root_with(
+    db: &DbConnection,
+    indexing: &IndexingState,
+    paused: &AtomicBool,
+    events: &impl IndexEvents,
+    root: &str,
+    source: IndexSource,
+    options: IndexRunOptions,
+) -> Result<IndexRunSummary, Error> {
+    let prune = options.prune;
+    let _running = indexing.begin(root, source)?;
+
+    let throttle = match options.throttle {
+        Some(throttle) => throttle,
+        None => {
+            let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+            load_index_throttle(&conn)?
+        }
+    };
+    indexing.throttled.store(throttle.is_active(), Ordering::SeqCst);
+    let _priority = if throttle.background_priority {
+        BackgroundPriority::enter()
+    } else {
+        None
+    };
+    let mut pacer = (throttle.is_active() || options.only_when_idle)
+        .then(|| Pacer::new(&throttle, options.only_when_idle));
+
+    let started_at = unix_now();
+    let activity;
+    let run_id = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        let previous: Option<(u64, Option<f64>, u64)> = conn
+            .query_row(
+                "SELECT processed, avg_rate, started_at FROM index_runs
+                 WHERE root = ?1 AND status = 'completed'
+                 ORDER BY id DESC LIMIT 1",
+                rusqlite::params![root],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+        if let Ok(mut progress) = indexing.progress.lock() {
+            *progress = (
+                RateTracker::new(previous.and_then(|(_, rate, _)| rate)),
+                previous.map(|(processed, ..)| processed).filter(|&n| n > 0),
+            );
+        }
+        conn.execute(
+            "INSERT INTO index_runs (root, source, started_at, status) VALUES (?1, ?2, ?3, 'running')",
+            rusqlite::params![root, source.as_str(), started_at],
+        )?;
+        activity = if activity_paused(&conn)? {
+            RunActivity::Off
+        } else {
+            match previous {
+                None => RunActivity::FirstRun {
+                    window_start: started_at.saturating_sub(ACTIVITY_FIRST_RUN_WINDOW_SECS),
+                },
+                Some((.., previous_start)) => {
+                    let debounced = started_at.saturating_sub(ACTIVITY_DEBOUNCE_SECS);
+                    RunActivity::Changes { known_since: previous_start.min(debounced) }
+                }
+            }
+        };
+        conn.last_insert_rowid()
+    };
+    let started = std::time::Instant::now();
+    let mut last_progress_event = started;
+
+    let mut created = 0u64;
+    let mut updated = 0u64;
+    let mut collisions = 0u64;
+    let mut flush = |batch: &mut Vec<FileMeta>| -> Result<(), Error> {
+        let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        let tx = conn.transaction()?;
+        for file_meta in batch.drain(..) {
+            let before = match activity {
+                RunActivity::Changes { .. } => {
+                    indexed_version(&tx, &file_meta.path).unwrap_or_default()
+                }
+                _ => None,
+            };
+            match insert_file_meta(&tx, &file_meta) {
+                Ok(UpsertOutcome::Created) => created += 1,
+                Ok(UpsertOutcome::Updated) => updated += 1,
+                Ok(UpsertOutcome::Replaced { previous_size }) => {
+                    updated += 1;
+                    if let Err(err) =
+                        record_path_collision(&tx, run_id, &file_meta, previous_size, collisions)
+                    {
+                        eprintln!("Collision record failed for {:?}: {:?}", file_meta.path, err);
+                    }
+                    collisions += 1;
+                }
+                Err(err) => eprintln!("DB insert error for {:?}: {:?}", file_meta.path, err),
+            }
+            let event = match activity {
+                RunActivity::Off => None,
+                RunActivity::FirstRun { window_start } => {
+                    first_run_event(&file_meta, window_start).map(|(kind, at)| (kind, at, at))
+                }
+                RunActivity::Changes { known_since } => {
+                    let now = unix_now();
+                    match before {
+                        None => Some((FileEventKind::Created, now, known_since)),
+                        // a directory's mtime moves with every child; the child says more
+                        Some(version)
+                            if !file_meta.is_dir
+                                && version != (file_meta.modified, file_meta.size) =>
+                        {
+                            Some((FileEventKind::Modified, now, known_since))
+                        }
+                        Some(_) => None,
+                    }
+                }
+            };
+            if let Some((kind, at, known_since)) = event {
+                if let Err(err) = record_file_event(&tx, &file_meta.path, kind, at, known_since) {
+                    eprintln!("Activity record failed for {:?}: {:?}", file_meta.path, err);
+                }
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    let mut batch = Vec::with_capacity(INDEX_BATCH_SIZE);
+    let mut cancelled = false;
+    let mut result = ensure_root_reachable(root);
+    let mut walk = index_walk(root);
+    let mut unreadable = Vec::new();
+
+    if result.is_ok() {
+        for entry in &mut walk {
+            if indexing.cancel.load(Ordering::SeqCst) {
+                cancelled = true;
+                break;
+            }
+            match get_file_meta(entry.path()) {
+                Ok(file_meta) => {
+                    if prune {
+                        seen.insert(file_meta.path.clone());
+                    }
+                    batch.push(file_meta);
+                    indexing.processed.fetch_add(1, Ordering::SeqCst);
+                }
+                Err(_) => unreadable.push(entry.into_path()),
+            }
+            if let Some(pacer) = &mut pacer {
+                pacer.step(indexing, paused);
+            }
+            let now = std::time::Instant::now();
+            if let Ok(mut progress) = indexing.progress.lock() {
+                progress.0.record(now, indexing.processed.load(Ordering::SeqCst));
+            }
+            if now.duration_since(last_progress_event) >= INDEX_PROGRESS_INTERVAL {
+                last_progress_event = now;
+                if let Ok(status) = indexing.snapshot() {
+                    events.progress(&status);
+                }
+            }
+            if batch.len() >= INDEX_BATCH_SIZE {
+                result = flush(&mut batch);
+                if result.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    if result.is_ok() {
+        result = flush(&mut batch);
+    }
+    if cancelled && indexing.device_removed.load(Ordering::SeqCst) {
+        result = Err(Error::DeviceRemoved(root.to_string()));
+    }
+    let mut pruned = 0;
+    if result.is_ok() && prune && !cancelled {
+        unreadable.extend(walk.unreadable);
+        result = prune_unseen(db, root, &seen, &unreadable, activity)
+            .map(|count| pruned = count);
+    }
+
+    let processed = indexing.processed.load(Ordering::SeqCst);
+    let status = match (&result, cancelled) {
+        (Err(_), _) => "failed",
+        (Ok(()), true) => "cancelled",
+        (Ok(()), false) => "completed",
+    };
+    {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        recompute_dir_sizes(&conn)?;
+        prune_file_events(&conn)?;
+        refresh_query_stats(&conn, created + pruned >= ANALYZE_THRESHOLD)?;
+        conn.execute(
+            "UPDATE index_runs
+             SET finished_at = ?2, status = ?3, processed = ?4, created = ?5, updated = ?6, error = ?7,
+                 avg_rate = ?8
+             WHERE id = ?1",
+            rusqlite::params![
+                run_id,
+                unix_now(),
+                status,
+                processed,
+                created,
+                updated,
+                result.as_ref().err().map(|e| e.to_string()),
+                processed as f64 / started.elapsed().as_secs_f64().max(1.0)
+            ],
+        )?;
+        if status == "completed" {
+            snapshot_stats(&conn, run_id, root)?;
+        }
+    }
+
+    let summary = IndexRunSummary {
+        run_id,
+        root: root.to_string(),
+        source: source.as_str(),
+        status,
+        processed,
+        created,
+        updated,
+        collisions,
+    };
+    events.completed(&summary);
+
+    result?;
+    Ok(summary)
+}
+
+// An unplugged drive or a deleted folder walks as empty, which a pruning
+// run would take for everything under it having been deleted.
+fn ensure_root_reachable(root: &str) -> Result<(), Error> {
+    match fs::metadata(root) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(Error::Io(std::io::Error::new(
+            e.kind(),
+            format!("root '{}' is not reachable: {}", root, e),
+        ))),
+    }
+}
+
+const INDEX_COLLISION_LIMIT: u64 = 1000;
+
+// The walk put a different file where the index had one, say through a
+// followed link or a remounted volume. The new one is kept; the first
+// INDEX_COLLISION_LIMIT of a run are listed by list_index_collisions.
+fn record_path_collision(
+    conn: &Connection,
+    run_id: i64,
+    file: &FileMeta,
+    previous_size: u64,
+    recorded: u64,
+) -> Result<()> {
+    eprintln!(
+        "Path collision at {:?}: kept {} bytes, replaced {} bytes",
+        file.path, file.size, previous_size
+    );
+    if recorded < INDEX_COLLISION_LIMIT {
+        conn.execute(
+            "INSERT INTO index_collisions (run_id, path, kept_size, skipped_size)
+             VALUES (?1, ?2, ?3, ?4)",
+            rusqlite::params![run_id, file.path, file.size, previous_size],
+        )?;
+    }
+    Ok(())
+}
+
+// Collisions recorded by `run_id`, or by the latest run when none is given.
+#[tauri::command]
+fn list_index_collisions(
+    db: State<DbConnection>,
+    run_id: Option<i64>,
+) -> Result<Vec<PathCollision>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut stmt = conn.prepare(
+        "SELECT run_id, path, kept_size, skipped_size FROM index_collisions
+         WHERE run_id = COALESCE(?1, (SELECT MAX(id) FROM index_runs))
+         ORDER BY path",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![run_id], |row| {
+        Ok(PathCollision {
+            run_id: row.get(0)?,
+            path: row.get(1)?,
+            kept_size: row.get(2)?,
+            skipped_size: row.get(3)?,
+        })
+    })?;
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+// Drops the rows under `root` the walk did not see, except beneath the
+// places it could not read.
+fn prune_unseen(
+    db: &DbConnection,
+    root: &str,
+    seen: &std::collections::HashSet<String>,
+    unreadable: &[std::path::PathBuf],
+    activity: RunActivity,
+) -> Result<u64, Error> {
+    let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let tx = conn.transaction()?;
+    let stale: Vec<(i64, String)> = {
+        let mut stmt = tx.prepare(&format!(
+            "SELECT rowid, path FROM entries WHERE {} OR {}",
+            path_is("?1"),
+            dir_in_range("?2", "?3")
+        ))?;
+        let (start, end) = subtree_range(root);
+        let rows = stmt.query_map(rusqlite::params![root, start, end], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.filter_map(Result::ok)
+            .filter(|(_, path): &(i64, String)| !seen.contains(path))
+            .filter(|(_, path)| !unreadable.iter().any(|dir| Path::new(path).starts_with(dir)))
+            .collect()
+    };
+    for (rowid, path) in &stale {
+        tx.execute("DELETE FROM main_table WHERE rowid = ?1", rusqlite::params![rowid])?;
+        if let RunActivity::Changes { known_since } = activity {
+            record_file_event(&tx, path, FileEventKind::Removed, unix_now(), known_since)?;
+        }
+    }
+    if !stale.is_empty() {
+        prune_dirs(&tx)?;
+    }
+    tx.commit()?;
+    Ok(stale.len() as u64)
+}
+
+const ACTIVITY_PAUSED_SETTING: &str = "activity_paused";
+const ACTIVITY_RETENTION_SETTING: &str = "activity_retention_days";
+const DEFAULT_ACTIVITY_RETENTION_DAYS: u64 = 30;
+// the same change to a path seen again within this window is one event
+const ACTIVITY_DEBOUNCE_SECS: u64 = 60;
+// how far back a root's first run looks for files changed by their own times
+const ACTIVITY_FIRST_RUN_WINDOW_SECS: u64 = 86_400;
+// how long the activity watchers let a burst of notifications gather
+const ACTIVITY_WATCH_SETTLE: std::time::Duration = std::time::Duration::from_secs(2);
+const ACTIVITY_LIMIT: u32 = 500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum FileEventKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+impl FileEventKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            FileEventKind::Created => "created",
+            FileEventKind::Modified => "modified",
+            FileEventKind::Removed => "removed",
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct FileEvent {
+    path: String,
+    kind: String,
+    occurred_at: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ActivityGroup {
+    dir: String,
+    // newest first
+    events: Vec<FileEvent>,
+}
+
+fn activity_paused(conn: &Connection) -> Result<bool> {
+    Ok(get_setting(conn, ACTIVITY_PAUSED_SETTING)?.as_deref() == Some("true"))
+}
+
+// The (modified, size) the index holds for `path`, if it has a row.
+fn indexed_version(conn: &Connection, path: &str) -> Result<Option<(u64, u64)>> {
+    // asked once per walked file, so the statement is kept and the path is
+    // split here rather than by path_is's SQL functions
+    let sql = "SELECT modified, size FROM main_table
+               WHERE dir_id = (SELECT id FROM dir_paths WHERE path = ?1) AND name = ?2";
+    let (prefix, name) = split_path(path);
+    conn.prepare_cached(sql)?
+        .query_row(rusqlite::params![prefix, name], |row| Ok((row.get(0)?, row.get(1)?)))
+        .optional()
+}
+
+// What an index run adds to the activity feed.
+#[derive(Debug, Clone, Copy)]
+enum RunActivity {
+    // recording is paused
+    Off,
+    // A root's first walk finds everything new, which is no activity; only
+    // files whose own times fall after `window_start` are recorded, at those
+    // times, so the feed has today's changes from the start.
+    FirstRun { window_start: u64 },
+    // What changed against the previous run, unless the activity watcher
+    // already recorded the same change to the path since `known_since`.
+    Changes { known_since: u64 },
+}
+
+// A first run's event for `file`: created when its creation time is in the
+// window, else modified when its mtime is; directories have none.
+fn first_run_event(file: &FileMeta, window_start: u64) -> Option<(FileEventKind, u64)> {
+    if file.is_dir {
+        return None;
+    }
+    match file.created {
+        Some(created) if created >= window_start => Some((FileEventKind::Created, created)),
+        _ if file.modified >= window_start => Some((FileEventKind::Modified, file.modified)),
+        _ => None,
+    }
+}
+
+// Index runs and the activity watchers feed the activity feed with what
+// changed on disk; nothing is recorded when the same change to the path is
+// on record from `known_since` on. Paths the walk excludes are as noisy here
+// as they are to it.
+fn record_file_event(
+    conn: &Connection,
+    path: &str,
+    kind: FileEventKind,
+    occurred_at: u64,
+    known_since: u64,
+) -> Result<()> {
+    if is_excluded(path) {
+        return Ok(());
+    }
+    conn.execute(
+        "INSERT INTO file_events (path, kind, occurred_at)
+         SELECT ?1, ?2, ?3 WHERE NOT EXISTS
+            (SELECT 1 FROM file_events WHERE path = ?1 AND kind = ?2 AND occurred_at >= ?4)",
+        rusqlite::params![path, kind.as_str(), occurred_at, known_since],
+    )?;
+    Ok(())
+}
+
+// What a notification from an activity watcher amounts to. A rename is the
+// old path going and the new one arriving; access and metadata-only
+// notifications are no activity.
+fn watched_changes(event: &notify::Event) -> Vec<(String, FileEventKind)> {
+    use notify::event::{ModifyKind, RenameMode};
+    use notify::EventKind;
+
+    let paths = event.paths.iter().map(|p| p.to_string_lossy().to_string());
+    let kind = match event.kind {
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            let kinds = [FileEventKind::Removed, FileEventKind::Created];
+            return paths.zip(kinds).collect();
+        }
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(RenameMode::To)) => {
+            FileEventKind::Created
+        }
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            FileEventKind::Removed
+        }
+        EventKind::Modify(ModifyKind::Metadata(_)) => return Vec::new(),
+        EventKind::Modify(_) => FileEventKind::Modified,
+        _ => return Vec::new(),
+    };
+    paths.map(|path| (path, kind)).collect()
+}
+
+// One settled burst of watcher changes for record_watched_changes, each
+// (path, kind) once.
+#[derive(Default)]
+struct WatchedBatch {
+    changes: Vec<(String, FileEventKind)>,
+    seen: std::collections::HashSet<(String, FileEventKind)>,
+}
+
+impl WatchedBatch {
+    fn push(&mut self, change: (String, FileEventKind)) {
+        if self.seen.insert(change.clone()) {
+            self.changes.push(change);
+        }
+    }
+}
+
+fn record_watched_changes(
+    conn: &mut Connection,
+    changes: &[(String, FileEventKind)],
+) -> Result<(), Error> {
+    if activity_paused(conn)? {
+        return Ok(());
+    }
+    let now = unix_now();
+    let tx = conn.transaction()?;
+    for (path, kind) in changes {
+        // as for index runs, a directory's own mtime says nothing new
+        if *kind == FileEventKind::Modified && Path::new(path).is_dir() {
+            continue;
+        }
+        let known_since = now.saturating_sub(ACTIVITY_DEBOUNCE_SECS);
+        record_file_event(&tx, path, *kind, now, known_since)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+// Watches every root with a completed index run, recursively, and records
+// what changes below them between runs. Watchers are refreshed as roots are
+// added; one thread writes the changes, a settled burst at a time.
+fn spawn_activity_watcher(app: tauri::AppHandle) {
+    let (sender, receiver) = std::sync::mpsc::channel::<(String, FileEventKind)>();
+    let recorder = app.clone();
+    std::thread::spawn(move || {
+        while let Ok(first) = receiver.recv() {
+            let mut batch = WatchedBatch::default();
+            batch.push(first);
+            let settled = std::time::Instant::now() + ACTIVITY_WATCH_SETTLE;
+            while let Some(left) = settled.checked_duration_since(std::time::Instant::now()) {
+                match receiver.recv_timeout(left) {
+                    Ok(change) => batch.push(change),
+                    Err(_) => break,
+                }
+            }
+            if recorder.state::<IndexLock>().0.load(Ordering::SeqCst) {
+                continue;
+            }
+            let db = recorder.state::<DbConnection>();
+            let Ok(mut conn) = db.0.lock() else { continue };
+            if let Err(err) = record_watched_changes(&mut conn, &batch.changes) {
+                eprintln!("Recording watched changes failed: {}", err);
+            }
+        }
+    });
+    // the index's own writes under a watched root are not the user's
+    let index_file = match &*app.state::<DbLocation>() {
+        DbLocation::File(path) => Some(path.to_string_lossy().to_string()),
+        DbLocation::Memory => None,
+    };
+    let sender = ActivitySender { sender, index_file };
+    std::thread::spawn(move || {
+        let mut watchers = std::collections::HashMap::new();
+        loop {
+            if !app.state::<IndexLock>().0.load(Ordering::SeqCst) {
+                if let Err(err) = refresh_activity_watchers(&app, &mut watchers, &sender) {
+                    eprintln!("Watching indexed roots failed: {}", err);
+                }
+            }
+            std::thread::sleep(SCHEDULER_TICK);
+        }
+    });
+}
+
+// Hands watcher changes to the recording thread, minus those to the index
+// file and its journal.
+#[derive(Clone)]
+struct ActivitySender {
+    sender: std::sync::mpsc::Sender<(String, FileEventKind)>,
+    index_file: Option<String>,
+}
+
+impl ActivitySender {
+    fn send(&self, event: &notify::Event) {
+        for change in watched_changes(event) {
+            if self.index_file.as_deref().map_or(false, |own| change.0.starts_with(own)) {
+                continue;
+            }
+            let _ = self.sender.send(change);
+        }
+    }
+}
+
+fn refresh_activity_watchers(
+    app: &tauri::AppHandle,
+    watchers: &mut std::collections::HashMap<String, notify::RecommendedWatcher>,
+    sender: &ActivitySender,
+) -> Result<(), Error> {
+    use notify::Watcher;
+
+    let roots: std::collections::BTreeSet<String> = {
+        let db = app.state::<DbConnection>();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        completed_roots(&conn)?.into_iter().collect()
+    };
+    watchers.retain(|root, _| roots.contains(root));
+    for root in roots {
+        if watchers.contains_key(&root) {
+            continue;
+        }
+        let sender = sender.clone();
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            if let Ok(event) = res {
+                sender.send(&event);
+            }
+        })
+        .map_err(notify_error)?;
+        // a root that is not mounted is picked up again by the next refresh
+        if watcher.watch(Path::new(&root), notify::RecursiveMode::Recursive).is_ok() {
+            watchers.insert(root, watcher);
+        }
+    }
+    Ok(())
+}
+
+fn activity_retention_days(conn: &Connection) -> Result<u64> {
+    Ok(get_setting(conn, ACTIVITY_RETENTION_SETTING)?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_ACTIVITY_RETENTION_DAYS))
+}
+
+fn prune_file_events(conn: &Connection) -> Result<()> {
+    let cutoff = unix_now().saturating_sub(activity_retention_days(conn)? * 86_400);
+    conn.execute("DELETE FROM file_events WHERE occurred_at < ?1", rusqlite::params![cutoff])?;
+    Ok(())
+}
+
+// Changes index runs and the activity watchers found since `since`
+// (default: the last 24 hours), grouped by directory with the most recently
+// active first. `filter` matches anywhere in the path, case-insensitively.
+#[tauri::command]
+fn get_activity(
+    db: State<DbConnection>,
+    since: Option<u64>,
+    filter: Option<String>,
+    limit: Option<u32>,
+) -> Result<Vec<ActivityGroup>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let since = since.unwrap_or_else(|| unix_now().saturating_sub(86_400));
+    let pattern = filter
+        .filter(|f| !f.is_empty())
+        .map(|f| format!("%{}%", escape_like(&f)));
+    let mut stmt = conn.prepare(
+        "SELECT path, kind, occurred_at FROM file_events
+         WHERE occurred_at >= ?1 AND (?2 IS NULL OR path LIKE ?2 ESCAPE '\\')
+         ORDER BY occurred_at DESC, id DESC
+         LIMIT ?3",
+    )?;
+    let rows = stmt.query_map(
+        rusqlite::params![since, pattern, limit.unwrap_or(ACTIVITY_LIMIT).min(ACTIVITY_LIMIT)],
+        |row| {
+            Ok(FileEvent {
+                path: row.get(0)?,
+                kind: row.get(1)?,
+                occurred_at: row.get(2)?,
+            })
+        },
+    )?;
+
+    let mut groups: Vec<ActivityGroup> = Vec::new();
+    let mut positions = std::collections::HashMap::new();
+    for event in rows.filter_map(Result::ok) {
+        let dir = parent_dir(&event.path).unwrap_or_else(|| event.path.clone());
+        let at = *positions.entry(dir.clone()).or_insert_with(|| {
+            groups.push(ActivityGroup { dir, events: Vec::new() });
+            groups.len() - 1
+        });
+        groups[at].events.push(event);
+    }
+    Ok(groups)
+}
+
+// Flips activity recording and returns whether it is now paused. Events
+// already recorded stay until retention ages them out.
+#[tauri::command]
+fn pause_activity_recording(db: State<DbConnection>) -> Result<bool, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let paused = !activity_paused(&conn)?;
+    set_setting(&conn, ACTIVITY_PAUSED_SETTING, &paused.to_string())?;
+    Ok(paused)
+}
+
+#[tauri::command]
+fn get_activity_retention(db: State<DbConnection>) -> Result<u64, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(activity_retention_days(&conn)?)
+}
+
+#[tauri::command]
+fn set_activity_retention(db: State<DbConnection>, days: u64) -> Result<(), Error> {
+    if days == 0 {
+        return Err(Error::InvalidInput("retention must be at least one day".to_string()));
+    }
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    set_setting(&conn, ACTIVITY_RETENTION_SETTING, &days.to_string())?;
+    prune_file_events(&conn)?;
+    Ok(())
+}
+
+const STATS_RETENTION_SETTING: &str = "stats_retention_days";
+const DEFAULT_STATS_RETENTION_DAYS: u64 = 365;
+
+#[derive(Debug, Default, Clone, Copy, serde::Serialize)]
+struct StatsTotals {
+    bytes: u64,
+    files: u64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct StatsPoint {
+    run_id: i64,
+    taken_at: u64,
+    status: String,
+    // None for runs that did not complete: a gap in the series, not a zero
+    total: Option<StatsTotals>,
+    // by directory directly under the root; "." for files in the root itself
+    dirs: std::collections::BTreeMap<String, StatsTotals>,
+    categories: std::collections::BTreeMap<String, StatsTotals>,
+}
+
+// Files under `root` folded into their FileKinds categories.
+fn category_totals(
+    conn: &Connection,
+    root: &str,
+) -> Result<std::collections::BTreeMap<String, StatsTotals>, Error> {
+    let (start, end) = subtree_range(root);
+    let kinds = FileKinds::load(conn)?;
+    let mut categories: std::collections::BTreeMap<String, StatsTotals> = Default::default();
+    let mut stmt = conn.prepare(&format!(
+        "SELECT lower(extension), SUM(size), COUNT(*) FROM main_table
+         WHERE is_dir = 0 AND {}
+         GROUP BY 1",
+        dir_in_range("?1", "?2")
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![start, end], |row| {
+        Ok((row.get::<_, Option<String>>(0)?, row.get::<_, u64>(1)?, row.get::<_, u64>(2)?))
+    })?;
+    for (extension, bytes, files) in rows.filter_map(Result::ok) {
+        let category = kinds.resolve(extension.as_deref(), false).category;
+        let totals = categories.entry(category).or_default();
+        totals.bytes += bytes;
+        totals.files += files;
+    }
+    Ok(categories)
+}
+
+fn stats_retention_days(conn: &Connection) -> Result<u64, Error> {
+    Ok(get_setting(conn, STATS_RETENTION_SETTING)?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_STATS_RETENTION_DAYS))
+}
+
+// Aggregates of `root` after a completed run, one row per top-level
+// directory and per file category, for get_stats_timeline.
+fn snapshot_stats(conn: &Connection, run_id: i64, root: &str) -> Result<(), Error> {
+    let prefix = subtree_prefix(root);
+    let (start, end) = subtree_range(root);
+    let mut dirs: std::collections::BTreeMap<String, StatsTotals> = Default::default();
+    {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT CASE WHEN instr(rest, ?4) > 0 THEN substr(rest, 1, instr(rest, ?4) - 1) ELSE '.' END,
+                    SUM(size), COUNT(*)
+             FROM (SELECT substr(path, length(?1) + 1) AS rest, size FROM entries
+                   WHERE is_dir = 0 AND {})
+             GROUP BY 1",
+            dir_in_range("?2", "?3")
+        ))?;
+        let rows = stmt.query_map(
+            rusqlite::params![prefix, start, end, std::path::MAIN_SEPARATOR.to_string()],
+            |row| Ok((row.get::<_, String>(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+        for (dir, bytes, files) in rows.filter_map(Result::ok) {
+            dirs.insert(dir, StatsTotals { bytes, files });
+        }
+    }
+    let categories = category_totals(conn, root)?;
+
+    let taken_at = unix_now();
+    let mut insert = conn.prepare(
+        "INSERT INTO stats_snapshots (run_id, root, taken_at, dimension, key, bytes, files)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    for (dimension, totals) in [("dir", &dirs), ("category", &categories)] {
+        for (key, t) in totals {
+            insert.execute(rusqlite::params![run_id, root, taken_at, dimension, key, t.bytes, t.files])?;
+        }
+    }
+    let days = stats_retention_days(conn)?;
+    conn.execute(
+        "DELETE FROM stats_snapshots WHERE taken_at < ?1",
+        rusqlite::params![taken_at.saturating_sub(days * 86_400)],
+    )?;
+    Ok(())
+}
+
+// One point per finished run of `root` since `since`, oldest first. Runs
+// that failed or were cancelled stay in the series with no totals, so a chart
+// can break the line there instead of drawing through them.
+#[tauri::command]
+fn get_stats_timeline(
+    db: State<DbConnection>,
+    root: String,
+    since: Option<u64>,
+) -> Result<Vec<StatsPoint>, Error> {
+    let root = normalize_dir_input(&root);
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut runs = conn.prepare(
+        "SELECT id, COALESCE(finished_at, started_at), status FROM index_runs
+         WHERE root = ?1 AND started_at >= ?2 AND status != 'running'
+         ORDER BY id",
+    )?;
+    let runs: Vec<(i64, u64, String)> = runs
+        .query_map(rusqlite::params![root, since.unwrap_or(0)], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?
+        .filter_map(Result::ok)
+        .collect();
+    let mut snapshot = conn.prepare(
+        "SELECT dimension, key, bytes, files FROM stats_snapshots WHERE run_id = ?1",
+    )?;
+    let mut points = Vec::with_capacity(runs.len());
+    for (run_id, taken_at, status) in runs {
+        let mut point = StatsPoint {
+            run_id,
+            taken_at,
+            status,
+            total: None,
+            dirs: Default::default(),
+            categories: Default::default(),
+        };
+        let rows = snapshot.query_map(rusqlite::params![run_id], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get(2)?, row.get(3)?))
+        })?;
+        for (dimension, key, bytes, files) in rows.filter_map(Result::ok) {
+            let totals = StatsTotals { bytes, files };
+            if dimension == "dir" {
+                let total = point.total.get_or_insert_with(StatsTotals::default);
+                total.bytes += bytes;
+                total.files += files;
+                point.dirs.insert(key, totals);
+            } else {
+                point.categories.insert(key, totals);
+            }
+        }
+        // a completed run whose snapshot aged out carries nothing to chart
+        if point.status == "completed" && point.total.is_none() && point.categories.is_empty() {
+            continue;
+        }
+        points.push(point);
+    }
+    Ok(points)
+}
+
+#[tauri::command]
+fn get_stats_retention(db: State<DbConnection>) -> Result<u64, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    stats_retention_days(&conn)
+}
+
+#[tauri::command]
+fn set_stats_retention(db: State<DbConnection>, days: u64) -> Result<(), Error> {
+    if days == 0 {
+        return Err(Error::InvalidInput("retention must be at least one day".to_string()));
+    }
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    set_setting(&conn, STATS_RETENTION_SETTING, &days.to_string())?;
+    Ok(())
+}
+
+// How much of `root` the index knows about. The disk count walks the same way
+// indexing does, so skipped folders don't read as missing; rows for files
+// since deleted can push `indexed` past `on_disk`, but the percentage stops
+// at 100.
+#[tauri::command]
+async fn coverage(app: tauri::AppHandle, root: String) -> Result<Coverage, Error> {
+    let root = normalize_dir_input(&root);
+    if !Path::new(&root).is_dir() {
+        return Err(Error::InvalidInput(format!("'{}' is not a directory", root)));
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let on_disk = index_walk(&root).count() as u64;
+        let db = app.state::<DbConnection>();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        coverage_of(&conn, &root, on_disk)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn coverage_of(conn: &Connection, root: &str, on_disk: u64) -> Result<Coverage, Error> {
+    let (start, end) = subtree_range(root);
+    let indexed: u64 = conn.query_row(
+        &format!(
+            "SELECT COUNT(*) FROM main_table WHERE {} OR {}",
+            path_is("?1"),
+            dir_in_range("?2", "?3")
+        ),
+        rusqlite::params![root, start, end],
+        |row| row.get(0),
+    )?;
+    let coverage_pct = if on_disk == 0 {
+        100.0
+    } else {
+        (indexed as f64 * 100.0 / on_disk as f64).min(100.0)
+    };
+    Ok(Coverage { on_disk, indexed, coverage_pct })
+}
+
+// Rows created or pruned in one run past which the planner statistics are
+// rebuilt outright; smaller runs leave it to PRAGMA optimize.
+const ANALYZE_THRESHOLD: u64 = 10_000;
+
+// Without statistics SQLite guesses at index selectivity and picks poor plans
+// for the combined search filters until something runs ANALYZE.
+fn refresh_query_stats(conn: &Connection, full: bool) -> Result<()> {
+    if full {
+        conn.execute_batch("ANALYZE")
+    } else {
+        conn.execute_batch("PRAGMA optimize")
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct IndexInfo {
+    name: String,
+    table: String,
+    columns: Vec<String>,
+    // pages the index occupies, times the page size
+    size_bytes: u64,
+}
+
+// The database file's path, or "memory" in ephemeral mode.
+#[tauri::command]
+fn get_db_location(location: State<DbLocation>) -> String {
+    match &*location {
+        DbLocation::File(path) => path.to_string_lossy().to_string(),
+        DbLocation::Memory => "memory".to_string(),
+    }
+}
+
+// The connection setup starts with, and whether it is an encrypted file still
+// waiting for unlock_index; otherwise the schema is already in place.
+fn open_index(location: &DbLocation) -> Result<(Connection, bool), Error> {
+    let conn = match location {
+        DbLocation::Memory => Connection::open_in_memory()?,
+        DbLocation::File(path) => Connection::open(path)?,
+    };
+    let locked = matches!(location, DbLocation::File(path) if is_encrypted_database(path));
+    if !locked {
+        open_schema(&conn)?;
+    }
+    Ok((conn, locked))
+}
+
+// Set while an encrypted index waits for its passphrase; every command but
+// LOCKED_INDEX_COMMANDS is rejected with Error::Locked until then.
+struct IndexLock(AtomicBool);
+
+const LOCKED_INDEX_COMMANDS: &[&str] =
+    &["index_locked", "unlock_index", "get_db_location", "get_index_health"];
+
+// SQLCipher encrypts the header too, so a file that does not start with the
+// plain SQLite one is taken for an encrypted index. A new, empty file is not.
+fn is_encrypted_database(path: &Path) -> bool {
+    use std::io::Read;
+
+    let mut header = [0u8; 16];
+    match fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+        Ok(()) => &header != b"SQLite format 3\0",
+        Err(_) => false,
+    }
+}
+
+#[tauri::command]
+fn index_locked(lock: State<IndexLock>) -> bool {
+    lock.0.load(Ordering::SeqCst)
+}
+
+// A no-op when the index is not locked.
+#[tauri::command]
+fn unlock_index(
+    app: tauri::AppHandle,
+    db: State<DbConnection>,
+    location: State<DbLocation>,
+    lock: State<IndexLock>,
+    passphrase: String,
+) -> Result<(), Error> {
+    let DbLocation::File(path) = &*location else { return Ok(()) };
+    if !lock.0.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    unlock_with(&db, &lock, &app.state::<ListingCache>(), path, &passphrase)?;
+    spawn_index_health_report(app);
+    Ok(())
+}
+
+// A wrong passphrase leaves the index locked and the file untouched.
+fn unlock_with(
+    db: &DbConnection,
+    lock: &IndexLock,
+    cache: &ListingCache,
+    path: &Path,
+    passphrase: &str,
+) -> Result<(), Error> {
+    let conn = open_keyed(path, Some(passphrase))?;
+    open_schema(&conn)?;
+    watch_connection(&conn, cache)?;
+    *db.0.lock().map_err(|_| Error::MutexPoison)? = conn;
+    lock.0.store(false, Ordering::SeqCst);
+    Ok(())
+}
+
+// Whether the invoke handler turns `command` away with Error::Locked.
+fn refused_while_locked(lock: &IndexLock, command: &str) -> bool {
+    lock.0.load(Ordering::SeqCst) && !LOCKED_INDEX_COMMANDS.contains(&command)
+}
+
+// SQLCipher only notices a wrong key on first access, so the key is proven by
+// reading the schema before the connection is handed out.
+#[cfg(feature = "encrypted-index")]
+fn open_keyed(path: &Path, passphrase: Option<&str>) -> Result<Connection, Error> {
+    let conn = Connection::open(path)?;
+    if let Some(passphrase) = passphrase {
+        conn.pragma_update(None, "key", passphrase)?;
+    }
+    conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get::<_, i64>(0))
+        .map_err(|_| Error::WrongPassphrase)?;
+    Ok(conn)
+}
+
+#[cfg(not(feature = "encrypted-index"))]
+fn open_keyed(_path: &Path, _passphrase: Option<&str>) -> Result<Connection, Error> {
+    Err(Error::Unsupported("encrypted index (built without encrypted-index)"))
+}
+
+// Sets, changes or, with no `new`, removes the index passphrase. `old` must
+// match while the index is encrypted.
+#[cfg(feature = "encrypted-index")]
+#[tauri::command]
+fn set_index_passphrase(
+    app: tauri::AppHandle,
+    db: State<DbConnection>,
+    location: State<DbLocation>,
+    old: Option<String>,
+    new: Option<String>,
+) -> Result<(), Error> {
+    let DbLocation::File(path) = &*location else {
+        return Err(Error::Ephemeral("a passphrase"));
+    };
+    change_passphrase(&db, &app.state::<ListingCache>(), path, old, new)
+}
+
+#[cfg(feature = "encrypted-index")]
+fn change_passphrase(
+    db: &DbConnection,
+    cache: &ListingCache,
+    path: &Path,
+    old: Option<String>,
+    new: Option<String>,
+) -> Result<(), Error> {
+    let new = new.filter(|p| !p.is_empty());
+    let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let old = if is_encrypted_database(path) {
+        // only proves `old`; the connection is closed again straight away
+        let old = old.unwrap_or_default();
+        open_keyed(path, Some(&old))?;
+        Some(old)
+    } else {
+        None
+    };
+    match (old.as_deref(), new.as_deref()) {
+        (None, None) => Ok(()),
+        (Some(_), Some(new)) => conn.pragma_update(None, "rekey", new).map_err(Into::into),
+        // SQLCipher cannot rekey to or from plaintext, so those export a copy
+        (old, new) => export_rekeyed(cache, &mut conn, path, old, new),
+    }
+}
+
+#[cfg(not(feature = "encrypted-index"))]
+#[tauri::command]
+fn set_index_passphrase() -> Result<(), Error> {
+    Err(Error::Unsupported("encrypted index (built without encrypted-index)"))
+}
+
+// Writes the whole index to a sibling file under the new key, swaps it in and
+// reopens. Should the swap fail, the old file is reopened with the old key.
+#[cfg(feature = "encrypted-index")]
+fn export_rekeyed(
+    cache: &ListingCache,
+    conn: &mut Connection,
+    path: &Path,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> Result<(), Error> {
+    let export = path.with_extension("sqlite3.rekey");
+    if export.exists() {
+        fs::remove_file(&export)?;
+    }
+    conn.execute(
+        "ATTACH DATABASE ?1 AS rekeyed KEY ?2",
+        rusqlite::params![export.to_string_lossy(), new.unwrap_or("")],
+    )?;
+    let exported = conn.query_row("SELECT sqlcipher_export('rekeyed')", [], |_| Ok(()));
+    conn.execute("DETACH DATABASE rekeyed", [])?;
+    exported?;
+
+    // Windows will not replace a file that is still open.
+    drop(std::mem::replace(conn, Connection::open_in_memory()?));
+    let swapped = fs::rename(&export, path);
+    let reopened = open_keyed(path, if swapped.is_ok() { new } else { old })?;
+    open_schema(&reopened)?;
+    watch_connection(&reopened, cache)?;
+    *conn = reopened;
+    swapped.map_err(Into::into)
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct IntegrityReport {
+    // what PRAGMA integrity_check found; empty when the file is sound. These
+    // are reported only, never repaired here.
+    database_errors: Vec<String>,
+    // rows whose parent is missing or not a directory though something above
+    // it is indexed; fixing drops them with everything beneath, for the next
+    // run to add back where they belong
+    orphaned_rows: Vec<String>,
+    // cached sizes for directories with nothing indexed beneath them
+    orphaned_dir_sizes: Vec<String>,
+    stale_dir_sizes: Vec<DirSizeMismatch>,
+    repaired: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DirSizeMismatch {
+    path: String,
+    cached: u64,
+    actual: u64,
+}
+
+// Checks that every row hangs off an indexed directory, compares the cached
+// directory sizes against sums recomputed from main_table, and checks the
+// database file itself. With `fix`, orphaned rows are dropped first, then
+// orphaned sizes dropped and stale ones corrected, each in one transaction.
+#[tauri::command]
+fn check_index_integrity(db: State<DbConnection>, fix: bool) -> Result<IntegrityReport, Error> {
+    let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    integrity_report(&mut conn, fix)
+}
+
+fn integrity_report(conn: &mut Connection, fix: bool) -> Result<IntegrityReport, Error> {
+    let mut report = IntegrityReport::default();
+    {
+        let mut stmt = conn.prepare("PRAGMA integrity_check")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        report.database_errors = rows.filter_map(Result::ok).filter(|r| r != "ok").collect();
+    }
+
+    report.orphaned_rows = orphaned_rows(conn)?;
+    if fix && !report.orphaned_rows.is_empty() {
+        let tx = conn.transaction()?;
+        for path in &report.orphaned_rows {
+            let (start, end) = subtree_range(path);
+            tx.execute(
+                &format!(
+                    "DELETE FROM main_table WHERE {} OR {}",
+                    path_is("?1"),
+                    dir_in_range("?2", "?3")
+                ),
+                rusqlite::params![path, start, end],
+            )?;
+        }
+        prune_dirs(&tx)?;
+        tx.commit()?;
+        report.repaired = true;
+    }
+
+    let actual_sizes = summed_dir_sizes(conn)?;
+    let cached_sizes: Vec<(String, u64, bool)> = {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT d.path, d.size, EXISTS (SELECT 1 FROM main_table WHERE {} AND is_dir = 1)
+             FROM dir_sizes d",
+            path_is("d.path")
+        ))?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        rows.filter_map(Result::ok).collect()
+    };
+    for (path, cached, indexed) in cached_sizes {
+        match actual_sizes.get(&path) {
+            Some(&actual) if actual != cached => {
+                report.stale_dir_sizes.push(DirSizeMismatch { path, cached, actual })
+            }
+            Some(_) => {}
+            // an indexed directory without files really is empty
+            None if indexed && cached == 0 => {}
+            None if indexed => {
+                report.stale_dir_sizes.push(DirSizeMismatch { path, cached, actual: 0 })
+            }
+            None => report.orphaned_dir_sizes.push(path),
+        }
+    }
+
+    if fix && !(report.orphaned_dir_sizes.is_empty() && report.stale_dir_sizes.is_empty()) {
+        let tx = conn.transaction()?;
+        for path in &report.orphaned_dir_sizes {
+            tx.execute("DELETE FROM dir_sizes WHERE path = ?1", rusqlite::params![path])?;
+        }
+        for mismatch in &report.stale_dir_sizes {
+            tx.execute(
+                "UPDATE dir_sizes SET size = ?2 WHERE path = ?1",
+                rusqlite::params![mismatch.path, mismatch.actual],
+            )?;
+        }
+        tx.commit()?;
+        report.repaired = true;
+    }
+    Ok(report)
+}
+
+// A root row has nothing indexed above it and is fine; a row below an
+// indexed directory whose own parent went missing, or is a file, is not.
+// The parent's row is found from the row's directory: a root's own row has
+// the root's prefix and no name, any other's is its parent prefix and its
+// segment without the separator.
+fn orphaned_rows(conn: &Connection) -> Result<Vec<String>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT c.path FROM entries c JOIN dirs d ON d.id = c.dir_id
+         WHERE c.name <> '' AND NOT EXISTS (
+             SELECT 1 FROM main_table p
+             WHERE p.is_dir = 1 AND CASE WHEN d.parent_id = 0
+                 THEN p.dir_id = d.id AND p.name = ''
+                 ELSE p.dir_id = d.parent_id AND p.name = substr(d.name, 1, length(d.name) - 1)
+             END
+         )
+         ORDER BY c.path",
+    )?;
+    let unparented: Vec<String> =
+        stmt.query_map([], |row| row.get(0))?.filter_map(Result::ok).collect();
+    let mut indexed = conn.prepare(&format!(
+        "SELECT EXISTS(SELECT 1 FROM main_table WHERE {})",
+        path_is("?1")
+    ))?;
+    let mut orphaned = Vec::new();
+    for path in unparented {
+        let mut above = Path::new(&path).ancestors().skip(1);
+        let has_indexed_ancestor = above.any(|ancestor| {
+            indexed
+                .query_row(rusqlite::params![ancestor.to_string_lossy()], |row| row.get(0))
+                .unwrap_or(false)
+        });
+        // beneath another orphan, it goes when that one does
+        let under_orphan = orphaned.iter().any(|o: &String| path.starts_with(&subtree_prefix(o)));
+        if has_indexed_ancestor && !under_orphan {
+            orphaned.push(path);
+        }
+    }
+    Ok(orphaned)
+}
+
+// Rebuilds the planner statistics and lists the indexes with their sizes.
+#[tauri::command]
+fn optimize_database(db: State<DbConnection>) -> Result<Vec<IndexInfo>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    refresh_query_stats(&conn, true)?;
+    let mut stmt = conn.prepare(
+        "SELECT m.name, m.tbl_name, COALESCE(SUM(s.pgsize), 0)
+         FROM sqlite_master m LEFT JOIN dbstat s ON s.name = m.name
+         WHERE m.type = 'index'
+         GROUP BY m.name
+         ORDER BY m.tbl_name, m.name",
+    )?;
+    let indexes: Vec<(String, String, u64)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .filter_map(Result::ok)
+        .collect();
+    indexes
+        .into_iter()
+        .map(|(name, table, size_bytes)| {
+            let columns = index_columns(&conn, &name)?;
+            Ok(IndexInfo { name, table, columns, size_bytes })
+        })
+        .collect()
+}
+
+fn index_columns(conn: &Connection, index: &str) -> Result<Vec<String>> {
+    let mut info = conn.prepare("SELECT name FROM pragma_index_info(?1) ORDER BY seqno")?;
+    let columns = info
+        .query_map(rusqlite::params![index], |row| row.get(0))?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(columns)
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DbIndex {
+    name: String,
+    table: String,
+    columns: Vec<String>,
+    unique: bool,
+    // "c" for CREATE INDEX, "u" for a UNIQUE constraint, "pk" for a primary key
+    origin: String,
+}
+
+// Read-only view of every index on every table, for checking that
+// create_indexes ran. Unlike optimize_database it touches nothing.
+#[tauri::command]
+fn list_db_indexes(db: State<DbConnection>) -> Result<Vec<DbIndex>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    db_indexes(&conn)
+}
+
+fn db_indexes(conn: &Connection) -> Result<Vec<DbIndex>, Error> {
+    let mut tables = conn.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%'
+         ORDER BY name",
+    )?;
+    let tables: Vec<String> =
+        tables.query_map([], |row| row.get(0))?.filter_map(Result::ok).collect();
+    let mut indexes = Vec::new();
+    for table in tables {
+        let mut list = conn.prepare("SELECT name, \"unique\", origin FROM pragma_index_list(?1)")?;
+        let listed: Vec<(String, bool, String)> = list
+            .query_map(rusqlite::params![table], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .filter_map(Result::ok)
+            .collect();
+        for (name, unique, origin) in listed {
+            let columns = index_columns(conn, &name)?;
+            indexes.push(DbIndex { name, table: table.clone(), columns, unique, origin });
+        }
+    }
+    indexes.sort_by(|a, b| (&a.table, &a.name).cmp(&(&b.table, &b.name)));
+    Ok(indexes)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+const SCHEDULES_SETTING: &str = "index_schedules";
+const SCHEDULER_TICK: std::time::Duration = std::time::Duration::from_secs(60);
+const IDLE_THRESHOLD_SECS: u64 = 10 * 60;
+
+#[tauri::command]
+fn get_schedules(db: State<DbConnection>) -> Result<Vec<Schedule>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let configs = load_schedules(&conn)?;
+    let mut schedules = Vec::new();
+    for (root, config) in configs {
+        let last_run = last_index_run(&conn, &root)?;
+        schedules.push(Schedule {
+            next_run: Some(last_run.unwrap_or(0) + u64::from(config.interval_hours) * 3600),
+            root,
+            config,
+            last_run,
+        });
+    }
+    Ok(schedules)
+}
+
+// Passing no config removes the schedule for `root`.
+#[tauri::command]
+fn set_schedule(
+    db: State<DbConnection>,
+    root: String,
+    config: Option<ScheduleConfig>,
+) -> Result<(), Error> {
+    let root = normalize_dir_input(&root);
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut schedules = load_schedules(&conn)?;
+    match config {
+        Some(config) if config.interval_hours == 0 => {
+            return Err(Error::InvalidInput("interval_hours must be at least 1".to_string()))
+        }
+        Some(config) => {
+            schedules.insert(root, config);
+        }
+        None => {
+            schedules.remove(&root);
+        }
+    }
+    let value = serde_json::to_string(&schedules).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    set_setting(&conn, SCHEDULES_SETTING, &value)?;
+    Ok(())
+}
+
+fn load_schedules(
+    conn: &Connection,
+) -> Result<std::collections::BTreeMap<String, ScheduleConfig>, Error> {
+    match get_setting(conn, SCHEDULES_SETTING)? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| Error::InvalidInput(e.to_string())),
+        None => Ok(Default::default()),
+    }
+}
+
+fn last_index_run(conn: &Connection, root: &str) -> Result<Option<u64>> {
+    conn.query_row(
+        "SELECT MAX(started_at) FROM index_runs WHERE root = ?1 AND status = 'completed'",
+        rusqlite::params![root],
+        |row| row.get(0),
+    )
+}
+
+// Checks once a minute for schedules that are due. Never starts while another
+// index run (manual or scheduled) holds the IndexingState.
+fn spawn_scheduler(app: tauri::AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SCHEDULER_TICK);
+        if app.state::<BackgroundPaused>().0.load(Ordering::SeqCst) {
+            continue;
+        }
+
+        let due: DueSchedules = {
+            let db = app.state::<DbConnection>();
+            let Ok(conn) = db.0.lock() else { continue };
+            let Ok(schedules) = load_schedules(&conn) else { continue };
+            let now = unix_now();
+            schedules
+                .into_iter()
+                .filter(|(root, config)| {
+                    let last = last_index_run(&conn, root).ok().flatten().unwrap_or(0);
+                    now >= last + u64::from(config.interval_hours) * 3600
+                })
+                .collect()
+        };
+
+        let indexing = app.state::<IndexingState>();
+        let (ready, deferred) = split_for_idle(due, user_idle_seconds());
+        indexing.deferred_for_idle.store(!deferred.is_empty(), Ordering::SeqCst);
+
+        for (root, config) in ready {
+            if indexing.is_running() {
+                break;
+            }
+            let options = IndexRunOptions {
+                prune: true,
+                only_when_idle: config.only_when_idle,
+                ..IndexRunOptions::default()
+            };
+            if let Err(err) = index_root(&app, &root, IndexSource::Scheduled, options) {
+                eprintln!("Scheduled index of {} failed: {}", root, err);
+            }
+        }
+
+        // a run half way through would look inconsistent
+        if indexing.is_running() {
+            continue;
+        }
+        let checked = {
+            let db = app.state::<DbConnection>();
+            let Ok(mut conn) = db.0.lock() else { continue };
+            due_integrity_check(&mut conn, unix_now())
+        };
+        match checked {
+            Ok(Some(report)) => {
+                let _ = app.emit_all("index-integrity", &report);
+            }
+            Ok(None) => {}
+            Err(err) => eprintln!("Scheduled integrity check failed: {}", err),
+        }
+    });
+}
+
+const INTEGRITY_SCHEDULE_SETTING: &str = "integrity_schedule";
+// unix seconds of the last scheduled check
+const INTEGRITY_LAST_RUN_SETTING: &str = "integrity_last_run";
+
+// The maintenance schedule for check_index_integrity; its reports go out as
+// "index-integrity" events.
+#[derive(Debug, Clone, Copy, serde::Serialize, serde::Deserialize)]
+struct IntegritySchedule {
+    interval_hours: u32,
+    #[serde(default)]
+    fix: bool,
+}
+
+#[tauri::command]
+fn get_integrity_schedule(db: State<DbConnection>) -> Result<Option<IntegritySchedule>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    load_integrity_schedule(&conn)
+}
+
+// Passing no schedule stops the scheduled checks.
+#[tauri::command]
+fn set_integrity_schedule(
+    db: State<DbConnection>,
+    schedule: Option<IntegritySchedule>,
+) -> Result<(), Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    save_integrity_schedule(&conn, schedule)
+}
+
+fn save_integrity_schedule(
+    conn: &Connection,
+    schedule: Option<IntegritySchedule>,
+) -> Result<(), Error> {
+    match schedule {
+        Some(schedule) if schedule.interval_hours == 0 => {
+            Err(Error::InvalidInput("interval_hours must be at least 1".to_string()))
+        }
+        Some(schedule) => {
+            let value =
+                serde_json::to_string(&schedule).map_err(|e| Error::InvalidInput(e.to_string()))?;
+            set_setting(conn, INTEGRITY_SCHEDULE_SETTING, &value).map_err(Into::into)
+        }
+        None => {
+            conn.execute(
+                "DELETE FROM settings WHERE key = ?1",
+                rusqlite::params![INTEGRITY_SCHEDULE_SETTING],
+            )?;
+            Ok(())
+        }
+    }
+}
+
+fn load_integrity_schedule(conn: &Connection) -> Result<Option<IntegritySchedule>, Error> {
+    match get_setting(conn, INTEGRITY_SCHEDULE_SETTING)? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| Error::InvalidInput(e.to_string())),
+        None => Ok(None),
+    }
+}
+
+// Runs the scheduled check when its interval has passed since the last one.
+fn due_integrity_check(conn: &mut Connection, now: u64) -> Result<Option<IntegrityReport>, Error> {
+    let Some(schedule) = load_integrity_schedule(conn)? else { return Ok(None) };
+    let last: u64 = get_setting(conn, INTEGRITY_LAST_RUN_SETTING)?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    if now < last + u64::from(schedule.interval_hours) * 3600 {
+        return Ok(None);
+    }
+    set_setting(conn, INTEGRITY_LAST_RUN_SETTING, &now.to_string())?;
+    integrity_report(conn, schedule.fix).map(Some)
+}
+
+type DueSchedules = Vec<(String, ScheduleConfig)>;
+
+// Due schedules that may start now, and the only_when_idle ones that wait
+// for the user to be away.
+fn split_for_idle(due: DueSchedules, idle_seconds: u64) -> (DueSchedules, DueSchedules) {
+    let idle = idle_seconds >= IDLE_THRESHOLD_SECS;
+    due.into_iter().partition(|(_, config)| idle || !config.only_when_idle)
+}
+
+#[cfg(target_os = "windows")]
+fn user_idle_seconds() -> u64 {
+    use windows_sys::Win32::System::SystemInformation::GetTickCount;
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{GetLastInputInfo, LASTINPUTINFO};
+
+    let mut info = LASTINPUTINFO {
+        cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+        dwTime: 0,
+    };
+    // SAFETY: `info` is a properly sized LASTINPUTINFO.
+    if unsafe { GetLastInputInfo(&mut info) } == 0 {
+        return 0;
+    }
+    let now = unsafe { GetTickCount() };
+    u64::from(now.wrapping_sub(info.dwTime)) / 1000
+}
+
+// Without a system-wide input clock, time since the frontend last invoked a
+// command stands in for it.
+#[cfg(not(target_os = "windows"))]
+fn user_idle_seconds() -> u64 {
+    unix_now().saturating_sub(LAST_COMMAND_AT.load(Ordering::SeqCst))
+}
+
+static LAST_COMMAND_AT: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+fn note_command_invoked() {
+    LAST_COMMAND_AT.store(unix_now(), Ordering::SeqCst);
+}
+
+#[tauri::command]
+fn get_idle_seconds() -> u64 {
+    user_idle_seconds()
+}
+
+// The index only knows main-stream sizes; `include_streams` measures the
+// directory on disk instead, adding every alternate data stream.
+//
+// `accurate` counts hard-linked files once instead of once per link. It is
+// slower: from the index it needs a GROUP BY over every multiply-linked file,
+// and on disk it has to look up each file's identity (an extra open per file
+// on Windows). Blocks shared by deduplication or reflinks are still counted
+// per file, as no cheap API exposes them.
+#[tauri::command]
+async fn get_directory_size(
+    db: State<'_, DbConnection>,
+    path: String,
+    include_streams: Option<bool>,
+    accurate: Option<bool>,
+) -> Result<u64, Error> {
+    let path = normalize_dir_input(&path);
+    let accurate = accurate.unwrap_or(false);
+    if include_streams.unwrap_or(false) {
+        return tauri::async_runtime::spawn_blocking(move || {
+            size_with_streams(Path::new(&path), accurate)
+        })
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?;
+    }
+
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let below = dir_in_range("?1", "?2");
+    let mut stmt = conn.prepare(&if accurate {
+        format!(
+            "SELECT COALESCE(SUM(size), 0) FROM (
+                SELECT size FROM main_table
+                WHERE is_dir = 0 AND {0}
+                  AND (file_id IS NULL OR COALESCE(link_count, 1) <= 1)
+                UNION ALL
+                SELECT MAX(size) FROM main_table
+                WHERE is_dir = 0 AND {0}
+                  AND file_id IS NOT NULL AND link_count > 1
+                GROUP BY volume_id, file_id
+             )",
+            below
+        )
+    } else {
+        format!("SELECT COALESCE(SUM(size), 0) FROM main_table WHERE is_dir = 0 AND {}", below)
+    })?;
+
+    let (start, end) = subtree_range(&path);
+    let size: u64 = stmt.query_row(rusqlite::params![start, end], |row| row.get(0))?;
+    Ok(size)
+}
+
+// Everything below `dir` from the index in one pass; `dir` itself is not
+// counted.
+#[tauri::command]
+fn subtree_stats(db: State<DbConnection>, dir: String) -> Result<SubtreeStats, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    stats_below(&conn, &normalize_dir_input(&dir))
+}
+
+fn stats_below(conn: &Connection, dir: &str) -> Result<SubtreeStats, Error> {
+    let (start, end) = subtree_range(dir);
+    let stats = conn.query_row(
+        &format!(
+            "SELECT COALESCE(SUM(is_dir = 0), 0),
+                    COALESCE(SUM(is_dir = 1), 0),
+                    COALESCE(SUM(CASE WHEN is_dir = 0 THEN size ELSE 0 END), 0)
+             FROM main_table
+             WHERE {}",
+            dir_in_range("?1", "?2")
+        ),
+        rusqlite::params![start, end],
+        |row| {
+            Ok(SubtreeStats {
+                file_count: row.get(0)?,
+                dir_count: row.get(1)?,
+                total_size: row.get(2)?,
+            })
+        },
+    )?;
+    Ok(stats)
+}
+
+// Other indexed paths pointing at the same file as `path`.
+#[tauri::command]
+fn find_hard_links(db: State<DbConnection>, path: String) -> Result<Vec<FileMeta>, Error> {
+    let path = normalize_dir_input(&path);
+    let key = match fs::metadata(&path) {
+        Ok(metadata) => file_identity(Path::new(&path), &metadata).map(|(_, key)| key),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+        Err(e) => return Err(Error::Io(e)),
+    };
+    let Some(key) = key else { return Ok(Vec::new()) };
+
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM entries WHERE volume_id = ?1 AND file_id = ?2 AND path != ?3",
+        FILE_META_COLUMNS
+    ))?;
+    let links = stmt
+        .query_map(rusqlite::params![key.volume, key.index, path], file_meta_from_row)?
+        .filter_map(Result::ok)
+        .collect();
+    Ok(links)
+}
+
+#[tauri::command]
+fn get_cached_directory_size(db: State<DbConnection>, dir: String) -> Result<u64, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    cached_directory_size(&conn, &normalize_dir_input(&dir)).map_err(Into::into)
+}
+
+fn cached_directory_size(conn: &Connection, dir: &str) -> Result<u64> {
+    let cached: Option<u64> = conn
+        .query_row(
+            "SELECT size FROM dir_sizes WHERE path = ?1",
+            rusqlite::params![dir],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(size) = cached {
+        return Ok(size);
+    }
+
+    // Invalidated (or never computed): sum once and keep the result.
+    let (start, end) = subtree_range(dir);
+    let size: u64 = conn.query_row(
+        &format!(
+            "SELECT COALESCE(SUM(size), 0) FROM main_table WHERE is_dir = 0 AND {}",
+            dir_in_range("?1", "?2")
+        ),
+        rusqlite::params![start, end],
+        |row| row.get(0),
+    )?;
+    conn.execute(
+        "INSERT OR REPLACE INTO dir_sizes (path, size) VALUES (?1, ?2)",
+        rusqlite::params![dir, size],
+    )?;
+    Ok(size)
+}
+
+// The indexed size of `dir` leaving out files with the given extensions
+// ("tmp" and ".TMP" alike). Bypasses dir_sizes, which only holds full totals.
+#[tauri::command]
+fn directory_size_excluding(
+    db: State<DbConnection>,
+    dir: String,
+    exclude_exts: Vec<String>,
+) -> Result<u64, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    size_excluding(&conn, &normalize_dir_input(&dir), &exclude_exts)
+}
+
+fn size_excluding(conn: &Connection, dir: &str, exclude_exts: &[String]) -> Result<u64, Error> {
+    use rusqlite::types::Value;
+
+    let excluded: std::collections::BTreeSet<String> = exclude_exts
+        .iter()
+        .map(|e| e.trim_start_matches('.').to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect();
+    let mut sql = format!(
+        "SELECT COALESCE(SUM(size), 0) FROM main_table WHERE is_dir = 0 AND {}",
+        dir_in_range("?", "?")
+    );
+    let (start, end) = subtree_range(dir);
+    let mut params = vec![Value::Text(start), Value::Text(end)];
+    if !excluded.is_empty() {
+        sql.push_str(&format!(
+            " AND (extension IS NULL OR lower(extension) NOT IN ({}))",
+            vec!["?"; excluded.len()].join(", ")
+        ));
+        params.extend(excluded.into_iter().map(Value::Text));
+    }
+    let size = conn.query_row(&sql, rusqlite::params_from_iter(params), |row| row.get(0))?;
+    Ok(size)
+}
+
+#[derive(Debug, serde::Serialize)]
+struct CategoryShare {
+    category: String,
+    count: u64,
+    size: u64,
+    size_pct: f64,
+}
+
+// What `dir` is made of by file category, largest first. Percentages are of
+// the subtree's total file size and are all zero for an empty subtree.
+#[tauri::command]
+fn type_composition(db: State<DbConnection>, dir: String) -> Result<Vec<CategoryShare>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    composition(&conn, &normalize_dir_input(&dir))
+}
+
+fn composition(conn: &Connection, dir: &str) -> Result<Vec<CategoryShare>, Error> {
+    let categories = category_totals(conn, dir)?;
+    let total: u64 = categories.values().map(|t| t.bytes).sum();
+    let mut shares: Vec<CategoryShare> = categories
+        .into_iter()
+        .map(|(category, t)| CategoryShare {
+            category,
+            count: t.files,
+            size: t.bytes,
+            size_pct: if total == 0 {
+                0.0
+            } else {
+                t.bytes as f64 * 100.0 / total as f64
+            },
+        })
+        .collect();
+    shares.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.category.cmp(&b.category)));
+    Ok(shares)
+}
+
+// Renders the indexed subtree like the `tree` utility, e.g.
+//
+// C:\Projects
+// ├── app
+// │   └── main.rs
+// └── notes.txt
+#[tauri::command]
+fn export_tree_text(
+    db: State<DbConnection>,
+    root: String,
+    max_depth: u32,
+    annotate_sizes: Option<bool>,
+) -> Result<String, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let root = normalize_dir_input(&root);
+    tree_text(&conn, &root, max_depth, annotate_sizes.unwrap_or(false))
+}
+
+fn tree_text(conn: &Connection, root: &str, max_depth: u32, annotate: bool) -> Result<String, Error> {
+    let (start, end) = subtree_range(root);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+         FROM entries
+         WHERE {}
+         AND path_depth(path) <= ?3",
+        FILE_META_COLUMNS,
+        dir_in_range("?1", "?2")
+    ))?;
+    let rows = stmt.query_map(
+        rusqlite::params![start, end, path_depth(root) as i64 + i64::from(max_depth)],
+        file_meta_from_row,
+    )?;
+
+    let mut children: std::collections::HashMap<String, Vec<FileMeta>> =
+        std::collections::HashMap::new();
+    for file in rows.filter_map(Result::ok) {
+        let parent = Path::new(&file.path)
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        children.entry(parent).or_default().push(file);
+    }
+    for siblings in children.values_mut() {
+        siblings.sort_by_key(|f| f.name.to_lowercase());
+    }
+
+    let mut out = root.to_string();
+    if annotate {
+        out.push_str(&format!(" ({})", human_size(cached_directory_size(conn, root)?)));
+    }
+    out.push('\n');
+    render_tree(conn, &children, root, "", annotate, &mut out)?;
+    Ok(out)
+}
+
+fn render_tree(
+    conn: &Connection,
+    children: &std::collections::HashMap<String, Vec<FileMeta>>,
+    dir: &str,
+    indent: &str,
+    annotate: bool,
+    out: &mut String,
+) -> Result<()> {
+    let Some(entries) = children.get(dir) else { return Ok(()) };
+    for (i, entry) in entries.iter().enumerate() {
+        let last = i + 1 == entries.len();
+        out.push_str(indent);
+        out.push_str(if last { "└── " } else { "├── " });
+        out.push_str(&entry.name);
+        if annotate {
+            let size = if entry.is_dir {
+                cached_directory_size(conn, &entry.path)?
+            } else {
+                entry.size
+            };
+            out.push_str(&format!(" ({})", human_size(size)));
+        }
+        out.push('\n');
+        if entry.is_dir {
+            let child_indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+            render_tree(conn, children, &entry.path, &child_indent, annotate, out)?;
+        }
+    }
+    Ok(())
+}
+
+// Nodes written before the rest is summarised as "N more items".
+const DEFAULT_REPORT_NODE_CAP: usize = 20_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ReportFormat {
+    Html,
+    Markdown,
+    Text,
+}
+
+impl std::str::FromStr for ReportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(ReportFormat::Html),
+            "md" => Ok(ReportFormat::Markdown),
+            "txt" => Ok(ReportFormat::Text),
+            other => Err(Error::InvalidInput(format!("unknown report format '{}'", other))),
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct TreeReportSummary {
+    dest: String,
+    nodes: usize,
+    // true when the node cap cut the tree short
+    truncated: bool,
+}
+
+// Writes the indexed tree under `root` to `dest` as a single HTML page, a
+// Markdown list or `tree`-style text. Directories carry their total size
+// from dir_sizes and files their size and modification date. Files smaller
+// than `min_file_size` are left out; past `max_nodes` each directory's
+// remaining entries collapse into one "N more items" line.
+#[tauri::command]
+async fn export_tree_report(
+    app: tauri::AppHandle,
+    root: String,
+    max_depth: u32,
+    format: String,
+    dest: String,
+    min_file_size: Option<u64>,
+    max_nodes: Option<usize>,
+) -> Result<TreeReportSummary, Error> {
+    let format: ReportFormat = format.parse()?;
+    let (root, dest) = (normalize_dir_input(&root), normalize_dir_input(&dest));
+    tauri::async_runtime::spawn_blocking(move || {
+        use std::io::Write;
+
+        let db = app.state::<DbConnection>();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        ensure_writable(&conn, &[&dest])?;
+
+        let children = report_children(&conn, &root, max_depth, min_file_size.unwrap_or(0))?;
+        let file = std::io::BufWriter::new(fs::File::create(&dest)?);
+        let mut report = TreeReport {
+            conn: &conn,
+            children: &children,
+            format,
+            out: file,
+            budget: max_nodes.unwrap_or(DEFAULT_REPORT_NODE_CAP),
+            nodes: 0,
+            truncated: false,
+        };
+        report.write(&root)?;
+        report.out.flush()?;
+        Ok(TreeReportSummary {
+            dest,
+            nodes: report.nodes,
+            truncated: report.truncated,
+        })
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// Each directory's entries for a tree report, folders first.
+fn report_children(
+    conn: &Connection,
+    root: &str,
+    max_depth: u32,
+    min_file_size: u64,
+) -> Result<std::collections::HashMap<String, Vec<FileMeta>>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+         FROM entries
+         WHERE {}
+         AND path_depth(path) <= ?3
+         AND (is_dir = 1 OR size >= ?4)",
+        FILE_META_COLUMNS,
+        dir_in_range("?1", "?2")
+    ))?;
+    let (start, end) = subtree_range(root);
+    let rows = stmt.query_map(
+        rusqlite::params![
+            start,
+            end,
+            path_depth(root) as i64 + i64::from(max_depth),
+            min_file_size
+        ],
+        file_meta_from_row,
+    )?;
+    let mut children: std::collections::HashMap<String, Vec<FileMeta>> =
+        std::collections::HashMap::new();
+    for file in rows.filter_map(Result::ok) {
+        if let Some(parent) = parent_dir(&file.path) {
+            children.entry(parent).or_default().push(file);
+        }
+    }
+    for siblings in children.values_mut() {
+        // folders first, like the listing
+        siblings.sort_by_key(|f| (!f.is_dir, f.name.to_lowercase()));
+    }
+    Ok(children)
+}
+
+struct TreeReport<'a, W: std::io::Write> {
+    conn: &'a Connection,
+    children: &'a std::collections::HashMap<String, Vec<FileMeta>>,
+    format: ReportFormat,
+    out: W,
+    budget: usize,
+    nodes: usize,
+    truncated: bool,
+}
+
+impl<W: std::io::Write> TreeReport<'_, W> {
+    fn write(&mut self, root: &str) -> Result<(), Error> {
+        let total = human_size(cached_directory_size(self.conn, root)?);
+        let generated = format_timestamp(unix_now(), "%Y-%m-%d %H:%M")?;
+        match self.format {
+            ReportFormat::Html => write!(
+                self.out,
+                "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{root}</title>\n\
+                 <style>body{{font:14px system-ui,sans-serif;margin:2em}}\
+                 ul{{list-style:none;padding-left:1.2em;margin:0}}\
+                 summary{{cursor:pointer;font-weight:600}}\
+                 .meta{{color:#777}}.more{{color:#777;font-style:italic}}</style>\n\
+                 </head><body>\n<h1>{root}</h1>\n\
+                 <p class=\"meta\">{total}, generated {generated}</p>\n",
+                root = html_escape(root),
+                total = total,
+                generated = generated
+            )?,
+            ReportFormat::Markdown => write!(
+                self.out,
+                "# {}\n\n{}, generated {}\n\n",
+                markdown_escape(root),
+                total,
+                generated
+            )?,
+            ReportFormat::Text => writeln!(self.out, "{} ({})", root, total)?,
+        }
+        self.write_level(root, 0, "")?;
+        if self.format == ReportFormat::Html {
+            writeln!(self.out, "</body></html>")?;
+        }
+        Ok(())
+    }
+
+    fn write_level(&mut self, dir: &str, depth: usize, indent: &str) -> Result<(), Error> {
+        let children = self.children;
+        let Some(entries) = children.get(dir) else { return Ok(()) };
+        if self.format == ReportFormat::Html {
+            writeln!(self.out, "<ul>")?;
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let last = i + 1 == entries.len();
+            if self.budget == 0 {
+                self.truncated = true;
+                let more = format!("{} more items", entries.len() - i);
+                match self.format {
+                    ReportFormat::Html => writeln!(self.out, "<li class=\"more\">{}</li>", more)?,
+                    ReportFormat::Markdown => {
+                        writeln!(self.out, "{}- *{}*", "  ".repeat(depth), more)?
+                    }
+                    ReportFormat::Text => writeln!(self.out, "{}└── … {}", indent, more)?,
+                }
+                break;
+            }
+            self.budget -= 1;
+            self.nodes += 1;
+
+            let meta = if entry.is_dir {
+                human_size(cached_directory_size(self.conn, &entry.path)?)
+            } else {
+                format!(
+                    "{}, {}",
+                    human_size(entry.size),
+                    format_timestamp(entry.modified, "%Y-%m-%d")?
+                )
+            };
+            match self.format {
+                ReportFormat::Html if entry.is_dir => write!(
+                    self.out,
+                    "<li><details open><summary>{} <span class=\"meta\">({})</span></summary>",
+                    html_escape(&entry.name),
+                    meta
+                )?,
+                ReportFormat::Html => writeln!(
+                    self.out,
+                    "<li>{} <span class=\"meta\">({})</span></li>",
+                    html_escape(&entry.name),
+                    meta
+                )?,
+                ReportFormat::Markdown => {
+                    let name = markdown_escape(&entry.name);
+                    let name = if entry.is_dir { format!("**{}/**", name) } else { name };
+                    writeln!(self.out, "{}- {} ({})", "  ".repeat(depth), name, meta)?
+                }
+                ReportFormat::Text => writeln!(
+                    self.out,
+                    "{}{}{} ({})",
+                    indent,
+                    if last { "└── " } else { "├── " },
+                    entry.name,
+                    meta
+                )?,
+            }
+            if entry.is_dir {
+                let child_indent = format!("{}{}", indent, if last { "    " } else { "│   " });
+                self.write_level(&entry.path, depth + 1, &child_indent)?;
+                if self.format == ReportFormat::Html {
+                    writeln!(self.out, "</details></li>")?;
+                }
+            }
+        }
+        if self.format == ReportFormat::Html {
+            writeln!(self.out, "</ul>")?;
+        }
+        Ok(())
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Backslash-escapes what Markdown would otherwise read as formatting.
+fn markdown_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Same units as formatFileSize in the frontend.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["Bytes", "KB", "MB", "GB", "TB"];
+    if bytes == 0 {
+        return "0 Bytes".to_string();
+    }
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    let rounded = format!("{:.2}", value);
+    let trimmed = rounded.trim_end_matches('0').trim_end_matches('.');
+    format!("{} {}", trimmed, UNITS[unit])
+}
+
+// Buckets files per local calendar day of their creation time (falling back
+// to mtime where the filesystem kept none), or of their mtime when
+// `basis` is "modified".
+#[tauri::command]
+fn creation_histogram(
+    db: State<DbConnection>,
+    within_path: Option<String>,
+    basis: Option<String>,
+) -> Result<Vec<DayBucket>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    day_histogram(&conn, within_path.as_deref(), basis.as_deref().unwrap_or("created"))
+}
+
+fn day_histogram(
+    conn: &Connection,
+    within_path: Option<&str>,
+    basis: &str,
+) -> Result<Vec<DayBucket>, Error> {
+    let timestamp = match basis {
+        "created" => "COALESCE(created, modified)",
+        "modified" => "modified",
+        other => return Err(Error::InvalidInput(format!("unknown histogram basis '{}'", other))),
+    };
+    let within_path = within_path.map(normalize_dir_input);
+    let (start, end) = optional_subtree_range(within_path.as_deref());
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT date({}, 'unixepoch', 'localtime') AS day, COUNT(*), COALESCE(SUM(size), 0)
+         FROM main_table
+         WHERE is_dir = 0
+         AND (?1 IS NULL OR {})
+         GROUP BY day
+         ORDER BY day",
+        timestamp,
+        dir_in_range("?1", "?2")
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![start, end], |row| {
+        Ok(DayBucket {
+            day: row.get(0)?,
+            count: row.get(1)?,
+            total_size: row.get(2)?,
+        })
+    })?;
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+#[tauri::command]
+fn find_broken_symlinks(db: State<DbConnection>) -> Result<Vec<BrokenSymlink>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    broken_symlinks(&conn)
+}
+
+fn broken_symlinks(conn: &Connection) -> Result<Vec<BrokenSymlink>, Error> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM entries WHERE is_symlink = 1",
+        FILE_META_COLUMNS
+    ))?;
+    let links = stmt
+        .query_map([], file_meta_from_row)?
+        .filter_map(Result::ok)
+        .collect::<Vec<_>>();
+
+    Ok(links
+        .into_iter()
+        // fs::metadata follows the link, so it fails exactly when the target is gone.
+        .filter(|link| fs::metadata(&link.path).is_err())
+        .map(|link| BrokenSymlink {
+            target: fs::read_link(&link.path)
+                .ok()
+                .map(|t| t.to_string_lossy().to_string()),
+            link,
+        })
+        .collect())
+}
+
+// Names differing only in case are distinct on Linux but collide when the
+// folder is synced to Windows or macOS. SQLite's lower() folds ASCII only,
+// which covers the usual README/Readme cases.
+#[tauri::command]
+fn find_case_collisions(
+    db: State<DbConnection>,
+    within_path: Option<String>,
+) -> Result<Vec<CaseCollision>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    case_collisions(&conn, within_path.as_deref())
+}
+
+fn case_collisions(
+    conn: &Connection,
+    within_path: Option<&str>,
+) -> Result<Vec<CaseCollision>, Error> {
+    let within_path = within_path.map(normalize_dir_input);
+    let (start, end) = optional_subtree_range(within_path.as_deref());
+    let mut stmt = conn.prepare(&format!(
+        "WITH keyed AS (
+            SELECT *, lower(dir_path) AS dir_key, lower(name) AS name_key
+            FROM entries
+            WHERE ?1 IS NULL OR {}
+         )
+         SELECT {}, dir_key, name_key FROM keyed
+         WHERE (dir_key, name_key) IN (
+            SELECT dir_key, name_key FROM keyed
+            GROUP BY dir_key, name_key HAVING COUNT(*) > 1
+         )
+         ORDER BY dir_key, name_key, path",
+        dir_in_range("?1", "?2"),
+        FILE_META_COLUMNS
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![start, end], |row| {
+        let key: (String, String) = (
+            row.get(FILE_META_COLUMN_COUNT)?,
+            row.get(FILE_META_COLUMN_COUNT + 1)?,
+        );
+        Ok((key, file_meta_from_row(row)?))
+    })?;
+
+    let mut groups: Vec<CaseCollision> = Vec::new();
+    let mut last_key = None;
+    for (key, file) in rows.filter_map(Result::ok) {
+        if last_key.as_ref() != Some(&key) {
+            let directory = Path::new(&file.path)
+                .parent()
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_default();
+            groups.push(CaseCollision { directory, entries: Vec::new() });
+            last_key = Some(key);
+        }
+        if let Some(group) = groups.last_mut() {
+            group.entries.push(file);
+        }
+    }
+    Ok(groups)
+}
+
+#[tauri::command]
+fn stale_large_files(
+    db: State<DbConnection>,
+    min_size: u64,
+    min_age_days: u64,
+    root: Option<String>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+) -> Result<StaleFilesReport, Error> {
+    let root = root.as_deref().map(normalize_dir_input);
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let access_times_reliable = last_access_updates_enabled(root.as_deref().unwrap_or("C:\\"));
+
+    let now = std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let cutoff = now.saturating_sub(min_age_days.saturating_mul(86_400));
+
+    let last_touched = if access_times_reliable {
+        "MAX(modified, COALESCE(accessed, modified))"
+    } else {
+        "modified"
+    };
+    let (start, end) = optional_subtree_range(root.as_deref());
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+         FROM entries
+         WHERE size >= ?1
+         AND {} <= ?2
+         AND (?3 IS NULL OR {})
+         ORDER BY size DESC
+         LIMIT ?5 OFFSET ?6",
+        FILE_META_COLUMNS,
+        last_touched,
+        dir_in_range("?3", "?4")
+    ))?;
+    let rows = stmt.query_map(
+        rusqlite::params![
+            min_size,
+            cutoff,
+            start,
+            end,
+            limit.unwrap_or(100),
+            offset.unwrap_or(0)
+        ],
+        file_meta_from_row,
+    )?;
+
+    Ok(StaleFilesReport {
+        files: rows.filter_map(Result::ok).collect(),
+        access_times_reliable,
+    })
+}
+
+// NTFS stops updating last-access times when NtfsDisableLastAccessUpdate has
+// its low bit set (the default on many Windows installs).
+#[cfg(target_os = "windows")]
+fn last_access_updates_enabled(_root: &str) -> bool {
+    use winreg::enums::HKEY_LOCAL_MACHINE;
+
+    winreg::RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey("SYSTEM\\CurrentControlSet\\Control\\FileSystem")
+        .and_then(|key| key.get_value::<u32, _>("NtfsDisableLastAccessUpdate"))
+        .map(|value| value & 1 == 0)
+        .unwrap_or(true)
+}
+
+#[cfg(target_os = "linux")]
+fn last_access_updates_enabled(root: &str) -> bool {
+    let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mount_point = fields.nth(1)?;
+            let options = fields.nth(1)?;
+            Some((mount_point, options))
+        })
+        .filter(|(mount_point, _)| root.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .map(|(_, options)| !options.split(',').any(|o| o == "noatime"))
+        .unwrap_or(true)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn last_access_updates_enabled(_root: &str) -> bool {
+    true
+}
+
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "bmp", "tif", "tiff", "webp"];
+
+#[tauri::command]
+async fn find_similar_images(
+    window: tauri::Window,
+    root: String,
+    threshold: u32,
+    token: Option<String>,
+) -> Result<Outcome<SimilarImagesReport>, Error> {
+    let root = normalize_dir_input(&root);
+    tauri::async_runtime::spawn_blocking(move || {
+        find_similar_images_blocking(&window, &root, threshold, token)
+    })
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// A cancelled run still clusters the images it got to.
+fn find_similar_images_blocking(
+    window: &tauri::Window,
+    root: &str,
+    threshold: u32,
+    token: Option<String>,
+) -> Result<Outcome<SimilarImagesReport>, Error> {
+    use img_hash::{HashAlg, HasherConfig, ImageHash};
+
+    let registry = window.state::<TokenRegistry>();
+    let op = registry.begin(window, token, "find_similar_images")?;
+    let db = window.state::<DbConnection>();
+
+    // Only image extensions ever reach the decoder.
+    let in_list = IMAGE_EXTENSIONS
+        .iter()
+        .map(|e| format!("'{}'", e))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let candidates = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {}, phash, phash_mtime
+             FROM entries
+             WHERE is_dir = 0
+             AND lower(extension) IN ({})
+             AND {}",
+            FILE_META_COLUMNS,
+            in_list,
+            dir_in_range("?1", "?2")
+        ))?;
+        let (start, end) = subtree_range(root);
+        let rows = stmt.query_map(rusqlite::params![start, end], |row| {
+            Ok((
+                file_meta_from_row(row)?,
+                row.get::<_, Option<String>>(FILE_META_COLUMN_COUNT)?,
+                row.get::<_, Option<u64>>(FILE_META_COLUMN_COUNT + 1)?,
+            ))
+        })?;
+        rows.filter_map(Result::ok).collect::<Vec<_>>()
+    };
+
+    let hasher = HasherConfig::new().hash_alg(HashAlg::Gradient).to_hasher();
+    let total = candidates.len() as u64;
+    let mut hashed: Vec<(FileMeta, ImageHash)> = Vec::new();
+    let mut fresh: Vec<(String, String, u64)> = Vec::new();
+    let mut skipped = Vec::new();
+    let mut cancelled = false;
+
+    for (index, (file, cached, cached_mtime)) in candidates.into_iter().enumerate() {
+        if op.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+
+        let reusable = cached
+            .filter(|_| cached_mtime == Some(file.modified))
+            .and_then(|encoded| ImageHash::from_base64(&encoded).ok());
+        match reusable {
+            Some(hash) => hashed.push((file, hash)),
+            None => match img_hash::image::open(&file.path) {
+                Ok(image) => {
+                    let hash = hasher.hash_image(&image);
+                    fresh.push((file.path.clone(), hash.to_base64(), file.modified));
+                    hashed.push((file, hash));
+                }
+                Err(e) => skipped.push(SkippedImage {
+                    path: file.path,
+                    reason: e.to_string(),
+                }),
+            },
+        }
+
+        let processed = index as u64 + 1;
+        if processed % 25 == 0 || processed == total {
+            let _ = window.emit("similar-images-progress", ProgressPayload { processed, total });
+        }
+    }
+
+    // Keep whatever was decoded, even on cancellation, so the next run is faster.
+    {
+        let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        let tx = conn.transaction()?;
+        for (path, hash, mtime) in &fresh {
+            tx.execute(
+                &format!(
+                    "UPDATE main_table SET phash = ?2, phash_mtime = ?3 WHERE {}",
+                    path_is("?1")
+                ),
+                rusqlite::params![path, hash, mtime],
+            )?;
+        }
+        tx.commit()?;
+    }
+
+    // Union-find over every pair under the threshold.
+    let mut parent: Vec<usize> = (0..hashed.len()).collect();
+    fn find(parent: &mut [usize], i: usize) -> usize {
+        let mut root = i;
+        while parent[root] != root {
+            root = parent[root];
+        }
+        parent[i] = root;
+        root
+    }
+    for i in 0..hashed.len() {
+        for j in (i + 1)..hashed.len() {
+            if hashed[i].1.dist(&hashed[j].1) <= threshold {
+                let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+                if a != b {
+                    parent[b] = a;
+                }
+            }
+        }
+    }
+
+    let mut groups: std::collections::HashMap<usize, Vec<usize>> = std::collections::HashMap::new();
+    for i in 0..hashed.len() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+
+    let mut slots: Vec<Option<(FileMeta, ImageHash)>> = hashed.into_iter().map(Some).collect();
+    let mut clusters = Vec::new();
+    for members in groups.into_values().filter(|m| m.len() > 1) {
+        let first_hash = slots[members[0]].as_ref().map(|(_, h)| h.clone());
+        let mut cluster = Vec::new();
+        for i in members {
+            if let (Some((file, hash)), Some(first)) = (slots[i].take(), first_hash.as_ref()) {
+                cluster.push(SimilarImage {
+                    distance: first.dist(&hash),
+                    file,
+                });
+            }
+        }
+        clusters.push(cluster);
+    }
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.len()));
+
+    Ok(Outcome::new(SimilarImagesReport { clusters, skipped }, cancelled))
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DuplicateDirGroup {
+    dirs: Vec<String>,
+    // size and file count of each copy
+    bytes: u64,
+    files: u64,
+    wasted_bytes: u64,
+    // None unless a sample was verified against the disk
+    verified: Option<bool>,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct NearDuplicateDirs {
+    larger: String,
+    smaller: String,
+    shared_bytes: u64,
+    // shared bytes as a share of the larger directory
+    similarity_pct: f64,
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DuplicateDirsReport {
+    groups: Vec<DuplicateDirGroup>,
+    near_duplicates: Vec<NearDuplicateDirs>,
+}
+
+struct DirNode {
+    path: String,
+    name: String,
+    parent: Option<usize>,
+    // (lowercased name, original name, size)
+    files: Vec<(String, String, u64)>,
+    subdirs: Vec<usize>,
+    bytes: u64,
+    file_count: u64,
+    signature: [u8; 32],
+}
+
+// Tuples shared by more directories than this (desktop.ini and the like)
+// do not make two directories near-duplicate candidates.
+const NEAR_DUPLICATE_FANOUT: usize = 32;
+const NEAR_DUPLICATE_LIMIT: usize = 200;
+
+// Finds directory trees under `root` whose contents match, from the index
+// alone. Each directory's signature hashes its files' (name, size) and its
+// subdirectories' (name, signature), bottom-up, so equal signatures mean
+// equal trees by name and size; the index keeps no content hashes to add.
+// Groups are reported largest waste first, leaving out ones that only exist
+// because their parents are duplicates too. With `near_duplicates` set to a
+// percentage, pairs sharing at least that much of the larger directory are
+// reported as well. `verify_sample` hashes up to that many files of each
+// group on disk and compares them across copies.
+#[tauri::command]
+async fn find_duplicate_directories(
+    app: tauri::AppHandle,
+    root: String,
+    min_size: u64,
+    near_duplicates: Option<f64>,
+    verify_sample: Option<u32>,
+) -> Result<DuplicateDirsReport, Error> {
+    let root = normalize_dir_input(&root);
+    tauri::async_runtime::spawn_blocking(move || {
+        let rows: Vec<(String, bool, u64)> = {
+            let db = app.state::<DbConnection>();
+            let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+            let (start, end) = subtree_range(&root);
+            let mut stmt = conn.prepare(&format!(
+                "SELECT path, is_dir, size FROM entries WHERE {} ORDER BY path",
+                dir_in_range("?1", "?2")
+            ))?;
+            let rows = stmt.query_map(rusqlite::params![start, end], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })?;
+            rows.filter_map(Result::ok).collect()
+        };
+        let nodes = build_dir_nodes(rows);
+        let groups = duplicate_dir_groups(&nodes, min_size, verify_sample);
+        let near_duplicates = match near_duplicates {
+            Some(min_pct) => near_duplicate_dirs(&nodes, min_size, min_pct),
+            None => Vec::new(),
+        };
+        Ok(DuplicateDirsReport { groups, near_duplicates })
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// Rows come sorted by path, so every directory precedes its contents and the
+// reverse order visits children before their parents.
+fn build_dir_nodes(rows: Vec<(String, bool, u64)>) -> Vec<DirNode> {
+    use sha2::Digest;
+
+    let mut nodes: Vec<DirNode> = Vec::new();
+    let mut by_path: std::collections::HashMap<String, usize> = std::collections::HashMap::new();
+    for (path, is_dir, size) in rows {
+        let parent = parent_dir(&path).and_then(|p| by_path.get(&p).copied());
+        let name = Path::new(&path)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if is_dir {
+            let index = nodes.len();
+            if let Some(parent) = parent {
+                nodes[parent].subdirs.push(index);
+            }
+            by_path.insert(path.clone(), index);
+            nodes.push(DirNode {
+                path,
+                name,
+                parent,
+                files: Vec::new(),
+                subdirs: Vec::new(),
+                bytes: 0,
+                file_count: 0,
+                signature: [0; 32],
+            });
+        } else if let Some(parent) = parent {
+            nodes[parent].files.push((name.to_lowercase(), name, size));
+        }
+    }
+
+    for i in (0..nodes.len()).rev() {
+        let mut entries: Vec<(bool, String, Vec<u8>)> = nodes[i]
+            .files
+            .iter()
+            .map(|(key, _, size)| (false, key.clone(), size.to_le_bytes().to_vec()))
+            .collect();
+        let mut bytes: u64 = nodes[i].files.iter().map(|f| f.2).sum();
+        let mut file_count = nodes[i].files.len() as u64;
+        for &sub in &nodes[i].subdirs {
+            let sub = &nodes[sub];
+            entries.push((true, sub.name.to_lowercase(), sub.signature.to_vec()));
+            bytes += sub.bytes;
+            file_count += sub.file_count;
+        }
+        entries.sort();
+        let mut hasher = sha2::Sha256::new();
+        for (is_dir, name, value) in &entries {
+            hasher.update([u8::from(*is_dir)]);
+            hasher.update(name.as_bytes());
+            hasher.update([0]);
+            hasher.update(value);
+        }
+        let node = &mut nodes[i];
+        node.signature = hasher.finalize().into();
+        node.bytes = bytes;
+        node.file_count = file_count;
+    }
+    nodes
+}
+
+fn duplicate_dir_groups(
+    nodes: &[DirNode],
+    min_size: u64,
+    verify_sample: Option<u32>,
+) -> Vec<DuplicateDirGroup> {
+    let mut by_signature: std::collections::HashMap<[u8; 32], Vec<usize>> = Default::default();
+    for (i, node) in nodes.iter().enumerate() {
+        // empty trees all look alike and waste nothing
+        if node.file_count > 0 && node.bytes >= min_size {
+            by_signature.entry(node.signature).or_default().push(i);
+        }
+    }
+    by_signature.retain(|_, members| members.len() > 1);
+
+    let mut groups: Vec<DuplicateDirGroup> = by_signature
+        .values()
+        .filter(|members| {
+            !members.iter().all(|&m| {
+                nodes[m]
+                    .parent
+                    .map_or(false, |p| by_signature.contains_key(&nodes[p].signature))
+            })
+        })
+        .map(|members| {
+            let first = &nodes[members[0]];
+            let dirs: Vec<String> = members.iter().map(|&m| nodes[m].path.clone()).collect();
+            let verified = verify_sample.map(|n| verify_duplicate_dirs(nodes, members, n as usize));
+            DuplicateDirGroup {
+                wasted_bytes: first.bytes * (dirs.len() as u64 - 1),
+                bytes: first.bytes,
+                files: first.file_count,
+                dirs,
+                verified,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.wasted_bytes.cmp(&a.wasted_bytes).then_with(|| a.dirs.cmp(&b.dirs)));
+    groups
+}
+
+// Hashes up to `sample` files of the first copy and the same relative paths
+// in every other copy; any difference or read failure fails the group.
+fn verify_duplicate_dirs(nodes: &[DirNode], members: &[usize], sample: usize) -> bool {
+    fn collect(
+        nodes: &[DirNode],
+        dir: usize,
+        prefix: &Path,
+        out: &mut Vec<std::path::PathBuf>,
+        sample: usize,
+    ) {
+        for (_, name, _) in &nodes[dir].files {
+            if out.len() >= sample {
+                return;
+            }
+            out.push(prefix.join(name));
+        }
+        for &sub in &nodes[dir].subdirs {
+            collect(nodes, sub, &prefix.join(&nodes[sub].name), out, sample);
+        }
+    }
+
+    let mut relative = Vec::new();
+    collect(nodes, members[0], Path::new(""), &mut relative, sample);
+    relative.iter().all(|rel| {
+        let mut digests = members
+            .iter()
+            .map(|&m| stream_digest::<sha2::Sha256>(&Path::new(&nodes[m].path).join(rel)).ok());
+        let first = digests.next().flatten();
+        first.is_some() && digests.all(|d| d == first)
+    })
+}
+
+// Candidate pairs share at least one file (name, size) or identical
+// subdirectory; the similarity then matches both trees by name.
+fn near_duplicate_dirs(nodes: &[DirNode], min_size: u64, min_pct: f64) -> Vec<NearDuplicateDirs> {
+    use std::collections::{HashMap, HashSet};
+
+    let mut holders: HashMap<(String, Vec<u8>), Vec<usize>> = HashMap::new();
+    for (i, node) in nodes.iter().enumerate().filter(|(_, n)| n.bytes >= min_size) {
+        for (key, _, size) in node.files.iter().filter(|f| f.2 > 0) {
+            holders.entry((key.clone(), size.to_le_bytes().to_vec())).or_default().push(i);
+        }
+        for &sub in nodes[i].subdirs.iter().filter(|&&s| nodes[s].bytes > 0) {
+            let sub = &nodes[sub];
+            holders.entry((sub.name.to_lowercase(), sub.signature.to_vec())).or_default().push(i);
+        }
+    }
+    let mut pairs = HashSet::new();
+    for dirs in holders.values().filter(|d| d.len() > 1 && d.len() <= NEAR_DUPLICATE_FANOUT) {
+        for (x, &a) in dirs.iter().enumerate() {
+            for &b in &dirs[x + 1..] {
+                if a != b {
+                    pairs.insert((a.min(b), a.max(b)));
+                }
+            }
+        }
+    }
+
+    let mut near: Vec<NearDuplicateDirs> = pairs
+        .into_iter()
+        .filter(|&(a, b)| {
+            nodes[a].signature != nodes[b].signature
+                && !Path::new(&nodes[b].path).starts_with(&nodes[a].path)
+        })
+        .filter_map(|(a, b)| {
+            let (larger, smaller) = if nodes[a].bytes >= nodes[b].bytes { (a, b) } else { (b, a) };
+            let shared_bytes = shared_dir_bytes(nodes, larger, smaller);
+            let similarity_pct = shared_bytes as f64 * 100.0 / nodes[larger].bytes as f64;
+            (similarity_pct >= min_pct).then(|| NearDuplicateDirs {
+                larger: nodes[larger].path.clone(),
+                smaller: nodes[smaller].path.clone(),
+                shared_bytes,
+                similarity_pct,
+            })
+        })
+        .collect();
+    near.sort_by(|a, b| b.shared_bytes.cmp(&a.shared_bytes).then_with(|| a.larger.cmp(&b.larger)));
+    near.truncate(NEAR_DUPLICATE_LIMIT);
+    near
+}
+
+// Bytes in files present in both trees at the same relative path and size.
+fn shared_dir_bytes(nodes: &[DirNode], a: usize, b: usize) -> u64 {
+    if nodes[a].signature == nodes[b].signature {
+        return nodes[a].bytes;
+    }
+    let files: std::collections::HashMap<&str, u64> =
+        nodes[b].files.iter().map(|(key, _, size)| (key.as_str(), *size)).collect();
+    let mut shared: u64 = nodes[a]
+        .files
+        .iter()
+        .filter(|(key, _, size)| files.get(key.as_str()) == Some(size))
+        .map(|f| f.2)
+        .sum();
+    let subdirs: std::collections::HashMap<String, usize> =
+        nodes[b].subdirs.iter().map(|&s| (nodes[s].name.to_lowercase(), s)).collect();
+    for &sub in &nodes[a].subdirs {
+        if let Some(&other) = subdirs.get(&nodes[sub].name.to_lowercase()) {
+            shared += shared_dir_bytes(nodes, sub, other);
+        }
+    }
+    shared
+}
+
+#[tauri::command]
+fn list_drives() -> Vec<DriveInfo> {
+    read_drives()
+}
+
+fn read_drives() -> Vec<DriveInfo> {
+    let disks = sysinfo::Disks::new_with_refreshed_list();
+    let mut drives: Vec<DriveInfo> = disks
+        .list()
+        .iter()
+        .map(|disk| DriveInfo {
+            name: disk.name().to_string_lossy().to_string(),
+            mount_point: disk.mount_point().to_string_lossy().to_string(),
+            file_system: disk.file_system().to_string_lossy().to_string(),
+            total_space: disk.total_space(),
+            available_space: disk.available_space(),
+            is_removable: disk.is_removable(),
+        })
+        .collect();
+    drives.sort_by(|a, b| a.mount_point.cmp(&b.mount_point));
+    drives
+}
+
+const DRIVE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+// Watches the parent directory rather than the file itself, so a log that is
+// rotated (renamed away and recreated) keeps reporting under the same path.
+#[tauri::command]
+fn watch_file(
+    window: tauri::Window,
+    watchers: State<FileWatchers>,
+    path: String,
+) -> Result<(), Error> {
+    use notify::Watcher;
+
+    let path = normalize_dir_input(&path);
+    let metadata = fs::metadata(&path)?;
+    if metadata.is_dir() {
+        return Err(Error::InvalidInput(format!("'{}' is a directory", path)));
+    }
+    let parent = Path::new(&path)
+        .parent()
+        .ok_or_else(|| Error::InvalidInput(format!("'{}' has no parent directory", path)))?
+        .to_path_buf();
+
+    let key = (window.label().to_string(), path.clone());
+    let mut watchers = watchers.0.lock().map_err(|_| Error::MutexPoison)?;
+    if watchers.contains_key(&key) {
+        return Ok(());
+    }
+
+    let watched = std::path::PathBuf::from(&path);
+    let event_path = path.clone();
+    let parent_dir = normalize_dir_input(&parent.to_string_lossy());
+    let mut last_seen = Some((metadata.len(), metadata_mtime(&metadata)));
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        let Ok(event) = res else { return };
+        if !event.paths.iter().any(|p| p == &watched) {
+            return;
+        }
+        window.state::<ListingCache>().invalidate_dir(&parent_dir);
+        let current = fs::metadata(&watched)
+            .ok()
+            .map(|metadata| (metadata.len(), metadata_mtime(&metadata)));
+        match file_watch_news(&mut last_seen, current) {
+            Some(Some((size, modified))) => {
+                let changed = FileChanged { path: event_path.clone(), size, modified };
+                let _ = window.emit("file-changed", changed);
+            }
+            Some(None) => {
+                let _ = window.emit("file-removed", &event_path);
+            }
+            None => {}
+        }
+    })
+    .map_err(notify_error)?;
+    watcher
+        .watch(&parent, notify::RecursiveMode::NonRecursive)
+        .map_err(notify_error)?;
+    watchers.insert(key, watcher);
+    Ok(())
+}
+
+// What a notification on a watched file means given its (size, mtime) now,
+// None once it is gone: Some(Some(..)) for a change, Some(None) for the
+// removal. Access-only and duplicate notifications carry no news.
+fn file_watch_news(
+    last_seen: &mut Option<(u64, u64)>,
+    current: Option<(u64, u64)>,
+) -> Option<Option<(u64, u64)>> {
+    if *last_seen == current {
+        return None;
+    }
+    *last_seen = current;
+    Some(current)
+}
+
+#[tauri::command]
+fn unwatch_file(
+    window: tauri::Window,
+    watchers: State<FileWatchers>,
+    path: String,
+) -> Result<(), Error> {
+    let key = (window.label().to_string(), normalize_dir_input(&path));
+    watchers.0.lock().map_err(|_| Error::MutexPoison)?.remove(&key);
+    Ok(())
+}
+
+fn notify_error(err: notify::Error) -> Error {
+    match err.kind {
+        notify::ErrorKind::Io(e) => Error::Io(e),
+        _ => Error::Background(err.to_string()),
+    }
+}
+
+const AUTO_INDEX_REMOVABLE_SETTING: &str = "auto_index_removable";
+
+#[tauri::command]
+fn subscribe_drive_changes(app: tauri::AppHandle) -> Result<(), Error> {
+    start_drive_watcher(&app)
+}
+
+// Emits `drives-changed` with the full drive list whenever the set of mounted
+// volumes differs from the previous poll, plus `drive-added` / `drive-removed`
+// for each volume that came or went. Free space changes are ignored.
+fn start_drive_watcher(app: &tauri::AppHandle) -> Result<(), Error> {
+    let watcher = app.state::<DriveWatcher>();
+    let mut running = watcher.0.lock().map_err(|_| Error::MutexPoison)?;
+    if running.is_some() {
+        return Ok(());
+    }
+
+    let stop = std::sync::Arc::new(AtomicBool::new(false));
+    *running = Some(stop.clone());
+
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let mut known = read_drives();
+        while !stop.load(Ordering::SeqCst) {
+            std::thread::sleep(DRIVE_POLL_INTERVAL);
+            if app.state::<BackgroundPaused>().0.load(Ordering::SeqCst) {
+                continue;
+            }
+            let drives = read_drives();
+            let (removed, added) = drive_changes(&known, &drives);
+            if removed.is_empty() && added.is_empty() {
+                continue;
+            }
+
+            for drive in removed {
+                abort_indexing_on(&app, &drive.mount_point);
+                let _ = app.emit_all("drive-removed", &drive);
+            }
+            for drive in added {
+                let _ = app.emit_all("drive-added", &drive);
+                if drive.is_removable && setting_enabled(&app, AUTO_INDEX_REMOVABLE_SETTING) {
+                    let app = app.clone();
+                    std::thread::spawn(move || {
+                        if let Err(err) =
+                            index_root(&app, &drive.mount_point, IndexSource::Removable, Default::default())
+                        {
+                            eprintln!("Indexing {} failed: {}", drive.mount_point, err);
+                        }
+                    });
+                }
+            }
+            let _ = app.emit_all("drives-changed", &drives);
+            known = drives;
+        }
+    });
+    Ok(())
+}
+
+// The volumes of `known` missing from `current`, then those new in it.
+// Drives are matched by mount point, so free space changes are no change.
+fn drive_changes(known: &[DriveInfo], current: &[DriveInfo]) -> (Vec<DriveInfo>, Vec<DriveInfo>) {
+    let is_known = |list: &[DriveInfo], drive: &DriveInfo| {
+        list.iter().any(|d| d.mount_point == drive.mount_point)
+    };
+    let removed = known.iter().filter(|d| !is_known(current, d)).cloned().collect();
+    let added = current.iter().filter(|d| !is_known(known, d)).cloned().collect();
+    (removed, added)
+}
+
+// Stops a run over a volume that just disappeared; it ends with
+// Error::DeviceRemoved instead of a stream of failed reads.
+fn abort_indexing_on(app: &tauri::AppHandle, mount_point: &str) {
+    let indexing = app.state::<IndexingState>();
+    let Ok(current) = indexing.current.lock() else { return };
+    if let Some((root, _, _)) = &*current {
+        if relative_path(mount_point.to_string(), root.clone()).is_ok() {
+            indexing.device_removed.store(true, Ordering::SeqCst);
+            indexing.cancel.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+#[tauri::command]
+fn get_drive_settings(db: State<DbConnection>) -> Result<DriveSettings, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(DriveSettings {
+        auto_index_removable: get_setting(&conn, AUTO_INDEX_REMOVABLE_SETTING)?.as_deref()
+            == Some("true"),
+    })
+}
+
+#[tauri::command]
+fn set_drive_settings(
+    app: tauri::AppHandle,
+    db: State<DbConnection>,
+    settings: DriveSettings,
+) -> Result<(), Error> {
+    {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        set_setting(
+            &conn,
+            AUTO_INDEX_REMOVABLE_SETTING,
+            &settings.auto_index_removable.to_string(),
+        )?;
+    }
+    // Auto-indexing needs the poller even when no window subscribed to it.
+    if settings.auto_index_removable {
+        start_drive_watcher(&app)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn unsubscribe_drive_changes(watcher: State<DriveWatcher>) -> Result<(), Error> {
+    if let Some(stop) = watcher.0.lock().map_err(|_| Error::MutexPoison)?.take() {
+        stop.store(true, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+const MINIMIZE_TO_TRAY_SETTING: &str = "minimize_to_tray";
+const QUICK_SEARCH_SHORTCUT_SETTING: &str = "quick_search_shortcut";
+const DEFAULT_QUICK_SEARCH_SHORTCUT: &str = "CmdOrCtrl+Shift+Space";
+const QUICK_SEARCH_LABEL: &str = "quick-search";
+
+fn build_tray() -> tauri::SystemTray {
+    let menu = tauri::SystemTrayMenu::new()
+        .add_item(tauri::CustomMenuItem::new("open", "Open"))
+        .add_item(tauri::CustomMenuItem::new("index_now", "Index now"))
+        .add_item(tauri::CustomMenuItem::new("pause_watcher", "Pause watcher"))
+        .add_native_item(tauri::SystemTrayMenuItem::Separator)
+        .add_item(tauri::CustomMenuItem::new("quit", "Quit"));
+    tauri::SystemTray::new().with_menu(menu)
+}
+
+fn handle_tray_event(app: &tauri::AppHandle, event: tauri::SystemTrayEvent) {
+    match event {
+        tauri::SystemTrayEvent::LeftClick { .. } => show_main_window(app),
+        tauri::SystemTrayEvent::MenuItemClick { id, .. } => match id.as_str() {
+            "open" => show_main_window(app),
+            "index_now" => {
+                let app = app.clone();
+                std::thread::spawn(move || {
+                    for root in configured_roots(&app) {
+                        if let Err(err) = index_root(&app, &root, IndexSource::Manual, Default::default()) {
+                            eprintln!("Index of {} failed: {}", root, err);
+                        }
+                    }
+                });
+            }
+            "pause_watcher" => {
+                let paused = &app.state::<BackgroundPaused>().0;
+                let now_paused = !paused.fetch_xor(true, Ordering::SeqCst);
+                let title = if now_paused { "Resume watcher" } else { "Pause watcher" };
+                let _ = app.tray_handle().get_item("pause_watcher").set_title(title);
+            }
+            "quit" => app.exit(0),
+            _ => {}
+        },
+        _ => {}
+    }
+}
+
+// Scheduled roots, or the default drive when nothing is scheduled yet.
+fn configured_roots(app: &tauri::AppHandle) -> Vec<String> {
+    let db = app.state::<DbConnection>();
+    let roots = match db.0.lock() {
+        Ok(conn) => load_schedules(&conn)
+            .map(|s| s.into_keys().collect::<Vec<_>>())
+            .unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    if roots.is_empty() {
+        vec!["C:\\".to_string()]
+    } else {
+        roots
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct RootHealth {
+    root: String,
+    // start of the last completed run; None if it never completed
+    last_indexed: Option<u64>,
+    age_seconds: Option<u64>,
+    // false while the drive is disconnected; its rows are kept
+    reachable: bool,
+    rows: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+struct IndexHealth {
+    // nothing else can be known until unlock_index succeeds
+    locked: bool,
+    total_rows: u64,
+    roots: Vec<RootHealth>,
+}
+
+fn index_health(app: &tauri::AppHandle) -> Result<IndexHealth, Error> {
+    if app.state::<IndexLock>().0.load(Ordering::SeqCst) {
+        return Ok(IndexHealth { locked: true, total_rows: 0, roots: Vec::new() });
+    }
+    let roots = configured_roots(app);
+    let db = app.state::<DbConnection>();
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let now = unix_now();
+    let mut health = Vec::with_capacity(roots.len());
+    for root in roots {
+        let (start, end) = subtree_range(&root);
+        let rows = conn.query_row(
+            &format!(
+                "SELECT COUNT(*) FROM main_table WHERE {} OR {}",
+                path_is("?1"),
+                dir_in_range("?2", "?3")
+            ),
+            rusqlite::params![root, start, end],
+            |row| row.get(0),
+        )?;
+        let last_indexed = last_index_run(&conn, &root)?;
+        health.push(RootHealth {
+            reachable: Path::new(&root).exists(),
+            age_seconds: last_indexed.map(|at| now.saturating_sub(at)),
+            last_indexed,
+            rows,
+            root,
+        });
+    }
+    let total_rows = conn.query_row("SELECT COUNT(*) FROM main_table", [], |row| row.get(0))?;
+    Ok(IndexHealth { locked: false, total_rows, roots: health })
+}
+
+// Sent once the app is up and again after unlock_index; windows that start
+// listening later ask get_index_health instead.
+fn spawn_index_health_report(app: tauri::AppHandle) {
+    std::thread::spawn(move || match index_health(&app) {
+        Ok(health) => {
+            let _ = app.emit_all("index-health", &health);
+        }
+        Err(err) => eprintln!("Index health check failed: {}", err),
+    });
+}
+
+#[tauri::command]
+fn get_index_health(app: tauri::AppHandle) -> Result<IndexHealth, Error> {
+    index_health(&app)
+}
+
+fn show_main_window(app: &tauri::AppHandle) {
+    if let Some(window) = app.get_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+#[tauri::command]
+fn set_active_directory(
+    window: tauri::Window,
+    active: State<ActiveDirectory>,
+    path: String,
+) -> Result<(), Error> {
+    active
+        .0
+        .lock()
+        .map_err(|_| Error::MutexPoison)?
+        .insert(window.label().to_string(), normalize_dir_input(&path));
+    Ok(())
+}
+
+// Files dropped from the OS copy into the displayed directory, or move when
+// Shift is held, mirroring Explorer.
+fn handle_file_drop(window: &tauri::Window, paths: &[std::path::PathBuf]) {
+    let dest = match window.state::<ActiveDirectory>().0.lock() {
+        Ok(active) => active.get(window.label()).cloned(),
+        Err(_) => None,
+    };
+    let Some(dest) = dest else { return };
+    let sources: Vec<String> = paths.iter().map(|p| p.to_string_lossy().to_string()).collect();
+    let move_requested = shift_held();
+
+    let window = window.clone();
+    std::thread::spawn(move || {
+        let dest_dir = Path::new(&dest);
+        let result = if move_requested {
+            move_entries_blocking(&window, &sources, dest_dir, ConflictPolicy::Rename, None)
+        } else {
+            let policy = ConflictPolicy::Rename;
+            copy_entries_blocking(&window, &sources, dest_dir, policy, None, false)
+        };
+        let error = result.as_ref().err().map(|e| e.to_string());
+        let payload = DropCompleted {
+            dest,
+            moved: move_requested,
+            report: result.ok(),
+            error,
+        };
+        let _ = window.emit("drop-completed", payload);
+    });
+}
+
+#[cfg(target_os = "windows")]
+fn shift_held() -> bool {
+    use windows_sys::Win32::UI::Input::KeyboardAndMouse::{GetAsyncKeyState, VK_SHIFT};
+    // SAFETY: GetAsyncKeyState has no preconditions.
+    (unsafe { GetAsyncKeyState(i32::from(VK_SHIFT)) } as u16 & 0x8000) != 0
+}
+
+#[cfg(not(target_os = "windows"))]
+fn shift_held() -> bool {
+    false
+}
+
+fn handle_window_event(event: tauri::GlobalWindowEvent) {
+    if let tauri::WindowEvent::FileDrop(tauri::FileDropEvent::Dropped(paths)) = event.event() {
+        handle_file_drop(event.window(), paths);
+        return;
+    }
+    if let tauri::WindowEvent::Destroyed = event.event() {
+        forget_window(event.window());
+        return;
+    }
+    if let tauri::WindowEvent::CloseRequested { api, .. } = event.event() {
+        let window = event.window();
+        save_window_geometry(window);
+        if window.label() == QUICK_SEARCH_LABEL {
+            api.prevent_close();
+            let _ = window.hide();
+        } else if window.label() == "main" && setting_enabled(&window.app_handle(), MINIMIZE_TO_TRAY_SETTING) {
+            // Keeps the process, and with it the scheduler and watchers, alive.
+            api.prevent_close();
+            let _ = window.hide();
+        }
+    }
+}
+
+// Geometry and navigation history are recorded by the backend; a state
+// without them keeps the last recorded ones.
+#[tauri::command]
+fn save_session(window: tauri::Window, db: State<DbConnection>, state_json: String) -> Result<(), Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    store_session(&conn, window.label(), &state_json)
+}
+
+fn store_session(conn: &Connection, label: &str, state_json: &str) -> Result<(), Error> {
+    let mut state: SessionState =
+        serde_json::from_str(state_json).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    if state.window.is_none() || state.history.is_none() {
+        let stored = read_session(conn, label)?.unwrap_or_default();
+        state.window = state.window.or(stored.window);
+        state.history = state.history.or(stored.history);
+    }
+    write_session(conn, label, &state)
+}
+
+const HISTORY_LIMIT: usize = 100;
+
+// Visiting a directory drops any forward entries, like a browser.
+#[tauri::command]
+fn push_history(
+    db: State<DbConnection>,
+    window_label: String,
+    path: String,
+) -> Result<NavigationResult, Error> {
+    let path = normalize_dir_input(&path);
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut state = read_session(&conn, &window_label)?.unwrap_or_default();
+    let history = state.history.get_or_insert_with(NavigationHistory::default);
+
+    if history.entries.get(history.position) != Some(&path) {
+        history.entries.truncate(history.position + 1);
+        history.entries.push(path);
+        if history.entries.len() > HISTORY_LIMIT {
+            let excess = history.entries.len() - HISTORY_LIMIT;
+            history.entries.drain(..excess);
+        }
+        history.position = history.entries.len() - 1;
+        record_visit(&conn, &history.entries[history.position])?;
+    }
+    let result = navigation_result(history, None);
+    write_session(&conn, &window_label, &state)?;
+    Ok(result)
+}
+
+// `direction` is "back" or "forward". Entries whose directory has since
+// disappeared are stepped over.
+#[tauri::command]
+fn navigate_history(
+    db: State<DbConnection>,
+    window_label: String,
+    direction: String,
+) -> Result<NavigationResult, Error> {
+    let forward = match direction.as_str() {
+        "back" => false,
+        "forward" => true,
+        other => return Err(Error::InvalidInput(format!("unknown direction '{}'", other))),
+    };
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut state = read_session(&conn, &window_label)?.unwrap_or_default();
+    let history = state.history.get_or_insert_with(NavigationHistory::default);
+
+    let is_dir = |i: &usize| Path::new(&history.entries[*i]).is_dir();
+    let target = if forward {
+        (history.position + 1..history.entries.len()).find(is_dir)
+    } else {
+        (0..history.position).rev().find(is_dir)
+    };
+    let path = target.map(|i| {
+        history.position = i;
+        history.entries[i].clone()
+    });
+    let result = navigation_result(history, path);
+    write_session(&conn, &window_label, &state)?;
+    Ok(result)
+}
+
+const LOCATION_HISTORY_LIMIT: u32 = 1000;
+
+fn record_visit(conn: &Connection, path: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO location_history (path, visits, last_visited) VALUES (?1, 1, ?2)
+         ON CONFLICT(path) DO UPDATE SET visits = visits + 1, last_visited = ?2",
+        rusqlite::params![path, unix_now()],
+    )?;
+    conn.execute(
+        "DELETE FROM location_history WHERE path NOT IN
+         (SELECT path FROM location_history ORDER BY last_visited DESC LIMIT ?1)",
+        rusqlite::params![LOCATION_HISTORY_LIMIT],
+    )?;
+    Ok(())
+}
+
+// Visit count weighted by how recently the last visit was, in the spirit of
+// browser frecency: recent and frequent both count.
+fn frecency(visits: i64, last_visited: u64, now: u64) -> f64 {
+    let weight = match now.saturating_sub(last_visited) / 86_400 {
+        0..=3 => 100.0,
+        4..=14 => 70.0,
+        15..=31 => 50.0,
+        32..=90 => 30.0,
+        _ => 10.0,
+    };
+    visits as f64 * weight
+}
+
+fn location_frecency(conn: &Connection) -> Result<std::collections::HashMap<String, f64>> {
+    let now = unix_now();
+    let mut stmt = conn.prepare("SELECT path, visits, last_visited FROM location_history")?;
+    let scores = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?, row.get::<_, u64>(2)?))
+        })?
+        .filter_map(Result::ok)
+        .map(|(path, visits, last)| (path, frecency(visits, last, now)))
+        .collect();
+    Ok(scores)
+}
+
+// How much a history score can lift a match; log-scaled so a folder visited
+// every day cannot bury a much better match on the name.
+const FRECENCY_WEIGHT: f64 = 6.0;
+
+// Directories for a Ctrl+P style palette, ranked by fuzzy match on the name
+// plus visit frecency. An empty query lists the most frecent directories.
+#[tauri::command]
+fn quick_jump(
+    db: State<DbConnection>,
+    jump: State<JumpCandidates>,
+    query: String,
+    limit: u8,
+) -> Result<Vec<JumpMatch>, Error> {
+    let candidates = jump_candidates(&db, &jump)?;
+    let scores = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        location_frecency(&conn)?
+    };
+    Ok(rank_jump_candidates(&candidates, &scores, &query, limit))
+}
+
+fn rank_jump_candidates(
+    candidates: &[JumpCandidate],
+    scores: &std::collections::HashMap<String, f64>,
+    query: &str,
+    limit: u8,
+) -> Vec<JumpMatch> {
+    let query: Vec<char> = query
+        .trim()
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .filter_map(|c| c.to_lowercase().next())
+        .collect();
+
+    let mut matches: Vec<(JumpMatch, usize)> = candidates
+        .iter()
+        .filter_map(|candidate| {
+            let history = scores.get(&candidate.path).copied().unwrap_or(0.0);
+            let (fuzzy, ranges) = if query.is_empty() {
+                if history == 0.0 {
+                    return None;
+                }
+                (0, Vec::new())
+            } else {
+                fuzzy_match(&query, &candidate.lower)?
+            };
+            let score = fuzzy as f64 + FRECENCY_WEIGHT * (1.0 + history / 100.0).ln();
+            let found = JumpMatch {
+                path: candidate.path.clone(),
+                name: candidate.name.clone(),
+                score,
+                ranges,
+            };
+            Some((found, candidate.depth))
+        })
+        .collect();
+    matches.sort_by(|(a, a_depth), (b, b_depth)| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a_depth.cmp(b_depth))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+    matches
+        .into_iter()
+        .take(limit as usize)
+        .map(|(found, _)| found)
+        .collect()
+}
+
+// Greedy in-order match of every query char. Consecutive runs and matches at
+// word starts score higher; the ranges are merged runs of matched chars.
+fn fuzzy_match(query: &[char], name: &[char]) -> Option<(i64, Vec<(usize, usize)>)> {
+    let mut score = 0i64;
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    let mut next = 0;
+    for &wanted in query {
+        let offset = name[next..].iter().position(|&c| c == wanted)?;
+        let index = next + offset;
+        score += 1;
+        let word_start = index == 0 || matches!(name[index - 1], ' ' | '_' | '-' | '.');
+        if word_start {
+            score += 8;
+        }
+        match ranges.last_mut() {
+            Some(last) if last.1 == index => {
+                last.1 += 1;
+                score += 5;
+            }
+            _ => ranges.push((index, index + 1)),
+        }
+        next = index + 1;
+    }
+    if query.len() == name.len() {
+        score += 10;
+    }
+    Some((score, ranges))
+}
+
+fn jump_candidates(
+    db: &DbConnection,
+    jump: &JumpCandidates,
+) -> Result<std::sync::Arc<Vec<JumpCandidate>>, Error> {
+    if let Some(candidates) = &*jump.0.lock().map_err(|_| Error::MutexPoison)? {
+        return Ok(candidates.clone());
+    }
+    let loaded = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        std::sync::Arc::new(load_jump_candidates(&conn)?)
+    };
+    *jump.0.lock().map_err(|_| Error::MutexPoison)? = Some(loaded.clone());
+    Ok(loaded)
+}
+
+fn load_jump_candidates(conn: &Connection) -> Result<Vec<JumpCandidate>> {
+    let mut stmt = conn.prepare("SELECT name, path FROM entries WHERE is_dir = 1")?;
+    let candidates = stmt
+        .query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))?
+        .filter_map(Result::ok)
+        .map(|(name, path)| JumpCandidate {
+            lower: name.chars().map(|c| c.to_lowercase().next().unwrap_or(c)).collect(),
+            depth: path_depth(&path),
+            name,
+            path,
+        })
+        .collect();
+    Ok(candidates)
+}
+
+fn reload_jump_candidates(app: &tauri::AppHandle) {
+    let db = app.state::<DbConnection>();
+    let loaded = match db.0.lock() {
+        Ok(conn) => load_jump_candidates(&conn),
+        Err(_) => return,
+    };
+    match loaded {
+        Ok(candidates) => {
+            if let Ok(mut cached) = app.state::<JumpCandidates>().0.lock() {
+                *cached = Some(std::sync::Arc::new(candidates));
+            }
+        }
+        Err(err) => eprintln!("Failed to reload quick jump candidates: {}", err),
+    }
+}
+
+fn navigation_result(history: &NavigationHistory, path: Option<String>) -> NavigationResult {
+    let exists = |entries: &[String]| entries.iter().any(|e| Path::new(e).is_dir());
+    let split = (history.position + 1).min(history.entries.len());
+    NavigationResult {
+        path,
+        can_go_back: exists(&history.entries[..history.position.min(split)]),
+        can_go_forward: exists(&history.entries[split..]),
+    }
+}
+
+// Saved paths that no longer exist are replaced by their nearest existing
+// ancestor; tabs left with nothing at all are dropped.
+#[tauri::command]
+fn load_session(window: tauri::Window, db: State<DbConnection>) -> Result<SessionState, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    restore_session(&conn, window.label())
+}
+
+fn restore_session(conn: &Connection, label: &str) -> Result<SessionState, Error> {
+    let mut state = read_session(conn, label)?.unwrap_or_default();
+    state.tabs = state.tabs.iter().filter_map(|tab| nearest_existing_ancestor(tab)).collect();
+    state.active_tab = state.active_tab.min(state.tabs.len().saturating_sub(1));
+    Ok(state)
+}
+
+fn nearest_existing_ancestor(path: &str) -> Option<String> {
+    Path::new(&normalize_dir_input(path))
+        .ancestors()
+        .find(|p| !p.as_os_str().is_empty() && p.is_dir())
+        .map(|p| p.to_string_lossy().to_string())
+}
+
+fn read_session(conn: &Connection, label: &str) -> Result<Option<SessionState>, Error> {
+    let state: Option<String> = conn
+        .query_row(
+            "SELECT state FROM sessions WHERE label = ?1",
+            rusqlite::params![label],
+            |row| row.get(0),
+        )
+        .optional()?;
+    // A state written by an incompatible version is treated as no state.
+    Ok(state.and_then(|s| serde_json::from_str(&s).ok()))
+}
+
+fn write_session(conn: &Connection, label: &str, state: &SessionState) -> Result<(), Error> {
+    let value = serde_json::to_string(state).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    conn.execute(
+        "INSERT OR REPLACE INTO sessions (label, state, updated_at) VALUES (?1, ?2, ?3)",
+        rusqlite::params![label, value, unix_now()],
+    )?;
+    Ok(())
+}
+
+fn save_window_geometry(window: &tauri::Window) {
+    // The quick-search popup positions itself.
+    if window.label() == QUICK_SEARCH_LABEL {
+        return;
+    }
+    let (Ok(position), Ok(size)) = (window.outer_position(), window.outer_size()) else { return };
+    let geometry = WindowGeometry {
+        x: position.x,
+        y: position.y,
+        width: size.width,
+        height: size.height,
+        maximized: window.is_maximized().unwrap_or(false),
+    };
+    let db = window.state::<DbConnection>();
+    let Ok(conn) = db.0.lock() else { return };
+    let result = read_session(&conn, window.label()).and_then(|state| {
+        let mut state = state.unwrap_or_default();
+        state.window = Some(geometry);
+        write_session(&conn, window.label(), &state)
+    });
+    if let Err(err) = result {
+        eprintln!("Failed to save window geometry: {}", err);
+    }
+}
+
+// Skips geometry that would put the window on a monitor that is no longer
+// attached.
+fn restore_window_geometry(window: &tauri::Window) {
+    let geometry = {
+        let db = window.state::<DbConnection>();
+        let Ok(conn) = db.0.lock() else { return };
+        match read_session(&conn, window.label()) {
+            Ok(Some(SessionState { window: Some(geometry), .. })) => geometry,
+            _ => return,
+        }
+    };
+    let on_screen = window.available_monitors().unwrap_or_default().iter().any(|monitor| {
+        let (pos, size) = (monitor.position(), monitor.size());
+        geometry.x < pos.x + size.width as i32
+            && geometry.x + geometry.width as i32 > pos.x
+            && geometry.y < pos.y + size.height as i32
+            && geometry.y + geometry.height as i32 > pos.y
+    });
+    if !on_screen || geometry.width < 200 || geometry.height < 150 {
+        return;
+    }
+    let _ = window.set_size(tauri::PhysicalSize::new(geometry.width, geometry.height));
+    let _ = window.set_position(tauri::PhysicalPosition::new(geometry.x, geometry.y));
+    if geometry.maximized {
+        let _ = window.maximize();
+    }
+}
+
+static NEXT_WINDOW_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+// The new window loads the same frontend and reaches the same commands; the
+// starting directory travels in the URL hash.
+#[tauri::command]
+async fn create_new_window(
+    app: tauri::AppHandle,
+    initial_path: Option<String>,
+) -> Result<String, Error> {
+    let label = format!("explorer-{}", NEXT_WINDOW_ID.fetch_add(1, Ordering::SeqCst));
+    let url = match initial_path {
+        Some(path) => format!("index.html#open={}", encode_uri_component(&normalize_dir_input(&path))),
+        None => "index.html".to_string(),
+    };
+    tauri::WindowBuilder::new(&app, &label, tauri::WindowUrl::App(url.into()))
+        .title("file-explorer")
+        .inner_size(800.0, 600.0)
+        .build()
+        .map_err(|e| Error::Background(e.to_string()))?;
+    Ok(label)
+}
+
+fn encode_uri_component(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// Drops everything keyed by a window that has closed and stops its jobs.
+fn forget_window(window: &tauri::Window) {
+    let label = window.label();
+    window.state::<TokenRegistry>().cancel_window(label);
+    if let Ok(mut active) = window.state::<ActiveDirectory>().0.lock() {
+        active.remove(label);
+    }
+    if let Ok(mut watchers) = window.state::<FileWatchers>().0.lock() {
+        watchers.retain(|(owner, _), _| owner != label);
+    }
+}
+
+fn setting_enabled(app: &tauri::AppHandle, key: &str) -> bool {
+    let db = app.state::<DbConnection>();
+    let value = match db.0.lock() {
+        Ok(conn) => get_setting(&conn, key).ok().flatten(),
+        Err(_) => None,
+    };
+    value.as_deref() == Some("true")
+}
+
+fn toggle_quick_search(app: &tauri::AppHandle) {
+    if let Some(window) = app.get_window(QUICK_SEARCH_LABEL) {
+        if window.is_visible().unwrap_or(false) {
+            let _ = window.hide();
+        } else {
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+        return;
+    }
+
+    let built = tauri::WindowBuilder::new(
+        app,
+        QUICK_SEARCH_LABEL,
+        tauri::WindowUrl::App("index.html#quick-search".into()),
+    )
+    .title("Quick search")
+    .inner_size(600.0, 360.0)
+    .resizable(false)
+    .decorations(false)
+    .always_on_top(true)
+    .skip_taskbar(true)
+    .center()
+    .build();
+    if let Err(err) = built {
+        eprintln!("Could not open quick search window: {}", err);
+    }
+}
+
+fn register_quick_search_shortcut(app: &tauri::AppHandle, shortcut: &str) -> Result<(), Error> {
+    use tauri::GlobalShortcutManager;
+
+    let mut manager = app.global_shortcut_manager();
+    manager
+        .unregister_all()
+        .map_err(|e| Error::Background(e.to_string()))?;
+    let handle = app.clone();
+    manager
+        .register(shortcut, move || toggle_quick_search(&handle))
+        .map_err(|e| Error::InvalidInput(format!("cannot register shortcut '{}': {}", shortcut, e)))
+}
+
+#[tauri::command]
+fn get_tray_settings(db: State<DbConnection>) -> Result<TraySettings, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(TraySettings {
+        minimize_to_tray: get_setting(&conn, MINIMIZE_TO_TRAY_SETTING)?.as_deref() == Some("true"),
+        quick_search_shortcut: get_setting(&conn, QUICK_SEARCH_SHORTCUT_SETTING)?
+            .unwrap_or_else(|| DEFAULT_QUICK_SEARCH_SHORTCUT.to_string()),
+    })
+}
+
+#[tauri::command]
+fn set_tray_settings(
+    app: tauri::AppHandle,
+    db: State<DbConnection>,
+    settings: TraySettings,
+) -> Result<(), Error> {
+    // Register first so a bad accelerator is rejected before it is stored.
+    register_quick_search_shortcut(&app, &settings.quick_search_shortcut)?;
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    set_setting(&conn, MINIMIZE_TO_TRAY_SETTING, &settings.minimize_to_tray.to_string())?;
+    set_setting(&conn, QUICK_SEARCH_SHORTCUT_SETTING, &settings.quick_search_shortcut)?;
+    Ok(())
+}
+
+#[tauri::command]
+fn hide_quick_search(app: tauri::AppHandle) {
+    if let Some(window) = app.get_window(QUICK_SEARCH_LABEL) {
+        let _ = window.hide();
+    }
+}
+
+// Drags the given files out of the app so they can be dropped into Explorer,
+// Outlook and the like. Resolves once the drop finished with "copy", "move",
+// "link" or "none".
+#[tauri::command]
+async fn start_native_drag(app: tauri::AppHandle, paths: Vec<String>) -> Result<String, Error> {
+    if paths.is_empty() {
+        return Err(Error::InvalidInput("nothing to drag".to_string()));
+    }
+    start_native_drag_impl(app, paths).await
+}
+
+#[cfg(target_os = "windows")]
+async fn start_native_drag_impl(app: tauri::AppHandle, paths: Vec<String>) -> Result<String, Error> {
+    let (tx, rx) = std::sync::mpsc::channel();
+    // DoDragDrop runs its own modal loop, which keeps pumping the window's
+    // messages, so calling it from the main thread does not stall the UI.
+    app.run_on_main_thread(move || {
+        let _ = tx.send(native_drag::drag_files(&paths));
+    })
+    .map_err(|e| Error::Background(e.to_string()))?;
+
+    tauri::async_runtime::spawn_blocking(move || rx.recv())
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?
+        .map_err(|e| Error::Background(e.to_string()))?
+}
+
+#[cfg(not(target_os = "windows"))]
+async fn start_native_drag_impl(_app: tauri::AppHandle, _paths: Vec<String>) -> Result<String, Error> {
+    Err(Error::Unsupported("native drag-out"))
+}
+
+#[cfg(target_os = "windows")]
+mod native_drag {
+    use super::{to_wide, Error};
+    use windows::core::{implement, HRESULT, PCWSTR};
+    use windows::Win32::Foundation::{
+        BOOL, DRAGDROP_S_CANCEL, DRAGDROP_S_DROP, DRAGDROP_S_USEDEFAULTCURSORS, S_OK,
+    };
+    use windows::Win32::System::Com::{IBindCtx, IDataObject};
+    use windows::Win32::System::Ole::{
+        DoDragDrop, IDropSource, IDropSource_Impl, OleInitialize, DROPEFFECT, DROPEFFECT_COPY,
+        DROPEFFECT_LINK, DROPEFFECT_MOVE,
+    };
+    use windows::Win32::System::SystemServices::{MK_LBUTTON, MODIFIERKEYS_FLAGS};
+    use windows::Win32::UI::Shell::Common::ITEMIDLIST;
+    use windows::Win32::UI::Shell::{ILFree, SHCreateDataObject, SHParseDisplayName};
+
+    #[implement(IDropSource)]
+    struct DropSource;
+
+    impl IDropSource_Impl for DropSource {
+        fn QueryContinueDrag(&self, escape_pressed: BOOL, key_state: MODIFIERKEYS_FLAGS) -> HRESULT {
+            if escape_pressed.as_bool() {
+                DRAGDROP_S_CANCEL
+            } else if key_state.0 & MK_LBUTTON.0 == 0 {
+                DRAGDROP_S_DROP
+            } else {
+                S_OK
+            }
+        }
+
+        fn GiveFeedback(&self, _effect: DROPEFFECT) -> HRESULT {
+            DRAGDROP_S_USEDEFAULTCURSORS
+        }
+    }
+
+    // Absolute PIDLs, freed on drop.
+    struct Pidls(Vec<*mut ITEMIDLIST>);
+
+    impl Drop for Pidls {
+        fn drop(&mut self) {
+            for pidl in &self.0 {
+                // SAFETY: every pointer came from SHParseDisplayName.
+                unsafe { ILFree(Some(*pidl)) };
+            }
+        }
+    }
+
+    pub fn drag_files(paths: &[String]) -> Result<String, Error> {
+        let com_error = |e: windows::core::Error| Error::Background(e.to_string());
+
+        // SAFETY: plain OLE calls on the UI thread; every PIDL handed to the
+        // shell stays alive until `pidls` is dropped at the end of the scope.
+        unsafe {
+            // Already initialised on the UI thread by the webview; S_FALSE is fine.
+            let _ = OleInitialize(None);
+
+            let mut pidls = Pidls(Vec::with_capacity(paths.len()));
+            for path in paths {
+                let wide = to_wide(std::ffi::OsStr::new(path));
+                let mut pidl = std::ptr::null_mut();
+                SHParseDisplayName(PCWSTR(wide.as_ptr()), None::<&IBindCtx>, &mut pidl, 0, None)
+                    .map_err(|e| Error::InvalidInput(format!("{}: {}", path, e)))?;
+                pidls.0.push(pidl);
+            }
+
+            // The shell data object renders CF_HDROP (plus the shell formats
+            // Explorer prefers) for these items.
+            let items: Vec<*const ITEMIDLIST> = pidls.0.iter().map(|p| *p as *const _).collect();
+            let data: IDataObject =
+                SHCreateDataObject(None, Some(&items), None::<&IDataObject>).map_err(com_error)?;
+            let source: IDropSource = DropSource.into();
+
+            let mut effect = DROPEFFECT(0);
+            DoDragDrop(
+                &data,
+                &source,
+                DROPEFFECT_COPY | DROPEFFECT_MOVE | DROPEFFECT_LINK,
+                &mut effect,
+            )
+            .ok()
+            .map_err(com_error)?;
+
+            Ok(if effect.0 & DROPEFFECT_MOVE.0 != 0 {
+                "move"
+            } else if effect.0 & DROPEFFECT_COPY.0 != 0 {
+                "copy"
+            } else if effect.0 & DROPEFFECT_LINK.0 != 0 {
+                "link"
+            } else {
+                "none"
+            }
+            .to_string())
+        }
+    }
+}
+
+const EXECUTABLE_EXTENSIONS: &[&str] = &["exe", "msi", "bat", "cmd", "com"];
+
+fn is_executable(path: &Path) -> bool {
+    path.extension()
+        .map(|e| EXECUTABLE_EXTENSIONS.contains(&e.to_string_lossy().to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+// Verbs that elevate or switch user; still offered, but the UI should make
+// them stand out.
+const DANGEROUS_VERBS: &[&str] = &["runas", "runasuser"];
+
+#[tauri::command]
+fn get_shell_verbs(path: String) -> Result<Vec<ShellVerb>, Error> {
+    let path = Path::new(&path);
+    let is_dir = fs::metadata(path)?.is_dir();
+    let mut verbs = platform_shell_verbs(path, is_dir);
+
+    let mut statics = vec![("open", "Open")];
+    if is_executable(path) {
+        statics.push(("runas", "Run as administrator"));
+    }
+    statics.push(("properties", "Properties"));
+    for (verb, label) in statics {
+        if !verbs.iter().any(|v| v.verb.eq_ignore_ascii_case(verb)) {
+            verbs.push(ShellVerb::new(verb, label, "static"));
+        }
+    }
+    Ok(verbs)
+}
+
+#[tauri::command]
+fn invoke_shell_verb(db: State<DbConnection>, path: String, verb: String) -> Result<(), Error> {
+    if verb.trim().is_empty() {
+        return Err(Error::InvalidInput("verb must not be empty".to_string()));
+    }
+    ensure_allowed(&*db.0.lock().map_err(|_| Error::MutexPoison)?, &[&path])?;
+    platform_invoke_verb(Path::new(&path), &verb)
+}
+
+// Collects the verbs registered for the file's ProgID, its
+// SystemFileAssociations entry and the catch-all classes, in the order
+// Explorer consults them; the first registration of a verb wins.
+#[cfg(target_os = "windows")]
+fn platform_shell_verbs(path: &Path, is_dir: bool) -> Vec<ShellVerb> {
+    use winreg::enums::HKEY_CLASSES_ROOT;
+
+    let classes_root = winreg::RegKey::predef(HKEY_CLASSES_ROOT);
+    let mut classes = Vec::new();
+    if is_dir {
+        classes.push("Directory".to_string());
+        classes.push("Folder".to_string());
+    } else {
+        if let Some(ext) = path.extension() {
+            let ext = format!(".{}", ext.to_string_lossy());
+            if let Ok(prog_id) = classes_root
+                .open_subkey(&ext)
+                .and_then(|key| key.get_value::<String, _>(""))
+            {
+                classes.push(prog_id);
+            }
+            classes.push(format!("SystemFileAssociations\\{}", ext));
+        }
+        classes.push("*".to_string());
+    }
+    classes.push("AllFilesystemObjects".to_string());
+
+    let mut verbs: Vec<ShellVerb> = Vec::new();
+    for class in classes {
+        let Ok(shell) = classes_root.open_subkey(format!("{}\\shell", class)) else { continue };
+        for name in shell.enum_keys().filter_map(Result::ok) {
+            if verbs.iter().any(|v| v.verb.eq_ignore_ascii_case(&name)) {
+                continue;
+            }
+            let Ok(key) = shell.open_subkey(&name) else { continue };
+            // Hidden from menus by their registrants.
+            let hidden = ["LegacyDisable", "ProgrammaticAccessOnly"]
+                .iter()
+                .any(|value| key.get_raw_value(value).is_ok());
+            if hidden {
+                continue;
+            }
+            // Indirect "@dll,-id" labels would need SHLoadIndirectString; the
+            // verb name reads well enough for those.
+            let label = key
+                .get_value::<String, _>("MUIVerb")
+                .or_else(|_| key.get_value::<String, _>(""))
+                .ok()
+                .filter(|l| !l.is_empty() && !l.starts_with('@'))
+                .map(|l| l.replace('&', ""))
+                .unwrap_or_else(|| name.clone());
+            verbs.push(ShellVerb::new(&name, &label, "registry"));
+        }
+    }
+    verbs
+}
+
+#[cfg(not(target_os = "windows"))]
+fn platform_shell_verbs(_path: &Path, _is_dir: bool) -> Vec<ShellVerb> {
+    vec![
+        ShellVerb::new("open", "Open", "static"),
+        ShellVerb::new("reveal", "Show in file manager", "static"),
+    ]
+}
+
+#[cfg(target_os = "windows")]
+fn platform_invoke_verb(path: &Path, verb: &str) -> Result<(), Error> {
+    use windows_sys::Win32::UI::Shell::{ShellExecuteExW, SEE_MASK_INVOKEIDLIST, SHELLEXECUTEINFOW};
+    use windows_sys::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+    let wide_verb = to_wide(std::ffi::OsStr::new(verb));
+    let wide_path = to_wide(path.as_os_str());
+    // SAFETY: SHELLEXECUTEINFOW is plain data; zeroed is its documented
+    // initial state, and both strings outlive the call.
+    let ok = unsafe {
+        let mut info: SHELLEXECUTEINFOW = std::mem::zeroed();
+        info.cbSize = std::mem::size_of::<SHELLEXECUTEINFOW>() as u32;
+        // INVOKEIDLIST lets context-menu-only verbs such as "properties" run.
+        info.fMask = SEE_MASK_INVOKEIDLIST;
+        info.lpVerb = wide_verb.as_ptr();
+        info.lpFile = wide_path.as_ptr();
+        info.nShow = SW_SHOWNORMAL;
+        ShellExecuteExW(&mut info)
+    };
+    if ok == 0 {
+        return Err(Error::Io(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn platform_invoke_verb(path: &Path, verb: &str) -> Result<(), Error> {
+    let target = match verb {
+        "open" => path.to_path_buf(),
+        "reveal" => path.parent().unwrap_or(path).to_path_buf(),
+        _ => return Err(Error::Unsupported("shell verbs other than open and reveal")),
+    };
+    open::that(target).map_err(Error::Io)
+}
+
+// Streams the file through the digest, so size does not matter: "sha256",
+// "blake3" or "md5", in any case.
+#[tauri::command]
+async fn file_checksum(path: String, algo: String) -> Result<String, Error> {
+    let path = normalize_dir_input(&path);
+    tauri::async_runtime::spawn_blocking(move || checksum(Path::new(&path), &algo))
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn checksum(path: &Path, algo: &str) -> Result<String, Error> {
+    match algo.to_ascii_lowercase().as_str() {
+        "sha256" => stream_digest::<sha2::Sha256>(path),
+        "md5" => stream_digest::<md5::Md5>(path),
+        "blake3" => {
+            let mut hasher = blake3::Hasher::new();
+            read_chunks(path, |chunk| {
+                hasher.update(chunk);
+            })?;
+            Ok(hasher.finalize().to_hex().to_string())
+        }
+        other => Err(Error::InvalidInput(format!("unsupported checksum algorithm '{}'", other))),
+    }
+}
+
+fn stream_digest<D: sha2::Digest>(path: &Path) -> Result<String, Error> {
+    let mut digest = D::new();
+    read_chunks(path, |chunk| digest.update(chunk))?;
+    Ok(digest.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn read_chunks(path: &Path, mut chunk: impl FnMut(&[u8])) -> Result<(), Error> {
+    use std::io::Read;
+
+    let mut file = fs::File::open(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        chunk(&buf[..n]);
+    }
+}
+
+// Title, author, dates and page or word counts of a docx, xlsx, pptx or PDF.
+#[tauri::command]
+async fn get_document_metadata(
+    db: State<'_, DbConnection>,
+    path: String,
+) -> Result<documents::DocumentMetadata, Error> {
+    let path = normalize_dir_input(&path);
+    ensure_allowed(&*db.0.lock().map_err(|_| Error::MutexPoison)?, &[&path])?;
+    tauri::async_runtime::spawn_blocking(move || {
+        documents::read_document_metadata(Path::new(&path))
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+#[derive(Debug, Default, serde::Serialize)]
+struct DocumentIndexReport {
+    updated: u64,
+    // already read at their current mtime
+    unchanged: u64,
+    failed: Vec<FailedEntry>,
+}
+
+// Stores title and author of every indexed document under `root` in
+// doc_title and doc_author, for search_files to filter on. Files are only
+// re-read when their mtime changed. A document that fails to parse is
+// reported and marked read at its mtime, so it is not retried until it
+// changes; a read error leaves it for the next pass.
+#[tauri::command]
+async fn index_document_metadata(
+    window: tauri::Window,
+    root: String,
+    token: Option<String>,
+) -> Result<Outcome<DocumentIndexReport>, Error> {
+    let root = normalize_dir_input(&root);
+    tauri::async_runtime::spawn_blocking(move || {
+        index_document_metadata_blocking(&window, &root, token)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// A cancelled pass keeps what it had read.
+fn index_document_metadata_blocking(
+    window: &tauri::Window,
+    root: &str,
+    token: Option<String>,
+) -> Result<Outcome<DocumentIndexReport>, Error> {
+    let registry = window.state::<TokenRegistry>();
+    let op = registry.begin(window, token, "index_document_metadata")?;
+    let db = window.state::<DbConnection>();
+
+    let in_list = documents::DOCUMENT_EXTENSIONS
+        .iter()
+        .map(|e| format!("'{}'", e))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let (start, end) = subtree_range(root);
+    let candidates: Vec<(String, u64, Option<u64>)> = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT path, modified, doc_meta_mtime
+             FROM entries
+             WHERE is_dir = 0
+             AND lower(extension) IN ({})
+             AND {}",
+            in_list,
+            dir_in_range("?1", "?2")
+        ))?;
+        let rows = stmt.query_map(rusqlite::params![start, end], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        rows.filter_map(Result::ok).collect()
+    };
+
+    let total = candidates.len() as u64;
+    let mut report = DocumentIndexReport::default();
+    let mut fresh: Vec<(String, Option<String>, Option<String>, u64)> = Vec::new();
+    let mut cancelled = false;
+
+    for (index, (path, modified, read_at)) in candidates.into_iter().enumerate() {
+        if op.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        if read_at == Some(modified) {
+            report.unchanged += 1;
+        } else {
+            match documents::read_document_metadata(Path::new(&path)) {
+                Ok(meta) => {
+                    report.updated += 1;
+                    fresh.push((path, meta.title, meta.author, modified));
+                }
+                Err(e) => {
+                    report.failed.push(FailedEntry::new(&path, &e.to_string()));
+                    if matches!(e, Error::MalformedDocument(..)) {
+                        fresh.push((path, None, None, modified));
+                    }
+                }
+            }
+        }
+
+        let processed = index as u64 + 1;
+        if processed % 25 == 0 || processed == total {
+            let _ = window.emit("document-metadata-progress", ProgressPayload { processed, total });
+        }
+    }
+
+    {
+        let mut conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        let tx = conn.transaction()?;
+        for (path, title, author, mtime) in &fresh {
+            tx.execute(
+                &format!(
+                    "UPDATE main_table SET doc_title = ?2, doc_author = ?3, doc_meta_mtime = ?4
+                     WHERE {}",
+                    path_is("?1")
+                ),
+                rusqlite::params![path, title, author, mtime],
+            )?;
+        }
+        tx.commit()?;
+    }
+    Ok(Outcome::new(report, cancelled))
+}
+
+// Evenly spaced blocks read from files too large to read whole.
+const ENTROPY_SAMPLE_BLOCKS: u64 = 16;
+const ENTROPY_BLOCK_LEN: u64 = 64 * 1024;
+
+// Shannon entropy in bits per byte, from 0 for a single repeated byte to 8
+// for random data; compressed and encrypted files sit close to 8. Kept on
+// the file's index row until its mtime changes.
+#[tauri::command]
+async fn file_entropy(app: tauri::AppHandle, path: String) -> Result<f64, Error> {
+    let path = normalize_dir_input(&path);
+    tauri::async_runtime::spawn_blocking(move || {
+        let metadata = fs::metadata(&path)?;
+        if metadata.is_dir() {
+            return Err(Error::InvalidInput(format!("'{}' is a directory", path)));
+        }
+        // reading one would download it
+        if is_cloud_placeholder(&metadata) {
+            return Err(Error::InvalidInput(format!("'{}' is not available offline", path)));
+        }
+        let modified = metadata_mtime(&metadata);
+        let db = app.state::<DbConnection>();
+        let cached: Option<f64> = {
+            let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+            conn.query_row(
+                &format!(
+                    "SELECT entropy FROM main_table WHERE {} AND entropy_mtime = ?2",
+                    path_is("?1")
+                ),
+                rusqlite::params![path, modified],
+                |row| row.get(0),
+            )
+            .optional()?
+            .flatten()
+        };
+        if let Some(entropy) = cached {
+            return Ok(entropy);
+        }
+        let entropy = sampled_entropy(Path::new(&path), metadata.len())?;
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        conn.execute(
+            &format!(
+                "UPDATE main_table SET entropy = ?2, entropy_mtime = ?3 WHERE {}",
+                path_is("?1")
+            ),
+            rusqlite::params![path, entropy, modified],
+        )?;
+        Ok(entropy)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn sampled_entropy(path: &Path, len: u64) -> Result<f64, Error> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let offsets: Vec<u64> = if len <= ENTROPY_BLOCK_LEN * ENTROPY_SAMPLE_BLOCKS {
+        (0..(len + ENTROPY_BLOCK_LEN - 1) / ENTROPY_BLOCK_LEN)
+            .map(|i| i * ENTROPY_BLOCK_LEN)
+            .collect()
+    } else {
+        let step = (len - ENTROPY_BLOCK_LEN) / (ENTROPY_SAMPLE_BLOCKS - 1);
+        (0..ENTROPY_SAMPLE_BLOCKS).map(|i| i * step).collect()
+    };
+    let mut file = fs::File::open(path)?;
+    let mut counts = [0u64; 256];
+    let mut block = Vec::with_capacity(ENTROPY_BLOCK_LEN as usize);
+    for offset in offsets {
+        file.seek(SeekFrom::Start(offset))?;
+        block.clear();
+        (&mut file).take(ENTROPY_BLOCK_LEN).read_to_end(&mut block)?;
+        for &byte in &block {
+            counts[byte as usize] += 1;
+        }
+    }
+    let total: u64 = counts.iter().sum();
+    if total == 0 {
+        return Ok(0.0);
+    }
+    Ok(counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum())
+}
+
+#[derive(Debug, serde::Serialize)]
+struct ShortcutTarget {
+    target: String,
+    arguments: String,
+    working_dir: Option<String>,
+    // false when the shortcut points at something since moved or deleted
+    target_exists: bool,
+}
+
+// Reads what a .lnk points at without resolving it, so a broken shortcut
+// never triggers the shell's search for its target.
+#[tauri::command]
+async fn resolve_shortcut(path: String) -> Result<ShortcutTarget, Error> {
+    let path = normalize_dir_input(&path);
+    let is_shortcut = Path::new(&path)
+        .extension()
+        .map_or(false, |e| e.eq_ignore_ascii_case("lnk"));
+    if !is_shortcut {
+        return Err(Error::InvalidInput(format!("'{}' is not a .lnk shortcut", path)));
+    }
+    fs::metadata(&path)?;
+    tauri::async_runtime::spawn_blocking(move || platform_resolve_shortcut(Path::new(&path)))
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?
+}
+
+#[cfg(target_os = "windows")]
+fn platform_resolve_shortcut(path: &Path) -> Result<ShortcutTarget, Error> {
+    use windows::core::{ComInterface, PCWSTR};
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, IPersistFile, CLSCTX_INPROC_SERVER,
+        COINIT_APARTMENTTHREADED, STGM_READ,
+    };
+    use windows::Win32::UI::Shell::{IShellLinkW, ShellLink};
+
+    let com_error = |e: windows::core::Error| Error::Background(e.to_string());
+    let text = |buf: &[u16]| {
+        let len = buf.iter().position(|&c| c == 0).unwrap_or(buf.len());
+        String::from_utf16_lossy(&buf[..len])
+    };
+    let wide_path = to_wide(path.as_os_str());
+    // SAFETY: COM is initialised for this blocking-pool thread before use,
+    // the path is NUL-terminated and every buffer outlives its call.
+    let (target, arguments, working_dir) = unsafe {
+        // S_FALSE on a thread that already has an apartment is fine
+        let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+        let link: IShellLinkW =
+            CoCreateInstance(&ShellLink, None, CLSCTX_INPROC_SERVER).map_err(com_error)?;
+        let file: IPersistFile = link.cast().map_err(com_error)?;
+        file.Load(PCWSTR(wide_path.as_ptr()), STGM_READ)
+            .map_err(|e| Error::InvalidInput(format!("{}: {}", path.display(), e)))?;
+        // long enough for extended-length paths and command lines
+        let mut buf = vec![0u16; 32_768];
+        link.GetPath(&mut buf, std::ptr::null_mut(), 0).map_err(com_error)?;
+        let target = text(&buf);
+        buf.fill(0);
+        link.GetArguments(&mut buf).map_err(com_error)?;
+        let arguments = text(&buf);
+        buf.fill(0);
+        link.GetWorkingDirectory(&mut buf).map_err(com_error)?;
+        (target, arguments, text(&buf))
+    };
+    // shortcuts to virtual items (Control Panel, printers) have no file path
+    if target.is_empty() {
+        return Err(Error::Unsupported("shortcuts to items that are not files or folders"));
+    }
+    Ok(ShortcutTarget {
+        target_exists: Path::new(&target).exists(),
+        target,
+        arguments,
+        working_dir: Some(working_dir).filter(|d| !d.is_empty()),
+    })
+}
+
+#[cfg(not(target_os = "windows"))]
+fn platform_resolve_shortcut(_path: &Path) -> Result<ShortcutTarget, Error> {
+    Err(Error::Unsupported("reading .lnk shortcuts"))
+}
+
+// Sync commands run on the main thread, whose message loop the dialog needs.
+#[tauri::command]
+fn show_os_properties(path: String) -> Result<(), Error> {
+    let path = normalize_dir_input(&path);
+    fs::symlink_metadata(&path)?;
+    platform_show_properties(Path::new(&path))
+}
+
+#[cfg(target_os = "windows")]
+fn platform_show_properties(path: &Path) -> Result<(), Error> {
+    use windows_sys::Win32::UI::Shell::SHObjectProperties;
+
+    let (object_type, wide_path) = properties_args(path);
+    // SAFETY: the path is NUL-terminated and outlives the call; the dialog
+    // copies what it needs before returning.
+    let ok = unsafe { SHObjectProperties(0, object_type, wide_path.as_ptr(), std::ptr::null()) };
+    if ok == 0 {
+        return Err(Error::Io(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+// The object type and NUL-terminated path SHObjectProperties takes. The
+// shell parses neither forward slashes nor the `\\?\` prefix canonicalize
+// leaves, so both are rewritten to the plain form.
+#[cfg(target_os = "windows")]
+fn properties_args(path: &Path) -> (u32, Vec<u16>) {
+    use windows_sys::Win32::UI::Shell::SHOP_FILEPATH;
+
+    let wide = |s: &str| s.encode_utf16().collect::<Vec<u16>>();
+    let mut path: Vec<u16> = to_wide(path.as_os_str())
+        .into_iter()
+        .map(|c| if c == u16::from(b'/') { u16::from(b'\\') } else { c })
+        .collect();
+    let (unc, verbatim) = (wide(r"\\?\UNC\"), wide(r"\\?\"));
+    if path.starts_with(&unc) {
+        path.splice(..unc.len(), wide(r"\\"));
+    } else if path.starts_with(&verbatim) {
+        path.drain(..verbatim.len());
+    }
+    (SHOP_FILEPATH as u32, path)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn platform_show_properties(_path: &Path) -> Result<(), Error> {
+    Err(Error::Unsupported("native properties dialog"))
+}
+
+// Puts the files themselves on the clipboard, so pasting in Explorer (or a
+// Linux file manager) copies them, or moves them when `cut` is set.
+#[tauri::command]
+fn clipboard_copy_files(window: tauri::Window, paths: Vec<String>, cut: bool) -> Result<(), Error> {
+    if paths.is_empty() {
+        return Err(Error::InvalidInput("no files to put on the clipboard".to_string()));
+    }
+    let paths: Vec<String> = paths.iter().map(|p| normalize_dir_input(p)).collect();
+    for path in &paths {
+        fs::symlink_metadata(path)?;
+    }
+    platform_clipboard_copy_files(&window, &paths, cut)
+}
+
+#[cfg(target_os = "windows")]
+fn platform_clipboard_copy_files(
+    window: &tauri::Window,
+    paths: &[String],
+    cut: bool,
+) -> Result<(), Error> {
+    use windows_sys::Win32::Foundation::GlobalFree;
+    use windows_sys::Win32::System::DataExchange::{
+        CloseClipboard, EmptyClipboard, OpenClipboard, RegisterClipboardFormatW, SetClipboardData,
+    };
+    use windows_sys::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+    use windows_sys::Win32::System::Ole::{CF_HDROP, DROPEFFECT_COPY, DROPEFFECT_MOVE};
+
+    // SAFETY: the clipboard is open; on success it owns the allocation.
+    unsafe fn set_data(format: u32, bytes: &[u8]) -> Result<(), Error> {
+        let memory = GlobalAlloc(GMEM_MOVEABLE, bytes.len());
+        if memory.is_null() {
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+        let target = GlobalLock(memory) as *mut u8;
+        if target.is_null() {
+            GlobalFree(memory);
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), target, bytes.len());
+        GlobalUnlock(memory);
+        if SetClipboardData(format, memory as isize) == 0 {
+            let err = std::io::Error::last_os_error();
+            GlobalFree(memory);
+            return Err(Error::Io(err));
+        }
+        Ok(())
+    }
+
+    // With no owner window EmptyClipboard leaves SetClipboardData failing.
+    let owner = window.hwnd().map_err(|e| Error::Background(e.to_string()))?.0;
+    let effect = if cut { DROPEFFECT_MOVE } else { DROPEFFECT_COPY };
+    let format_name = to_wide(std::ffi::OsStr::new("Preferred DropEffect"));
+    // SAFETY: the clipboard is opened and closed here on one thread, and the
+    // format name is NUL-terminated.
+    unsafe {
+        if OpenClipboard(owner) == 0 {
+            return Err(Error::Io(std::io::Error::last_os_error()));
+        }
+        let result = (|| {
+            if EmptyClipboard() == 0 {
+                return Err(Error::Io(std::io::Error::last_os_error()));
+            }
+            set_data(u32::from(CF_HDROP), &dropfiles_buffer(paths))?;
+            let drop_effect = RegisterClipboardFormatW(format_name.as_ptr());
+            if drop_effect != 0 {
+                set_data(drop_effect, &effect.to_le_bytes())?;
+            }
+            Ok(())
+        })();
+        CloseClipboard();
+        result
+    }
+}
+
+// A DROPFILES header followed by every path as NUL-terminated UTF-16, with
+// one more NUL closing the list.
+#[cfg(target_os = "windows")]
+fn dropfiles_buffer(paths: &[String]) -> Vec<u8> {
+    use windows_sys::Win32::UI::Shell::DROPFILES;
+
+    let header = std::mem::size_of::<DROPFILES>();
+    let mut buffer = Vec::new();
+    // pFiles, then pt and fNC left zero, then fWide
+    buffer.extend_from_slice(&(header as u32).to_le_bytes());
+    buffer.resize(header - 4, 0);
+    buffer.extend_from_slice(&1u32.to_le_bytes());
+    for path in paths {
+        for unit in to_wide(std::ffi::OsStr::new(path)) {
+            buffer.extend_from_slice(&unit.to_le_bytes());
+        }
+    }
+    buffer.extend_from_slice(&[0, 0]);
+    buffer
+}
+
+// Goes through wl-copy or xclip. A plain copy offers text/uri-list, which
+// every file manager reads; a cut needs GNOME's format to be pasted as a move.
+#[cfg(not(target_os = "windows"))]
+fn platform_clipboard_copy_files(
+    _window: &tauri::Window,
+    paths: &[String],
+    cut: bool,
+) -> Result<(), Error> {
+    use std::io::Write;
+
+    let (mime, body) = clipboard_files_body(paths, cut);
+    let mut command = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        let mut command = std::process::Command::new("wl-copy");
+        command.args(["--type", mime]);
+        command
+    } else {
+        let mut command = std::process::Command::new("xclip");
+        command.args(["-selection", "clipboard", "-t", mime]);
+        command
+    };
+    let mut child = match command.stdin(std::process::Stdio::piped()).spawn() {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::Unsupported("file clipboard without wl-copy or xclip"))
+        }
+        Err(e) => return Err(Error::Io(e)),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body.as_bytes())?;
+    }
+    // both fork a server for the selection, so this returns right away
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::Background(format!("clipboard helper failed: {}", status)));
+    }
+    Ok(())
+}
+
+// The target type and contents to offer for `paths`.
+#[cfg(not(target_os = "windows"))]
+fn clipboard_files_body(paths: &[String], cut: bool) -> (&'static str, String) {
+    let uris: Vec<String> = paths.iter().map(|p| file_uri(p)).collect();
+    if cut {
+        ("x-special/gnome-copied-files", format!("cut\n{}", uris.join("\n")))
+    } else {
+        ("text/uri-list", format!("{}\r\n", uris.join("\r\n")))
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn file_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ElevationOutcome {
+    Launched,
+    // the user declined the UAC / polkit prompt
+    Cancelled,
+}
+
+#[tauri::command]
+async fn open_file_elevated(path: String) -> Result<ElevationOutcome, Error> {
+    if !is_executable(Path::new(&path)) {
+        return Err(Error::NotExecutable(path));
+    }
+    // The elevation prompt blocks the calling thread until it is answered.
+    tauri::async_runtime::spawn_blocking(move || platform_open_elevated(Path::new(&path)))
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?
+}
+
+#[cfg(target_os = "windows")]
+fn platform_open_elevated(path: &Path) -> Result<ElevationOutcome, Error> {
+    use windows_sys::Win32::Foundation::ERROR_CANCELLED;
+    use windows_sys::Win32::UI::Shell::ShellExecuteW;
+    use windows_sys::Win32::UI::WindowsAndMessaging::SW_SHOWNORMAL;
+
+    let verb = to_wide(std::ffi::OsStr::new("runas"));
+    let file = to_wide(path.as_os_str());
+    let directory = path.parent().map(|p| to_wide(p.as_os_str()));
+    // SAFETY: all strings are NUL-terminated and outlive the call.
+    let result = unsafe {
+        ShellExecuteW(
+            0,
+            verb.as_ptr(),
+            file.as_ptr(),
+            std::ptr::null(),
+            directory.as_ref().map_or(std::ptr::null(), |d| d.as_ptr()),
+            SW_SHOWNORMAL,
+        )
+    };
+    // Values above 32 mean success; anything else is an error code, with the
+    // details left in GetLastError.
+    if result > 32 {
+        return Ok(ElevationOutcome::Launched);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.raw_os_error() == Some(ERROR_CANCELLED as i32) {
+        return Ok(ElevationOutcome::Cancelled);
+    }
+    Err(Error::Io(err))
+}
+
+#[cfg(not(target_os = "windows"))]
+fn platform_open_elevated(path: &Path) -> Result<ElevationOutcome, Error> {
+    let status = match std::process::Command::new("pkexec").arg(path).status() {
+        Ok(status) => status,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::Unsupported("elevation without pkexec"))
+        }
+        Err(e) => return Err(Error::Io(e)),
+    };
+    // pkexec exits with 126 when the authentication dialog is dismissed.
+    match status.code() {
+        Some(126) => Ok(ElevationOutcome::Cancelled),
+        _ => Ok(ElevationOutcome::Launched),
+    }
+}
+
+// Anything Windows will run on double-click, not just what open_file_elevated
+// accepts.
+const RISKY_EXTENSIONS: &[&str] = &[
+    "exe", "msi", "bat", "cmd", "com", "scr", "pif", "ps1", "vbs", "vbe", "js", "jse", "wsf",
+    "hta", "jar", "lnk", "cpl",
+];
+
+const DOCUMENT_EXTENSIONS: &[&str] = &[
+    "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx", "txt", "rtf", "jpg", "jpeg", "png", "gif",
+    "mp3", "mp4", "zip",
+];
+
+// Only metadata and extension checks plus, on Windows, one stream probe per
+// file; contents are never read.
+#[tauri::command]
+fn assess_risk(paths: Vec<String>) -> RiskReport {
+    let mut report = RiskReport::default();
+    for path in paths {
+        let path = normalize_dir_input(&path);
+        match risk_flags(Path::new(&path)) {
+            Ok(flags) => report.assessed.push(RiskAssessment { path, flags }),
+            Err(e) => report.failed.push(FailedEntry::new(&path, &e.to_string())),
+        }
+    }
+    report
+}
+
+fn risk_flags(path: &Path) -> std::io::Result<RiskFlags> {
+    let metadata = fs::symlink_metadata(path)?;
+    if metadata.is_dir() {
+        return Ok(RiskFlags::default());
+    }
+
+    let lower_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let mut parts = lower_name.rsplit('.');
+    let last = parts.next().unwrap_or_default();
+    let previous = parts.next();
+    let has_extension = lower_name.contains('.');
+
+    let executable = has_extension && RISKY_EXTENSIONS.contains(&last);
+    let masquerading_extension = executable
+        && parts.next().is_some()
+        && previous.map_or(false, |ext| DOCUMENT_EXTENSIONS.contains(&ext));
+
+    Ok(RiskFlags {
+        executable,
+        masquerading_extension,
+        mark_of_the_web: zone_identifier_path(path).map_or(false, |zone| zone.exists()),
+        hidden_executable: executable && is_hidden(path, &metadata),
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn is_hidden(_path: &Path, metadata: &fs::Metadata) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    metadata.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_hidden(path: &Path, _metadata: &fs::Metadata) -> bool {
+    path.file_name()
+        .map_or(false, |n| n.to_string_lossy().starts_with('.'))
+}
+
+// The stream is addressable as "file:Zone.Identifier" through the normal
+// file APIs, so no stream enumeration is needed.
+#[cfg(target_os = "windows")]
+fn zone_identifier_path(path: &Path) -> Option<std::path::PathBuf> {
+    let mut stream = path.as_os_str().to_os_string();
+    stream.push(":Zone.Identifier");
+    Some(stream.into())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn zone_identifier_path(_path: &Path) -> Option<std::path::PathBuf> {
+    None
+}
+
+// Returns false when the file carried no mark to begin with.
+#[tauri::command]
+fn remove_mark_of_the_web(db: State<DbConnection>, path: String) -> Result<bool, Error> {
+    let path = normalize_dir_input(&path);
+    ensure_writable(&*db.0.lock().map_err(|_| Error::MutexPoison)?, &[&path])?;
+    let zone = zone_identifier_path(Path::new(&path))
+        .ok_or(Error::Unsupported("mark of the web"))?;
+    match fs::remove_file(zone) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(Error::Io(e)),
+    }
+}
+
+#[tauri::command]
+fn list_alternate_streams(path: String) -> Result<Vec<AlternateStream>, Error> {
+    alternate_streams(Path::new(&normalize_dir_input(&path)))
+}
+
+// `encoding` takes any WHATWG label, such as the one detect_encoding returns.
+#[tauri::command]
+fn read_alternate_stream(
+    path: String,
+    stream: String,
+    max_bytes: u64,
+    encoding: Option<String>,
+) -> Result<StreamContents, Error> {
+    use std::io::Read;
+
+    let encoding = match encoding {
+        Some(label) => encoding_for_label(&label)?,
+        None => encoding_rs::UTF_8,
+    };
+    let stream_path = alternate_stream_path(Path::new(&normalize_dir_input(&path)), &stream)?;
+    let file = fs::File::open(&stream_path)?;
+    let size = file.metadata()?.len();
+    let mut data = Vec::new();
+    file.take(max_bytes).read_to_end(&mut data)?;
+    Ok(StreamContents {
+        data: encoding.decode(&data).0.into_owned(),
+        size,
+        truncated: size > max_bytes,
+    })
+}
+
+const ENCODING_SNIFF_BYTES: u64 = 64 * 1024;
+
+fn encoding_for_label(label: &str) -> Result<&'static encoding_rs::Encoding, Error> {
+    encoding_rs::Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| Error::InvalidInput(format!("unknown encoding '{}'", label)))
+}
+
+// Sniffs the start of the file: a byte order mark wins, then UTF-16 guessed
+// from where the zero bytes fall, then text that is valid UTF-8. Anything
+// else is taken for windows-1252, which also covers Latin-1.
+#[tauri::command]
+fn detect_encoding(path: String) -> Result<String, Error> {
+    use std::io::Read;
+
+    let mut sample = Vec::new();
+    fs::File::open(normalize_dir_input(&path))?
+        .take(ENCODING_SNIFF_BYTES)
+        .read_to_end(&mut sample)?;
+    Ok(sniff_encoding(&sample).name().to_string())
+}
+
+fn sniff_encoding(sample: &[u8]) -> &'static encoding_rs::Encoding {
+    if let Some((encoding, _)) = encoding_rs::Encoding::for_bom(sample) {
+        return encoding;
+    }
+    // Zero bytes are valid UTF-8, so UTF-16 text that is mostly ASCII would
+    // pass the UTF-8 check below; its layout has to be ruled out first.
+    let zeros_at = |parity: usize| {
+        sample.iter().skip(parity).step_by(2).filter(|&&b| b == 0).count()
+    };
+    let half = sample.len() / 2;
+    let (even, odd) = (zeros_at(0), zeros_at(1));
+    if half > 0 && odd * 2 > half && even * 10 < half {
+        return encoding_rs::UTF_16LE;
+    }
+    if half > 0 && even * 2 > half && odd * 10 < half {
+        return encoding_rs::UTF_16BE;
+    }
+    match std::str::from_utf8(sample) {
+        Ok(_) => encoding_rs::UTF_8,
+        // cut off mid-character by the sample limit
+        Err(e) if e.error_len().is_none() => encoding_rs::UTF_8,
+        Err(_) => encoding_rs::WINDOWS_1252,
+    }
+}
+
+#[tauri::command]
+fn delete_alternate_stream(
+    db: State<DbConnection>,
+    path: String,
+    stream: String,
+) -> Result<(), Error> {
+    let path = normalize_dir_input(&path);
+    ensure_writable(&*db.0.lock().map_err(|_| Error::MutexPoison)?, &[&path])?;
+    let stream_path = alternate_stream_path(Path::new(&path), &stream)?;
+    fs::remove_file(stream_path).map_err(Into::into)
+}
+
+// Accepts "Zone.Identifier", ":Zone.Identifier" or ":Zone.Identifier:$DATA".
+// The unnamed main stream is refused: deleting it would delete the file.
+#[cfg(target_os = "windows")]
+fn alternate_stream_path(path: &Path, stream: &str) -> Result<std::path::PathBuf, Error> {
+    let name = stream.trim_start_matches(':');
+    let name = name.strip_suffix(":$DATA").unwrap_or(name);
+    if name.is_empty() || name.contains([':', '\\', '/']) {
+        return Err(Error::InvalidInput(format!("'{}' is not an alternate stream name", stream)));
+    }
+    let mut stream_path = path.as_os_str().to_os_string();
+    stream_path.push(format!(":{}", name));
+    Ok(stream_path.into())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn alternate_stream_path(_path: &Path, _stream: &str) -> Result<std::path::PathBuf, Error> {
+    Err(Error::Unsupported("alternate data streams"))
+}
+
+#[cfg(target_os = "windows")]
+fn alternate_streams(path: &Path) -> Result<Vec<AlternateStream>, Error> {
+    use windows_sys::Win32::Foundation::{ERROR_HANDLE_EOF, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{
+        FindClose, FindFirstStreamW, FindNextStreamW, FindStreamInfoStandard,
+        WIN32_FIND_STREAM_DATA,
+    };
+
+    let wide_path = to_wide(path.as_os_str());
+    let mut streams = Vec::new();
+    // SAFETY: WIN32_FIND_STREAM_DATA is plain data written by the API, the
+    // path outlives the calls and the handle is closed exactly once.
+    unsafe {
+        let mut data: WIN32_FIND_STREAM_DATA = std::mem::zeroed();
+        let handle = FindFirstStreamW(
+            wide_path.as_ptr(),
+            FindStreamInfoStandard,
+            &mut data as *mut _ as *mut std::ffi::c_void,
+            0,
+        );
+        if handle == INVALID_HANDLE_VALUE {
+            let err = std::io::Error::last_os_error();
+            // Raised for entries without any stream, e.g. most directories.
+            if err.raw_os_error() == Some(ERROR_HANDLE_EOF as i32) {
+                return Ok(streams);
+            }
+            return Err(Error::Io(err));
+        }
+        loop {
+            let name_buf = &data.cStreamName;
+            let len = name_buf.iter().position(|&c| c == 0).unwrap_or(name_buf.len());
+            let raw = String::from_utf16_lossy(&name_buf[..len]);
+            // Names come back as ":name:$DATA"; "::$DATA" is the main stream.
+            let name = raw.trim_start_matches(':');
+            let name = name.strip_suffix(":$DATA").unwrap_or(name);
+            if !name.is_empty() {
+                streams.push(AlternateStream {
+                    name: name.to_string(),
+                    size: data.StreamSize as u64,
+                });
+            }
+            if FindNextStreamW(handle, &mut data as *mut _ as *mut std::ffi::c_void) == 0 {
+                break;
+            }
+        }
+        FindClose(handle);
+    }
+    Ok(streams)
+}
+
+#[cfg(not(target_os = "windows"))]
+fn alternate_streams(_path: &Path) -> Result<Vec<AlternateStream>, Error> {
+    Err(Error::Unsupported("alternate data streams"))
+}
+
+fn size_with_streams(root: &Path, accurate: bool) -> Result<u64, Error> {
+    let mut seen = std::collections::HashSet::new();
+    let mut total = 0;
+    for entry in WalkDir::new(root).follow_links(false).into_iter().filter_map(Result::ok) {
+        if entry.file_type().is_file() {
+            let Ok(metadata) = entry.metadata() else { continue };
+            if accurate {
+                if let Some((links, key)) = file_identity(entry.path(), &metadata) {
+                    // Streams belong to the file, so a repeated link adds nothing.
+                    if links > 1 && !seen.insert(key) {
+                        continue;
+                    }
+                }
+            }
+            total += metadata.len();
+        }
+        // Directories can carry streams of their own. Unreadable entries are
+        // skipped like in the indexer; only a missing platform API is fatal.
+        match alternate_streams(entry.path()) {
+            Ok(streams) => total += streams.iter().map(|s| s.size).sum::<u64>(),
+            Err(Error::Unsupported(what)) => return Err(Error::Unsupported(what)),
+            Err(_) => {}
+        }
+    }
+    Ok(total)
+}
+
+// Restart Manager can stall for a long time on busy systems or network paths.
+const LOCK_QUERY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+// An empty list means nothing holds the file or the lookup timed out.
+#[tauri::command]
+async fn who_locks_file(path: String) -> Result<Vec<LockingProcess>, Error> {
+    let path = normalize_dir_input(&path);
+    let (tx, rx) = std::sync::mpsc::channel();
+    // A detached thread, so a query that never returns is left behind rather
+    // than holding up the command.
+    std::thread::spawn(move || {
+        let _ = tx.send(platform_locking_processes(Path::new(&path)));
+    });
+    tauri::async_runtime::spawn_blocking(move || {
+        rx.recv_timeout(LOCK_QUERY_TIMEOUT).unwrap_or_else(|_| Ok(Vec::new()))
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+#[cfg(target_os = "windows")]
+fn platform_locking_processes(path: &Path) -> Result<Vec<LockingProcess>, Error> {
+    use windows_sys::Win32::Foundation::{ERROR_MORE_DATA, ERROR_SUCCESS};
+    use windows_sys::Win32::System::RestartManager::{
+        RmEndSession, RmGetList, RmRegisterResources, RmStartSession, CCH_RM_SESSION_KEY,
+        RM_PROCESS_INFO,
+    };
+
+    let os_error = |code: u32| Error::Io(std::io::Error::from_raw_os_error(code as i32));
+    let wide_path = to_wide(path.as_os_str());
+    let mut infos: Vec<RM_PROCESS_INFO> = Vec::new();
+    // SAFETY: every buffer handed to Restart Manager is sized as the API
+    // requires and outlives the calls; the session is always ended.
+    unsafe {
+        let mut session = 0u32;
+        let mut key = [0u16; CCH_RM_SESSION_KEY as usize + 1];
+        let err = RmStartSession(&mut session, 0, key.as_mut_ptr());
+        if err != ERROR_SUCCESS {
+            return Err(os_error(err));
+        }
+        let files = [wide_path.as_ptr()];
+        let mut err = RmRegisterResources(
+            session,
+            1,
+            files.as_ptr(),
+            0,
+            std::ptr::null(),
+            0,
+            std::ptr::null(),
+        );
+        // The list can grow between the sizing call and the fetch; retry a
+        // few times before giving up.
+        let mut fetched = false;
+        for _ in 0..3 {
+            if err != ERROR_SUCCESS {
+                break;
+            }
+            let mut needed = 0u32;
+            let mut count = infos.len() as u32;
+            let mut reasons = 0u32;
+            err = RmGetList(session, &mut needed, &mut count, infos.as_mut_ptr(), &mut reasons);
+            if err == ERROR_MORE_DATA {
+                infos = vec![std::mem::zeroed(); needed as usize];
+                err = ERROR_SUCCESS;
+                continue;
+            }
+            if err == ERROR_SUCCESS {
+                infos.truncate(count as usize);
+                fetched = true;
+            }
+            break;
+        }
+        RmEndSession(session);
+        if err != ERROR_SUCCESS {
+            return Err(os_error(err));
+        }
+        if !fetched {
+            return Err(os_error(ERROR_MORE_DATA));
+        }
+    }
+
+    let mut system = sysinfo::System::new();
+    Ok(infos
+        .iter()
+        .map(|info| {
+            let pid = info.Process.dwProcessId;
+            let len = info.strAppName.iter().position(|&c| c == 0).unwrap_or(info.strAppName.len());
+            let app_name = String::from_utf16_lossy(&info.strAppName[..len]);
+            let sys_pid = sysinfo::Pid::from_u32(pid);
+            system.refresh_process(sys_pid);
+            LockingProcess {
+                pid,
+                name: system
+                    .process(sys_pid)
+                    .map(|p| p.name().to_string())
+                    .unwrap_or_else(|| app_name.clone()),
+                app_name: Some(app_name).filter(|n| !n.is_empty()),
+            }
+        })
+        .collect())
+}
+
+// Best effort: only processes whose /proc/<pid>/fd we may read are seen.
+#[cfg(target_os = "linux")]
+fn platform_locking_processes(path: &Path) -> Result<Vec<LockingProcess>, Error> {
+    let target = fs::canonicalize(path)?;
+    let mut holders = Vec::new();
+    for proc_entry in fs::read_dir("/proc")?.filter_map(Result::ok) {
+        let Some(pid) = proc_entry.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) else {
+            continue;
+        };
+        let Ok(fds) = fs::read_dir(proc_entry.path().join("fd")) else { continue };
+        let holds = fds
+            .filter_map(Result::ok)
+            .any(|fd| fs::read_link(fd.path()).map_or(false, |link| link == target));
+        if holds {
+            let name = fs::read_to_string(proc_entry.path().join("comm"))
+                .map(|s| s.trim_end().to_string())
+                .unwrap_or_default();
+            holders.push(LockingProcess { pid, name, app_name: None });
+        }
+    }
+    Ok(holders)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn platform_locking_processes(_path: &Path) -> Result<Vec<LockingProcess>, Error> {
+    Err(Error::Unsupported("finding processes that hold a file"))
+}
+
+#[tauri::command]
+fn get_owner(path: String) -> Result<FileOwner, Error> {
+    let path = normalize_dir_input(&path);
+    platform_owner(Path::new(&path))
+}
+
+#[cfg(target_os = "windows")]
+fn platform_owner(path: &Path) -> Result<FileOwner, Error> {
+    use windows_sys::Win32::Foundation::{LocalFree, ERROR_SUCCESS, PSID};
+    use windows_sys::Win32::Security::Authorization::{GetNamedSecurityInfoW, SE_FILE_OBJECT};
+    use windows_sys::Win32::Security::{
+        GROUP_SECURITY_INFORMATION, OWNER_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR,
+    };
+
+    let wide_path = to_wide(path.as_os_str());
+    let mut owner: PSID = std::ptr::null_mut();
+    let mut group: PSID = std::ptr::null_mut();
+    let mut descriptor: PSECURITY_DESCRIPTOR = std::ptr::null_mut();
+    // SAFETY: all out-pointers are valid; the SIDs point into `descriptor`,
+    // which stays alive until the LocalFree below.
+    let status = unsafe {
+        GetNamedSecurityInfoW(
+            wide_path.as_ptr(),
+            SE_FILE_OBJECT,
+            OWNER_SECURITY_INFORMATION | GROUP_SECURITY_INFORMATION,
+            &mut owner,
+            &mut group,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut descriptor,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(Error::Io(std::io::Error::from_raw_os_error(status as i32)));
+    }
+    let result = FileOwner {
+        owner: account_name(owner),
+        group: account_name(group),
+        owner_id: sid_string(owner),
+    };
+    // SAFETY: `descriptor` was allocated by GetNamedSecurityInfoW.
+    unsafe { LocalFree(descriptor as _) };
+    Ok(result)
+}
+
+// DOMAIN\name, or None for SIDs with no account behind them, such as those
+// of deleted users.
+#[cfg(target_os = "windows")]
+fn account_name(sid: windows_sys::Win32::Foundation::PSID) -> Option<String> {
+    use windows_sys::Win32::Security::LookupAccountSidW;
+
+    if sid.is_null() {
+        return None;
+    }
+    let (mut name_len, mut domain_len, mut sid_use) = (0u32, 0u32, 0);
+    // SAFETY: a first call with no buffers only reports the sizes needed.
+    unsafe {
+        LookupAccountSidW(
+            std::ptr::null(),
+            sid,
+            std::ptr::null_mut(),
+            &mut name_len,
+            std::ptr::null_mut(),
+            &mut domain_len,
+            &mut sid_use,
+        )
+    };
+    if name_len == 0 {
+        return None;
+    }
+    let mut name = vec![0u16; name_len as usize];
+    let mut domain = vec![0u16; domain_len as usize];
+    // SAFETY: the buffers are as large as the first call asked for.
+    let ok = unsafe {
+        LookupAccountSidW(
+            std::ptr::null(),
+            sid,
+            name.as_mut_ptr(),
+            &mut name_len,
+            domain.as_mut_ptr(),
+            &mut domain_len,
+            &mut sid_use,
+        )
+    };
+    if ok == 0 {
+        return None;
+    }
+    let name = String::from_utf16_lossy(&name[..name_len as usize]);
+    let domain = String::from_utf16_lossy(&domain[..domain_len as usize]);
+    Some(if domain.is_empty() {
+        name
+    } else {
+        format!("{}\\{}", domain, name)
+    })
+}
+
+#[cfg(target_os = "windows")]
+fn sid_string(sid: windows_sys::Win32::Foundation::PSID) -> Option<String> {
+    use windows_sys::Win32::Foundation::LocalFree;
+    use windows_sys::Win32::Security::Authorization::ConvertSidToStringSidW;
+
+    if sid.is_null() {
+        return None;
+    }
+    let mut wide: windows_sys::core::PWSTR = std::ptr::null_mut();
+    // SAFETY: on success `wide` is a NUL-terminated string we free below.
+    if unsafe { ConvertSidToStringSidW(sid, &mut wide) } == 0 {
+        return None;
+    }
+    let text = unsafe {
+        let len = (0..).take_while(|&i| *wide.add(i) != 0).count();
+        String::from_utf16_lossy(std::slice::from_raw_parts(wide, len))
+    };
+    unsafe { LocalFree(wide as _) };
+    Some(text)
+}
+
+// Names come from the local passwd and group files, so accounts known only to
+// a directory service show up by id alone.
+#[cfg(unix)]
+fn platform_owner(path: &Path) -> Result<FileOwner, Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::symlink_metadata(path)?;
+    Ok(FileOwner {
+        owner: local_account_name("/etc/passwd", metadata.uid()),
+        group: local_account_name("/etc/group", metadata.gid()),
+        owner_id: Some(metadata.uid().to_string()),
+    })
+}
+
+// Both files are "name:password:id:..." per line.
+#[cfg(unix)]
+fn local_account_name(file: &str, id: u32) -> Option<String> {
+    fs::read_to_string(file).ok()?.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        let name = fields.next()?;
+        fields.next()?;
+        let line_id: u32 = fields.next()?.parse().ok()?;
+        (line_id == id).then(|| name.to_string())
+    })
+}
+
+#[cfg(not(any(target_os = "windows", unix)))]
+fn platform_owner(_path: &Path) -> Result<FileOwner, Error> {
+    Err(Error::Unsupported("file owners"))
+}
+
+#[cfg(target_os = "windows")]
+fn to_wide(s: &std::ffi::OsStr) -> Vec<u16> {
+    use std::os::windows::ffi::OsStrExt;
+    s.encode_wide().chain(std::iter::once(0)).collect()
+}
+
+// names_only skips the per-entry stat; callers fetch size/mtime lazily via
+// get_file_meta_command for the rows they actually show.
+#[tauri::command]
+fn list_directory_contents(
+    db: State<DbConnection>,
+    path: String,
+    names_only: Option<bool>,
+) -> Result<DirectoryListing, Error> {
+    let path = normalize_dir_input(&path);
+    let dir = Path::new(&path);
+
+    if names_only.unwrap_or(false) {
+        return Ok(DirectoryListing::NamesOnly(read_entry_names(dir)?));
+    }
+
+    let mut contents = read_entry_metas(dir)?;
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    FileKinds::load(&conn)?.annotate(&mut contents);
+    Ok(DirectoryListing::Full(contents))
+}
+
+fn read_entry_names(dir: &Path) -> Result<Vec<EntryName>, Error> {
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).map_err(Error::Io)? {
+        match entry {
+            Ok(entry) => names.push(EntryName {
+                name: entry.file_name().to_string_lossy().to_string(),
+                path: entry.path().to_string_lossy().to_string(),
+                is_dir: entry.file_type().map(|t| t.is_dir()).unwrap_or(false),
+            }),
+            Err(e) => eprintln!("Error reading directory entry: {}", e),
+        }
+    }
+    Ok(names)
+}
+
+fn read_entry_metas(dir: &Path) -> Result<Vec<FileMeta>, Error> {
+    let mut contents = Vec::new();
+    for entry in fs::read_dir(dir).map_err(Error::Io)? {
+        match entry {
+            Ok(entry) => {
+                if let Ok(meta) = get_file_meta(entry.path().as_ref()) {
+                    contents.push(meta);
+                }
+            }
+            Err(e) => eprintln!("Error reading directory entry: {}", e),
+        }
+    }
+    Ok(contents)
+}
+
+// Goes through ShellExecute rather than `cmd /C start`, which spawned a
+// console per file and mangled paths containing '&' or '^'.
+#[tauri::command]
+fn open_file(db: State<DbConnection>, path: String) -> Result<(), Error> {
+    ensure_allowed(&*db.0.lock().map_err(|_| Error::MutexPoison)?, &[&path])?;
+    platform_invoke_verb(Path::new(&path), "open")
+}
+
+const OPEN_FILES_CONFIRM_SETTING: &str = "open_files_confirm_above";
+const DEFAULT_OPEN_FILES_CONFIRM_ABOVE: usize = 15;
+
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum OpenFilesOutcome {
+    // call again with this token to launch them anyway
+    ConfirmationRequired { count: usize, confirm_token: String },
+    // lines up with the paths given; Err holds why that one did not open
+    Opened { results: Vec<Result<(), String>> },
+}
+
+// The same selection always yields the same token, so a confirmation only
+// covers the exact set of files it was shown for.
+fn open_files_token(paths: &[String]) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    paths.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+fn open_files_confirm_above(conn: &Connection) -> Result<usize> {
+    Ok(get_setting(conn, OPEN_FILES_CONFIRM_SETTING)?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_OPEN_FILES_CONFIRM_ABOVE))
+}
+
+// Opens each path with its default handler in one call. Selections larger
+// than the confirm threshold need the token from a first, refused call, so
+// a stray Enter does not launch dozens of windows. Every file gets its own
+// launch; handlers are not asked to take several at once.
+#[tauri::command]
+async fn open_files(
+    db: State<'_, DbConnection>,
+    paths: Vec<String>,
+    confirm_token: Option<String>,
+) -> Result<OpenFilesOutcome, Error> {
+    let limit = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        ensure_allowed(&conn, &paths)?;
+        open_files_confirm_above(&conn)?
+    };
+    if paths.len() > limit {
+        let expected = open_files_token(&paths);
+        if confirm_token.as_deref() != Some(expected.as_str()) {
+            return Ok(OpenFilesOutcome::ConfirmationRequired {
+                count: paths.len(),
+                confirm_token: expected,
+            });
+        }
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let results = paths
+            .iter()
+            .map(|p| platform_invoke_verb(Path::new(p), "open").map_err(|e| e.to_string()))
+            .collect();
+        OpenFilesOutcome::Opened { results }
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))
+}
+
+#[derive(Debug, serde::Serialize)]
+struct OpenAttempt {
+    path: String,
+    // why it did not open; None when the handler launched
+    error: Option<String>,
+}
+
+// Opens every indexed direct child of `dir` with the given extension ("open
+// all images here"). Unlike open_files there is no confirmation round: more
+// matches than the open files threshold is an error, and the caller can
+// fall back to open_files with an explicit selection.
+#[tauri::command]
+async fn open_all_in_dir(
+    db: State<'_, DbConnection>,
+    dir: String,
+    extension: String,
+) -> Result<Vec<OpenAttempt>, Error> {
+    let dir = normalize_dir_input(&dir);
+    let paths = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        files_to_open_in_dir(&conn, &dir, &extension)?
+    };
+    tauri::async_runtime::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .map(|path| {
+                let error =
+                    platform_invoke_verb(Path::new(&path), "open").err().map(|e| e.to_string());
+                OpenAttempt { path, error }
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))
+}
+
+// The direct children open_all_in_dir attempts, by name.
+fn files_to_open_in_dir(
+    conn: &Connection,
+    dir: &str,
+    extension: &str,
+) -> Result<Vec<String>, Error> {
+    let extension = extension.trim_start_matches('.');
+    if extension.is_empty() {
+        return Err(Error::InvalidInput("extension must not be empty".to_string()));
+    }
+    ensure_allowed(conn, &[dir])?;
+    let mut matches: Vec<FileMeta> = query_children(conn, dir)?
+        .into_iter()
+        .filter(|f| !f.is_dir)
+        .filter(|f| f.extension.as_deref().map_or(false, |e| e.eq_ignore_ascii_case(extension)))
+        .collect();
+    let limit = open_files_confirm_above(conn)?;
+    if matches.len() > limit {
+        return Err(Error::InvalidInput(format!(
+            "{} .{} files in {}; opening more than {} at once is refused",
+            matches.len(),
+            extension,
+            dir,
+            limit
+        )));
+    }
+    matches.sort_by_key(|f| f.name.to_lowercase());
+    Ok(matches.into_iter().map(|f| f.path).collect())
+}
+
+#[tauri::command]
+fn get_open_files_confirm_above(db: State<DbConnection>) -> Result<usize, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(open_files_confirm_above(&conn)?)
+}
+
+#[tauri::command]
+fn set_open_files_confirm_above(db: State<DbConnection>, count: usize) -> Result<(), Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    set_setting(&conn, OPEN_FILES_CONFIRM_SETTING, &count.to_string())?;
+    Ok(())
+}
+
+#[derive(Clone, serde::Serialize)]
+struct DirectoryInvalidated {
+    dir: String,
+}
+
+// Sent right after an explicit write, once per distinct parent of `paths`, so
+// the views showing those directories refresh without waiting for the
+// coalesced index-updated event.
+fn emit_parents_invalidated<S: AsRef<str>>(app: &tauri::AppHandle, paths: &[S]) {
+    emit_directories_invalidated(app, invalidated_parents(paths));
+}
+
+fn invalidated_parents<S: AsRef<str>>(paths: &[S]) -> std::collections::BTreeSet<String> {
+    paths.iter().filter_map(|p| parent_dir(p.as_ref())).collect()
+}
+
+fn emit_directories_invalidated(app: &tauri::AppHandle, dirs: impl IntoIterator<Item = String>) {
+    let dirs: std::collections::BTreeSet<String> = dirs.into_iter().collect();
+    for dir in dirs {
+        let _ = app.emit_all("directory-invalidated", DirectoryInvalidated { dir });
+    }
+}
+
+const PROTECTED_ROOTS_SETTING: &str = "protected_roots";
+
+fn load_protected_roots(conn: &Connection) -> Result<std::collections::BTreeSet<String>, Error> {
+    match get_setting(conn, PROTECTED_ROOTS_SETTING)? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| Error::InvalidInput(e.to_string())),
+        None => Ok(Default::default()),
+    }
+}
+
+fn save_protected_roots(
+    conn: &Connection,
+    roots: &std::collections::BTreeSet<String>,
+) -> Result<(), Error> {
+    let value = serde_json::to_string(roots).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    set_setting(conn, PROTECTED_ROOTS_SETTING, &value)?;
+    Ok(())
+}
+
+fn ensure_unprotected<S: AsRef<str>>(conn: &Connection, paths: &[S]) -> Result<(), Error> {
+    let roots = load_protected_roots(conn)?;
+    for path in paths {
+        let path = path.as_ref();
+        if let Some(root) = roots.iter().find(|root| is_within(Path::new(path), Path::new(root))) {
+            return Err(Error::Protected(path.to_string(), root.clone()));
+        }
+    }
+    Ok(())
+}
+
+// Makes everything under `root` read-only to this app's move, rename, copy,
+// sync and timestamp commands. Changes made outside the app are unaffected.
+#[tauri::command]
+fn protect_root(db: State<DbConnection>, root: String) -> Result<Vec<String>, Error> {
+    let root = normalize_dir_input(&root);
+    if !Path::new(&root).is_dir() {
+        return Err(Error::InvalidInput(format!("'{}' is not a directory", root)));
+    }
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut roots = load_protected_roots(&conn)?;
+    roots.insert(root);
+    save_protected_roots(&conn, &roots)?;
+    Ok(roots.into_iter().collect())
+}
+
+#[tauri::command]
+fn unprotect_root(db: State<DbConnection>, root: String) -> Result<Vec<String>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut roots = load_protected_roots(&conn)?;
+    roots.remove(&normalize_dir_input(&root));
+    save_protected_roots(&conn, &roots)?;
+    Ok(roots.into_iter().collect())
+}
+
+#[tauri::command]
+fn list_protected_roots(db: State<DbConnection>) -> Result<Vec<String>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(load_protected_roots(&conn)?.into_iter().collect())
+}
+
+const RESTRICTED_MODE_SETTING: &str = "restricted_mode";
+// JSON array of directories allowed on top of the indexed roots.
+const APPROVED_DIRS_SETTING: &str = "approved_dirs";
+
+#[derive(Debug, serde::Serialize)]
+struct RestrictedMode {
+    enabled: bool,
+    approved_dirs: Vec<String>,
+}
+
+fn restricted_mode_enabled(conn: &Connection) -> Result<bool, Error> {
+    Ok(get_setting(conn, RESTRICTED_MODE_SETTING)?.as_deref() == Some("1"))
+}
+
+fn load_approved_dirs(conn: &Connection) -> Result<std::collections::BTreeSet<String>, Error> {
+    match get_setting(conn, APPROVED_DIRS_SETTING)? {
+        Some(value) => serde_json::from_str(&value).map_err(|e| Error::InvalidInput(e.to_string())),
+        None => Ok(Default::default()),
+    }
+}
+
+// Every root an index run has completed for.
+fn completed_roots(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT root FROM index_runs WHERE status = 'completed' AND root != ''",
+    )?;
+    let roots = stmt.query_map([], |row| row.get(0))?.filter_map(Result::ok).collect();
+    Ok(roots)
+}
+
+// Scheduled roots, roots with a completed run and the approved directories,
+// canonicalized. Ones that do not resolve (a drive that is not plugged in)
+// allow nothing until they do.
+fn allowed_roots(conn: &Connection) -> Result<Vec<std::path::PathBuf>, Error> {
+    let mut roots: std::collections::BTreeSet<String> = load_schedules(conn)?.into_keys().collect();
+    roots.extend(completed_roots(conn)?);
+    roots.extend(load_approved_dirs(conn)?);
+    Ok(roots.iter().filter_map(|r| fs::canonicalize(r).ok()).collect())
+}
+
+// Resolves `path` the way the OS would when writing to it: the deepest
+// existing ancestor is canonicalized, so symlinks, junctions and `..` in it
+// are followed, and the rest is appended as plain names. Relative and
+// drive-relative ("C:foo", "\foo") paths, and a trailing `..` past the
+// existing part, are refused rather than guessed at.
+fn resolve_for_check(path: &Path) -> Option<std::path::PathBuf> {
+    if !path.is_absolute() {
+        return None;
+    }
+    let mut existing = path;
+    let mut rest = Vec::new();
+    let base = loop {
+        match fs::canonicalize(existing) {
+            Ok(base) => break base,
+            Err(_) => {
+                // None for a trailing `..`, which refuses the path
+                rest.push(existing.file_name()?);
+                existing = existing.parent()?;
+            }
+        }
+    };
+    let mut resolved = base;
+    resolved.extend(rest.into_iter().rev());
+    Some(resolved)
+}
+
+// With restricted mode on, commands that change or launch files may only
+// touch paths inside allowed_roots; read-only commands are not checked.
+fn ensure_allowed<S: AsRef<str>>(conn: &Connection, paths: &[S]) -> Result<(), Error> {
+    if !restricted_mode_enabled(conn)? {
+        return Ok(());
+    }
+    let roots = allowed_roots(conn)?;
+    for path in paths {
+        let path = path.as_ref();
+        let allowed = resolve_for_check(Path::new(path))
+            .map_or(false, |resolved| roots.iter().any(|root| resolved.starts_with(root)));
+        if !allowed {
+            return Err(Error::Forbidden(path.to_string()));
+        }
+    }
+    Ok(())
+}
+
+// Commands that change files on disk call this first with every path they
+// would write, remove or move away.
+fn ensure_writable<S: AsRef<str>>(conn: &Connection, paths: &[S]) -> Result<(), Error> {
+    ensure_allowed(conn, paths)?;
+    ensure_unprotected(conn, paths)
+}
+
+#[tauri::command]
+fn get_restricted_mode(db: State<DbConnection>) -> Result<RestrictedMode, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(RestrictedMode {
+        enabled: restricted_mode_enabled(&conn)?,
+        approved_dirs: load_approved_dirs(&conn)?.into_iter().collect(),
+    })
+}
+
+#[tauri::command]
+fn set_restricted_mode(db: State<DbConnection>, enabled: bool) -> Result<(), Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    set_setting(&conn, RESTRICTED_MODE_SETTING, if enabled { "1" } else { "0" })?;
+    Ok(())
+}
+
+// Passing approved = false takes a directory back out of the allowed set.
+#[tauri::command]
+fn set_directory_approved(
+    db: State<DbConnection>,
+    dir: String,
+    approved: bool,
+) -> Result<Vec<String>, Error> {
+    let dir = normalize_dir_input(&dir);
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut dirs = load_approved_dirs(&conn)?;
+    if approved {
+        if !Path::new(&dir).is_dir() {
+            return Err(Error::InvalidInput(format!("'{}' is not a directory", dir)));
+        }
+        dirs.insert(dir);
+    } else {
+        dirs.remove(&dir);
+    }
+    let value = serde_json::to_string(&dirs).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    set_setting(&conn, APPROVED_DIRS_SETTING, &value)?;
+    Ok(dirs.into_iter().collect())
+}
+
+#[tauri::command]
+fn move_entry(
+    app: tauri::AppHandle,
+    db: State<DbConnection>,
+    source: String,
+    dest_dir: String,
+    conflict: String,
+) -> Result<Undoable, Error> {
+    let policy: ConflictPolicy = conflict.parse()?;
+    let (source, dest_dir) = (normalize_dir_input(&source), normalize_dir_input(&dest_dir));
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    ensure_writable(&conn, &[&source, &dest_dir])?;
+    let entry = move_one(&conn, &source, Path::new(&dest_dir), policy)?;
+    let undo_token = log_operation(&conn, OperationKind::Move, &source, &entry.path)?;
+    emit_parents_invalidated(&app, &[&source, &entry.path]);
+    Ok(Undoable { entry, undo_token })
+}
+
+// Renames in place; `new_name` is a bare name, not a path.
+#[tauri::command]
+fn rename_entry(
+    app: tauri::AppHandle,
+    db: State<DbConnection>,
+    path: String,
+    new_name: String,
+) -> Result<Undoable, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let renamed = rename_in_place(&conn, &normalize_dir_input(&path), &new_name)?;
+    emit_parents_invalidated(&app, &[&renamed.entry.path]);
+    Ok(renamed)
+}
+
+fn rename_in_place(conn: &Connection, path: &str, new_name: &str) -> Result<Undoable, Error> {
+    let new_name = new_name.trim();
+    if matches!(new_name, "" | "." | "..") || new_name.contains(['/', '\\']) {
+        return Err(Error::InvalidInput(format!("'{}' is not a valid name", new_name)));
+    }
+    let source = Path::new(path);
+    let parent = source
+        .parent()
+        .ok_or_else(|| Error::InvalidInput(format!("'{}' has no parent directory", path)))?;
+    let target = parent.join(new_name);
+    // a case-only rename targets the same file on case-insensitive volumes
+    let same_file = target.to_string_lossy().eq_ignore_ascii_case(path);
+    if target.exists() && !same_file {
+        return Err(Error::AlreadyExists(target.to_string_lossy().to_string()));
+    }
+
+    ensure_writable(conn, &[path])?;
+    fs::rename(source, &target)?;
+    let target = target.to_string_lossy().to_string();
+    rename_in_index(conn, path, &target)?;
+    let entry = get_file_meta(Path::new(&target))?;
+    insert_file_meta(conn, &entry)?;
+    let undo_token = log_operation(conn, OperationKind::Rename, path, &target)?;
+    Ok(Undoable { entry, undo_token })
+}
+
+#[derive(Debug)]
+enum TemplatePart {
+    Literal(String),
+    Name,
+    Ext,
+    // strftime-style format, validated when parsed
+    Date(String),
+    // zero-padding width
+    Sequence(usize),
+}
+
+fn parse_rename_template(template: &str) -> Result<Vec<TemplatePart>, Error> {
+    let invalid = |msg: String| Error::InvalidInput(format!("rename template: {}", msg));
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => return Err(invalid("unmatched '}' (write '}}' for a literal brace)".to_string())),
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => return Err(invalid(format!("unclosed placeholder '{{{}'", field))),
+                    }
+                }
+                if !literal.is_empty() {
+                    parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                }
+                let (key, arg) = match field.split_once(':') {
+                    Some((key, arg)) => (key, Some(arg)),
+                    None => (field.as_str(), None),
+                };
+                parts.push(match (key, arg) {
+                    ("name", None) => TemplatePart::Name,
+                    ("ext", None) => TemplatePart::Ext,
+                    ("date", None) => TemplatePart::Date("%Y-%m-%d".to_string()),
+                    ("date", Some(format)) => {
+                        format_timestamp(0, format)?;
+                        TemplatePart::Date(format.to_string())
+                    }
+                    ("n", None) => TemplatePart::Sequence(1),
+                    ("n", Some(width)) => TemplatePart::Sequence(
+                        width
+                            .parse()
+                            .ok()
+                            .filter(|w| (1..=9).contains(w))
+                            .ok_or_else(|| invalid(format!("'{}' is not a width from 1 to 9", width)))?,
+                    ),
+                    _ => return Err(invalid(format!("unknown placeholder '{{{}}}'", field))),
+                });
+            }
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(TemplatePart::Literal(literal));
+    }
+    if parts.is_empty() {
+        return Err(invalid("template is empty".to_string()));
+    }
+    Ok(parts)
+}
+
+// Trailing dots and spaces are dropped, as Windows would on its own.
+fn render_rename_template(
+    parts: &[TemplatePart],
+    path: &Path,
+    modified: u64,
+    n: usize,
+) -> Result<String, Error> {
+    let mut name = String::new();
+    for part in parts {
+        match part {
+            TemplatePart::Literal(text) => name.push_str(text),
+            TemplatePart::Name => name.push_str(&path.file_stem().unwrap_or_default().to_string_lossy()),
+            TemplatePart::Ext => name.push_str(&path.extension().unwrap_or_default().to_string_lossy()),
+            TemplatePart::Date(format) => name.push_str(&format_timestamp(modified, format)?),
+            TemplatePart::Sequence(width) => name.push_str(&format!("{:0width$}", n, width = *width)),
+        }
+    }
+    Ok(name.trim_end_matches(['.', ' ']).to_string())
+}
+
+// %Y %m %d %H %M %S and %%, the handful a file name needs.
+fn format_timestamp(secs: u64, format: &str) -> Result<String, Error> {
+    let [year, month, day, hour, minute, second] = calendar_time(secs);
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => out.push_str(&format!("{:04}", year)),
+            Some('m') => out.push_str(&format!("{:02}", month)),
+            Some('d') => out.push_str(&format!("{:02}", day)),
+            Some('H') => out.push_str(&format!("{:02}", hour)),
+            Some('M') => out.push_str(&format!("{:02}", minute)),
+            Some('S') => out.push_str(&format!("{:02}", second)),
+            Some('%') => out.push('%'),
+            other => {
+                return Err(Error::InvalidInput(format!(
+                    "rename template: unsupported date field '%{}'",
+                    other.map(String::from).unwrap_or_default()
+                )))
+            }
+        }
+    }
+    Ok(out)
+}
+
+// Year, month, day, hour, minute and second of a unix timestamp in local
+// time.
+#[cfg(target_os = "windows")]
+fn calendar_time(secs: u64) -> [i64; 6] {
+    use windows_sys::Win32::Foundation::{FILETIME, SYSTEMTIME};
+    use windows_sys::Win32::System::Time::{FileTimeToSystemTime, SystemTimeToTzSpecificLocalTime};
+
+    let ticks = match secs.checked_add(11_644_473_600).and_then(|s| s.checked_mul(10_000_000)) {
+        Some(ticks) => ticks,
+        None => return utc_calendar_time(secs),
+    };
+    let file_time = FILETIME {
+        dwLowDateTime: ticks as u32,
+        dwHighDateTime: (ticks >> 32) as u32,
+    };
+    // SAFETY: both calls only read and fill in the plain structs passed.
+    unsafe {
+        let mut utc: SYSTEMTIME = std::mem::zeroed();
+        let mut local: SYSTEMTIME = std::mem::zeroed();
+        if FileTimeToSystemTime(&file_time, &mut utc) != 0
+            && SystemTimeToTzSpecificLocalTime(std::ptr::null(), &utc, &mut local) != 0
+        {
+            return [
+                local.wYear.into(),
+                local.wMonth.into(),
+                local.wDay.into(),
+                local.wHour.into(),
+                local.wMinute.into(),
+                local.wSecond.into(),
+            ];
+        }
+    }
+    utc_calendar_time(secs)
+}
+
+// Without a time zone API to ask, other platforms get UTC.
+#[cfg(not(target_os = "windows"))]
+fn calendar_time(secs: u64) -> [i64; 6] {
+    utc_calendar_time(secs)
+}
+
+// Days to a proleptic Gregorian date after Howard Hinnant's civil_from_days.
+fn utc_calendar_time(secs: u64) -> [i64; 6] {
+    let secs = secs as i64;
+    let (days, time) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    [year, month, day, time / 3600, time % 3600 / 60, time % 60]
+}
+
+// The inverse, after days_from_civil. None for out-of-range fields and for
+// times before 1970.
+fn utc_timestamp(
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+) -> Option<u64> {
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return None;
+    }
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+    u64::try_from(days * 86_400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+// Renames every path to `template` rendered for it, e.g.
+// "{date:%Y-%m-%d} {n:3}.{ext}". {name} is the name without its extension,
+// {ext} the extension without the dot, {date} the modification date and {n}
+// the 1-based position in `paths`, optionally zero-padded to a width; `{{`
+// and `}}` are literal braces. Nothing is renamed unless every new name is
+// valid and free. Names already held by the selection itself count as free,
+// so a renumbering can shift names along.
+#[tauri::command]
+fn rename_template(
+    app: tauri::AppHandle,
+    db: State<DbConnection>,
+    paths: Vec<String>,
+    template: String,
+) -> Result<Vec<Undoable>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let renamed = rename_by_template(&conn, &paths, &template)?;
+    let targets: Vec<&String> = renamed.iter().map(|r| &r.entry.path).collect();
+    emit_parents_invalidated(&app, &targets);
+    Ok(renamed)
+}
+
+fn rename_by_template(
+    conn: &Connection,
+    paths: &[String],
+    template: &str,
+) -> Result<Vec<Undoable>, Error> {
+    use std::collections::HashSet;
+
+    let parts = parse_rename_template(template)?;
+    let mut plans: Vec<(String, String)> = Vec::with_capacity(paths.len());
+    let mut sources = HashSet::new();
+    for (i, path) in paths.iter().enumerate() {
+        let path = normalize_dir_input(path);
+        let source = Path::new(&path);
+        let parent = source
+            .parent()
+            .ok_or_else(|| Error::InvalidInput(format!("'{}' has no parent directory", path)))?;
+        let modified = get_file_meta(source)?.modified;
+        let name = render_rename_template(&parts, source, modified, i + 1)?;
+        if matches!(name.as_str(), "" | "." | "..")
+            || name.contains(['/', '\\', ':', '*', '?', '"', '<', '>', '|'])
+        {
+            return Err(Error::InvalidInput(format!(
+                "the template gives '{}' for '{}', which is not a valid name",
+                name, path
+            )));
+        }
+        if !sources.insert(path.to_lowercase()) {
+            return Err(Error::InvalidInput(format!("'{}' is listed twice", path)));
+        }
+        let target = parent.join(&name).to_string_lossy().to_string();
+        plans.push((path, target));
+    }
+    let mut targets = HashSet::new();
+    for (source, target) in &plans {
+        if !targets.insert(target.to_lowercase()) {
+            return Err(Error::InvalidInput(format!(
+                "the template gives '{}' for more than one entry",
+                target
+            )));
+        }
+        if Path::new(target).exists() && !sources.contains(&target.to_lowercase()) {
+            return Err(Error::AlreadyExists(target.clone()));
+        }
+        if let Some((nested, _)) = plans
+            .iter()
+            .find(|(other, _)| other != source && Path::new(other).starts_with(source))
+        {
+            return Err(Error::InvalidInput(format!(
+                "'{}' is inside '{}', which is renamed as well",
+                nested, source
+            )));
+        }
+    }
+    plans.retain(|(source, target)| source != target);
+
+    let touched: Vec<&String> = plans.iter().flat_map(|(source, target)| [source, target]).collect();
+    ensure_writable(conn, &touched)?;
+
+    // Everything moves to a temporary name first, then to its new one.
+    let temps: Vec<String> = plans
+        .iter()
+        .enumerate()
+        .map(|(i, (source, _))| format!("{}.renaming-{}-{}", source, std::process::id(), i))
+        .collect();
+    if let Some(temp) = temps.iter().find(|temp| Path::new(temp).exists()) {
+        return Err(Error::AlreadyExists(temp.clone()));
+    }
+    let (mut staged, mut placed) = (0, 0);
+    let mut outcome = Ok(());
+    for ((source, _), temp) in plans.iter().zip(&temps) {
+        outcome = fs::rename(source, temp);
+        if outcome.is_err() {
+            break;
+        }
+        staged += 1;
+    }
+    if outcome.is_ok() {
+        for ((_, target), temp) in plans.iter().zip(&temps) {
+            outcome = fs::rename(temp, target);
+            if outcome.is_err() {
+                break;
+            }
+            placed += 1;
+        }
+    }
+    if let Err(e) = outcome {
+        // best effort: put back whatever already moved
+        for i in (0..placed).rev() {
+            let _ = fs::rename(&plans[i].1, &temps[i]);
+        }
+        for i in (0..staged).rev() {
+            let _ = fs::rename(&temps[i], &plans[i].0);
+        }
+        return Err(e.into());
+    }
+
+    for ((source, _), temp) in plans.iter().zip(&temps) {
+        rename_in_index(conn, source, temp)?;
+    }
+    let mut renamed = Vec::with_capacity(plans.len());
+    for ((source, target), temp) in plans.iter().zip(&temps) {
+        rename_in_index(conn, temp, target)?;
+        let entry = get_file_meta(Path::new(target))?;
+        insert_file_meta(conn, &entry)?;
+        let undo_token = log_operation(conn, OperationKind::Rename, source, target)?;
+        renamed.push(Undoable { entry, undo_token });
+    }
+    Ok(renamed)
+}
+
+const OPERATION_LOG_LIMIT: u32 = 100;
+
+#[derive(Debug, Clone, Copy)]
+enum OperationKind {
+    Rename,
+    Move,
+    // taken by a rule; undone by deleting the copy while it is unchanged
+    Copy,
+    // taken by a rule; recorded but not undoable
+    Trash,
+    // added by a rule; `current` is the tag
+    Tag,
+    // written by the legacy path audit; never undoable
+    Normalize,
+    Merge,
+    Remove,
+}
+
+impl OperationKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            OperationKind::Rename => "rename",
+            OperationKind::Move => "move",
+            OperationKind::Copy => "copy",
+            OperationKind::Trash => "trash",
+            OperationKind::Tag => "tag",
+            OperationKind::Normalize => "normalize",
+            OperationKind::Merge => "merge",
+            OperationKind::Remove => "remove",
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize)]
+struct Undoable {
+    entry: FileMeta,
+    // hand back to undo_operation to reverse this change
+    undo_token: String,
+}
+
+// Records where an entry came from so undo_operation can put it back. Only
+// the newest OPERATION_LOG_LIMIT operations stay undoable.
+fn log_operation(
+    conn: &Connection,
+    kind: OperationKind,
+    original: &str,
+    current: &str,
+) -> Result<String> {
+    conn.execute(
+        "INSERT INTO operation_log (kind, original, current, performed_at) VALUES (?1, ?2, ?3, ?4)",
+        rusqlite::params![kind.as_str(), original, current, unix_now()],
+    )?;
+    let id = conn.last_insert_rowid();
+    conn.execute(
+        "DELETE FROM operation_log WHERE run_id IS NULL AND id NOT IN
+            (SELECT id FROM operation_log WHERE run_id IS NULL ORDER BY id DESC LIMIT ?1)",
+        rusqlite::params![OPERATION_LOG_LIMIT],
+    )?;
+    Ok(id.to_string())
+}
+
+// Reverses a logged operation: a rename or move goes back to where it found
+// the entry, a rule's copy is deleted and a rule's tag comes off. Each token
+// undoes once; one already used or aged out of the log is rejected, as is a
+// trash, which only the recycle bin can restore.
+#[tauri::command]
+fn undo_operation(
+    app: tauri::AppHandle,
+    db: State<DbConnection>,
+    token: String,
+) -> Result<FileMeta, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let (meta, undone_from) = undo_logged(&conn, &token)?;
+    emit_parents_invalidated(&app, &[&undone_from, &meta.path]);
+    Ok(meta)
+}
+
+// Returns the entry the undo acted on and the path it was undone from.
+fn undo_logged(conn: &Connection, token: &str) -> Result<(FileMeta, String), Error> {
+    let unknown = || Error::InvalidInput(format!("no undoable operation for token '{}'", token));
+    let id: i64 = token.parse().map_err(|_| unknown())?;
+    let (kind, original, current): (String, String, String) = conn
+        .query_row(
+            "SELECT kind, original, current FROM operation_log
+             WHERE id = ?1 AND run_id IS NULL AND kind IN ('rename', 'move', 'copy', 'tag')",
+            rusqlite::params![id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?
+        .ok_or_else(unknown)?;
+
+    let undone = match kind.as_str() {
+        "copy" => undo_copy(conn, &current)?,
+        "tag" => {
+            conn.execute(
+                "DELETE FROM file_tags WHERE path = ?1 AND tag = ?2",
+                rusqlite::params![original, current],
+            )?;
+            (get_file_meta(Path::new(&original))?, original)
+        }
+        _ => undo_move(conn, &original, &current)?,
+    };
+    conn.execute("DELETE FROM operation_log WHERE id = ?1", rusqlite::params![id])?;
+    Ok(undone)
+}
+
+// The copy goes for good, so only while it is still what the rule wrote.
+fn undo_copy(conn: &Connection, copy: &str) -> Result<(FileMeta, String), Error> {
+    ensure_writable(conn, &[copy])?;
+    let meta = get_file_meta(Path::new(copy))
+        .map_err(|_| Error::InvalidInput(format!("'{}' no longer exists", copy)))?;
+    if indexed_version(conn, copy)? != Some((meta.modified, meta.size)) {
+        return Err(Error::InvalidInput(format!("'{}' changed since it was copied", copy)));
+    }
+    fs::remove_file(copy)?;
+    remove_subtree_from_index(conn, copy)?;
+    Ok((meta, copy.to_string()))
+}
+
+fn undo_move(conn: &Connection, original: &str, current: &str) -> Result<(FileMeta, String), Error> {
+    ensure_writable(conn, &[current, original])?;
+    let (from, to) = (Path::new(current), Path::new(original));
+    if !from.exists() {
+        return Err(Error::InvalidInput(format!("'{}' no longer exists", current)));
+    }
+    if to.exists() && !original.eq_ignore_ascii_case(current) {
+        return Err(Error::AlreadyExists(original.to_string()));
+    }
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    move_path(from, to)?;
+    rename_in_index(conn, current, original)?;
+    let meta = get_file_meta(to)?;
+    insert_file_meta(conn, &meta)?;
+    Ok((meta, current.to_string()))
+}
+
+#[tauri::command]
+async fn move_entries(
+    window: tauri::Window,
+    sources: Vec<String>,
+    dest_dir: String,
+    conflict: String,
+    token: Option<String>,
+) -> Result<Outcome<CopyReport>, Error> {
+    let policy: ConflictPolicy = conflict.parse()?;
+    let sources: Vec<String> = sources.iter().map(|s| normalize_dir_input(s)).collect();
+    let dest_dir = normalize_dir_input(&dest_dir);
+    tauri::async_runtime::spawn_blocking(move || {
+        move_entries_blocking(&window, &sources, Path::new(&dest_dir), policy, token)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// Progress and cancellation are per entry; a directory moves as one unit.
+// `copied` in the report counts moved entries.
+fn move_entries_blocking(
+    window: &tauri::Window,
+    sources: &[String],
+    dest_dir: &Path,
+    policy: ConflictPolicy,
+    token: Option<String>,
+) -> Result<Outcome<CopyReport>, Error> {
+    {
+        let db = window.state::<DbConnection>();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        ensure_writable(&conn, &[dest_dir.to_string_lossy()])?;
+        ensure_writable(&conn, sources)?;
+    }
+    let registry = window.state::<TokenRegistry>();
+    let op = registry.begin(window, token, "move_entries")?;
+    let mut progress = CopyProgress::new(window, &op.flag, sources.len() as u64);
+    let mut report = CopyReport::default();
+    let db = window.state::<DbConnection>();
+    let mut touched = Vec::new();
+
+    let mut cancelled = false;
+    for source in sources {
+        if let Err(e) = progress.step(Path::new(source)) {
+            match e {
+                Error::Cancelled => cancelled = true,
+                e => return Err(e),
+            }
+            break;
+        }
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        match move_one(&conn, source, dest_dir, policy) {
+            Ok(meta) => {
+                report.copied += 1;
+                touched.push(source.clone());
+                touched.push(meta.path);
+            }
+            Err(e) => report.failed.push(FailedEntry::new(source, &e.to_string())),
+        }
+    }
+    emit_parents_invalidated(&window.app_handle(), &touched);
+    Ok(Outcome::new(report, cancelled))
+}
+
+fn move_one(
+    conn: &Connection,
+    source: &str,
+    dest_dir: &Path,
+    policy: ConflictPolicy,
+) -> Result<FileMeta, Error> {
+    let source_path = Path::new(source);
+    let file_name = source_path
+        .file_name()
+        .ok_or_else(|| Error::InvalidInput(format!("'{}' has no file name", source)))?;
+
+    let mut target = dest_dir.join(file_name);
+    if target == source_path {
+        return get_file_meta(&target).map_err(Into::into);
+    }
+    if is_within(dest_dir, source_path) {
+        return Err(Error::InvalidInput(format!(
+            "cannot move '{}' into itself",
+            source
+        )));
+    }
+
+    if target.exists() {
+        match policy {
+            ConflictPolicy::Error => {
+                return Err(Error::AlreadyExists(target.to_string_lossy().to_string()))
+            }
+            ConflictPolicy::Overwrite => {
+                if target.is_dir() {
+                    fs::remove_dir_all(&target)?;
+                } else {
+                    fs::remove_file(&target)?;
+                }
+                remove_subtree_from_index(conn, &target.to_string_lossy())?;
+            }
+            ConflictPolicy::Rename => target = free_destination_name(&target),
+        }
+    }
+
+    move_path(source_path, &target)?;
+    rename_in_index(conn, source, &target.to_string_lossy())?;
+
+    let meta = get_file_meta(&target)?;
+    insert_file_meta(conn, &meta)?;
+    Ok(meta)
+}
+
+// Moves every file below `dir` straight into `dest`, numbering names that
+// clash. With `remove_empty` the subdirectories left empty are deleted, though
+// never `dir` itself or anything holding `dest`.
+#[tauri::command]
+async fn flatten_directory(
+    app: tauri::AppHandle,
+    dir: String,
+    dest: String,
+    remove_empty: Option<bool>,
+) -> Result<FlattenReport, Error> {
+    let (dir, dest) = (normalize_dir_input(&dir), normalize_dir_input(&dest));
+    if !Path::new(&dir).is_dir() {
+        return Err(Error::InvalidInput(format!("'{}' is not a directory", dir)));
+    }
+    {
+        let db = app.state::<DbConnection>();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        ensure_writable(&conn, &[&dir, &dest])?;
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let remove_empty = remove_empty.unwrap_or(false);
+        let db = app.state::<DbConnection>();
+        flatten_directory_blocking(&db, Path::new(&dir), Path::new(&dest), remove_empty)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn flatten_directory_blocking(
+    db: &DbConnection,
+    dir: &Path,
+    dest: &Path,
+    remove_empty: bool,
+) -> Result<FlattenReport, Error> {
+    fs::create_dir_all(dest)?;
+    // strictly below `dir`; flattening `dir` into itself skips nothing
+    let dest_inside = is_within(dest, dir) && !is_within(dir, dest);
+    // Collected up front so files already moved into a `dest` below `dir`
+    // are not walked again.
+    let files: Vec<std::path::PathBuf> = WalkDir::new(dir)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| !e.file_type().is_dir())
+        .map(|e| e.into_path())
+        .filter(|p| p.parent() != Some(dest) && !(dest_inside && is_within(p, dest)))
+        .collect();
+
+    let mut report = FlattenReport::default();
+    for file in &files {
+        let source = file.to_string_lossy().to_string();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        match move_one(&conn, &source, dest, ConflictPolicy::Rename) {
+            Ok(meta) => {
+                report.moved += 1;
+                if file.file_name() != Path::new(&meta.path).file_name() {
+                    report.collisions.push(RenamedEntry { from: source, to: meta.path });
+                }
+            }
+            Err(e) => report.failed.push(FailedEntry::new(&source, &e.to_string())),
+        }
+    }
+
+    if remove_empty {
+        let dirs: Vec<std::path::PathBuf> = WalkDir::new(dir)
+            .min_depth(1)
+            .contents_first(true)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter(|e| e.file_type().is_dir())
+            .map(|e| e.into_path())
+            .filter(|p| !is_within(dest, p))
+            .collect();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        for sub in dirs {
+            // remove_dir refuses directories that still hold anything
+            if fs::remove_dir(&sub).is_ok() {
+                let sub = sub.to_string_lossy().to_string();
+                remove_subtree_from_index(&conn, &sub)?;
+                report.removed_dirs.push(sub);
+            }
+        }
+    }
+    Ok(report)
+}
+
+// True when `path` is `ancestor` itself or lies somewhere beneath it.
+fn is_within(path: &Path, ancestor: &Path) -> bool {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let ancestor = fs::canonicalize(ancestor).unwrap_or_else(|_| ancestor.to_path_buf());
+    path.starts_with(ancestor)
+}
+
+// Picks "name (1).ext", "name (2).ext", ... until one does not exist yet.
+fn free_destination_name(target: &Path) -> std::path::PathBuf {
+    let mut n = 1;
+    loop {
+        let candidate = numbered_destination(target, n);
+        if !candidate.exists() {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+fn numbered_destination(target: &Path, n: u32) -> std::path::PathBuf {
+    let parent = target.parent().unwrap_or_else(|| Path::new(""));
+    let stem = target
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let extension = if target.is_dir() {
+        None
+    } else {
+        target.extension().map(|e| e.to_string_lossy().to_string())
+    };
+    match extension {
+        Some(ext) => parent.join(format!("{} ({}).{}", stem, n, ext)),
+        None => parent.join(format!("{} ({})", stem, n)),
+    }
+}
+
+// fs::rename fails across volumes, so fall back to copy + delete there.
+fn move_path(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    copy_recursive(from, to)?;
+    if from.is_dir() {
+        fs::remove_dir_all(from)
+    } else {
+        fs::remove_file(from)
+    }
+}
+
+fn copy_recursive(from: &Path, to: &Path) -> std::io::Result<()> {
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &to.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn compare_directories(left: String, right: String) -> Result<DirectoryComparison, Error> {
+    compare_trees(
+        Path::new(&normalize_dir_input(&left)),
+        Path::new(&normalize_dir_input(&right)),
+    )
+    .map_err(Into::into)
+}
+
+// Two files match when their sizes agree and their mtimes are within the
+// 2-second resolution of FAT volumes.
+fn compare_trees(left: &Path, right: &Path) -> std::io::Result<DirectoryComparison> {
+    fn collect(root: &Path) -> std::collections::BTreeMap<String, (u64, u64, bool)> {
+        WalkDir::new(root)
+            .min_depth(1)
+            .follow_links(false)
+            .into_iter()
+            .filter_map(Result::ok)
+            .filter_map(|e| {
+                let meta = e.metadata().ok()?;
+                let rel = e.path().strip_prefix(root).ok()?.to_string_lossy().to_string();
+                let modified = meta
+                    .modified()
+                    .ok()?
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                Some((rel, (meta.len(), modified, meta.is_dir())))
+            })
+            .collect()
+    }
+
+    if !left.is_dir() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("{} is not a directory", left.display()),
+        ));
+    }
+    let left_entries = collect(left);
+    let right_entries = collect(right);
+
+    let mut comparison = DirectoryComparison::default();
+    for (rel, (size, modified, is_dir)) in &left_entries {
+        match right_entries.get(rel) {
+            None => comparison.only_in_left.push(rel.clone()),
+            Some(_) if *is_dir => comparison.identical += 1,
+            Some((other_size, other_modified, _)) => {
+                if size == other_size && modified.abs_diff(*other_modified) <= 2 {
+                    comparison.identical += 1;
+                } else {
+                    comparison.changed.push(rel.clone());
+                }
+            }
+        }
+    }
+    comparison.only_in_right = right_entries
+        .keys()
+        .filter(|rel| !left_entries.contains_key(*rel))
+        .cloned()
+        .collect();
+    Ok(comparison)
+}
+
+// With `verify`, every file written is read back and compared against the
+// hash taken while copying it; see copy_file_verified.
+#[tauri::command]
+async fn copy_entries(
+    window: tauri::Window,
+    sources: Vec<String>,
+    dest_dir: String,
+    conflict: String,
+    token: Option<String>,
+    verify: Option<bool>,
+) -> Result<Outcome<CopyReport>, Error> {
+    let policy: ConflictPolicy = conflict.parse()?;
+    let sources: Vec<String> = sources.iter().map(|s| normalize_dir_input(s)).collect();
+    let dest_dir = normalize_dir_input(&dest_dir);
+    tauri::async_runtime::spawn_blocking(move || {
+        let verify = verify.unwrap_or(false);
+        copy_entries_blocking(&window, &sources, Path::new(&dest_dir), policy, token, verify)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn copy_entries_blocking(
+    window: &tauri::Window,
+    sources: &[String],
+    dest_dir: &Path,
+    policy: ConflictPolicy,
+    token: Option<String>,
+    verify: bool,
+) -> Result<Outcome<CopyReport>, Error> {
+    let mut verification = {
+        let db = window.state::<DbConnection>();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        ensure_writable(&conn, &[dest_dir.to_string_lossy()])?;
+        if verify {
+            Some(Verification::new(copy_verify_retries(&conn)?))
+        } else {
+            None
+        }
+    };
+    let registry = window.state::<TokenRegistry>();
+    let op = registry.begin(window, token, "copy_entries")?;
+
+    let total = sources
+        .iter()
+        .map(|s| WalkDir::new(s).into_iter().filter_map(Result::ok).count() as u64)
+        .sum();
+    let mut progress = CopyProgress::new(window, &op.flag, total);
+    let mut report = CopyReport::default();
+    let mut written = Vec::new();
+
+    for source in sources {
+        let source_path = Path::new(source);
+        let target = match copy_target(source_path, dest_dir, policy) {
+            Ok(target) => target,
+            Err(reason) => {
+                report.failed.push(FailedEntry::new(source, &reason));
+                continue;
+            }
+        };
+
+        match copy_tree(source_path, &target, &mut progress, verification.as_mut()) {
+            Ok(count) => {
+                report.copied += count;
+                written.push(target);
+            }
+            Err(Error::Cancelled) => {
+                written.push(target);
+                index_written_paths(&window.state::<DbConnection>(), &written);
+                emit_parents_invalidated(&window.app_handle(), &written_paths(&written));
+                if let Some(verification) = verification {
+                    verification.finish(&mut report.failed, &mut report.verification);
+                }
+                return Ok(Outcome::Cancelled(report));
+            }
+            Err(e) => report.failed.push(FailedEntry::new(source, &e.to_string())),
+        }
+    }
+
+    index_written_paths(&window.state::<DbConnection>(), &written);
+    emit_parents_invalidated(&window.app_handle(), &written_paths(&written));
+    if let Some(verification) = verification {
+        verification.finish(&mut report.failed, &mut report.verification);
+    }
+    Ok(Outcome::Completed(report))
+}
+
+// Where `source` lands in `dest_dir` under `policy`, clearing the way for an
+// overwrite. Err is the reason the entry is skipped.
+fn copy_target(
+    source_path: &Path,
+    dest_dir: &Path,
+    policy: ConflictPolicy,
+) -> Result<std::path::PathBuf, String> {
+    let file_name = source_path.file_name().ok_or("no file name")?;
+    let target = dest_dir.join(file_name);
+    if source_path.is_dir() && is_within(dest_dir, source_path) {
+        return Err("cannot copy a folder into itself".to_string());
+    }
+    if !target.exists() {
+        return Ok(target);
+    }
+    match policy {
+        ConflictPolicy::Error => Err("destination already exists".to_string()),
+        // overwriting would delete the source before copying it
+        ConflictPolicy::Overwrite if is_within(&target, source_path) => {
+            Err("source and destination are the same".to_string())
+        }
+        ConflictPolicy::Overwrite => {
+            let removed = if target.is_dir() {
+                fs::remove_dir_all(&target)
+            } else {
+                fs::remove_file(&target)
+            };
+            removed.map_err(|e| e.to_string())?;
+            Ok(target)
+        }
+        ConflictPolicy::Rename => Ok(free_destination_name(&target)),
+    }
+}
+
+// Copies a file or directory tree, preserving modification times, and
+// returns how many files were written. Files that never verified are not
+// counted.
+fn copy_tree(
+    from: &Path,
+    to: &Path,
+    progress: &mut CopyProgress,
+    mut verification: Option<&mut Verification>,
+) -> Result<u64, Error> {
+    progress.step(from)?;
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        let mut count = 0;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            let to = to.join(entry.file_name());
+            count += copy_tree(&entry.path(), &to, progress, verification.as_deref_mut())?;
+        }
+        Ok(count)
+    } else if let Some(verification) = verification {
+        Ok(u64::from(verification.copy(from, to, progress)?))
+    } else {
+        copy_file_preserving(from, to)?;
+        Ok(1)
+    }
+}
+
+const COPY_VERIFY_RETRIES_SETTING: &str = "copy_verify_retries";
+const DEFAULT_COPY_VERIFY_RETRIES: u32 = 2;
+
+fn copy_verify_retries(conn: &Connection) -> Result<u32> {
+    Ok(get_setting(conn, COPY_VERIFY_RETRIES_SETTING)?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_COPY_VERIFY_RETRIES))
+}
+
+// How many times a copy whose read-back does not match is redone before
+// the file is reported as failed.
+#[tauri::command]
+fn get_copy_verify_retries(db: State<DbConnection>) -> Result<u32, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(copy_verify_retries(&conn)?)
+}
+
+#[tauri::command]
+fn set_copy_verify_retries(db: State<DbConnection>, retries: u32) -> Result<(), Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    set_setting(&conn, COPY_VERIFY_RETRIES_SETTING, &retries.to_string())?;
+    Ok(())
+}
+
+struct Verification {
+    retries: u32,
+    results: Vec<VerifiedCopy>,
+}
+
+impl Verification {
+    fn new(retries: u32) -> Self {
+        Verification {
+            retries,
+            results: Vec::new(),
+        }
+    }
+
+    // Copies `from` once per attempt, hashing it as it is read, then reads
+    // `to` back and compares. Each attempt costs one read of either side.
+    // Returns whether the copy verified.
+    fn copy(&mut self, from: &Path, to: &Path, progress: &mut CopyProgress) -> Result<bool, Error> {
+        self.copy_reporting(from, to, |to| progress.verifying(to))
+    }
+
+    // `verifying` runs between each write and its read-back.
+    fn copy_reporting(
+        &mut self,
+        from: &Path,
+        to: &Path,
+        mut verifying: impl FnMut(&Path) -> Result<(), Error>,
+    ) -> Result<bool, Error> {
+        let mut attempts = 0;
+        let (sha256, verified) = loop {
+            attempts += 1;
+            let source_hash = copy_file_hashing(from, to)?;
+            verifying(to)?;
+            let verified = stream_digest::<sha2::Sha256>(to)? == source_hash;
+            if verified || attempts > self.retries {
+                break (source_hash, verified);
+            }
+        };
+        if verified {
+            // last, so a read-only source does not block the retries
+            fs::set_permissions(to, fs::metadata(from)?.permissions())?;
+        }
+        self.results.push(VerifiedCopy {
+            path: to.to_string_lossy().to_string(),
+            sha256,
+            attempts,
+            verified,
+        });
+        Ok(verified)
+    }
+
+    fn finish(self, failed: &mut Vec<FailedEntry>, verification: &mut Vec<VerifiedCopy>) {
+        for result in self.results.iter().filter(|r| !r.verified) {
+            let why = format!("copy did not verify after {} attempts", result.attempts);
+            failed.push(FailedEntry::new(&result.path, &why));
+        }
+        verification.extend(self.results);
+    }
+}
+
+// fs::copy, but hashing the bytes on their way through. The destination is
+// flushed to the device before returning so the read-back is not only of
+// what the copy left in memory buffers.
+fn copy_file_hashing(from: &Path, to: &Path) -> Result<String, Error> {
+    use sha2::Digest;
+    use std::io::{Read, Write};
+
+    let mut source = fs::File::open(from)?;
+    let mut target = fs::File::create(to)?;
+    let mut digest = sha2::Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = source.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        digest.update(&buf[..n]);
+        target.write_all(&buf[..n])?;
+    }
+    target.sync_all()?;
+    drop(target);
+    let modified = filetime::FileTime::from_last_modification_time(&source.metadata()?);
+    filetime::set_file_mtime(to, modified)?;
+    Ok(digest.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn copy_file_preserving(from: &Path, to: &Path) -> std::io::Result<()> {
+    fs::copy(from, to)?;
+    let modified = filetime::FileTime::from_last_modification_time(&fs::metadata(from)?);
+    filetime::set_file_mtime(to, modified)
+}
+
+fn written_paths(roots: &[std::path::PathBuf]) -> Vec<String> {
+    roots.iter().map(|p| p.to_string_lossy().to_string()).collect()
+}
+
+// Adds freshly written copies to the index; failures only cost freshness.
+fn index_written_paths(db: &DbConnection, roots: &[std::path::PathBuf]) {
+    let Ok(mut conn) = db.0.lock() else { return };
+    let Ok(tx) = conn.transaction() else { return };
+    for root in roots {
+        for entry in WalkDir::new(root).into_iter().filter_map(Result::ok) {
+            if let Ok(meta) = get_file_meta(entry.path()) {
+                let _ = insert_file_meta(&tx, &meta);
+            }
+        }
+        let _ = invalidate_dir_sizes(&tx, &root.to_string_lossy());
+    }
+    let _ = tx.commit();
+}
+
+// Connects once with the given credentials before saving anything, so a
+// remote that cannot log in is never stored. The server's host key is
+// pinned here; later connections presenting another key are refused.
+#[tauri::command]
+async fn add_remote(
+    app: tauri::AppHandle,
+    name: String,
+    sftp_url: String,
+    auth: remote::RemoteAuth,
+) -> Result<remote::Remote, Error> {
+    remote::validate_remote_name(&name)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = app.state::<DbConnection>();
+        {
+            let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+            if remote::load_remote(&conn, &name).is_ok() {
+                return Err(Error::AlreadyExists(remote::remote_path(&name, "")));
+            }
+        }
+        let added = remote::probe_remote(&name, &sftp_url, &auth)?;
+        remote::store_new_secret(&auth, &name)?;
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        remote::insert_remote(&conn, &added)?;
+        Ok(added)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+#[tauri::command]
+fn list_remotes(db: State<DbConnection>) -> Result<Vec<remote::Remote>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    remote::load_remotes(&conn)
+}
+
+#[tauri::command]
+fn remove_remote(db: State<DbConnection>, name: String) -> Result<(), Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    remote::load_remote(&conn, &name)?;
+    remote::forget_secret(&name)?;
+    conn.execute("DELETE FROM remotes WHERE name = ?1", [&name])?;
+    Ok(())
+}
+
+// Looks the remote up without holding the lock while connecting.
+fn connect_remote(
+    db: &DbConnection,
+    path: &str,
+) -> Result<(remote::RemoteSession, String), Error> {
+    let (name, rel) = remote::split_remote_path(path)?;
+    let found = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        remote::load_remote(&conn, name)?
+    };
+    Ok((found.connect()?, rel.to_string()))
+}
+
+// Always read live from the server; remote entries are never indexed.
+#[tauri::command]
+async fn list_remote_directory(
+    app: tauri::AppHandle,
+    path: String,
+) -> Result<Vec<FileMeta>, Error> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = app.state::<DbConnection>();
+        let (session, rel) = connect_remote(&db, &path)?;
+        let mut entries = session.list(&rel)?;
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        FileKinds::load(&conn)?.annotate(&mut entries);
+        Ok(entries)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// Copies sftp:// sources into a local directory. Progress, cancellation and
+// indexing of the written copies work as in copy_entries; name clashes in
+// `dest_dir` are skipped and reported.
+#[tauri::command]
+async fn download_remote(
+    window: tauri::Window,
+    sources: Vec<String>,
+    dest_dir: String,
+    token: Option<String>,
+) -> Result<Outcome<CopyReport>, Error> {
+    let dest_dir = std::path::PathBuf::from(normalize_dir_input(&dest_dir));
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = window.state::<DbConnection>();
+        {
+            let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+            ensure_writable(&conn, &[dest_dir.to_string_lossy()])?;
+        }
+        let registry = window.state::<TokenRegistry>();
+        let op = registry.begin(&window, token, "download_remote")?;
+        // the entry count is not known without walking the server first
+        let mut progress = CopyProgress::new(&window, &op.flag, 0);
+        let mut report = CopyReport::default();
+        let mut written = Vec::new();
+
+        for source in &sources {
+            let downloaded = connect_remote(&db, source).and_then(|(session, rel)| {
+                let name = rel.rsplit('/').next().filter(|n| !n.is_empty()).ok_or_else(|| {
+                    Error::InvalidInput("download the remote's folders, not its root".to_string())
+                })?;
+                let target = dest_dir.join(name);
+                if target.exists() {
+                    return Err(Error::AlreadyExists(target.to_string_lossy().to_string()));
+                }
+                written.push(target.clone());
+                session.download(&rel, &target, &mut progress)
+            });
+            match downloaded {
+                Ok(count) => report.copied += count,
+                Err(Error::Cancelled) => {
+                    index_written_paths(&db, &written);
+                    emit_parents_invalidated(&window.app_handle(), &written_paths(&written));
+                    return Ok(Outcome::Cancelled(report));
+                }
+                Err(e) => report.failed.push(FailedEntry::new(source, &e.to_string())),
+            }
+        }
+
+        index_written_paths(&db, &written);
+        emit_parents_invalidated(&window.app_handle(), &written_paths(&written));
+        Ok(Outcome::Completed(report))
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// Copies local files or folders into an sftp:// directory over one session.
+// Existing remote entries of the same name are skipped and reported.
+#[tauri::command]
+async fn upload_remote(
+    window: tauri::Window,
+    sources: Vec<String>,
+    remote_dir: String,
+    token: Option<String>,
+) -> Result<Outcome<CopyReport>, Error> {
+    let sources: Vec<String> = sources.iter().map(|s| normalize_dir_input(s)).collect();
+    tauri::async_runtime::spawn_blocking(move || {
+        let db = window.state::<DbConnection>();
+        ensure_allowed(&*db.0.lock().map_err(|_| Error::MutexPoison)?, &sources)?;
+        let (session, dir) = connect_remote(&db, &remote_dir)?;
+        let registry = window.state::<TokenRegistry>();
+        let op = registry.begin(&window, token, "upload_remote")?;
+        let total = sources
+            .iter()
+            .map(|s| WalkDir::new(s).into_iter().filter_map(Result::ok).count() as u64)
+            .sum();
+        let mut progress = CopyProgress::new(&window, &op.flag, total);
+        let mut report = CopyReport::default();
+
+        for source in &sources {
+            let Some(name) = Path::new(source).file_name() else {
+                report.failed.push(FailedEntry::new(source, "no file name"));
+                continue;
+            };
+            let target = remote::join_remote(&dir, &name.to_string_lossy());
+            if session.exists(&target) {
+                report.failed.push(FailedEntry::new(source, "destination already exists"));
+                continue;
+            }
+            match session.upload(Path::new(source), &target, &mut progress) {
+                Ok(count) => report.copied += count,
+                Err(Error::Cancelled) => return Ok(Outcome::Cancelled(report)),
+                Err(e) => report.failed.push(FailedEntry::new(source, &e.to_string())),
+            }
+        }
+        Ok(Outcome::Completed(report))
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// Returns the entry's new sftp:// path.
+#[tauri::command]
+async fn rename_remote(
+    app: tauri::AppHandle,
+    path: String,
+    new_name: String,
+) -> Result<String, Error> {
+    if new_name.is_empty() || new_name.contains('/') || new_name == "." || new_name == ".." {
+        return Err(Error::InvalidInput(format!("'{}' is not a valid name", new_name)));
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let (session, rel) = connect_remote(&app.state::<DbConnection>(), &path)?;
+        if rel.is_empty() {
+            return Err(Error::InvalidInput("cannot rename a remote's root".to_string()));
+        }
+        let (name, _) = remote::split_remote_path(&path)?;
+        Ok(remote::remote_path(name, &session.rename(&rel, &new_name)?))
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+// Permanent: SFTP servers have no recycle bin.
+#[tauri::command]
+async fn delete_remote(app: tauri::AppHandle, path: String) -> Result<(), Error> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let (session, rel) = connect_remote(&app.state::<DbConnection>(), &path)?;
+        if rel.is_empty() {
+            return Err(Error::InvalidInput("cannot delete a remote's root".to_string()));
+        }
+        session.delete(&rel)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+struct RuleCriteria {
+    // without the dot; any case
+    extension: Option<String>,
+    // `*` and `?` wildcards against the file name, ignoring case
+    name_glob: Option<String>,
+    // since the last modification
+    min_age_secs: Option<u64>,
+    min_size: Option<u64>,
+}
+
+impl RuleCriteria {
+    fn matches(&self, name: &str, size: u64, age_secs: u64) -> bool {
+        let extension = Path::new(name)
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase());
+        self.extension
+            .as_ref()
+            .map_or(true, |wanted| extension.as_deref() == Some(wanted.as_str()))
+            && self.name_glob.as_ref().map_or(true, |glob| glob_matches(glob, name))
+            && self.min_age_secs.map_or(true, |min| age_secs >= min)
+            && self.min_size.map_or(true, |min| size >= min)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RuleAction {
+    Move,
+    Copy,
+    // to the recycle bin, never a permanent delete
+    Trash,
+    Tag,
+}
+
+impl RuleAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            RuleAction::Move => "move",
+            RuleAction::Copy => "copy",
+            RuleAction::Trash => "trash",
+            RuleAction::Tag => "tag",
+        }
+    }
+}
+
+impl std::str::FromStr for RuleAction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "move" => Ok(RuleAction::Move),
+            "copy" => Ok(RuleAction::Copy),
+            "trash" => Ok(RuleAction::Trash),
+            "tag" => Ok(RuleAction::Tag),
+            other => Err(Error::InvalidInput(format!("unknown rule action '{}'", other))),
+        }
+    }
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+// Applies to files directly inside `watch_dir`. Rules for one folder are
+// tried by ascending priority, then age, and the first match wins.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Rule {
+    // ignored by add_rule
+    #[serde(default)]
+    id: i64,
+    watch_dir: String,
+    #[serde(default)]
+    priority: i64,
+    #[serde(default = "enabled_by_default")]
+    enabled: bool,
+    #[serde(default)]
+    criteria: RuleCriteria,
+    action: RuleAction,
+    // the folder to move or copy into; unused by trash and tag
+    destination: Option<String>,
+    // what a tag rule tags the file with; unused by the other actions
+    #[serde(default)]
+    tag: Option<String>,
+}
+
+impl Rule {
+    fn validate(mut self) -> Result<Self, Error> {
+        self.watch_dir = normalize_dir_input(&self.watch_dir);
+        if !Path::new(&self.watch_dir).is_dir() {
+            return Err(Error::InvalidInput(format!("'{}' is not a directory", self.watch_dir)));
+        }
+        if let Some(extension) = &mut self.criteria.extension {
+            *extension = extension.trim_start_matches('.').to_lowercase();
+        }
+        self.tag = match (self.action, self.tag.as_deref().map(str::trim)) {
+            (RuleAction::Tag, Some(tag)) if !tag.is_empty() => Some(tag.to_string()),
+            (RuleAction::Tag, _) => {
+                return Err(Error::InvalidInput("a tag rule needs a tag".to_string()))
+            }
+            _ => None,
+        };
+        self.destination = match (self.action, self.destination.as_deref()) {
+            (RuleAction::Trash | RuleAction::Tag, _) => None,
+            (_, None) => {
+                return Err(Error::InvalidInput(format!(
+                    "a {} rule needs a destination",
+                    self.action.as_str()
+                )))
+            }
+            (_, Some(dest)) => {
+                let dest = normalize_dir_input(dest);
+                if dest.eq_ignore_ascii_case(&self.watch_dir) {
+                    return Err(Error::InvalidInput(
+                        "the destination is the watched folder itself".to_string(),
+                    ));
+                }
+                Some(dest)
+            }
+        };
+        Ok(self)
+    }
+}
+
+// `*` matches any run of characters and `?` any one, ignoring case.
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // where the last `*` was, and how much of the name it has taken so far
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, taken)) = backtrack {
+            backtrack = Some((star, taken + 1));
+            p = star + 1;
+            n = taken + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn load_rules(conn: &Connection, watch_dir: Option<&str>) -> Result<Vec<Rule>, Error> {
+    let mut stmt = conn.prepare(
+        "SELECT id, watch_dir, priority, enabled, criteria, action, destination, tag FROM rules
+         WHERE ?1 IS NULL OR watch_dir = ?1 COLLATE NOCASE
+         ORDER BY priority, id",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![watch_dir], |row| {
+        Ok((
+            row.get::<_, i64>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, i64>(2)?,
+            row.get::<_, bool>(3)?,
+            row.get::<_, String>(4)?,
+            row.get::<_, String>(5)?,
+            row.get::<_, Option<String>>(6)?,
+            row.get::<_, Option<String>>(7)?,
+        ))
+    })?;
+    let mut rules = Vec::new();
+    for row in rows.filter_map(Result::ok) {
+        let (id, watch_dir, priority, enabled, criteria, action, destination, tag) = row;
+        rules.push(Rule {
+            id,
+            watch_dir,
+            priority,
+            enabled,
+            criteria: serde_json::from_str(&criteria)
+                .map_err(|e| Error::InvalidInput(e.to_string()))?,
+            action: action.parse()?,
+            destination,
+            tag,
+        });
+    }
+    Ok(rules)
+}
+
+fn write_rule(conn: &Connection, rule: &Rule) -> Result<i64, Error> {
+    let criteria =
+        serde_json::to_string(&rule.criteria).map_err(|e| Error::InvalidInput(e.to_string()))?;
+    let params = rusqlite::params![
+        rule.watch_dir,
+        rule.priority,
+        rule.enabled,
+        criteria,
+        rule.action.as_str(),
+        rule.destination,
+        rule.tag,
+        rule.id
+    ];
+    if rule.id == 0 {
+        conn.execute(
+            "INSERT INTO rules (watch_dir, priority, enabled, criteria, action, destination, tag)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            &params[..7],
+        )?;
+        return Ok(conn.last_insert_rowid());
+    }
+    let changed = conn.execute(
+        "UPDATE rules SET watch_dir = ?1, priority = ?2, enabled = ?3, criteria = ?4,
+                          action = ?5, destination = ?6, tag = ?7
+         WHERE id = ?8",
+        params,
+    )?;
+    if changed == 0 {
+        return Err(Error::InvalidInput(format!("no rule with id {}", rule.id)));
+    }
+    Ok(rule.id)
+}
+
+#[tauri::command]
+fn list_rules(db: State<DbConnection>) -> Result<Vec<Rule>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    load_rules(&conn, None)
+}
+
+#[tauri::command]
+fn add_rule(app: tauri::AppHandle, db: State<DbConnection>, rule: Rule) -> Result<Rule, Error> {
+    let mut rule = rule.validate()?;
+    rule.id = 0;
+    rule.id = write_rule(&*db.0.lock().map_err(|_| Error::MutexPoison)?, &rule)?;
+    refresh_rule_watchers(&app)?;
+    Ok(rule)
+}
+
+#[tauri::command]
+fn update_rule(app: tauri::AppHandle, db: State<DbConnection>, rule: Rule) -> Result<Rule, Error> {
+    if rule.id == 0 {
+        return Err(Error::InvalidInput("the rule has no id; use add_rule".to_string()));
+    }
+    let rule = rule.validate()?;
+    write_rule(&*db.0.lock().map_err(|_| Error::MutexPoison)?, &rule)?;
+    refresh_rule_watchers(&app)?;
+    Ok(rule)
+}
+
+#[tauri::command]
+fn delete_rule(app: tauri::AppHandle, db: State<DbConnection>, id: i64) -> Result<(), Error> {
+    db.0.lock()
+        .map_err(|_| Error::MutexPoison)?
+        .execute("DELETE FROM rules WHERE id = ?1", rusqlite::params![id])?;
+    refresh_rule_watchers(&app)
+}
+
+#[derive(Debug, serde::Serialize)]
+struct RuleMatch {
+    rule_id: i64,
+    path: String,
+    action: RuleAction,
+    // where the file ended up; None for trash and for dry runs
+    result_path: Option<String>,
+    // set when the action was attempted and failed
+    error: Option<String>,
+    // for moves, copies and tags, hand back to undo_operation to reverse
+    undo_token: Option<String>,
+}
+
+// Evaluates the rules watching `dir` once. A dry run reports what would
+// happen without touching anything.
+#[tauri::command]
+async fn run_rules_now(
+    app: tauri::AppHandle,
+    dir: String,
+    dry_run: Option<bool>,
+) -> Result<Vec<RuleMatch>, Error> {
+    let dir = normalize_dir_input(&dir);
+    tauri::async_runtime::spawn_blocking(move || run_rules(&app, &dir, dry_run.unwrap_or(false)))
+        .await
+        .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn run_rules(app: &tauri::AppHandle, dir: &str, dry_run: bool) -> Result<Vec<RuleMatch>, Error> {
+    let watchers = app.state::<RuleWatchers>();
+    // the watcher and the periodic pass must not both act on one file
+    let _evaluating = watchers.evaluating.lock().map_err(|_| Error::MutexPoison)?;
+    let db = app.state::<DbConnection>();
+    let rules: Vec<Rule> = {
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        load_rules(&conn, Some(dir))?.into_iter().filter(|r| r.enabled).collect()
+    };
+    if rules.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let now = unix_now();
+    let mut files: Vec<(String, String, u64, u64)> = fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let path = entry.path().to_string_lossy().to_string();
+            Some((path, name, metadata.len(), metadata_mtime(&metadata)))
+        })
+        .filter(|(path, ..)| !is_excluded(path))
+        .collect();
+    files.sort();
+
+    let mut matches = Vec::new();
+    let mut touched = Vec::new();
+    for (path, name, size, modified) in files {
+        let age = now.saturating_sub(modified);
+        let Some(rule) = rules.iter().find(|r| r.criteria.matches(&name, size, age)) else {
+            continue;
+        };
+        let mut found = RuleMatch {
+            rule_id: rule.id,
+            path: path.clone(),
+            action: rule.action,
+            result_path: None,
+            error: None,
+            undo_token: None,
+        };
+        if !dry_run {
+            let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+            match apply_rule(&conn, rule, &path, size, modified) {
+                // already copied or tagged on an earlier pass
+                Ok(None) if matches!(rule.action, RuleAction::Copy | RuleAction::Tag) => continue,
+                Ok(outcome) => {
+                    if let Some((result_path, undo_token)) = outcome {
+                        touched.push(result_path.clone());
+                        found.result_path = Some(result_path);
+                        found.undo_token = undo_token;
+                    }
+                    touched.push(path);
+                }
+                Err(e) => found.error = Some(e.to_string()),
+            }
+        }
+        matches.push(found);
+    }
+    if !touched.is_empty() {
+        emit_parents_invalidated(app, &touched);
+    }
+    Ok(matches)
+}
+
+// The new path (the file itself for a tag) and the undo token. None for
+// trash, for a copy whose destination already holds the same file and for a
+// file that already has the tag.
+fn apply_rule(
+    conn: &Connection,
+    rule: &Rule,
+    path: &str,
+    size: u64,
+    modified: u64,
+) -> Result<Option<(String, Option<String>)>, Error> {
+    let destination = rule.destination.as_deref().unwrap_or_default();
+    match rule.action {
+        RuleAction::Move => {
+            ensure_writable(conn, &[path, destination])?;
+            fs::create_dir_all(destination)?;
+            let meta = move_one(conn, path, Path::new(destination), ConflictPolicy::Rename)?;
+            let undo_token = log_operation(conn, OperationKind::Move, path, &meta.path)?;
+            Ok(Some((meta.path, Some(undo_token))))
+        }
+        RuleAction::Copy => {
+            ensure_writable(conn, &[destination])?;
+            fs::create_dir_all(destination)?;
+            let name = Path::new(path).file_name().unwrap_or_default();
+            let first = Path::new(destination).join(name);
+            let mut target = first.clone();
+            let mut n = 1;
+            // a copy from an earlier pass, even one numbered around a clash,
+            // means this file is done
+            while let Ok(existing) = fs::metadata(&target) {
+                if existing.len() == size && metadata_mtime(&existing) == modified {
+                    return Ok(None);
+                }
+                target = numbered_destination(&first, n);
+                n += 1;
+            }
+            copy_file_preserving(Path::new(path), &target)?;
+            let meta = get_file_meta(&target)?;
+            insert_file_meta(conn, &meta)?;
+            invalidate_dir_sizes(conn, &meta.path)?;
+            let undo_token = log_operation(conn, OperationKind::Copy, path, &meta.path)?;
+            Ok(Some((meta.path, Some(undo_token))))
+        }
+        RuleAction::Trash => {
+            ensure_writable(conn, &[path])?;
+            platform_move_to_trash(Path::new(path))?;
+            remove_subtree_from_index(conn, path)?;
+            log_operation(conn, OperationKind::Trash, path, "")?;
+            Ok(None)
+        }
+        RuleAction::Tag => {
+            let tag = rule.tag.as_deref().unwrap_or_default();
+            if !tag_file(conn, path, tag)? {
+                return Ok(None);
+            }
+            // the log's `current` holds the tag, the path being unchanged
+            let undo_token = log_operation(conn, OperationKind::Tag, path, tag)?;
+            Ok(Some((path.to_string(), Some(undo_token))))
+        }
+    }
+}
+
+// False when `path` already had `tag`.
+fn tag_file(conn: &Connection, path: &str, tag: &str) -> Result<bool> {
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO file_tags (path, tag, tagged_at) VALUES (?1, ?2, ?3)",
+        rusqlite::params![path, tag, unix_now()],
+    )?;
+    Ok(added > 0)
+}
+
+fn file_tags(conn: &Connection, path: &str) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT tag FROM file_tags WHERE path = ?1 ORDER BY tag")?;
+    let tags = stmt.query_map(rusqlite::params![path], |row| row.get(0))?;
+    Ok(tags.filter_map(Result::ok).collect())
+}
+
+#[tauri::command]
+fn get_file_tags(db: State<DbConnection>, path: String) -> Result<Vec<String>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(file_tags(&conn, &normalize_dir_input(&path))?)
+}
+
+// Indexed files carrying `tag`, by name.
+#[tauri::command]
+fn find_tagged(db: State<DbConnection>, tag: String) -> Result<Vec<FileMeta>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM entries
+         WHERE path IN (SELECT path FROM file_tags WHERE tag = ?1)
+         ORDER BY name COLLATE NOCASE, path",
+        FILE_META_COLUMNS
+    ))?;
+    let rows = stmt.query_map(rusqlite::params![tag], file_meta_from_row)?;
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+#[tauri::command]
+fn remove_file_tag(db: State<DbConnection>, path: String, tag: String) -> Result<(), Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    conn.execute(
+        "DELETE FROM file_tags WHERE path = ?1 AND tag = ?2",
+        rusqlite::params![normalize_dir_input(&path), tag],
+    )?;
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn platform_move_to_trash(path: &Path) -> Result<(), Error> {
+    use windows_sys::Win32::UI::Shell::{
+        SHFileOperationW, FOF_ALLOWUNDO, FOF_NOCONFIRMATION, FOF_NOERRORUI, FOF_SILENT, FO_DELETE,
+        SHFILEOPSTRUCTW,
+    };
+
+    // pFrom is a list, closed by a second NUL
+    let mut from = to_wide(path.as_os_str());
+    from.push(0);
+    let mut operation = SHFILEOPSTRUCTW {
+        hwnd: 0,
+        wFunc: FO_DELETE,
+        pFrom: from.as_ptr(),
+        pTo: std::ptr::null(),
+        fFlags: (FOF_ALLOWUNDO | FOF_NOCONFIRMATION | FOF_NOERRORUI | FOF_SILENT) as u16,
+        fAnyOperationsAborted: 0,
+        hNameMappings: std::ptr::null_mut(),
+        lpszProgressTitle: std::ptr::null(),
+    };
+    // SAFETY: the path list outlives the call and the struct is fully set.
+    let code = unsafe { SHFileOperationW(&mut operation) };
+    if code != 0 || operation.fAnyOperationsAborted != 0 {
+        return Err(Error::Background(format!(
+            "could not move '{}' to the recycle bin (code {:#x})",
+            path.display(),
+            code
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "windows"))]
+fn platform_move_to_trash(path: &Path) -> Result<(), Error> {
+    let status = match std::process::Command::new("gio").arg("trash").arg(path).status() {
+        Ok(status) => status,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::Unsupported("trash without gio"))
+        }
+        Err(e) => return Err(Error::Io(e)),
+    };
+    if !status.success() {
+        return Err(Error::Background(format!(
+            "could not move '{}' to the trash: {}",
+            path.display(),
+            status
+        )));
+    }
+    Ok(())
+}
+
+// One non-recursive watcher per folder some enabled rule watches.
+#[derive(Default)]
+struct RuleWatchers {
+    watchers: Mutex<std::collections::HashMap<String, notify::RecommendedWatcher>>,
+    evaluating: Mutex<()>,
+}
+
+// Age criteria only become true with time, so every watched folder is also
+// evaluated this often regardless of events.
+const RULES_TICK: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+// Lets a download finish landing before its folder is evaluated.
+const RULES_SETTLE: std::time::Duration = std::time::Duration::from_secs(2);
+
+fn rule_dirs(app: &tauri::AppHandle) -> Result<std::collections::BTreeSet<String>, Error> {
+    let db = app.state::<DbConnection>();
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    Ok(load_rules(&conn, None)?
+        .into_iter()
+        .filter(|r| r.enabled)
+        .map(|r| r.watch_dir)
+        .collect())
+}
+
+fn refresh_rule_watchers(app: &tauri::AppHandle) -> Result<(), Error> {
+    use notify::Watcher;
+
+    let dirs = rule_dirs(app)?;
+    let state = app.state::<RuleWatchers>();
+    let mut watchers = state.watchers.lock().map_err(|_| Error::MutexPoison)?;
+    watchers.retain(|dir, _| dirs.contains(dir));
+    for dir in dirs {
+        if watchers.contains_key(&dir) {
+            continue;
+        }
+        let pending = std::sync::Arc::new(AtomicBool::new(false));
+        let (handle, watched) = (app.clone(), dir.clone());
+        let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let Ok(event) = res else { return };
+            if !(event.kind.is_create() || event.kind.is_modify()) {
+                return;
+            }
+            // one evaluation per burst of events
+            if pending.swap(true, Ordering::SeqCst) {
+                return;
+            }
+            let (app, dir, pending) = (handle.clone(), watched.clone(), pending.clone());
+            std::thread::spawn(move || {
+                std::thread::sleep(RULES_SETTLE);
+                pending.store(false, Ordering::SeqCst);
+                evaluate_rules_in_background(&app, &dir);
+            });
+        })
+        .map_err(notify_error)?;
+        // a folder that went away is picked up again by the next refresh
+        if watcher.watch(Path::new(&dir), notify::RecursiveMode::NonRecursive).is_ok() {
+            watchers.insert(dir, watcher);
+        }
+    }
+    Ok(())
+}
+
+// Emits `rules-applied` with whatever the rules did.
+fn evaluate_rules_in_background(app: &tauri::AppHandle, dir: &str) {
+    if app.state::<BackgroundPaused>().0.load(Ordering::SeqCst) {
+        return;
+    }
+    match run_rules(app, dir, false) {
+        Ok(matches) if !matches.is_empty() => {
+            let _ = app.emit_all("rules-applied", &matches);
+        }
+        Ok(_) => {}
+        Err(err) => eprintln!("Rules for {} failed: {}", dir, err),
+    }
+}
+
+fn spawn_rule_evaluator(app: tauri::AppHandle) {
+    std::thread::spawn(move || loop {
+        // the index may still be locked; rules load once it is open
+        if app.state::<IndexLock>().0.load(Ordering::SeqCst) {
+            std::thread::sleep(RULES_SETTLE);
+            continue;
+        }
+        if let Err(err) = refresh_rule_watchers(&app) {
+            eprintln!("Watching rule folders failed: {}", err);
+        }
+        if let Ok(dirs) = rule_dirs(&app) {
+            for dir in dirs {
+                evaluate_rules_in_background(&app, &dir);
+            }
+        }
+        std::thread::sleep(RULES_TICK);
+    });
+}
+
+#[tauri::command]
+async fn sync_folders(
+    window: tauri::Window,
+    source: String,
+    dest: String,
+    mode: String,
+    dry_run: bool,
+    token: Option<String>,
+    verify: Option<bool>,
+) -> Result<Outcome<SyncReport>, Error> {
+    let mirror = match mode.as_str() {
+        "mirror" => true,
+        "update_only" => false,
+        other => return Err(Error::InvalidInput(format!("unknown sync mode '{}'", other))),
+    };
+    let (source, dest) = (normalize_dir_input(&source), normalize_dir_input(&dest));
+    let mut verification = None;
+    if !dry_run {
+        let db = window.state::<DbConnection>();
+        let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+        ensure_writable(&conn, &[&dest])?;
+        if verify.unwrap_or(false) {
+            verification = Some(Verification::new(copy_verify_retries(&conn)?));
+        }
+    }
+    tauri::async_runtime::spawn_blocking(move || {
+        let (source, dest) = (Path::new(&source), Path::new(&dest));
+        sync_folders_blocking(&window, source, dest, mirror, dry_run, token, verification)
+    })
+    .await
+    .map_err(|e| Error::Background(e.to_string()))?
+}
+
+fn sync_folders_blocking(
+    window: &tauri::Window,
+    source: &Path,
+    dest: &Path,
+    mirror: bool,
+    dry_run: bool,
+    token: Option<String>,
+    mut verification: Option<Verification>,
+) -> Result<Outcome<SyncReport>, Error> {
+    let mut report = plan_sync(source, dest, mirror, dry_run)?;
+    if dry_run {
+        return Ok(Outcome::Completed(report));
+    }
+    fs::create_dir_all(dest)?;
+
+    let registry = window.state::<TokenRegistry>();
+    let op = registry.begin(window, token, "sync_folders")?;
+    let mut progress = CopyProgress::new(window, &op.flag, report.actions.len() as u64);
+    let mut written = Vec::new();
+    let mut removed = Vec::new();
+    let mut cancelled = false;
+
+    for action in &report.actions {
+        if progress.step(Path::new(&action.path)).is_err() {
+            cancelled = true;
+            break;
+        }
+        let from = source.join(&action.path);
+        let to = dest.join(&action.path);
+        // Ok(false) is a copy that never verified
+        let result = match (action.action, verification.as_mut()) {
+            ("copy", Some(verification)) if !from.is_dir() => {
+                verification.copy(&from, &to, &mut progress)
+            }
+            _ => {
+                let done = match action.action {
+                    "copy" if from.is_dir() => fs::create_dir_all(&to),
+                    "copy" => copy_file_preserving(&from, &to),
+                    _ if to.is_dir() => fs::remove_dir_all(&to),
+                    _ => fs::remove_file(&to),
+                };
+                done.map(|()| true).map_err(Error::from)
+            }
+        };
+        match (result, action.action) {
+            (Ok(verified), "copy") => {
+                report.copied += u64::from(verified);
+                written.push(to);
+            }
+            (Ok(_), _) => {
+                report.deleted += 1;
+                removed.push(to);
+            }
+            (Err(Error::Cancelled), _) => {
+                written.push(to);
+                cancelled = true;
+                break;
+            }
+            (Err(e), _) => report
+                .failed
+                .push(FailedEntry::new(&to.to_string_lossy(), &e.to_string())),
+        }
+    }
+    if let Some(verification) = verification {
+        verification.finish(&mut report.failed, &mut report.verification);
+    }
+
+    index_written_paths(&window.state::<DbConnection>(), &written);
+    if let Ok(conn) = window.state::<DbConnection>().0.lock() {
+        for path in &removed {
+            let _ = remove_subtree_from_index(&conn, &path.to_string_lossy());
+        }
+    }
+    Ok(Outcome::new(report, cancelled))
+}
+
+// The copies and deletes that make `dest` match `source`, in path order.
+// Nested folders are refused: `dest` inside `source` would copy into itself
+// and a mirror onto a parent of `source` would delete `source`.
+fn plan_sync(source: &Path, dest: &Path, mirror: bool, dry_run: bool) -> Result<SyncReport, Error> {
+    if is_within(dest, source) || is_within(source, dest) {
+        return Err(Error::InvalidInput(format!(
+            "cannot sync '{}' and '{}': one lies inside the other",
+            source.display(),
+            dest.display()
+        )));
+    }
+    let comparison = compare_trees(source, dest)?;
+    let mut report = SyncReport {
+        skipped: comparison.identical,
+        dry_run,
+        ..Default::default()
+    };
+
+    for rel in comparison.only_in_left {
+        report.actions.push(SyncAction { action: "copy", path: rel });
+    }
+    for rel in comparison.changed {
+        let newer = mtime_secs(&source.join(&rel)) > mtime_secs(&dest.join(&rel));
+        if mirror || newer {
+            report.actions.push(SyncAction { action: "copy", path: rel });
+        } else {
+            report.skipped += 1;
+        }
+    }
+    if mirror {
+        let mut deleted_dirs: Vec<String> = Vec::new();
+        for rel in comparison.only_in_right {
+            // Deleting a directory already covers everything beneath it.
+            if deleted_dirs.iter().any(|d| Path::new(&rel).starts_with(d)) {
+                continue;
+            }
+            if dest.join(&rel).is_dir() {
+                deleted_dirs.push(rel.clone());
+            }
+            report.actions.push(SyncAction { action: "delete", path: rel });
+        }
+    }
+    report.actions.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(report)
+}
+
+fn mtime_secs(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| metadata_mtime(&m)).unwrap_or(0)
+}
+
+const LEGACY_PATHS_AUDITED_KEY: &str = "legacy_paths_audited";
+
+// Older builds matched listings with double-escaped patterns and stored some
+// paths with doubled or trailing separators, leaving rows no directory
+// listing ever finds. Runs once per database: each such row is rewritten to
+// its std::path form, merged into the row already there, or dropped when its
+// drive letter cannot exist. Rows on unplugged drives are left alone. Every
+// change goes into operation_log under an index_runs row with source
+// 'normalization'.
+fn normalize_legacy_paths(conn: &Connection) -> Result<()> {
+    if get_setting(conn, LEGACY_PATHS_AUDITED_KEY)?.is_some() {
+        return Ok(());
+    }
+    let candidates: Vec<String> = {
+        let mut stmt = conn.prepare(
+            r"SELECT path FROM entries
+              WHERE path LIKE '%\\%' OR path LIKE '_%//%' OR path LIKE '%\' OR path LIKE '%/'
+                 OR (substr(path, 2, 1) = ':' AND upper(substr(path, 1, 1)) NOT BETWEEN 'A' AND 'Z')",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.filter_map(Result::ok).collect()
+    };
+
+    let tx = conn.unchecked_transaction()?;
+    let now = unix_now();
+    tx.execute(
+        "INSERT INTO index_runs (root, source, started_at, status) VALUES ('', 'normalization', ?1, 'running')",
+        rusqlite::params![now],
+    )?;
+    let run_id = tx.last_insert_rowid();
+    let delete = format!("DELETE FROM main_table WHERE {}", path_is("?1"));
+    let mut changed = 0u64;
+    for path in &candidates {
+        let (kind, current) = if has_bogus_drive(path) {
+            tx.execute(&delete, rusqlite::params![path])?;
+            (OperationKind::Remove, String::new())
+        } else if let Some(normalized) = normalized_legacy_path(path) {
+            let exists: bool = tx.query_row(
+                &format!("SELECT EXISTS(SELECT 1 FROM main_table WHERE {})", path_is("?1")),
+                rusqlite::params![normalized],
+                |row| row.get(0),
+            )?;
+            if exists {
+                tx.execute(&delete, rusqlite::params![path])?;
+                (OperationKind::Merge, normalized)
+            } else {
+                let (prefix, name) = split_path(&normalized);
+                let dir_id = intern_dir(&tx, prefix)?;
+                let update =
+                    format!("UPDATE main_table SET dir_id = ?1, name = ?2 WHERE {}", path_is("?3"));
+                tx.execute(&update, rusqlite::params![dir_id, name, path])?;
+                (OperationKind::Normalize, normalized)
+            }
+        } else {
+            continue;
+        };
+        // the size is recomputed for the surviving path on the next run
+        tx.execute("DELETE FROM dir_sizes WHERE path = ?1", rusqlite::params![path])?;
+        tx.execute(
+            "INSERT INTO operation_log (kind, original, current, performed_at, run_id)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            rusqlite::params![kind.as_str(), path, current, now, run_id],
+        )?;
+        changed += 1;
+    }
+    tx.execute(
+        "UPDATE index_runs SET finished_at = ?1, status = 'completed', processed = ?2, updated = ?3
+         WHERE id = ?4",
+        rusqlite::params![unix_now(), candidates.len() as u64, changed, run_id],
+    )?;
+    prune_dirs(&tx)?;
+    set_setting(&tx, LEGACY_PATHS_AUDITED_KEY, "1")?;
+    tx.commit()
+}
+
+// "1:\foo" and the like; no volume is ever mounted there.
+fn has_bogus_drive(p: &str) -> bool {
+    let bytes = p.as_bytes();
+    bytes.len() >= 2 && bytes[1] == b':' && !bytes[0].is_ascii_alphabetic()
+}
+
+// The path rebuilt from its std::path components, when that differs: doubled
+// separators collapse and trailing ones go, except on roots. A leading "\\"
+// is a UNC prefix, not a doubled separator.
+fn normalized_legacy_path(p: &str) -> Option<String> {
+    let rest = p.get(1..).unwrap_or_default();
+    let doubled = rest.contains("\\\\") || rest.contains("//");
+    if !doubled && normalize_dir_input(p) == p {
+        return None;
+    }
+    let rebuilt: std::path::PathBuf = Path::new(p).components().collect();
+    let rebuilt = normalize_dir_input(&rebuilt.to_string_lossy());
+    (!rebuilt.is_empty() && rebuilt != p).then(|| rebuilt)
+}
+
+#[derive(Debug, serde::Serialize)]
+struct NormalizationChange {
+    // "normalize", "merge" into an existing row, or "remove"
+    action: String,
+    original: String,
+    // None for removed rows
+    current: Option<String>,
+    performed_at: i64,
+}
+
+// What the legacy path audit changed; `run_id` defaults to its latest run.
+#[tauri::command]
+fn list_normalization_changes(
+    db: State<DbConnection>,
+    run_id: Option<i64>,
+) -> Result<Vec<NormalizationChange>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    let mut stmt = conn.prepare(
+        "SELECT kind, original, current, performed_at FROM operation_log
+         WHERE run_id = COALESCE(?1, (SELECT MAX(id) FROM index_runs WHERE source = 'normalization'))
+         ORDER BY id",
+    )?;
+    let rows = stmt.query_map(rusqlite::params![run_id], |row| {
+        let current: String = row.get(2)?;
+        Ok(NormalizationChange {
+            action: row.get(0)?,
+            original: row.get(1)?,
+            current: (!current.is_empty()).then(|| current),
+            performed_at: row.get(3)?,
+        })
+    })?;
+    Ok(rows.filter_map(Result::ok).collect())
+}
+
+// Commands accept "C:\Foo\" and "C:\Foo" alike; only drive and filesystem
+// roots keep their trailing separator.
+fn normalize_dir_input(s: &str) -> String {
+    let trimmed = s.trim_end_matches(['\\', '/']);
+    if trimmed.is_empty() {
+        // "/" or "\" on its own
+        return s.chars().take(1).collect();
+    }
+    if trimmed.ends_with(':') && trimmed.len() == 2 {
+        return format!("{}\\", trimmed);
+    }
+    trimmed.to_string()
+}
+
+fn subtree_prefix(path: &str) -> String {
+    format!("{}{}", path.trim_end_matches(['\\', '/']), std::path::MAIN_SEPARATOR)
+}
+
+// Components below the root: "C:\", "/" and "\\server\share" are 0,
+// "C:\Users" is 1. Either separator counts and trailing ones don't.
+fn path_depth(p: &str) -> usize {
+    let mut parts = p.split(['\\', '/']).filter(|part| !part.is_empty()).peekable();
+    if p.starts_with("\\\\") || p.starts_with("//") {
+        // server and share
+        parts.nth(1);
+    } else if parts.peek().map_or(false, |first| first.len() == 2 && first.ends_with(':')) {
+        parts.next();
+    }
+    parts.count()
+}
+
+#[tauri::command]
+fn path_depth_command(path: String) -> usize {
+    path_depth(&path)
+}
+
+// The deepest indexed directory, as path_depth counts it, overall or within
+// one subtree. None when there are no directories to look at.
+#[tauri::command]
+fn max_depth(db: State<DbConnection>, within_path: Option<String>) -> Result<Option<usize>, Error> {
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    deepest_dir(&conn, within_path.as_deref())
+}
+
+fn deepest_dir(conn: &Connection, within_path: Option<&str>) -> Result<Option<usize>, Error> {
+    let (start, end) = match within_path.map(normalize_dir_input) {
+        Some(dir) => subtree_range(&dir),
+        None => (String::new(), char::MAX.to_string()),
+    };
+    let depth: Option<i64> = conn.query_row(
+        &format!(
+            "SELECT MAX(path_depth(path)) FROM entries WHERE is_dir = 1 AND {}",
+            dir_in_range("?1", "?2")
+        ),
+        rusqlite::params![start, end],
+        |row| row.get(0),
+    )?;
+    Ok(depth.map(|d| d as usize))
+}
+
+#[derive(Debug, serde::Serialize)]
+struct DirectoryActivity {
+    // newest modification of anything in the subtree, folders included, so
+    // a deletion counts too; 0 when nothing under `dir` is indexed
+    last_modified: u64,
+    newest_file: Option<FileMeta>,
+    file_count: u64,
+}
+
+// When anything under `dir` was last touched, from the index alone.
+#[tauri::command]
+fn directory_activity(db: State<DbConnection>, dir: String) -> Result<DirectoryActivity, Error> {
+    let dir = normalize_dir_input(&dir);
+    let conn = db.0.lock().map_err(|_| Error::MutexPoison)?;
+    activity_below(&conn, &dir)
+}
+
+fn activity_below(conn: &Connection, dir: &str) -> Result<DirectoryActivity, Error> {
+    let (start, end) = subtree_range(dir);
+    let (last_modified, file_count): (Option<u64>, u64) = conn.query_row(
+        &format!(
+            "SELECT MAX(modified), COALESCE(SUM(is_dir = 0), 0)
+             FROM main_table
+             WHERE {} OR {}",
+            dir_in_range("?1", "?2"),
+            path_is("?3")
+        ),
+        rusqlite::params![start, end, dir],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    let mut newest_file = conn
+        .query_row(
+            &format!(
+                "SELECT {}
+                 FROM entries
+                 WHERE {} AND is_dir = 0
+                 ORDER BY modified DESC, path
+                 LIMIT 1",
+                FILE_META_COLUMNS,
+                dir_in_range("?1", "?2")
+            ),
+            rusqlite::params![start, end],
+            file_meta_from_row,
+        )
+        .optional()?;
+    if let Some(file) = newest_file.as_mut() {
+        FileKinds::load(conn)?.annotate(std::slice::from_mut(file));
+    }
+    Ok(DirectoryActivity {
+        last_modified: last_modified.unwrap_or(0),
+        newest_file,
+        file_count,
+    })
+}
+
+// The extension from the path string alone, without touching disk: "gz" for
+// "archive.tar.gz", None for dotfiles like ".gitignore" and for "name.".
+#[tauri::command]
+fn get_extension(path: String) -> Option<String> {
+    let name = path.trim_end_matches(['\\', '/']).rsplit(['\\', '/']).next()?;
+    Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .filter(|ext| !ext.is_empty())
+        .map(|ext| ext.to_string())
+}
+
+// The directory holding `p`, in the form directory listings use: drive roots
+// keep their separator. None for a root itself.
+fn parent_dir(p: &str) -> Option<String> {
+    let trimmed = p.trim_end_matches(['\\', '/']);
+    let cut = trimmed.rfind(['\\', '/'])?;
+    let parent = &trimmed[..cut];
+    if parent.is_empty() {
+        // "/etc" on Unix
+        return Some(trimmed[..1].to_string());
+    }
+    if parent.len() == 2 && parent.ends_with(':') {
+        return Some(format!("{}\\", parent));
+    }
+    Some(parent.to_string())
+}
+
+// Schema setup shared by startup and unlock_index.
+fn open_schema(conn: &Connection) -> Result<()> {
+    // dir_paths and the other temp objects are rebuilt on every open, so
+    // they never need a file of their own; this must precede them all
+    conn.execute_batch("PRAGMA temp_store = MEMORY")?;
+    register_sql_functions(conn)?;
+    create_table(conn)?;
+    migrate_schema(conn)?;
+    create_indexes(conn)?;
+    load_dir_paths(conn)?;
+    normalize_legacy_paths(conn)
+}
+
+// Hooks the listing cache and the index-updated notifier up to `conn`.
+fn watch_index_changes(conn: &Connection, app: &tauri::AppHandle) -> Result<()> {
+    watch_connection(conn, &app.state::<ListingCache>())
+}
+
+fn watch_connection(conn: &Connection, cache: &ListingCache) -> Result<()> {
+    install_change_tracking(conn)?;
+    cache.watch(conn);
+    Ok(())
+}
+
+// Every write to main_table, whichever code path makes it, notes the parent
+// directory of the rows it touched. The temp objects live only as long as the
+// connection and never reach the database file.
+fn install_change_tracking(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TEMP TABLE IF NOT EXISTS changed_dirs (dir TEXT PRIMARY KEY NOT NULL);
+         CREATE TEMP TRIGGER IF NOT EXISTS main_table_inserted AFTER INSERT ON main.main_table
+         BEGIN
+             INSERT OR IGNORE INTO changed_dirs
+             SELECT dir FROM (SELECT parent_dir(path || NEW.name) AS dir
+                              FROM dir_paths WHERE id = NEW.dir_id)
+             WHERE dir IS NOT NULL;
+         END;
+         CREATE TEMP TRIGGER IF NOT EXISTS main_table_updated AFTER UPDATE ON main.main_table
+         BEGIN
+             INSERT OR IGNORE INTO changed_dirs
+             SELECT dir FROM (SELECT parent_dir(path || OLD.name) AS dir
+                              FROM dir_paths WHERE id = OLD.dir_id
+                              UNION
+                              SELECT parent_dir(path || NEW.name)
+                              FROM dir_paths WHERE id = NEW.dir_id)
+             WHERE dir IS NOT NULL;
+         END;
+         CREATE TEMP TRIGGER IF NOT EXISTS main_table_deleted AFTER DELETE ON main.main_table
+         BEGIN
+             INSERT OR IGNORE INTO changed_dirs
+             SELECT dir FROM (SELECT parent_dir(path || OLD.name) AS dir
+                              FROM dir_paths WHERE id = OLD.dir_id)
+             WHERE dir IS NOT NULL;
+         END;",
+    )
+}
+
+const INDEX_UPDATE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
+
+// Beyond this many directories the event says "everything" instead.
+const INDEX_UPDATE_MAX_DIRS: usize = 500;
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+struct IndexUpdated {
+    dirs: Vec<String>,
+    // too many directories changed to list; refetch whatever is on screen
+    all: bool,
+}
+
+// Drains changed_dirs into one "index-updated" event per interval. Rows only
+// show up here once committed: a transaction holds the connection lock for
+// as long as it is open.
+fn spawn_index_update_notifier(app: tauri::AppHandle) {
+    std::thread::spawn(move || {
+        let generation = app.state::<ListingCache>().generation.clone();
+        let mut seen = generation.load(Ordering::SeqCst);
+        loop {
+            std::thread::sleep(INDEX_UPDATE_INTERVAL);
+            let current = generation.load(Ordering::SeqCst);
+            if current == seen {
+                continue;
+            }
+            seen = current;
+            let db = app.state::<DbConnection>();
+            let Ok(conn) = db.0.lock() else { continue };
+            let Ok(update) = take_changed_dirs(&conn) else { continue };
+            drop(conn);
+            if update.all || !update.dirs.is_empty() {
+                let _ = app.emit_all("index-updated", &update);
+            }
+        }
+    });
+}
+
+fn take_changed_dirs(conn: &Connection) -> Result<IndexUpdated> {
+    let mut stmt = conn.prepare("SELECT dir FROM changed_dirs ORDER BY dir LIMIT ?1")?;
+    let dirs = stmt
+        .query_map(rusqlite::params![INDEX_UPDATE_MAX_DIRS as i64 + 1], |row| row.get(0))?
+        .collect::<Result<Vec<String>>>()?;
+    conn.execute("DELETE FROM changed_dirs", [])?;
+    Ok(if dirs.len() > INDEX_UPDATE_MAX_DIRS {
+        IndexUpdated { dirs: Vec::new(), all: true }
+    } else {
+        IndexUpdated { dirs, all: false }
+    })
+}
+
+fn rename_in_index(conn: &Connection, old: &str, new: &str) -> Result<()> {
+    invalidate_dir_sizes(conn, old)?;
+    invalidate_dir_sizes(conn, new)?;
+    let (old_prefix, old_end) = subtree_range(old);
+    let new_prefix = subtree_prefix(new);
+    // each directory below `old` hands its rows to the same directory below
+    // `new`, then goes
+    let moved: Vec<(i64, String)> = conn
+        .prepare("SELECT id, path FROM dir_paths WHERE path >= ?1 AND path < ?2")?
+        .query_map(rusqlite::params![old_prefix, old_end], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_>>()?;
+    for (id, prefix) in &moved {
+        let target = intern_dir(conn, &format!("{}{}", new_prefix, &prefix[old_prefix.len()..]))?;
+        conn.execute(
+            "UPDATE main_table SET dir_id = ?2 WHERE dir_id = ?1",
+            rusqlite::params![id, target],
+        )?;
+    }
+    for (id, _) in &moved {
+        conn.execute("DELETE FROM dirs WHERE id = ?1", rusqlite::params![id])?;
+    }
+    let delete = format!("DELETE FROM main_table WHERE {}", path_is("?1"));
+    conn.execute(&delete, rusqlite::params![old])?;
+    conn.execute(
+        "UPDATE OR REPLACE file_tags SET path = ?2 || substr(path, length(?1) + 1)
+         WHERE path >= ?1 AND path < ?3",
+        rusqlite::params![old_prefix, new_prefix, old_end],
+    )?;
+    conn.execute(
+        "UPDATE OR REPLACE file_tags SET path = ?2 WHERE path = ?1",
+        rusqlite::params![old, new],
+    )?;
+    Ok(())
+}
+
+fn remove_subtree_from_index(conn: &Connection, path: &str) -> Result<()> {
+    invalidate_dir_sizes(conn, path)?;
+    let (start, end) = subtree_range(path);
+    conn.execute(
+        &format!("DELETE FROM main_table WHERE {} OR {}", path_is("?1"), dir_in_range("?2", "?3")),
+        rusqlite::params![path, start, end],
+    )?;
+    conn.execute(
+        "DELETE FROM file_tags WHERE path = ?1 OR (path >= ?2 AND path < ?3)",
+        rusqlite::params![path, start, end],
+    )?;
+    conn.execute(
+        "DELETE FROM dirs WHERE id IN (SELECT id FROM dir_paths WHERE path >= ?1 AND path < ?2)",
+        rusqlite::params![start, end],
+    )?;
+    Ok(())
+}
+
+// Rebuilds every directory's recursive size by pushing each file's size up
+// through all of its ancestors.
+fn recompute_dir_sizes(conn: &Connection) -> Result<()> {
+    let sizes = summed_dir_sizes(conn)?;
+    conn.execute("DELETE FROM dir_sizes", [])?;
+    let mut insert = conn.prepare("INSERT INTO dir_sizes (path, size) VALUES (?1, ?2)")?;
+    for (path, size) in sizes {
+        insert.execute(rusqlite::params![path, size])?;
+    }
+    Ok(())
+}
+
+fn summed_dir_sizes(conn: &Connection) -> Result<std::collections::HashMap<String, u64>> {
+    let mut sizes: std::collections::HashMap<String, u64> = std::collections::HashMap::new();
+    let mut stmt = conn.prepare("SELECT path, size FROM entries WHERE is_dir = 0")?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?)))?;
+    for (path, size) in rows.filter_map(Result::ok) {
+        for ancestor in Path::new(&path).ancestors().skip(1) {
+            if ancestor.as_os_str().is_empty() {
+                break;
+            }
+            *sizes.entry(ancestor.to_string_lossy().to_string()).or_default() += size;
+        }
+    }
+    Ok(sizes)
+}
+
+// Drops the cached sizes of `path`, its descendants and all of its ancestors;
+// get_cached_directory_size recomputes them on the next read.
+fn invalidate_dir_sizes(conn: &Connection, path: &str) -> Result<()> {
+    let mut stmt = conn.prepare("DELETE FROM dir_sizes WHERE path = ?1")?;
+    for ancestor in Path::new(path).ancestors() {
+        if ancestor.as_os_str().is_empty() {
+            break;
+        }
+        stmt.execute(rusqlite::params![ancestor.to_string_lossy()])?;
+    }
+    let (start, end) = subtree_range(path);
+    conn.execute(
+        "DELETE FROM dir_sizes WHERE path >= ?1 AND path < ?2",
+        rusqlite::params![start, end],
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        open_schema(&conn).unwrap();
+        conn
+    }
+
+    fn path_str(p: &Path) -> String {
+        p.to_string_lossy().to_string()
+    }
+
+    fn write_file(path: &Path, contents: &str) {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, contents).unwrap();
+    }
+
+    // "/idx/a" with the platform's separator, for rows that never touch disk.
+    fn native(path: &str) -> String {
+        path.replace('/', &std::path::MAIN_SEPARATOR.to_string())
+    }
+
+    fn file_row(path: &str, size: u64, is_dir: bool) -> FileMeta {
+        let path = native(path);
+        let p = Path::new(&path);
+        FileMeta {
+            name: p.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            extension: p.extension().map(|e| e.to_string_lossy().to_string()),
+            path,
+            size,
+            modified: 1_000,
+            accessed: None,
+            is_dir,
+            created: None,
+            is_symlink: false,
+            link_count: None,
+            file_key: None,
+            kind: None,
+        }
+    }
+
+    fn insert_rows(conn: &Connection, rows: &[(&str, u64, bool)]) {
+        for &(path, size, is_dir) in rows {
+            insert_file_meta(conn, &file_row(path, size, is_dir)).unwrap();
+        }
+    }
+
+    // A row for `path` as the walk would produce it.
+    fn index_path(conn: &Connection, path: &Path) {
+        insert_file_meta(conn, &get_file_meta(path).unwrap()).unwrap();
+    }
+
+    fn indexed(conn: &Connection, path: &Path) -> bool {
+        conn.query_row(
+            "SELECT EXISTS(SELECT 1 FROM entries WHERE path = ?1)",
+            rusqlite::params![path_str(path)],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn conflict_policy_parses_known_names_only() {
+        assert_eq!("error".parse::<ConflictPolicy>().unwrap(), ConflictPolicy::Error);
+        assert_eq!("overwrite".parse::<ConflictPolicy>().unwrap(), ConflictPolicy::Overwrite);
+        assert_eq!("rename".parse::<ConflictPolicy>().unwrap(), ConflictPolicy::Rename);
+        assert!(matches!("Replace".parse::<ConflictPolicy>(), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn move_one_error_policy_leaves_both_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = memory_db();
+        let source = dir.path().join("a").join("report.txt");
+        let existing = dir.path().join("b").join("report.txt");
+        write_file(&source, "new");
+        write_file(&existing, "old");
+
+        let dest = dir.path().join("b");
+        let result = move_one(&conn, &path_str(&source), &dest, ConflictPolicy::Error);
+        assert!(matches!(result, Err(Error::AlreadyExists(p)) if p == path_str(&existing)));
+        assert_eq!(fs::read_to_string(&source).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "old");
+    }
+
+    #[test]
+    fn move_one_overwrite_replaces_target_and_index_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = memory_db();
+        let source = dir.path().join("a").join("report.txt");
+        let existing = dir.path().join("b").join("report.txt");
+        write_file(&source, "new");
+        write_file(&existing, "old");
+        index_path(&conn, &source);
+        index_path(&conn, &existing);
+
+        let dest = dir.path().join("b");
+        let meta = move_one(&conn, &path_str(&source), &dest, ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(meta.path, path_str(&existing));
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "new");
+        assert!(!source.exists());
+        assert!(!indexed(&conn, &source));
+        assert!(indexed(&conn, &existing));
+    }
+
+    #[test]
+    fn move_one_rename_picks_a_free_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = memory_db();
+        let source = dir.path().join("a").join("report.txt");
+        write_file(&source, "new");
+        write_file(&dir.path().join("b").join("report.txt"), "old");
+        write_file(&dir.path().join("b").join("report (1).txt"), "older");
+
+        let dest = dir.path().join("b");
+        let meta = move_one(&conn, &path_str(&source), &dest, ConflictPolicy::Rename).unwrap();
+        assert_eq!(meta.name, "report (2).txt");
+        assert_eq!(fs::read_to_string(dest.join("report (2).txt")).unwrap(), "new");
+    }
+
+    #[test]
+    fn move_one_into_own_parent_is_a_no_op() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = memory_db();
+        let source = dir.path().join("report.txt");
+        write_file(&source, "keep");
+
+        let policy = ConflictPolicy::Overwrite;
+        let meta = move_one(&conn, &path_str(&source), dir.path(), policy).unwrap();
+        assert_eq!(meta.path, path_str(&source));
+        assert_eq!(fs::read_to_string(&source).unwrap(), "keep");
+    }
+
+    #[test]
+    fn move_one_refuses_to_move_a_folder_into_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = memory_db();
+        let folder = dir.path().join("folder");
+        fs::create_dir_all(folder.join("inner")).unwrap();
+
+        let inner = folder.join("inner");
+        let result = move_one(&conn, &path_str(&folder), &inner, ConflictPolicy::Error);
+        assert!(matches!(result, Err(Error::InvalidInput(_))));
+        assert!(inner.is_dir());
+    }
+
+    #[test]
+    fn names_only_listing_reports_names_and_kinds_without_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("notes.txt"), "hello");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+
+        let mut names = read_entry_names(dir.path()).unwrap();
+        names.sort_by(|a, b| a.name.cmp(&b.name));
+        let summary: Vec<(&str, bool)> =
+            names.iter().map(|e| (e.name.as_str(), e.is_dir)).collect();
+        assert_eq!(summary, [("notes.txt", false), ("sub", true)]);
+        assert_eq!(names[0].path, path_str(&dir.path().join("notes.txt")));
+    }
+
+    #[test]
+    fn full_listing_carries_size_and_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("notes.txt"), "hello");
+
+        let metas = read_entry_metas(dir.path()).unwrap();
+        assert_eq!(metas.len(), 1);
+        assert_eq!(metas[0].size, 5);
+        assert_eq!(metas[0].extension.as_deref(), Some("txt"));
+        assert!(metas[0].modified > 0);
+    }
+
+    #[test]
+    fn listing_a_missing_directory_is_an_io_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("gone");
+        assert!(matches!(read_entry_names(&missing), Err(Error::Io(_))));
+        assert!(matches!(read_entry_metas(&missing), Err(Error::Io(_))));
+    }
+
+    fn drive(mount_point: &str, available_space: u64) -> DriveInfo {
+        DriveInfo {
+            name: String::new(),
+            mount_point: mount_point.to_string(),
+            file_system: "NTFS".to_string(),
+            total_space: 1 << 40,
+            available_space,
+            is_removable: mount_point != "C:\\",
+        }
+    }
+
+    #[test]
+    fn drive_changes_reports_added_and_removed_volumes() {
+        let known = [drive("C:\\", 100), drive("E:\\", 100)];
+        let current = [drive("C:\\", 100), drive("F:\\", 100)];
+        let (removed, added) = drive_changes(&known, &current);
+        assert_eq!(removed, [drive("E:\\", 100)]);
+        assert_eq!(added, [drive("F:\\", 100)]);
+    }
+
+    #[test]
+    fn drive_changes_ignores_free_space() {
+        let known = [drive("C:\\", 100), drive("E:\\", 100)];
+        let current = [drive("C:\\", 50), drive("E:\\", 10)];
+        let (removed, added) = drive_changes(&known, &current);
+        assert!(removed.is_empty() && added.is_empty());
+    }
+
+    #[test]
+    fn read_drives_is_sorted_by_mount_point() {
+        let drives = read_drives();
+        assert!(drives.windows(2).all(|w| w[0].mount_point <= w[1].mount_point));
+    }
+
+    #[test]
+    fn copy_target_overwrite_refuses_to_replace_the_source_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("report.txt");
+        write_file(&source, "keep");
+
+        let result = copy_target(&source, dir.path(), ConflictPolicy::Overwrite);
+        assert_eq!(result.unwrap_err(), "source and destination are the same");
+        assert_eq!(fs::read_to_string(&source).unwrap(), "keep");
+    }
+
+    #[test]
+    fn copy_target_overwrite_clears_an_existing_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a").join("report.txt");
+        let existing = dir.path().join("b").join("report.txt");
+        write_file(&source, "new");
+        write_file(&existing, "old");
+
+        let dest = dir.path().join("b");
+        let target = copy_target(&source, &dest, ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(target, existing);
+        assert!(!existing.exists());
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn copy_target_rename_copies_beside_the_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("report.txt");
+        write_file(&source, "keep");
+
+        let target = copy_target(&source, dir.path(), ConflictPolicy::Rename).unwrap();
+        assert_eq!(target, dir.path().join("report (1).txt"));
+        let error = copy_target(&source, dir.path(), ConflictPolicy::Error).unwrap_err();
+        assert_eq!(error, "destination already exists");
+    }
+
+    #[test]
+    fn copy_target_refuses_a_folder_into_itself() {
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("folder");
+        fs::create_dir_all(folder.join("inner")).unwrap();
+
+        let result = copy_target(&folder, &folder.join("inner"), ConflictPolicy::Rename);
+        assert_eq!(result.unwrap_err(), "cannot copy a folder into itself");
+    }
+
+    fn set_mtime(path: &Path, secs: i64) {
+        filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(secs, 0)).unwrap();
+    }
+
+    fn planned(report: &SyncReport) -> Vec<(&str, &str)> {
+        report.actions.iter().map(|a| (a.action, a.path.as_str())).collect()
+    }
+
+    #[test]
+    fn plan_sync_mirror_copies_changes_and_deletes_extras() {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, dest) = (dir.path().join("src"), dir.path().join("dst"));
+        write_file(&source.join("same.txt"), "same");
+        write_file(&dest.join("same.txt"), "same");
+        set_mtime(&source.join("same.txt"), 1_000);
+        set_mtime(&dest.join("same.txt"), 1_000);
+        write_file(&source.join("new.txt"), "new");
+        write_file(&source.join("edited.txt"), "old text");
+        write_file(&dest.join("edited.txt"), "newer text");
+        set_mtime(&source.join("edited.txt"), 1_000);
+        set_mtime(&dest.join("edited.txt"), 2_000);
+        write_file(&dest.join("extra").join("a.txt"), "a");
+        write_file(&dest.join("extra").join("b.txt"), "b");
+
+        let report = plan_sync(&source, &dest, true, true).unwrap();
+        assert_eq!(
+            planned(&report),
+            [("copy", "edited.txt"), ("delete", "extra"), ("copy", "new.txt")]
+        );
+        assert_eq!(report.skipped, 1);
+        assert!(report.dry_run);
+
+        // update_only keeps the newer destination copy and all extras
+        let report = plan_sync(&source, &dest, false, true).unwrap();
+        assert_eq!(planned(&report), [("copy", "new.txt")]);
+        assert_eq!(report.skipped, 2);
+    }
+
+    #[test]
+    fn plan_sync_refuses_nested_folders() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("src");
+        fs::create_dir_all(source.join("backup")).unwrap();
+
+        let into_source = plan_sync(&source, &source.join("backup"), true, false);
+        assert!(matches!(into_source, Err(Error::InvalidInput(_))));
+        let onto_parent = plan_sync(&source, dir.path(), true, false);
+        assert!(matches!(onto_parent, Err(Error::InvalidInput(_))));
+        assert!(source.join("backup").is_dir());
+    }
+
+    fn directory_tree(conn: &Connection) {
+        insert_rows(
+            conn,
+            &[
+                ("/idx", 0, true),
+                ("/idx/a", 0, true),
+                ("/idx/a/one.bin", 10, false),
+                ("/idx/a/deep", 0, true),
+                ("/idx/a/deep/two.bin", 20, false),
+                ("/idx/b", 0, true),
+                ("/idx/b/three.bin", 40, false),
+                ("/idx/ab.bin", 80, false),
+            ],
+        );
+    }
+
+    #[test]
+    fn cached_directory_size_sums_the_subtree_and_keeps_it() {
+        let conn = memory_db();
+        directory_tree(&conn);
+        // "/idx/ab.bin" shares a prefix with "/idx/a" but is not below it
+        assert_eq!(cached_directory_size(&conn, &native("/idx/a")).unwrap(), 30);
+        assert_eq!(cached_directory_size(&conn, &native("/idx")).unwrap(), 150);
+
+        // served from dir_sizes until invalidated
+        insert_rows(&conn, &[("/idx/a/late.bin", 5, false)]);
+        assert_eq!(cached_directory_size(&conn, &native("/idx/a")).unwrap(), 30);
+        invalidate_dir_sizes(&conn, &native("/idx/a/late.bin")).unwrap();
+        assert_eq!(cached_directory_size(&conn, &native("/idx/a")).unwrap(), 35);
+        assert_eq!(cached_directory_size(&conn, &native("/idx")).unwrap(), 155);
+    }
+
+    #[test]
+    fn integrity_check_finds_and_drops_rows_cut_off_from_their_parent() {
+        let mut conn = memory_db();
+        directory_tree(&conn);
+        recompute_dir_sizes(&conn).unwrap();
+        assert!(integrity_report(&mut conn, false).unwrap().orphaned_rows.is_empty());
+
+        // a directory lost without its contents, a row under a file, and a
+        // second root with nothing indexed above it
+        let delete = format!("DELETE FROM main_table WHERE {}", path_is("?1"));
+        conn.execute(&delete, [native("/idx/a")]).unwrap();
+        insert_rows(&conn, &[("/idx/ab.bin/inner", 1, false), ("/other/solo.txt", 5, false)]);
+        let report = integrity_report(&mut conn, false).unwrap();
+        let expected = ["/idx/a/deep", "/idx/a/one.bin", "/idx/ab.bin/inner"].map(native);
+        assert_eq!(report.orphaned_rows, expected);
+        assert!(!report.repaired);
+
+        let report = integrity_report(&mut conn, true).unwrap();
+        assert!(report.repaired);
+        assert!(!indexed(&conn, Path::new(&native("/idx/a/deep/two.bin"))));
+        assert!(indexed(&conn, Path::new(&native("/other/solo.txt"))));
+        let after = integrity_report(&mut conn, false).unwrap();
+        assert!(after.orphaned_rows.is_empty());
+        assert!(after.stale_dir_sizes.is_empty() && after.orphaned_dir_sizes.is_empty());
+    }
+
+    #[test]
+    fn the_integrity_check_runs_on_its_schedule() {
+        const DAY: u64 = 24 * 3600;
+        let mut conn = memory_db();
+        assert!(due_integrity_check(&mut conn, DAY).unwrap().is_none());
+        let never = IntegritySchedule { interval_hours: 0, fix: false };
+        assert!(matches!(save_integrity_schedule(&conn, Some(never)), Err(Error::InvalidInput(_))));
+
+        let daily = IntegritySchedule { interval_hours: 24, fix: true };
+        save_integrity_schedule(&conn, Some(daily)).unwrap();
+        directory_tree(&conn);
+        let delete = format!("DELETE FROM main_table WHERE {}", path_is("?1"));
+        conn.execute(&delete, [native("/idx/b")]).unwrap();
+        let report = due_integrity_check(&mut conn, DAY).unwrap().unwrap();
+        assert_eq!(report.orphaned_rows, [native("/idx/b/three.bin")]);
+        assert!(report.repaired);
+        assert!(due_integrity_check(&mut conn, DAY + 3600).unwrap().is_none());
+        assert!(due_integrity_check(&mut conn, 2 * DAY).unwrap().is_some());
+
+        save_integrity_schedule(&conn, None).unwrap();
+        assert!(due_integrity_check(&mut conn, 3 * DAY).unwrap().is_none());
+    }
+
+    #[test]
+    fn invalidate_dir_sizes_spares_unrelated_directories() {
+        let conn = memory_db();
+        directory_tree(&conn);
+        recompute_dir_sizes(&conn).unwrap();
+        invalidate_dir_sizes(&conn, &native("/idx/a")).unwrap();
+
+        let cached = |dir: &str| -> Option<u64> {
+            conn.query_row(
+                "SELECT size FROM dir_sizes WHERE path = ?1",
+                rusqlite::params![native(dir)],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap()
+        };
+        assert_eq!(cached("/idx/a"), None);
+        assert_eq!(cached("/idx/a/deep"), None);
+        assert_eq!(cached("/idx"), None);
+        assert_eq!(cached("/idx/b"), Some(40));
+    }
+
+    #[test]
+    fn recompute_dir_sizes_pushes_sizes_to_every_ancestor() {
+        let conn = memory_db();
+        directory_tree(&conn);
+        recompute_dir_sizes(&conn).unwrap();
+
+        let sizes = summed_dir_sizes(&conn).unwrap();
+        assert_eq!(sizes[&native("/idx/a/deep")], 20);
+        assert_eq!(sizes[&native("/idx/a")], 30);
+        assert_eq!(sizes[&native("/idx")], 150);
+        let stored: u64 = conn
+            .query_row("SELECT COUNT(*) FROM dir_sizes", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, sizes.len() as u64);
+    }
+
+    // What a pruning run does after its walk, minus the AppHandle plumbing.
+    fn walk_and_prune(db: &DbConnection, root: &Path) -> Result<u64, Error> {
+        let root = path_str(root);
+        ensure_root_reachable(&root)?;
+        let mut walk = index_walk(&root);
+        let seen = (&mut walk).map(|e| path_str(e.path())).collect();
+        prune_unseen(db, &root, &seen, &walk.unreadable, RunActivity::Off)
+    }
+
+    fn indexed_count(db: &DbConnection) -> u64 {
+        let conn = db.0.lock().unwrap();
+        conn.query_row("SELECT COUNT(*) FROM main_table", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn a_missing_root_keeps_its_rows_through_a_scheduled_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("usb");
+        write_file(&root.join("photos").join("a.jpg"), "a");
+        let db = DbConnection(Mutex::new(memory_db()));
+        {
+            let conn = db.0.lock().unwrap();
+            for entry in index_walk(&path_str(&root)) {
+                index_path(&conn, entry.path());
+            }
+        }
+        assert_eq!(indexed_count(&db), 3);
+
+        fs::remove_dir_all(&root).unwrap();
+        let result = walk_and_prune(&db, &root);
+        assert!(matches!(result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound));
+        assert_eq!(indexed_count(&db), 3);
+
+        // even without the reachability check the failed walk prunes nothing
+        let mut walk = index_walk(&path_str(&root));
+        assert!(walk.next().is_none());
+        assert_eq!(walk.unreadable, std::slice::from_ref(&root));
+        let seen = Default::default();
+        let pruned = prune_unseen(&db, &path_str(&root), &seen, &walk.unreadable, RunActivity::Off);
+        assert_eq!(pruned.unwrap(), 0);
+        assert_eq!(indexed_count(&db), 3);
+    }
+
+    #[test]
+    fn a_reachable_root_prunes_what_was_deleted() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("docs");
+        write_file(&root.join("keep.txt"), "k");
+        write_file(&root.join("gone.txt"), "g");
+        let db = DbConnection(Mutex::new(memory_db()));
+        {
+            let conn = db.0.lock().unwrap();
+            for entry in index_walk(&path_str(&root)) {
+                index_path(&conn, entry.path());
+            }
+        }
+        fs::remove_file(root.join("gone.txt")).unwrap();
+
+        assert_eq!(walk_and_prune(&db, &root).unwrap(), 1);
+        assert_eq!(indexed_count(&db), 2);
+    }
+
+    #[test]
+    fn prune_unseen_spares_rows_below_unreadable_directories() {
+        let db = DbConnection(Mutex::new(memory_db()));
+        insert_rows(
+            &db.0.lock().unwrap(),
+            &[
+                ("/idx", 0, true),
+                ("/idx/locked", 0, true),
+                ("/idx/locked/secret.txt", 1, false),
+                ("/idx/lockedout.txt", 1, false),
+            ],
+        );
+        let seen = [native("/idx")].into_iter().collect();
+        let unreadable = [std::path::PathBuf::from(native("/idx/locked"))];
+        let pruned =
+            prune_unseen(&db, &native("/idx"), &seen, &unreadable, RunActivity::Off).unwrap();
+        // a sibling sharing the name prefix is not below the locked folder
+        assert_eq!(pruned, 1);
+        assert_eq!(indexed_count(&db), 3);
+    }
+
+    #[test]
+    fn tree_text_draws_an_outline_down_to_max_depth() {
+        let conn = memory_db();
+        directory_tree(&conn);
+
+        let expected = [
+            native("/idx"),
+            "├── a".to_string(),
+            "│   ├── deep".to_string(),
+            "│   └── one.bin".to_string(),
+            "├── ab.bin".to_string(),
+            "└── b".to_string(),
+            "    └── three.bin".to_string(),
+        ];
+        let text = tree_text(&conn, &native("/idx"), 2, false).unwrap();
+        assert_eq!(text.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn tree_text_annotates_recursive_sizes() {
+        let conn = memory_db();
+        directory_tree(&conn);
+
+        let text = tree_text(&conn, &native("/idx/a"), 1, true).unwrap();
+        let expected = [
+            native("/idx/a (30 Bytes)"),
+            "├── deep (20 Bytes)".to_string(),
+            "└── one.bin (10 Bytes)".to_string(),
+        ];
+        assert_eq!(text.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn human_size_matches_the_frontend_units() {
+        assert_eq!(human_size(0), "0 Bytes");
+        assert_eq!(human_size(1023), "1023 Bytes");
+        assert_eq!(human_size(1024), "1 KB");
+        assert_eq!(human_size(1536), "1.5 KB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5 GB");
+        assert_eq!(human_size(u64::MAX), "16777216 TB");
+    }
+
+    fn names(files: &[FileMeta]) -> Vec<&str> {
+        let mut names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn locate_returns_the_parent_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = memory_db();
+        let file = dir.path().join("docs").join("a.txt");
+        write_file(&file, "a");
+        write_file(&dir.path().join("docs").join("b.txt"), "b");
+        for entry in index_walk(&path_str(dir.path())) {
+            index_path(&conn, entry.path());
+        }
+
+        let found = locate_in(&conn, &path_str(&file)).unwrap();
+        assert!(found.exists);
+        assert_eq!(found.parent, path_str(&dir.path().join("docs")));
+        assert_eq!(names(&found.children), ["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn locate_drops_a_vanished_file_from_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = memory_db();
+        let file = dir.path().join("a.txt");
+        write_file(&file, "a");
+        write_file(&dir.path().join("b.txt"), "b");
+        for entry in index_walk(&path_str(dir.path())) {
+            index_path(&conn, entry.path());
+        }
+        fs::remove_file(&file).unwrap();
+
+        let found = locate_in(&conn, &path_str(&file)).unwrap();
+        assert!(!found.exists);
+        assert_eq!(names(&found.children), ["b.txt"]);
+        assert!(!indexed(&conn, &file));
+    }
+
+    #[test]
+    fn query_children_takes_like_metacharacters_literally() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/100%_reports", 0, true),
+                ("/idx/100%_reports/q1.pdf", 1, false),
+                ("/idx/100%_reports/q1/deeper.pdf", 1, false),
+                ("/idx/100x_reports/other.pdf", 1, false),
+                ("/idx/100%xreports/other.pdf", 1, false),
+            ],
+        );
+        let children = query_children(&conn, &native("/idx/100%_reports")).unwrap();
+        assert_eq!(names(&children), ["q1.pdf"]);
+        let root = query_children(&conn, &native("/idx")).unwrap();
+        assert_eq!(names(&root), ["100%_reports"]);
+    }
+
+    fn metacharacter_tree(conn: &Connection) {
+        insert_rows(
+            conn,
+            &[
+                ("/idx/100%_reports", 0, true),
+                ("/idx/100%_reports/q1.pdf", 10, false),
+                ("/idx/100%_reports/q1/deeper.pdf", 20, false),
+                ("/idx/100x_reports/other.pdf", 100, false),
+                ("/idx/100%_reports2/other.pdf", 1_000, false),
+                ("/idx/100%_reports.old/other.pdf", 10_000, false),
+            ],
+        );
+    }
+
+    fn all_paths(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn.prepare("SELECT path FROM entries ORDER BY path").unwrap();
+        let rows = stmt.query_map([], |row| row.get(0)).unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn subtree_sums_take_metacharacters_and_shared_prefixes_literally() {
+        let conn = memory_db();
+        metacharacter_tree(&conn);
+        let dir = native("/idx/100%_reports");
+        assert_eq!(cached_directory_size(&conn, &dir).unwrap(), 30);
+        let stats = stats_below(&conn, &dir).unwrap();
+        assert_eq!((stats.file_count, stats.dir_count, stats.total_size), (2, 0, 30));
+        assert_eq!(coverage_of(&conn, &dir, 3).unwrap().indexed, 3);
+    }
+
+    #[test]
+    fn subtree_removal_and_rename_spare_lookalike_siblings() {
+        let conn = memory_db();
+        metacharacter_tree(&conn);
+        remove_subtree_from_index(&conn, &native("/idx/100%_reports")).unwrap();
+        assert_eq!(
+            all_paths(&conn),
+            [
+                native("/idx/100%_reports.old/other.pdf"),
+                native("/idx/100%_reports2/other.pdf"),
+                native("/idx/100x_reports/other.pdf"),
+            ]
+        );
+
+        let conn = memory_db();
+        metacharacter_tree(&conn);
+        rename_in_index(&conn, &native("/idx/100%_reports"), &native("/idx/q")).unwrap();
+        let paths = all_paths(&conn);
+        assert!(paths.contains(&native("/idx/q/q1/deeper.pdf")));
+        assert!(paths.contains(&native("/idx/100%_reports2/other.pdf")));
+        assert!(paths.contains(&native("/idx/100x_reports/other.pdf")));
+        assert_eq!(paths.len(), 5);
+    }
+
+    #[test]
+    fn prune_unseen_spares_lookalike_siblings() {
+        let db = DbConnection(Mutex::new(memory_db()));
+        metacharacter_tree(&db.0.lock().unwrap());
+        let root = native("/idx/100%_reports");
+        let seen = [root.clone()].into_iter().collect();
+        assert_eq!(prune_unseen(&db, &root, &seen, &[], RunActivity::Off).unwrap(), 2);
+        assert_eq!(indexed_count(&db), 4);
+    }
+
+    #[test]
+    fn subtree_ranges_are_served_by_the_path_index() {
+        let conn = memory_db();
+        let plan: Vec<String> = conn
+            .prepare(&format!(
+                "EXPLAIN QUERY PLAN SELECT COUNT(*) FROM main_table WHERE {}",
+                dir_in_range("?1", "?2")
+            ))
+            .unwrap()
+            .query_map(["/a/", "/a0"], |row| row.get(3))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert!(plan.iter().any(|step| step.contains("path>? AND path<?")), "{:?}", plan);
+        assert!(plan.iter().any(|step| step.contains("(dir_id=?)")), "{:?}", plan);
+        assert!(!plan.iter().any(|step| step.starts_with("SCAN")), "{:?}", plan);
+    }
+
+    // How releases before dirs keyed a row to its directory.
+    const FLAT_PARENT_KEY: &str = "substr(path, 1, length(path) - length(name) - 1)";
+
+    // An index laid out as releases before dirs left it: full paths in
+    // main_table under the indexes they had. The temp `entries` view lets the
+    // readers that only filter on columns run over it unchanged.
+    fn flat_index(conn: &Connection, rows: &[FileMeta]) {
+        register_sql_functions(conn).unwrap();
+        conn.execute_batch(&format!(
+            "CREATE TABLE main_table (
+                name TEXT NOT NULL, path TEXT UNIQUE NOT NULL, extension TEXT,
+                size INTEGER NOT NULL, modified INTEGER NOT NULL, accessed INTEGER,
+                is_dir INTEGER NOT NULL DEFAULT 0, phash TEXT, phash_mtime INTEGER,
+                created INTEGER, is_symlink INTEGER NOT NULL DEFAULT 0, link_count INTEGER,
+                volume_id INTEGER, file_id INTEGER, entropy REAL, entropy_mtime INTEGER,
+                doc_title TEXT, doc_author TEXT, doc_meta_mtime INTEGER
+             );
+             CREATE INDEX idx_path ON main_table(path);
+             CREATE INDEX idx_name ON main_table(name);
+             CREATE INDEX idx_extension ON main_table(extension, name);
+             CREATE INDEX idx_parent_name ON main_table({} COLLATE {}, name COLLATE NOCASE);
+             CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);",
+            FLAT_PARENT_KEY, PATH_COLLATION
+        ))
+        .unwrap();
+        let tx = conn.unchecked_transaction().unwrap();
+        {
+            let mut insert = tx
+                .prepare(
+                    "INSERT INTO main_table (name, path, extension, size, modified, is_dir)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )
+                .unwrap();
+            for f in rows {
+                insert
+                    .execute(rusqlite::params![
+                        f.name,
+                        f.path,
+                        f.extension,
+                        f.size,
+                        f.modified,
+                        f.is_dir
+                    ])
+                    .unwrap();
+            }
+        }
+        tx.commit().unwrap();
+        conn.execute_batch("VACUUM; CREATE TEMP VIEW entries AS SELECT rowid, * FROM main_table")
+            .unwrap();
+    }
+
+    // `count` files and every folder above them, a few levels down a
+    // handful of long shared prefixes the way a home directory is, in the
+    // order an index run walks them.
+    fn synthetic_tree(count: usize) -> Vec<FileMeta> {
+        let (projects, modules, parts) = (7, 20, 5);
+        let per_part = count / (projects * modules * parts);
+        let mut rows: Vec<FileMeta> = ["/", "/home", "/home/someone", "/home/someone/projects"]
+            .iter()
+            .map(|dir| file_row(dir, 0, true))
+            .collect();
+        let mut i = 0;
+        for project in 0..projects {
+            let project = format!("/home/someone/projects/project{}", project);
+            rows.push(file_row(&project, 0, true));
+            rows.push(file_row(&format!("{}/src", project), 0, true));
+            for module in 0..modules {
+                let module = format!("{}/src/module{}", project, module);
+                rows.push(file_row(&module, 0, true));
+                for part in 0..parts {
+                    let part = format!("{}/part{}", module, part);
+                    rows.push(file_row(&part, 0, true));
+                    for _ in 0..per_part {
+                        let ext = ["rs", "txt", "json"][i % 3];
+                        let path = format!("{}/file{}.{}", part, i, ext);
+                        rows.push(file_row(&path, i as u64, false));
+                        i += 1;
+                    }
+                }
+            }
+        }
+        rows
+    }
+
+    fn file_bytes(conn: &Connection) -> u64 {
+        conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count, pragma_page_size",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    fn metas(conn: &Connection, sql: &str, params: &[&str]) -> Vec<FileMeta> {
+        let mut stmt = conn.prepare_cached(sql).unwrap();
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), file_meta_from_row).unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    // Every row in insertion order; `entries` on either layout.
+    fn every_row(conn: &Connection) -> String {
+        let sql = format!("SELECT {} FROM entries ORDER BY rowid", FILE_META_COLUMNS);
+        serde_json::to_string(&metas(conn, &sql, &[])).unwrap()
+    }
+
+    fn flat_lookups(conn: &Connection, paths: &[String]) -> String {
+        let sql = format!("SELECT {} FROM main_table WHERE path = ?1", FILE_META_COLUMNS);
+        let rows: Vec<_> = paths.iter().flat_map(|p| metas(conn, &sql, &[p])).collect();
+        serde_json::to_string(&rows).unwrap()
+    }
+
+    fn interned_lookups(conn: &Connection, paths: &[String]) -> String {
+        let sql = format!("SELECT {} FROM entries WHERE {}", FILE_META_COLUMNS, path_is("?1"));
+        let rows: Vec<_> = paths.iter().flat_map(|p| metas(conn, &sql, &[p])).collect();
+        serde_json::to_string(&rows).unwrap()
+    }
+
+    const SAMPLE_DIRS: [&str; 3] = [
+        "/home/someone/projects",
+        "/home/someone/projects/project3/src",
+        "/home/someone/projects/project3/src/module4/part2",
+    ];
+
+    // query_children as releases before dirs ran it.
+    fn flat_children(conn: &Connection) -> String {
+        let sql = format!(
+            "SELECT {} FROM main_table WHERE {} = ?1 COLLATE {} AND name <> ''",
+            FILE_META_COLUMNS, FLAT_PARENT_KEY, PATH_COLLATION
+        );
+        let rows: Vec<_> = SAMPLE_DIRS
+            .iter()
+            .flat_map(|d| metas(conn, &sql, &[parent_key(&native(d))]))
+            .collect();
+        serde_json::to_string(&rows).unwrap()
+    }
+
+    fn interned_children(conn: &Connection) -> String {
+        let rows: Vec<_> =
+            SAMPLE_DIRS.iter().flat_map(|d| query_children(conn, &native(d)).unwrap()).collect();
+        serde_json::to_string(&rows).unwrap()
+    }
+
+    fn search_for_file12(conn: &Connection) -> String {
+        let filters = SearchFilters {
+            name: "file12".to_string(),
+            extension: String::new(),
+            doc_title: None,
+            doc_author: None,
+        };
+        let sort = Some("name".to_string());
+        let listing = search_listing(conn, &filters, sort, None, None, ListingFormat::Objects);
+        serde_json::to_string(&listing.unwrap()).unwrap()
+    }
+
+    fn sample_lookups(rows: &[FileMeta]) -> Vec<String> {
+        let mut lookups: Vec<String> = rows.iter().step_by(97).map(|f| f.path.clone()).collect();
+        lookups.push(native("/home/someone/projects/nowhere.txt"));
+        lookups
+    }
+
+    // Reopens the flat index in `file`, which interns it.
+    fn interned_copy(file: &Path) -> Connection {
+        let conn = Connection::open(file).unwrap();
+        open_schema(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn interning_a_flat_index_keeps_every_response_byte_for_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("index.db");
+        let mut rows = synthetic_tree(3_000);
+        rows.push(file_row("/home/someone/Ünïcode dir/100% naïve_file.txt", 1, false));
+        let lookups = sample_lookups(&rows);
+        let flat = Connection::open(&file).unwrap();
+        flat_index(&flat, &rows);
+        let expected = [
+            every_row(&flat),
+            flat_lookups(&flat, &lookups),
+            flat_children(&flat),
+            search_for_file12(&flat),
+        ];
+        drop(flat);
+
+        let conn = interned_copy(&file);
+        let interned = [
+            every_row(&conn),
+            interned_lookups(&conn, &lookups),
+            interned_children(&conn),
+            search_for_file12(&conn),
+        ];
+        for (interned, expected) in interned.iter().zip(&expected) {
+            assert_eq!(interned, expected);
+        }
+        assert!(expected[2].contains("part2") && expected[3].contains("file12"));
+    }
+
+    #[test]
+    fn an_interned_index_takes_much_less_room_than_the_flat_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("index.db");
+        let flat = Connection::open(&file).unwrap();
+        flat_index(&flat, &synthetic_tree(20_000));
+        let flat_bytes = file_bytes(&flat);
+        drop(flat);
+        let interned_bytes = file_bytes(&interned_copy(&file));
+        println!("flat {} bytes, interned {} bytes", flat_bytes, interned_bytes);
+        // the repeated prefixes were most of each row
+        assert!(interned_bytes * 10 <= flat_bytes * 7, "{} vs {}", interned_bytes, flat_bytes);
+    }
+
+    // Pages `f` had to read into the page cache on `conn`, which at the
+    // default cache size is what a large index costs beyond the CPU.
+    fn pages_read(conn: &Connection, f: impl FnOnce() -> String) -> i64 {
+        use rusqlite::ffi::{sqlite3_db_status, SQLITE_DBSTATUS_CACHE_MISS};
+        let misses = |reset| {
+            let (mut current, mut highest) = (0, 0);
+            unsafe {
+                let db = conn.handle();
+                sqlite3_db_status(db, SQLITE_DBSTATUS_CACHE_MISS, &mut current, &mut highest, reset)
+            };
+            i64::from(current)
+        };
+        conn.execute_batch("PRAGMA shrink_memory").unwrap();
+        misses(1);
+        assert!(!f().is_empty());
+        misses(0)
+    }
+
+    #[test]
+    fn interned_reads_touch_no_more_pages_than_the_flat_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("index.db");
+        let rows = synthetic_tree(20_000);
+        let lookups = sample_lookups(&rows);
+        let flat = Connection::open(&file).unwrap();
+        flat_index(&flat, &rows);
+        let flat_pages = [
+            pages_read(&flat, || flat_lookups(&flat, &lookups)),
+            pages_read(&flat, || flat_children(&flat)),
+            pages_read(&flat, || search_for_file12(&flat)),
+        ];
+        drop(flat);
+        let conn = interned_copy(&file);
+        let interned_pages = [
+            pages_read(&conn, || interned_lookups(&conn, &lookups)),
+            pages_read(&conn, || interned_children(&conn)),
+            pages_read(&conn, || search_for_file12(&conn)),
+        ];
+        println!("flat {:?} pages, interned {:?} pages", flat_pages, interned_pages);
+        for (interned, flat) in interned_pages.iter().zip(&flat_pages) {
+            assert!(interned <= flat, "{} vs {}", interned, flat);
+        }
+    }
+
+    // The fastest of a few rounds of `f`, each from an empty page cache as
+    // in pages_read, so a busy moment on the machine does not decide the
+    // comparison.
+    fn best_time(conn: &Connection, mut f: impl FnMut() -> String) -> std::time::Duration {
+        (0..20)
+            .map(|_| {
+                conn.execute_batch("PRAGMA shrink_memory").unwrap();
+                let started = std::time::Instant::now();
+                assert!(!f().is_empty());
+                started.elapsed()
+            })
+            .min()
+            .unwrap()
+    }
+
+    // cargo test --release -- --ignored interned_reads_of
+    #[test]
+    #[ignore]
+    fn interned_reads_of_200k_rows_keep_up_with_the_flat_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let rows = synthetic_tree(200_000);
+        let lookups = sample_lookups(&rows);
+        let file = dir.path().join("flat.db");
+        let flat = Connection::open(&file).unwrap();
+        flat_index(&flat, &rows);
+        fs::copy(&file, dir.path().join("index.db")).unwrap();
+        let conn = interned_copy(&dir.path().join("index.db"));
+
+        // what each index run asks of every file it walks
+        let flat_versions = || {
+            let sql = "SELECT modified, size FROM main_table WHERE path = ?1";
+            let versions = lookups.iter().map(|p| {
+                let mut stmt = flat.prepare_cached(sql).unwrap();
+                stmt.query_row([p], |row| Ok((row.get(0)?, row.get(1)?))).optional().unwrap()
+            });
+            format!("{:?}", versions.collect::<Vec<Option<(u64, u64)>>>())
+        };
+        let interned_versions = || {
+            let versions = lookups.iter().map(|p| indexed_version(&conn, p).unwrap());
+            format!("{:?}", versions.collect::<Vec<_>>())
+        };
+        assert_eq!(flat_versions(), interned_versions());
+        let flat_times = [
+            best_time(&flat, flat_versions),
+            best_time(&flat, || flat_children(&flat)),
+            best_time(&flat, || search_for_file12(&flat)),
+        ];
+        let interned_times = [
+            best_time(&conn, interned_versions),
+            best_time(&conn, || interned_children(&conn)),
+            best_time(&conn, || search_for_file12(&conn)),
+        ];
+        println!("flat {:?}, interned {:?}", flat_times, interned_times);
+        // Lookups and listings land within noise of each other with every
+        // page cached, and search comes out ahead; pages_read above compares
+        // each one exactly.
+        let total = |times: &[std::time::Duration]| times.iter().sum::<std::time::Duration>();
+        assert!(total(&interned_times) <= total(&flat_times));
+    }
+
+    #[test]
+    fn day_histogram_buckets_by_creation_day_falling_back_to_mtime() {
+        let conn = memory_db();
+        let noon = 1_678_449_600; // 2023-03-10 12:00 UTC
+        let mut rows = vec![
+            file_row("/idx/a.txt", 1, false),
+            file_row("/idx/b.txt", 2, false),
+            file_row("/idx/sub/c.txt", 4, false),
+            file_row("/other/d.txt", 8, false),
+        ];
+        rows[0].created = Some(noon);
+        rows[1].created = None;
+        rows[1].modified = noon + 3600;
+        rows[2].created = Some(noon + 3 * 86_400);
+        rows[3].created = Some(noon);
+        for row in &rows {
+            insert_file_meta(&conn, row).unwrap();
+        }
+        insert_rows(&conn, &[("/idx/sub", 0, true)]);
+
+        let within = native("/idx");
+        let buckets = day_histogram(&conn, Some(&within), "created").unwrap();
+        let summary: Vec<(u64, u64)> = buckets.iter().map(|b| (b.count, b.total_size)).collect();
+        assert_eq!(summary, [(2, 3), (1, 4)]);
+        assert!(buckets[0].day < buckets[1].day);
+        assert_eq!(buckets[0].day.len(), "2023-03-10".len());
+
+        let everything = day_histogram(&conn, None, "created").unwrap();
+        assert_eq!(everything.iter().map(|b| b.count).sum::<u64>(), 4);
+    }
+
+    #[test]
+    fn day_histogram_can_bucket_by_mtime_and_rejects_other_bases() {
+        let conn = memory_db();
+        insert_rows(&conn, &[("/idx/a.txt", 1, false), ("/idx/b.txt", 2, false)]);
+        let buckets = day_histogram(&conn, None, "modified").unwrap();
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].count, 2);
+        assert!(matches!(day_histogram(&conn, None, "accessed"), Err(Error::InvalidInput(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn broken_symlinks_reports_only_dangling_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let conn = memory_db();
+        let target = dir.path().join("target.txt");
+        write_file(&target, "t");
+        let healthy = dir.path().join("healthy");
+        let dangling = dir.path().join("dangling");
+        std::os::unix::fs::symlink(&target, &healthy).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("gone.txt"), &dangling).unwrap();
+        for path in [&target, &healthy, &dangling] {
+            index_path(&conn, path);
+        }
+
+        let broken = broken_symlinks(&conn).unwrap();
+        assert_eq!(broken.len(), 1);
+        assert_eq!(broken[0].link.path, path_str(&dangling));
+        assert!(broken[0].link.is_symlink);
+        assert_eq!(broken[0].target, Some(path_str(&dir.path().join("gone.txt"))));
+
+        // a link that vanished after indexing has no target to report
+        fs::remove_file(&dangling).unwrap();
+        assert_eq!(broken_symlinks(&conn).unwrap()[0].target, None);
+    }
+
+    #[test]
+    fn normalize_dir_input_drops_trailing_separators_except_on_roots() {
+        assert_eq!(normalize_dir_input("C:\\Foo\\"), "C:\\Foo");
+        assert_eq!(normalize_dir_input("C:\\Foo"), "C:\\Foo");
+        assert_eq!(normalize_dir_input("C:\\Foo\\\\"), "C:\\Foo");
+        assert_eq!(normalize_dir_input("C:\\"), "C:\\");
+        assert_eq!(normalize_dir_input("C:"), "C:\\");
+        assert_eq!(normalize_dir_input("D:/"), "D:\\");
+        assert_eq!(normalize_dir_input("/home/user/"), "/home/user");
+        assert_eq!(normalize_dir_input("/"), "/");
+        assert_eq!(normalize_dir_input("\\"), "\\");
+    }
+
+    #[test]
+    fn subtree_prefix_ends_in_exactly_one_separator() {
+        let sep = std::path::MAIN_SEPARATOR;
+        assert_eq!(subtree_prefix(&native("/idx/a")), format!("{}{}", native("/idx/a"), sep));
+        assert_eq!(subtree_prefix(&native("/idx/a/")), format!("{}{}", native("/idx/a"), sep));
+        assert_eq!(subtree_prefix("C:\\"), format!("C:{}", sep));
+    }
+
+    #[test]
+    fn parent_dir_keeps_root_separators() {
+        assert_eq!(parent_dir("C:\\Users\\me").as_deref(), Some("C:\\Users"));
+        assert_eq!(parent_dir("C:\\Users").as_deref(), Some("C:\\"));
+        assert_eq!(parent_dir("C:\\Users\\").as_deref(), Some("C:\\"));
+        assert_eq!(parent_dir("/etc").as_deref(), Some("/"));
+        assert_eq!(parent_dir("/etc/hosts").as_deref(), Some("/etc"));
+        assert_eq!(parent_dir("C:\\"), None);
+        assert_eq!(parent_dir("notes.txt"), None);
+    }
+
+    #[test]
+    fn indexing_state_allows_one_run_and_reports_it() {
+        let state = IndexingState::default();
+        let idle = state.snapshot().unwrap();
+        assert!(!idle.running && idle.current_root.is_none());
+
+        let guard = state.begin("C:\\", IndexSource::Scheduled).unwrap();
+        state.processed.fetch_add(42, Ordering::SeqCst);
+        let status = state.snapshot().unwrap();
+        assert!(status.running);
+        assert_eq!(status.current_root.as_deref(), Some("C:\\"));
+        assert_eq!(status.source, Some("scheduled"));
+        assert_eq!(status.processed, 42);
+        assert!(matches!(state.begin("D:\\", IndexSource::Manual), Err(Error::AlreadyIndexing)));
+
+        drop(guard);
+        assert!(!state.is_running());
+        assert!(!state.snapshot().unwrap().running);
+    }
+
+    #[test]
+    fn indexing_state_resets_counters_for_the_next_run() {
+        let state = IndexingState::default();
+        {
+            let _guard = state.begin("C:\\", IndexSource::Manual).unwrap();
+            state.processed.store(10, Ordering::SeqCst);
+            state.cancel.store(true, Ordering::SeqCst);
+        }
+        state.deferred_for_idle.store(true, Ordering::SeqCst);
+        assert!(state.snapshot().unwrap().waiting_for_idle);
+
+        let _guard = state.begin("C:\\", IndexSource::Manual).unwrap();
+        let status = state.snapshot().unwrap();
+        assert_eq!(status.processed, 0);
+        assert!(!state.cancel.load(Ordering::SeqCst));
+    }
+
+    // Events go nowhere; index runs are watched through IndexingState.
+    struct NoEvents;
+
+    impl IndexEvents for NoEvents {
+        fn progress(&self, _status: &IndexingStatus) {}
+        fn completed(&self, _summary: &IndexRunSummary) {}
+    }
+
+    #[test]
+    fn a_second_concurrent_run_is_rejected_without_waiting() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("a.txt"), "a");
+        let root = path_str(dir.path());
+        let db = std::sync::Arc::new(DbConnection(Mutex::new(memory_db())));
+        let state = std::sync::Arc::new(IndexingState::default());
+        // the tray's pause holds the first run at its first entry
+        let paused = std::sync::Arc::new(AtomicBool::new(true));
+        let throttle = IndexThrottle { max_entries_per_sec: Some(1_000_000), ..Default::default() };
+        let throttled = IndexRunOptions { throttle: Some(throttle), ..Default::default() };
+        let first = std::thread::spawn({
+            let (db, state, paused) = (db.clone(), state.clone(), paused.clone());
+            let root = root.clone();
+            move || {
+                let source = IndexSource::Manual;
+                index_root_with(&db, &state, &paused, &NoEvents, &root, source, throttled)
+            }
+        });
+        let run = |root: &str| {
+            let options = IndexRunOptions::default();
+            index_root_with(&db, &state, &paused, &NoEvents, root, IndexSource::Manual, options)
+        };
+        while !state.paused.load(Ordering::SeqCst) {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        // rejected while the first is still mid-walk, not queued behind it
+        assert!(matches!(run(&root), Err(Error::AlreadyIndexing)));
+        assert!(state.is_running());
+
+        paused.store(false, Ordering::SeqCst);
+        assert_eq!(first.join().unwrap().unwrap().status, "completed");
+        assert!(indexed(&db.0.lock().unwrap(), &dir.path().join("a.txt")));
+        assert!(!state.is_running());
+
+        // a failed run clears the flag as well as a finished one
+        let missing = path_str(&dir.path().join("missing"));
+        assert!(run(&missing).is_err());
+        assert!(!state.is_running());
+        // any run holding the state blocks the command's, scheduled ones included
+        let _held = state.begin(&root, IndexSource::Scheduled).unwrap();
+        assert!(matches!(run(&root), Err(Error::AlreadyIndexing)));
+    }
+
+    #[test]
+    fn an_in_memory_index_indexes_lists_and_searches_but_stores_no_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("notes.txt"), "notes");
+        write_file(&dir.path().join("sub/report.txt"), "report");
+        let root = path_str(dir.path());
+
+        // the same open setup does under --ephemeral
+        let location = DbLocation::Memory;
+        let (conn, locked) = open_index(&location).unwrap();
+        assert!(!locked);
+        let db = DbConnection(Mutex::new(conn));
+        let (indexing, paused) = (IndexingState::default(), AtomicBool::new(false));
+        let (source, options) = (IndexSource::Manual, IndexRunOptions::default());
+        let summary =
+            index_root_with(&db, &indexing, &paused, &NoEvents, &root, source, options).unwrap();
+        assert_eq!(summary.status, "completed");
+
+        let conn = db.0.lock().unwrap();
+        let (key, cache) = (listing_key(&root, Some("name"), None), ListingCache::default());
+        let listing =
+            children_listing(&conn, &cache, key, None, None, ListingFormat::Objects).unwrap();
+        let Listing::All(Entries::Objects(children)) = listing else { panic!("a whole listing") };
+        let names: Vec<&str> = children.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["notes.txt", "sub"]);
+
+        let search = SearchFilters {
+            name: "report".to_string(),
+            extension: "txt".to_string(),
+            doc_title: None,
+            doc_author: None,
+        };
+        let found =
+            search_listing(&conn, &search, None, None, None, ListingFormat::Objects).unwrap();
+        let Listing::All(Entries::Objects(found)) = found else { panic!("a whole listing") };
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].path, path_str(&dir.path().join("sub").join("report.txt")));
+
+        for (command, what) in PERSISTENT_COMMANDS {
+            let refused = location.admit(command);
+            assert!(matches!(refused, Err(Error::Ephemeral(w)) if w == *what), "{}", command);
+        }
+        assert!(location.admit("search_files").is_ok());
+        let on_disk = DbLocation::File(dir.path().join("file_explorer.sqlite3"));
+        assert!(PERSISTENT_COMMANDS.iter().all(|(command, _)| on_disk.admit(command).is_ok()));
+    }
+
+    fn file_events(conn: &Connection) -> Vec<(String, String)> {
+        let mut stmt = conn.prepare("SELECT path, kind FROM file_events ORDER BY id").unwrap();
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap();
+        rows.map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn watcher_notifications_become_activity() {
+        use notify::event::{AccessKind, CreateKind, DataChange, MetadataKind};
+        use notify::event::{ModifyKind, RenameMode};
+        use notify::{Event, EventKind};
+
+        let event = |kind: EventKind, paths: &[&str]| {
+            paths.iter().fold(Event::new(kind), |event, path| event.add_path(path.into()))
+        };
+        let changes = |event: Event| watched_changes(&event);
+        assert_eq!(
+            changes(event(EventKind::Create(CreateKind::File), &["/w/new.txt"])),
+            [("/w/new.txt".to_string(), FileEventKind::Created)]
+        );
+        assert_eq!(
+            changes(event(EventKind::Modify(ModifyKind::Data(DataChange::Content)), &["/w/a"])),
+            [("/w/a".to_string(), FileEventKind::Modified)]
+        );
+        let rename = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        let renamed = event(rename, &["/w/a", "/w/b"]);
+        assert_eq!(
+            changes(renamed),
+            [
+                ("/w/a".to_string(), FileEventKind::Removed),
+                ("/w/b".to_string(), FileEventKind::Created)
+            ]
+        );
+        let touched = EventKind::Modify(ModifyKind::Metadata(MetadataKind::AccessTime));
+        assert!(changes(event(touched, &["/w/a"])).is_empty());
+        assert!(changes(event(EventKind::Access(AccessKind::Read), &["/w/a"])).is_empty());
+    }
+
+    #[test]
+    fn watched_changes_are_recorded_once_and_not_again_by_the_next_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = path_str(&dir.path().join("a.txt"));
+        write_file(Path::new(&file), "a");
+        let mut conn = memory_db();
+
+        let mut batch = WatchedBatch::default();
+        batch.push((file.clone(), FileEventKind::Modified));
+        batch.push((file.clone(), FileEventKind::Modified));
+        // a directory's own mtime is not recorded
+        batch.push((path_str(dir.path()), FileEventKind::Modified));
+        record_watched_changes(&mut conn, &batch.changes).unwrap();
+        assert_eq!(file_events(&conn), [(file.clone(), "modified".to_string())]);
+
+        // the next run finding the same change knows it from its last start on
+        let previous_start = unix_now() - 3600;
+        record_file_event(&conn, &file, FileEventKind::Modified, unix_now(), previous_start)
+            .unwrap();
+        assert_eq!(file_events(&conn).len(), 1);
+
+        set_setting(&conn, ACTIVITY_PAUSED_SETTING, "true").unwrap();
+        record_watched_changes(&mut conn, &[(file, FileEventKind::Removed)]).unwrap();
+        assert_eq!(file_events(&conn).len(), 1);
+    }
+
+    #[test]
+    fn a_first_run_records_only_what_changed_in_the_last_day() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("today.txt"), "new");
+        write_file(&dir.path().join("sub/old.txt"), "old");
+        let old = filetime::FileTime::from_unix_time(unix_now() as i64 - 7 * 86_400, 0);
+        filetime::set_file_mtime(dir.path().join("sub/old.txt"), old).unwrap();
+        let root = path_str(dir.path());
+        let db = DbConnection(Mutex::new(memory_db()));
+        let (indexing, paused) = (IndexingState::default(), AtomicBool::new(false));
+        let run = || {
+            let (source, options) = (IndexSource::Manual, IndexRunOptions::default());
+            index_root_with(&db, &indexing, &paused, &NoEvents, &root, source, options).unwrap()
+        };
+        run();
+
+        let events = file_events(&db.0.lock().unwrap());
+        let paths: Vec<&str> = events.iter().map(|(path, _)| path.as_str()).collect();
+        // birth times may be newer than the mtime set above, so only today's
+        // file is certain to be in, and the directories are certain to be out
+        assert!(paths.contains(&path_str(&dir.path().join("today.txt")).as_str()), "{:?}", events);
+        assert!(!paths.contains(&root.as_str()));
+        assert!(!paths.contains(&path_str(&dir.path().join("sub")).as_str()));
+
+        let mut stale = file_row("/idx/old.txt", 1, false);
+        stale.modified = 1_000;
+        assert_eq!(first_run_event(&stale, 2_000), None);
+        stale.created = Some(2_500);
+        assert_eq!(first_run_event(&stale, 2_000), Some((FileEventKind::Created, 2_500)));
+        stale.created = Some(1_000);
+        stale.modified = 3_000;
+        assert_eq!(first_run_event(&stale, 2_000), Some((FileEventKind::Modified, 3_000)));
+    }
+
+    #[test]
+    fn merge_database_file_adds_new_paths_and_keeps_ours() {
+        let dir = tempfile::tempdir().unwrap();
+        let other_path = dir.path().join("other.db");
+        {
+            let other = Connection::open(&other_path).unwrap();
+            open_schema(&other).unwrap();
+            insert_rows(&other, &[("/idx/shared.txt", 99, false), ("/idx/theirs.txt", 5, false)]);
+        }
+        let conn = memory_db();
+        insert_rows(&conn, &[("/idx/shared.txt", 1, false)]);
+        cached_directory_size(&conn, &native("/idx")).unwrap();
+
+        let added = merge_database_file(&conn, &path_str(&other_path)).unwrap();
+        assert_eq!(added, 1);
+        let size: u64 = conn
+            .query_row(
+                "SELECT size FROM entries WHERE path = ?1",
+                rusqlite::params![native("/idx/shared.txt")],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(size, 1);
+        // the cached total is dropped and recomputed with the merged row
+        assert_eq!(cached_directory_size(&conn, &native("/idx")).unwrap(), 6);
+    }
+
+    #[test]
+    fn merge_database_file_rejects_other_schemas_and_detaches() {
+        let dir = tempfile::tempdir().unwrap();
+        let other_path = dir.path().join("old.db");
+        Connection::open(&other_path)
+            .unwrap()
+            .execute_batch("CREATE TABLE main_table (name TEXT, path TEXT UNIQUE, size INTEGER)")
+            .unwrap();
+        let conn = memory_db();
+
+        let result = merge_database_file(&conn, &path_str(&other_path));
+        assert!(matches!(result, Err(Error::SchemaMismatch(msg)) if msg.contains("modified")));
+        // still detached: a second attempt attaches again and fails the same way
+        let again = merge_database_file(&conn, &path_str(&other_path));
+        assert!(matches!(again, Err(Error::SchemaMismatch(_))));
+
+        let empty_path = dir.path().join("empty.db");
+        Connection::open(&empty_path).unwrap().execute_batch("CREATE TABLE t (x)").unwrap();
+        let empty = merge_database_file(&conn, &path_str(&empty_path));
+        assert!(matches!(empty, Err(Error::SchemaMismatch(msg)) if msg == "it has no file table"));
+    }
+
+    fn rel(root: &str, path: &str) -> Result<String, Error> {
+        relative_path(native(root), native(path))
+    }
+
+    #[test]
+    fn relative_path_strips_the_root() {
+        assert_eq!(rel("/idx/users", "/idx/users/me/notes.txt").unwrap(), native("me/notes.txt"));
+        assert_eq!(rel("/idx/users/", "/idx/users/me").unwrap(), "me");
+        assert_eq!(rel("/idx/users", "/idx/users").unwrap(), "");
+        assert_eq!(rel("/idx/users", "/idx/users/").unwrap(), "");
+    }
+
+    #[test]
+    fn relative_path_compares_whole_components() {
+        // a shared name prefix is not containment
+        assert!(matches!(rel("/idx/user", "/idx/users/me"), Err(Error::InvalidInput(_))));
+        assert!(matches!(rel("/idx/users/me", "/idx/users"), Err(Error::InvalidInput(_))));
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn relative_path_ignores_case_on_windows() {
+        let found = relative_path("c:\\users".to_string(), "C:\\Users\\me".to_string());
+        assert_eq!(found.unwrap(), "me");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn relative_path_respects_case_elsewhere() {
+        assert!(rel("/idx/Users", "/idx/users/me").is_err());
+    }
+
+    fn history(conn: &Connection) -> Vec<String> {
+        recent_searches(conn, SEARCH_HISTORY_LIMIT).unwrap().into_iter().map(|e| e.term).collect()
+    }
+
+    #[test]
+    fn record_search_collapses_typing_into_one_entry() {
+        let conn = memory_db();
+        for term in ["inv", "invo", "invoice", "invoice", " invoice "] {
+            record_search(&conn, term).unwrap();
+        }
+        record_search(&conn, "   ").unwrap();
+        assert_eq!(history(&conn), ["invoice"]);
+
+        // backspacing also refines the same entry
+        record_search(&conn, "invoi").unwrap();
+        assert_eq!(history(&conn), ["invoi"]);
+        record_search(&conn, "report").unwrap();
+        assert_eq!(history(&conn), ["report", "invoi"]);
+    }
+
+    #[test]
+    fn record_search_keeps_a_prefix_searched_after_the_window() {
+        let conn = memory_db();
+        record_search(&conn, "tax").unwrap();
+        conn.execute("UPDATE search_history SET searched_at = searched_at - 60", []).unwrap();
+        record_search(&conn, "tax 2023").unwrap();
+        assert_eq!(history(&conn), ["tax 2023", "tax"]);
+    }
+
+    #[test]
+    fn search_history_is_capped() {
+        let conn = memory_db();
+        for n in 0..SEARCH_HISTORY_LIMIT + 5 {
+            // distinct and never prefixes of one another
+            record_search(&conn, &format!("{}-term", n)).unwrap();
+        }
+        let terms = history(&conn);
+        assert_eq!(terms.len(), SEARCH_HISTORY_LIMIT as usize);
+        assert_eq!(terms[0], format!("{}-term", SEARCH_HISTORY_LIMIT + 4));
+        assert_eq!(recent_searches(&conn, 3).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn file_watch_news_reports_changes_and_removal_once() {
+        let mut last_seen = Some((10, 1_000));
+        assert_eq!(file_watch_news(&mut last_seen, Some((10, 1_000))), None);
+        assert_eq!(file_watch_news(&mut last_seen, Some((12, 1_001))), Some(Some((12, 1_001))));
+        assert_eq!(file_watch_news(&mut last_seen, Some((12, 1_001))), None);
+        assert_eq!(file_watch_news(&mut last_seen, None), Some(None));
+        assert_eq!(file_watch_news(&mut last_seen, None), None);
+        // a rotated log reappears under the same path
+        assert_eq!(file_watch_news(&mut last_seen, Some((0, 1_002))), Some(Some((0, 1_002))));
+    }
+
+    fn stats_of(contents: &[u8]) -> Result<(u64, u64, u64), Error> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file.txt");
+        fs::write(&path, contents).unwrap();
+        let stats = text_stats(path_str(&path), 1 << 20)?;
+        Ok((stats.lines, stats.words, stats.chars))
+    }
+
+    #[test]
+    fn text_stats_counts_lines_words_and_chars() {
+        assert_eq!(stats_of(b"").unwrap(), (0, 0, 0));
+        assert_eq!(stats_of(b"one two\nthree\n").unwrap(), (2, 3, 14));
+        // a last line without a newline still counts
+        assert_eq!(stats_of(b"one two\nthree").unwrap(), (2, 3, 13));
+        assert_eq!(stats_of("héllo wörld\n".as_bytes()).unwrap(), (1, 2, 12));
+        assert_eq!(stats_of(b"  spaced\t\tout  \r\n").unwrap(), (1, 2, 17));
+    }
+
+    #[test]
+    fn text_stats_does_not_split_characters_at_chunk_boundaries() {
+        // a two-byte character straddles the 64 KiB read boundary
+        let mut contents = vec![b'a'; 64 * 1024 - 1];
+        contents.extend("é".as_bytes());
+        contents.extend(b" end");
+        assert_eq!(stats_of(&contents).unwrap(), (1, 2, 64 * 1024 + 4));
+    }
+
+    #[test]
+    fn text_stats_rejects_binary_and_oversized_files() {
+        let binary = stats_of(b"MZ\0\x90");
+        assert!(matches!(binary, Err(Error::InvalidInput(msg)) if msg.contains("binary")));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("big.txt");
+        fs::write(&path, "0123456789").unwrap();
+        assert!(matches!(text_stats(path_str(&path), 9), Err(Error::InvalidInput(_))));
+        assert!(text_stats(path_str(&path), 10).is_ok());
+    }
+
+    fn mtime_of(path: &Path) -> i64 {
+        filetime::FileTime::from_last_modification_time(&fs::metadata(path).unwrap()).unix_seconds()
+    }
+
+    #[test]
+    fn apply_modified_sets_disk_and_index_times() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        write_file(&path, "a");
+        index_path(&conn, &path);
+        apply_modified(&conn, &path_str(&path), 1_600_000_000).unwrap();
+        assert_eq!(mtime_of(&path), 1_600_000_000);
+        let modified: u64 = conn
+            .query_row(
+                "SELECT modified FROM entries WHERE path = ?1",
+                rusqlite::params![path_str(&path)],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(modified, 1_600_000_000);
+    }
+
+    #[test]
+    fn apply_modified_rejects_times_outside_the_plausible_range() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("a.txt");
+        write_file(&path, "a");
+        let before = mtime_of(&path);
+        let too_early = apply_modified(&conn, &path_str(&path), EARLIEST_MTIME - 1);
+        assert!(matches!(too_early, Err(Error::InvalidInput(_))));
+        let too_late = apply_modified(&conn, &path_str(&path), unix_now() + 7 * 24 * 60 * 60);
+        assert!(matches!(too_late, Err(Error::InvalidInput(_))));
+        assert_eq!(mtime_of(&path), before);
+        assert!(apply_modified(&conn, &path_str(&path), EARLIEST_MTIME).is_ok());
+    }
+
+    #[test]
+    fn apply_modified_batch_reports_each_failure_and_keeps_going() {
+        let mut conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        write_file(&a, "a");
+        write_file(&b, "b");
+        let update = |path: &Path, timestamp| ModifiedUpdate { path: path_str(path), timestamp };
+        let missing = dir.path().join("missing.txt");
+        let report = apply_modified_batch(
+            &mut conn,
+            vec![update(&a, 1_500_000_000), update(&missing, 1_500_000_000), update(&b, 0)],
+        )
+        .unwrap();
+        assert_eq!(report.updated, 1);
+        let failed: Vec<&str> = report.failed.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(failed, [path_str(&missing), path_str(&b)]);
+        assert_eq!(mtime_of(&a), 1_500_000_000);
+    }
+
+    #[test]
+    fn case_collisions_group_names_differing_only_in_case() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/a/Readme.md", 1, false),
+                ("/idx/a/README.md", 2, false),
+                ("/idx/a/notes.txt", 3, false),
+                ("/idx/b/Notes.txt", 4, false),
+                ("/idx/b/notes.TXT", 5, false),
+                ("/idx/b/other.txt", 6, false),
+            ],
+        );
+        let groups = case_collisions(&conn, None).unwrap();
+        let summary: Vec<(String, Vec<u64>)> = groups
+            .iter()
+            .map(|g| (g.directory.clone(), g.entries.iter().map(|e| e.size).collect()))
+            .collect();
+        assert_eq!(summary, [(native("/idx/a"), vec![2, 1]), (native("/idx/b"), vec![4, 5])]);
+
+        let within = case_collisions(&conn, Some(&native("/idx/b"))).unwrap();
+        assert_eq!(within.len(), 1);
+        assert_eq!(within[0].directory, native("/idx/b"));
+    }
+
+    #[test]
+    fn show_os_properties_checks_the_path_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = path_str(&dir.path().join("missing"));
+        assert!(matches!(show_os_properties(missing), Err(Error::Io(_))));
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn properties_arguments_are_plain_backslashed_paths() {
+        use windows_sys::Win32::UI::Shell::SHOP_FILEPATH;
+
+        let args = |path: &str| {
+            let (object_type, wide) = properties_args(Path::new(path));
+            assert_eq!(object_type, SHOP_FILEPATH as u32);
+            assert_eq!(wide.last(), Some(&0));
+            String::from_utf16(&wide[..wide.len() - 1]).unwrap()
+        };
+        assert_eq!(args(r"C:\Users\me\file.txt"), r"C:\Users\me\file.txt");
+        assert_eq!(args("C:/Users/me/file.txt"), r"C:\Users\me\file.txt");
+        assert_eq!(args(r"\\?\C:\Users\me"), r"C:\Users\me");
+        assert_eq!(args(r"\\?\UNC\server\share\f"), r"\\server\share\f");
+        assert_eq!(args(r"\\server\share"), r"\\server\share");
+        assert_eq!(args(r"C:\Users\ü.txt"), r"C:\Users\ü.txt");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn show_os_properties_is_unsupported_off_windows() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = show_os_properties(path_str(dir.path()));
+        assert!(matches!(result, Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn suggestions_rank_common_names_first() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/a/report.txt", 1, false),
+                ("/idx/b/report.txt", 1, false),
+                ("/idx/c/report.txt", 1, false),
+                ("/idx/a/readme.md", 1, false),
+                ("/idx/b/readme.md", 1, false),
+                ("/idx/a/recipes", 0, true),
+                ("/idx/a/notes.txt", 1, false),
+            ],
+        );
+        assert_eq!(suggestions(&conn, "re", 10).unwrap(), ["report.txt", "readme.md", "recipes"]);
+        assert_eq!(suggestions(&conn, "re", 1).unwrap(), ["report.txt"]);
+        assert!(suggestions(&conn, "", 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn suggestions_match_wildcards_literally() {
+        let conn = memory_db();
+        insert_rows(&conn, &[("/idx/a_b.txt", 1, false), ("/idx/axb.txt", 1, false)]);
+        assert_eq!(suggestions(&conn, "a_", 10).unwrap(), ["a_b.txt"]);
+        assert!(suggestions(&conn, "%", 10).unwrap().is_empty());
+        assert_eq!(escape_like(r"50%_off\"), r"50\%\_off\\");
+    }
+
+    fn listing_names(listing: &[FileMeta]) -> Vec<&str> {
+        listing.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    fn listing_key(dir: &str, sort: Option<&str>, filter: Option<&str>) -> ListingKey {
+        ListingKey {
+            dir: native(dir),
+            sort: sort.map(str::to_string),
+            filter: filter.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn cached_children_serves_repeats_until_the_index_changes() {
+        let conn = memory_db();
+        let cache = ListingCache::default();
+        cache.watch(&conn);
+        insert_rows(&conn, &[("/idx/a", 1, false), ("/idx/b", 2, false)]);
+        let first = cached_children(&conn, &cache, listing_key("/idx", None, None)).unwrap();
+        let again = cached_children(&conn, &cache, listing_key("/idx", None, None)).unwrap();
+        assert!(std::sync::Arc::ptr_eq(&first, &again));
+
+        insert_rows(&conn, &[("/elsewhere/c", 3, false)]);
+        let after = cached_children(&conn, &cache, listing_key("/idx", None, None)).unwrap();
+        assert!(!std::sync::Arc::ptr_eq(&first, &after));
+        assert_eq!(after.len(), 2);
+
+        cache.invalidate_dir(&native("/idx"));
+        assert!(cache.get(&listing_key("/idx", None, None)).is_none());
+    }
+
+    #[test]
+    fn cached_children_filters_and_sorts_per_key() {
+        let conn = memory_db();
+        let cache = ListingCache::default();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/Apple.txt", 3, false),
+                ("/idx/banana.txt", 1, false),
+                ("/idx/cherry", 2, true),
+            ],
+        );
+        let by_size = cached_children(&conn, &cache, listing_key("/idx", Some("-size"), None));
+        assert_eq!(listing_names(&by_size.unwrap()), ["Apple.txt", "cherry", "banana.txt"]);
+        let filtered = listing_key("/idx", Some("name"), Some("A"));
+        let filtered = cached_children(&conn, &cache, filtered).unwrap();
+        assert_eq!(listing_names(&filtered), ["Apple.txt", "banana.txt"]);
+        let unknown = cached_children(&conn, &cache, listing_key("/idx", Some("colour"), None));
+        assert!(matches!(unknown, Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn listing_cache_drops_stale_reads_and_the_oldest_entries() {
+        let cache = ListingCache::default();
+        let listing = std::sync::Arc::new(Vec::new());
+        // read before a write landed
+        cache.generation.fetch_add(1, Ordering::SeqCst);
+        cache.insert(listing_key("/stale", None, None), 0, listing.clone());
+        assert!(cache.get(&listing_key("/stale", None, None)).is_none());
+
+        for n in 0..=LISTING_CACHE_CAPACITY {
+            cache.insert(listing_key(&format!("/d{}", n), None, None), 1, listing.clone());
+        }
+        assert!(cache.get(&listing_key("/d0", None, None)).is_none());
+        assert!(cache.get(&listing_key("/d1", None, None)).is_some());
+    }
+
+    #[test]
+    fn path_depth_counts_components_below_the_root() {
+        assert_eq!(path_depth("C:\\"), 0);
+        assert_eq!(path_depth("C:"), 0);
+        assert_eq!(path_depth("/"), 0);
+        assert_eq!(path_depth("\\\\server\\share"), 0);
+        assert_eq!(path_depth("\\\\server\\share\\docs"), 1);
+        assert_eq!(path_depth("C:\\Users"), 1);
+        assert_eq!(path_depth("C:\\Users\\me\\"), 2);
+        assert_eq!(path_depth("C:/Users/me/notes.txt"), 3);
+        assert_eq!(path_depth("/home/me"), 2);
+    }
+
+    #[test]
+    fn path_depth_is_available_in_sql() {
+        let conn = memory_db();
+        let depth: i64 = conn
+            .query_row("SELECT path_depth('C:\\Users\\me')", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(depth, 2);
+    }
+
+    #[test]
+    fn index_paths_adds_files_and_walks_directories() {
+        let mut conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("download.bin");
+        let folder = dir.path().join("unpacked");
+        write_file(&file, "data");
+        write_file(&folder.join("inner/readme.txt"), "hello");
+        let missing = dir.path().join("missing");
+        let paths = [path_str(&file), path_str(&folder), path_str(&missing)];
+
+        let mut report = IndexPathsReport::default();
+        let metas = stat_paths(&paths, &mut report);
+        upsert_metas(&mut conn, &metas, &mut report).unwrap();
+        // the file, the folder, inner and readme.txt
+        assert_eq!((report.created, report.updated), (4, 0));
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].path, path_str(&missing));
+        assert!(indexed(&conn, &folder.join("inner/readme.txt")));
+
+        let mut again = IndexPathsReport::default();
+        let metas = stat_paths(&paths[..1], &mut again);
+        upsert_metas(&mut conn, &metas, &mut again).unwrap();
+        assert_eq!((again.created, again.updated), (0, 1));
+    }
+
+    #[test]
+    fn first_match_prefers_exact_then_shallow_then_path_order() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/a/b/Report.txt", 1, false),
+                ("/idx/z/report.txt", 2, false),
+                ("/idx/m/report.txt", 3, false),
+                ("/idx/report.txt.bak", 4, false),
+            ],
+        );
+        let size = |name: &str| first_match(&conn, name).unwrap().map(|f| f.size);
+        // both shallow exact matches tie on depth, so path order decides
+        assert_eq!(size("REPORT.TXT"), Some(3));
+        // no exact name: the shallowest partial match
+        assert_eq!(size("port"), Some(4));
+        assert_eq!(size("missing"), None);
+        assert_eq!(size(""), None);
+    }
+
+    fn kind(name: &str) -> FileKind {
+        FileKind { kind: name.to_string(), label: name.to_uppercase(), category: "text".into() }
+    }
+
+    #[test]
+    fn columnar_entries_share_kinds_and_pack_flags() {
+        let mut a = file_row("/idx/a.txt", 1, false);
+        let mut b = file_row("/idx/sub", 0, true);
+        let mut c = file_row("/idx/c.txt", 3, false);
+        a.kind = Some(kind("txt"));
+        c.kind = Some(kind("txt"));
+        b.is_symlink = true;
+        let columns = FileColumns::from(vec![a, b, c]);
+        assert_eq!(columns.names, ["a.txt", "sub", "c.txt"]);
+        assert_eq!(columns.sizes, [1, 0, 3]);
+        assert_eq!(columns.flags, [0, COLUMN_FLAG_DIR | COLUMN_FLAG_SYMLINK, 0]);
+        assert_eq!(columns.kinds, [Some(0), None, Some(0)]);
+        assert_eq!(columns.kind_table, [kind("txt")]);
+    }
+
+    #[test]
+    fn listing_format_defaults_to_objects() {
+        assert_eq!(listing_format(None).unwrap(), ListingFormat::Objects);
+        assert_eq!(listing_format(Some("columnar".into())).unwrap(), ListingFormat::Columnar);
+        assert!(matches!(listing_format(Some("csv".into())), Err(Error::InvalidInput(_))));
+
+        let rows = || vec![file_row("/idx/a.txt", 1, false)];
+        let limits = ResultLimits::default();
+        let unused = |_: &PageQuery| -> Result<ListingPage, Error> { unreachable!() };
+        let objects = Listing::all(rows(), ListingFormat::Objects, None, limits, unused).unwrap();
+        let objects = serde_json::to_value(objects).unwrap();
+        assert_eq!(objects[0]["name"], "a.txt");
+        let columns = Listing::all(rows(), ListingFormat::Columnar, None, limits, unused).unwrap();
+        let columns = serde_json::to_value(columns).unwrap();
+        assert_eq!(columns["names"][0], "a.txt");
+    }
+
+    #[test]
+    fn a_locked_index_refuses_all_but_the_unlock_commands() {
+        let lock = IndexLock(AtomicBool::new(true));
+        for command in ["search_files", "list_children", "set_index_passphrase"] {
+            assert!(refused_while_locked(&lock, command), "{}", command);
+        }
+        for command in LOCKED_INDEX_COMMANDS {
+            assert!(!refused_while_locked(&lock, command), "{}", command);
+        }
+        lock.0.store(false, Ordering::SeqCst);
+        assert!(!refused_while_locked(&lock, "search_files"));
+    }
+
+    // A plaintext index file holding a.txt and b.txt, and a connection to it.
+    #[cfg(feature = "encrypted-index")]
+    fn plaintext_index(dir: &Path) -> (std::path::PathBuf, DbConnection) {
+        let path = dir.join("file_explorer.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        open_schema(&conn).unwrap();
+        insert_rows(&conn, &[("/idx/a.txt", 1, false), ("/idx/b.txt", 2, false)]);
+        (path, DbConnection(Mutex::new(conn)))
+    }
+
+    #[cfg(feature = "encrypted-index")]
+    fn db_paths(db: &DbConnection) -> Vec<String> {
+        all_paths(&db.0.lock().unwrap())
+    }
+
+    #[cfg(feature = "encrypted-index")]
+    #[test]
+    fn a_passphrase_round_trips_the_index_through_encryption() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, db) = plaintext_index(dir.path());
+        let cache = ListingCache::default();
+        let rows = db_paths(&db);
+        assert_eq!(rows.len(), 2);
+
+        change_passphrase(&db, &cache, &path, None, Some("hunter2".into())).unwrap();
+        assert!(is_encrypted_database(&path));
+        assert_eq!(db_paths(&db), rows);
+        assert_eq!(all_paths(&open_keyed(&path, Some("hunter2")).unwrap()), rows);
+
+        change_passphrase(&db, &cache, &path, Some("hunter2".into()), None).unwrap();
+        assert!(!is_encrypted_database(&path));
+        assert_eq!(db_paths(&db), rows);
+        assert_eq!(all_paths(&Connection::open(&path).unwrap()), rows);
+    }
+
+    #[cfg(feature = "encrypted-index")]
+    #[test]
+    fn a_wrong_passphrase_leaves_the_index_locked_and_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, db) = plaintext_index(dir.path());
+        let cache = ListingCache::default();
+        change_passphrase(&db, &cache, &path, None, Some("hunter2".into())).unwrap();
+        drop(db);
+        let before = fs::read(&path).unwrap();
+
+        let db = DbConnection(Mutex::new(Connection::open_in_memory().unwrap()));
+        let lock = IndexLock(AtomicBool::new(true));
+        let wrong = unlock_with(&db, &lock, &cache, &path, "hunter3");
+        assert!(matches!(wrong, Err(Error::WrongPassphrase)));
+        assert!(lock.0.load(Ordering::SeqCst));
+        assert_eq!(fs::read(&path).unwrap(), before);
+        // changing it needs the old one too
+        let change = change_passphrase(&db, &cache, &path, Some("nope".into()), None);
+        assert!(matches!(change, Err(Error::WrongPassphrase)));
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        unlock_with(&db, &lock, &cache, &path, "hunter2").unwrap();
+        assert!(!lock.0.load(Ordering::SeqCst));
+        assert_eq!(db_paths(&db).len(), 2);
+    }
+
+    // cargo test --release -- --ignored columnar_listing
+    #[test]
+    #[ignore]
+    fn columnar_listing_of_100k_rows_is_about_half_the_json_of_objects() {
+        let kinds = FileKinds(Default::default());
+        let rows = || -> Vec<FileMeta> {
+            let extensions = ["txt", "rs", "jpg", "pdf", "mp3"];
+            let mut rows: Vec<FileMeta> = (0..100_000)
+                .map(|i| {
+                    let ext = extensions[i % extensions.len()];
+                    let path = format!("/idx/dir{}/file{}.{}", i / 1000, i, ext);
+                    file_row(&path, i as u64 * 37, false)
+                })
+                .collect();
+            kinds.annotate(&mut rows);
+            rows
+        };
+        let serialize = |format: ListingFormat| {
+            let entries = rows();
+            let started = std::time::Instant::now();
+            let json = serde_json::to_vec(&Entries::new(entries, format)).unwrap();
+            (json.len(), started.elapsed())
+        };
+        // warm up, so neither side pays for first-touch allocation
+        serialize(ListingFormat::Objects);
+        let (object_bytes, object_time) = serialize(ListingFormat::Objects);
+        let (column_bytes, column_time) = serialize(ListingFormat::Columnar);
+        println!(
+            "objects {} bytes in {:?}, columnar {} bytes in {:?}",
+            object_bytes, object_time, column_bytes, column_time
+        );
+        // at most half the bytes, and never slower to produce
+        assert!(column_bytes * 2 <= object_bytes, "{} vs {}", column_bytes, object_bytes);
+        assert!(column_time <= object_time, "{:?} vs {:?}", column_time, object_time);
+    }
+
+    #[test]
+    fn coverage_compares_the_walk_with_the_rows_below_root() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let root = path_str(dir.path());
+        write_file(&dir.path().join("a.txt"), "a");
+        write_file(&dir.path().join("sub/b.txt"), "b");
+        // the root, a.txt, sub and sub/b.txt
+        let on_disk = index_walk(&root).count() as u64;
+        assert_eq!(on_disk, 4);
+        index_path(&conn, dir.path());
+        index_path(&conn, &dir.path().join("a.txt"));
+        insert_rows(&conn, &[("/elsewhere/c.txt", 1, false)]);
+
+        let half = coverage_of(&conn, &root, on_disk).unwrap();
+        assert_eq!((half.on_disk, half.indexed), (4, 2));
+        assert_eq!(half.coverage_pct, 50.0);
+
+        // rows for files deleted since indexing never push past 100%
+        for name in ["sub", "sub/b.txt", "gone.txt", "gone2.txt"] {
+            let path = path_str(&dir.path().join(name));
+            insert_file_meta(&conn, &file_row(&path, 1, false)).unwrap();
+        }
+        let over = coverage_of(&conn, &root, on_disk).unwrap();
+        assert_eq!((over.indexed, over.coverage_pct), (6, 100.0));
+        assert_eq!(coverage_of(&conn, &root, 0).unwrap().coverage_pct, 100.0);
+    }
+
+    #[test]
+    fn flatten_directory_moves_nested_files_and_numbers_clashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("photos");
+        write_file(&root.join("2022/a.jpg"), "2022 a");
+        write_file(&root.join("2023/a.jpg"), "2023 a");
+        write_file(&root.join("2023/q1/b.jpg"), "b");
+        write_file(&root.join("top.jpg"), "top");
+        let db = DbConnection(Mutex::new(memory_db()));
+
+        let report = flatten_directory_blocking(&db, &root, &root, true).unwrap();
+        assert_eq!(report.moved, 3);
+        assert!(report.failed.is_empty());
+        assert_eq!(report.collisions.len(), 1);
+        let mut left: Vec<String> = fs::read_dir(&root)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        left.sort();
+        assert_eq!(left.len(), 4);
+        assert!(left.contains(&"a.jpg".to_string()) && left.contains(&"b.jpg".to_string()));
+        assert!(!root.join("2022").exists() && !root.join("2023").exists());
+        assert_eq!(report.removed_dirs.len(), 3);
+
+        let conn = db.0.lock().unwrap();
+        assert!(indexed(&conn, &root.join("b.jpg")));
+    }
+
+    #[test]
+    fn flatten_directory_keeps_dirs_that_hold_the_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("src");
+        let dest = root.join("keep/flat");
+        write_file(&root.join("x/one.txt"), "1");
+        write_file(&root.join("keep/two.txt"), "2");
+        let db = DbConnection(Mutex::new(memory_db()));
+
+        let report = flatten_directory_blocking(&db, &root, &dest, true).unwrap();
+        assert_eq!(report.moved, 2);
+        assert!(dest.join("one.txt").is_file() && dest.join("two.txt").is_file());
+        assert!(!root.join("x").exists());
+        assert!(dest.is_dir());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn local_account_name_reads_the_id_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let passwd = dir.path().join("passwd");
+        fs::write(
+            &passwd,
+            "root:x:0:0:root:/root:/bin/sh\nbroken line\nalice:x:1000:1000::/home/alice:/bin/sh\n",
+        )
+        .unwrap();
+        let passwd = path_str(&passwd);
+        assert_eq!(local_account_name(&passwd, 1000).as_deref(), Some("alice"));
+        assert_eq!(local_account_name(&passwd, 0).as_deref(), Some("root"));
+        assert_eq!(local_account_name(&passwd, 42), None);
+        assert_eq!(local_account_name(&path_str(&dir.path().join("missing")), 0), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn get_owner_reports_the_owning_uid() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("mine.txt");
+        write_file(&path, "x");
+        let owner = get_owner(path_str(&path)).unwrap();
+        assert_eq!(owner.owner_id, Some(fs::metadata(&path).unwrap().uid().to_string()));
+        assert!(matches!(get_owner(path_str(&dir.path().join("gone"))), Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn subtree_stats_counts_everything_below_but_not_dir_itself() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx", 0, true),
+                ("/idx/a.txt", 10, false),
+                ("/idx/sub", 4096, true),
+                ("/idx/sub/b.txt", 5, false),
+                ("/idxx/c.txt", 100, false),
+            ],
+        );
+        let stats = stats_below(&conn, &native("/idx")).unwrap();
+        assert_eq!((stats.file_count, stats.dir_count, stats.total_size), (2, 1, 15));
+        let empty = stats_below(&conn, &native("/nothing")).unwrap();
+        assert_eq!((empty.file_count, empty.dir_count, empty.total_size), (0, 0, 0));
+    }
+
+    #[test]
+    fn rename_then_undo_restores_the_original_name_once() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("draft.txt");
+        write_file(&path, "text");
+        index_path(&conn, &path);
+
+        let renamed = rename_in_place(&conn, &path_str(&path), " final.txt ").unwrap();
+        let target = dir.path().join("final.txt");
+        assert_eq!(renamed.entry.path, path_str(&target));
+        assert!(target.is_file() && !path.exists());
+        assert!(indexed(&conn, &target) && !indexed(&conn, &path));
+
+        let (restored, from) = undo_logged(&conn, &renamed.undo_token).unwrap();
+        assert_eq!((restored.path, from), (path_str(&path), path_str(&target)));
+        assert!(path.is_file() && indexed(&conn, &path) && !indexed(&conn, &target));
+        // each token undoes once
+        assert!(matches!(undo_logged(&conn, &renamed.undo_token), Err(Error::InvalidInput(_))));
+        assert!(matches!(undo_logged(&conn, "not-a-token"), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn rename_rejects_paths_and_taken_names() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a.txt"), dir.path().join("b.txt"));
+        write_file(&a, "a");
+        write_file(&b, "b");
+        for bad in ["", "..", "sub/x.txt", "sub\\x.txt"] {
+            let result = rename_in_place(&conn, &path_str(&a), bad);
+            assert!(matches!(result, Err(Error::InvalidInput(_))), "{:?}", bad);
+        }
+        let taken = rename_in_place(&conn, &path_str(&a), "b.txt");
+        assert!(matches!(taken, Err(Error::AlreadyExists(_))));
+        assert_eq!(fs::read_to_string(&b).unwrap(), "b");
+    }
+
+    #[test]
+    fn undo_refuses_to_overwrite_whatever_took_the_old_name() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("old.txt");
+        write_file(&path, "first");
+        let renamed = rename_in_place(&conn, &path_str(&path), "new.txt").unwrap();
+        write_file(&path, "second");
+        let result = undo_logged(&conn, &renamed.undo_token);
+        assert!(matches!(result, Err(Error::AlreadyExists(_))));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+    }
+
+    #[test]
+    fn only_the_newest_operations_stay_undoable() {
+        let conn = memory_db();
+        let first = log_operation(&conn, OperationKind::Move, "/a", "/b").unwrap();
+        for n in 0..OPERATION_LOG_LIMIT {
+            log_operation(&conn, OperationKind::Move, &format!("/a{}", n), "/b").unwrap();
+        }
+        let logged: u32 = conn
+            .query_row("SELECT COUNT(*) FROM operation_log", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(logged, OPERATION_LOG_LIMIT);
+        assert!(matches!(undo_logged(&conn, &first), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn sniff_encoding_prefers_bom_then_utf8_then_utf16_layout() {
+        use encoding_rs::{UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+        assert_eq!(sniff_encoding(b"\xEF\xBB\xBFplain"), UTF_8);
+        assert_eq!(sniff_encoding(b"\xFF\xFEh\0i\0"), UTF_16LE);
+        assert_eq!(sniff_encoding("naïve café".as_bytes()), UTF_8);
+        // the sample limit cut a two-byte character in half
+        assert_eq!(sniff_encoding(&"café".as_bytes()[..4]), UTF_8);
+        assert_eq!(sniff_encoding(b"h\0e\0l\0l\0o\0"), UTF_16LE);
+        assert_eq!(sniff_encoding(b"\0h\0e\0l\0l\0o"), UTF_16BE);
+        assert_eq!(sniff_encoding(b"caf\xE9 cr\xE8me"), WINDOWS_1252);
+        assert_eq!(sniff_encoding(b""), UTF_8);
+    }
+
+    #[test]
+    fn detect_encoding_names_what_encoding_for_label_accepts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("latin1.txt");
+        fs::write(&path, b"r\xE9sum\xE9").unwrap();
+        let name = detect_encoding(path_str(&path)).unwrap();
+        assert_eq!(name, "windows-1252");
+        assert_eq!(encoding_for_label(&name).unwrap(), encoding_rs::WINDOWS_1252);
+        assert_eq!(encoding_for_label(" latin1 ").unwrap(), encoding_rs::WINDOWS_1252);
+        assert!(matches!(encoding_for_label("klingon"), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn changed_dirs_collect_every_write_until_taken() {
+        let conn = memory_db();
+        install_change_tracking(&conn).unwrap();
+        insert_rows(&conn, &[("/idx/a/one.txt", 1, false), ("/idx/b/two.txt", 2, false)]);
+        let moved_to = intern_dir(&conn, &native("/idx/c/")).unwrap();
+        conn.execute(
+            &format!("UPDATE main_table SET dir_id = ?1 WHERE {}", path_is("?2")),
+            rusqlite::params![moved_to, native("/idx/b/two.txt")],
+        )
+        .unwrap();
+        let update = take_changed_dirs(&conn).unwrap();
+        assert!(!update.all);
+        assert_eq!(update.dirs, [native("/idx/a"), native("/idx/b"), native("/idx/c")]);
+        assert!(take_changed_dirs(&conn).unwrap().dirs.is_empty());
+
+        conn.execute("DELETE FROM main_table WHERE name = 'one.txt'", []).unwrap();
+        assert_eq!(take_changed_dirs(&conn).unwrap().dirs, [native("/idx/a")]);
+    }
+
+    #[test]
+    fn changed_dirs_past_the_limit_say_everything() {
+        let conn = memory_db();
+        install_change_tracking(&conn).unwrap();
+        for n in 0..=INDEX_UPDATE_MAX_DIRS {
+            insert_file_meta(&conn, &file_row(&format!("/idx/d{}/f", n), 1, false)).unwrap();
+        }
+        let update = take_changed_dirs(&conn).unwrap();
+        assert!(update.all && update.dirs.is_empty());
+        assert!(!take_changed_dirs(&conn).unwrap().all);
+    }
+
+    #[test]
+    fn files_over_size_lists_files_largest_first() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/big", 1 << 40, true),
+                ("/idx/b.iso", 500, false),
+                ("/idx/a.iso", 500, false),
+                ("/idx/huge.mkv", 900, false),
+                ("/idx/small.txt", 100, false),
+            ],
+        );
+        let paths = |threshold, limit| -> Vec<String> {
+            let found = largest_files(&conn, threshold, limit).unwrap();
+            found.into_iter().map(|f| f.path).collect()
+        };
+        let expected = [native("/idx/huge.mkv"), native("/idx/a.iso"), native("/idx/b.iso")];
+        assert_eq!(paths(100, 10), expected);
+        assert_eq!(paths(100, 1), expected[..1]);
+        assert!(paths(900, 10).is_empty());
+    }
+
+    #[test]
+    fn a_first_run_restores_the_default_session() {
+        let conn = memory_db();
+        let state = restore_session(&conn, "main").unwrap();
+        assert!(state.tabs.is_empty() && state.extra.is_empty());
+        assert_eq!(state.active_tab, 0);
+        assert!(state.window.is_none() && state.history.is_none());
+    }
+
+    #[test]
+    fn a_saved_session_round_trips_per_window() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (path_str(dir.path()), path_str(&dir.path().join("b")));
+        fs::create_dir(&b).unwrap();
+        let saved = serde_json::json!({
+            "tabs": [a, b],
+            "active_tab": 1,
+            "window": { "x": 10, "y": 20, "width": 800, "height": 600, "maximized": true },
+            "sidebar_width": 240,
+        });
+        store_session(&conn, "main", &saved.to_string()).unwrap();
+
+        let state = restore_session(&conn, "main").unwrap();
+        assert_eq!(state.tabs, [a.clone(), b]);
+        assert_eq!(state.active_tab, 1);
+        let window = state.window.unwrap();
+        assert_eq!((window.x, window.width, window.maximized), (10, 800, true));
+        assert_eq!(state.extra["sidebar_width"], 240);
+        assert!(restore_session(&conn, "other").unwrap().tabs.is_empty());
+
+        // a state without geometry keeps the recorded one
+        store_session(&conn, "main", &serde_json::json!({ "tabs": [a] }).to_string()).unwrap();
+        let state = restore_session(&conn, "main").unwrap();
+        assert_eq!(state.window.unwrap().height, 600);
+        assert!(state.extra.is_empty());
+    }
+
+    #[test]
+    fn restored_tabs_fall_back_to_existing_ancestors() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let gone = path_str(&dir.path().join("deleted/deeper"));
+        let state = serde_json::json!({ "tabs": [gone], "active_tab": 5 });
+        store_session(&conn, "main", &state.to_string()).unwrap();
+        let state = restore_session(&conn, "main").unwrap();
+        assert_eq!(state.tabs, [path_str(dir.path())]);
+        assert_eq!(state.active_tab, 0);
+    }
+
+    #[test]
+    fn unreadable_session_state_is_rejected_or_ignored() {
+        let conn = memory_db();
+        let bad = store_session(&conn, "main", "{not json");
+        assert!(matches!(bad, Err(Error::InvalidInput(_))));
+        conn.execute(
+            "INSERT INTO sessions (label, state, updated_at) VALUES ('main', '[1, 2]', 0)",
+            [],
+        )
+        .unwrap();
+        assert!(restore_session(&conn, "main").unwrap().tabs.is_empty());
+    }
+
+    #[test]
+    fn a_replaced_row_is_recorded_as_a_collision() {
+        let conn = memory_db();
+        let first = FileMeta {
+            file_key: Some(FileKey { volume: 1, index: 7 }),
+            ..file_row("C:\\data\\a.bin", 10, false)
+        };
+        let second = FileMeta {
+            file_key: Some(FileKey { volume: 1, index: 8 }),
+            ..file_row("C:\\data\\a.bin", 20, false)
+        };
+        insert_file_meta(&conn, &first).unwrap();
+        let previous_size = match insert_file_meta(&conn, &second).unwrap() {
+            UpsertOutcome::Replaced { previous_size } => previous_size,
+            _ => panic!("a different file at the same path is a collision"),
+        };
+        record_path_collision(&conn, 1, &second, previous_size, 0).unwrap();
+        // past the limit the collision is only counted, not listed
+        record_path_collision(&conn, 1, &second, previous_size, INDEX_COLLISION_LIMIT).unwrap();
+        let rows: Vec<(String, u64, u64)> = conn
+            .prepare("SELECT path, kept_size, skipped_size FROM index_collisions")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows, vec![(native("C:\\data\\a.bin"), 20, 10)]);
+    }
+
+    fn advanced_names(conn: &Connection, query: &str) -> Vec<String> {
+        advanced_matches(conn, query).unwrap().into_iter().map(|f| f.name).collect()
+    }
+
+    #[test]
+    fn an_and_query_needs_every_term() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/docs/invoice-1.pdf", 10, false),
+                ("/docs/invoice-2.txt", 10, false),
+                ("/docs/notes.docx", 10, false),
+            ],
+        );
+        let query = r#"{"and": [{"field": "name", "op": "contains", "value": "invoice"},
+            {"field": "ext", "op": "in", "value": ["pdf", "docx"]}]}"#;
+        assert_eq!(advanced_names(&conn, query), ["invoice-1.pdf"]);
+    }
+
+    #[test]
+    fn an_or_query_needs_any_term() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/docs/big.bin", 5_000, false),
+                ("/docs/small.txt", 5, false),
+                ("/docs/other.txt", 50, false),
+            ],
+        );
+        let query = r#"{"or": [{"field": "size", "op": "gt", "value": 1000},
+            {"field": "name", "op": "starts_with", "value": "small"}]}"#;
+        assert_eq!(advanced_names(&conn, query), ["big.bin", "small.txt"]);
+    }
+
+    #[test]
+    fn unknown_fields_and_ops_are_rejected() {
+        let conn = memory_db();
+        for query in [
+            r#"{"field": "owner", "op": "eq", "value": "me"}"#,
+            r#"{"field": "name", "op": "matches", "value": "x"}"#,
+            r#"{"and": []}"#,
+        ] {
+            assert!(matches!(advanced_matches(&conn, query), Err(Error::InvalidInput(_))));
+        }
+    }
+
+    #[test]
+    fn rate_tracker_falls_back_to_the_seed_until_a_second_is_in() {
+        let start = std::time::Instant::now();
+        let mut rate = RateTracker::new(Some(40.0));
+        rate.record(start, 0);
+        rate.record(start + std::time::Duration::from_millis(500), 100);
+        assert_eq!(rate.rate(), Some(40.0));
+        rate.record(start + std::time::Duration::from_secs(2), 400);
+        assert_eq!(rate.rate(), Some(200.0));
+        assert_eq!(RateTracker::new(None).rate(), None);
+    }
+
+    #[test]
+    fn rate_tracker_forgets_samples_outside_the_window() {
+        let start = std::time::Instant::now();
+        let mut rate = RateTracker::new(None);
+        rate.record(start, 0);
+        // a slow start followed by ten fast seconds
+        rate.record(start + std::time::Duration::from_secs(5), 10);
+        rate.record(start + std::time::Duration::from_secs(15), 1_010);
+        assert_eq!(rate.rate(), Some(100.0));
+    }
+
+    #[test]
+    fn estimates_need_a_total_the_run_has_not_passed() {
+        let start = std::time::Instant::now();
+        let mut rate = RateTracker::new(None);
+        rate.record(start, 0);
+        rate.record(start + std::time::Duration::from_secs(2), 100);
+        assert_eq!(rate.estimate(100, Some(250)), (Some(3), Some(40.0)));
+        assert_eq!(rate.estimate(300, Some(250)), (None, None));
+        assert_eq!(rate.estimate(100, None), (None, None));
+    }
+
+    #[test]
+    fn checksums_match_known_digests() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("abc.txt");
+        write_file(&file, "abc");
+        assert_eq!(
+            checksum(&file, "sha256").unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            checksum(&file, "BLAKE3").unwrap(),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(checksum(&file, "md5").unwrap(), "900150983cd24fb0d6963f7d28e17f72");
+        assert!(matches!(checksum(&file, "sha512"), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn checksums_stream_files_bigger_than_the_buffer() {
+        use sha2::Digest;
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("big.bin");
+        let content = vec![7u8; 200 * 1024 + 3];
+        fs::write(&file, &content).unwrap();
+        let expected: String =
+            sha2::Sha256::digest(&content).iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(checksum(&file, "sha256").unwrap(), expected);
+        assert_eq!(checksum(&file, "blake3").unwrap(), blake3::hash(&content).to_hex().as_str());
+    }
+
+    #[test]
+    fn each_written_parent_is_invalidated_once() {
+        let paths = [
+            native("/idx/a/one.txt"),
+            native("/idx/a/two.txt"),
+            native("/idx/b"),
+            native("/"),
+        ];
+        let dirs: Vec<String> = invalidated_parents(&paths).into_iter().collect();
+        assert_eq!(dirs, [native("/idx"), native("/idx/a")]);
+    }
+
+    #[test]
+    fn indexing_invalidates_parents_and_walked_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("file.txt"), "x");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let paths = [path_str(&dir.path().join("file.txt")), path_str(&dir.path().join("sub"))];
+        let dirs: Vec<String> = reindexed_dirs(&paths).into_iter().collect();
+        assert_eq!(dirs, [path_str(dir.path()), path_str(&dir.path().join("sub"))]);
+    }
+
+    #[test]
+    fn file_metas_line_up_with_their_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = Vec::new();
+        for i in 0..40 {
+            let file = dir.path().join(format!("{}.txt", i));
+            if i % 3 == 0 {
+                write_file(&file, &"x".repeat(i));
+            }
+            paths.push(path_str(&file));
+        }
+        let results = file_metas(paths.clone());
+        assert_eq!(results.len(), paths.len());
+        for (i, (result, path)) in results.iter().zip(&paths).enumerate() {
+            match result {
+                Ok(meta) => {
+                    assert_eq!(i % 3, 0);
+                    assert_eq!((&meta.path, meta.size), (path, i as u64));
+                }
+                Err(_) => assert_ne!(i % 3, 0),
+            }
+        }
+    }
+
+    #[test]
+    fn excluded_extensions_do_not_count_toward_the_size() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/keep.txt", 10, false),
+                ("/idx/cache/blob.TMP", 100, false),
+                ("/idx/cache/blob.bak", 1_000, false),
+                ("/idx/Makefile", 10_000, false),
+                ("/other/skip.txt", 100_000, false),
+            ],
+        );
+        let dir = native("/idx");
+        assert_eq!(size_excluding(&conn, &dir, &[]).unwrap(), 11_110);
+        let excluded = [".tmp".to_string(), "BAK".to_string()];
+        assert_eq!(size_excluding(&conn, &dir, &excluded).unwrap(), 10_010);
+    }
+
+    #[test]
+    fn a_delete_under_a_protected_root_is_refused() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("photos");
+        let file = root.join("a.jpg");
+        write_file(&file, "jpeg");
+        let roots = [path_str(&root)].into_iter().collect();
+        save_protected_roots(&conn, &roots).unwrap();
+
+        let rule = Rule {
+            id: 1,
+            watch_dir: path_str(&root),
+            priority: 0,
+            enabled: true,
+            criteria: RuleCriteria::default(),
+            action: RuleAction::Trash,
+            destination: None,
+            tag: None,
+        };
+        match apply_rule(&conn, &rule, &path_str(&file), 4, 0) {
+            Err(Error::Protected(_, protected)) => assert_eq!(protected, path_str(&root)),
+            other => panic!("expected the protected root to refuse, got {:?}", other),
+        }
+        assert!(file.exists());
+        // a sibling sharing the name prefix is not protected
+        assert!(ensure_writable(&conn, &[path_str(&dir.path().join("photos-old"))]).is_ok());
+
+        save_protected_roots(&conn, &Default::default()).unwrap();
+        assert!(ensure_writable(&conn, &[path_str(&file)]).is_ok());
+    }
+
+    fn browse_rows(conn: &Connection) {
+        let rows = [
+            ("/idx/a.txt", 10, 100),
+            ("/idx/b.pdf", 2_000, 200),
+            ("/idx/sub", 0, 300),
+            ("/idx/sub/c.pdf", 5_000, 400),
+            ("/idx/sub/deep/d.PDF", 50, 500),
+            ("/other/e.pdf", 9_000, 600),
+        ];
+        for (path, size, modified) in rows {
+            let row = FileMeta { modified, ..file_row(path, size, !path.contains('.')) };
+            insert_file_meta(conn, &row).unwrap();
+        }
+    }
+
+    fn browsed(conn: &Connection, options: &str) -> (Vec<String>, u64, Option<String>) {
+        let options: BrowseOptions = serde_json::from_str(options).unwrap();
+        let result = browse_index(conn, options).unwrap();
+        let Entries::Objects(items) = result.items else { panic!("objects were asked for") };
+        (items.into_iter().map(|f| f.name).collect(), result.total, result.next_cursor)
+    }
+
+    #[test]
+    fn browse_lists_a_directory_or_its_whole_subtree() {
+        let conn = memory_db();
+        browse_rows(&conn);
+        let dir = serde_json::to_string(&native("/idx")).unwrap();
+        let (items, total, _) = browsed(&conn, &format!(r#"{{"dir": {}}}"#, dir));
+        assert_eq!((items, total), (vec!["a.txt".to_string(), "b.pdf".into(), "sub".into()], 3));
+
+        let options = format!(
+            r#"{{"dir": {}, "recursive": true, "extensions": [".pdf"], "sort": "-size"}}"#,
+            dir
+        );
+        let (items, total, _) = browsed(&conn, &options);
+        assert_eq!((items, total), (vec!["c.pdf".to_string(), "b.pdf".into(), "d.PDF".into()], 3));
+    }
+
+    #[test]
+    fn browse_searches_with_size_and_date_filters() {
+        let conn = memory_db();
+        browse_rows(&conn);
+        let options = r#"{"query": "PDF", "min_size": 100, "modified_before": 550}"#;
+        assert_eq!(browsed(&conn, options).0, ["b.pdf", "c.pdf"]);
+        let options = r#"{"query": ".", "files_only": true, "modified_after": 450}"#;
+        assert_eq!(browsed(&conn, options).0, ["d.PDF", "e.pdf"]);
+        let options: BrowseOptions = serde_json::from_str("{}").unwrap();
+        assert!(matches!(browse_index(&conn, options), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn browse_pages_keep_the_total_of_every_match() {
+        let conn = memory_db();
+        browse_rows(&conn);
+        let (items, total, cursor) = browsed(&conn, r#"{"query": "pdf", "page_size": 3}"#);
+        assert_eq!((items, total), (vec!["b.pdf".to_string(), "c.pdf".into(), "d.PDF".into()], 4));
+        let options = format!(
+            r#"{{"query": "pdf", "page_size": 3, "after_cursor": {}}}"#,
+            serde_json::to_string(&cursor.unwrap()).unwrap()
+        );
+        assert_eq!(browsed(&conn, &options), (vec!["e.pdf".to_string()], 4, None));
+    }
+
+    // The disk, except that the named files cannot be read.
+    struct Unreadable(Vec<std::path::PathBuf>);
+
+    impl MetadataSource for Unreadable {
+        fn stat(&self, path: &Path) -> std::io::Result<fs_meta::EntryStat> {
+            if self.0.iter().any(|p| p == path) {
+                return Err(std::io::Error::from(std::io::ErrorKind::PermissionDenied));
+            }
+            DiskMetadata.stat(path)
+        }
+    }
+
+    #[test]
+    fn stat_paths_use_the_given_walk_and_metadata_source() {
+        let dir = tempfile::tempdir().unwrap();
+        write_file(&dir.path().join("a.txt"), "a");
+        write_file(&dir.path().join("cache/b.txt"), "b");
+        let locked = dir.path().join("locked.txt");
+        write_file(&locked, "c");
+        let walk = WalkConfig { skip_keywords: vec!["cache".to_string()], follow_links: false };
+        let source = Unreadable(vec![locked.clone()]);
+
+        let mut report = IndexPathsReport::default();
+        let paths = [path_str(dir.path()), path_str(&locked)];
+        let metas = stat_paths_with(&walk, &source, &paths, &mut report);
+        let mut found: Vec<&str> = metas.iter().map(|m| m.name.as_str()).collect();
+        found.sort_unstable();
+        let mut expected = [dir.path().file_name().unwrap().to_str().unwrap(), "a.txt"];
+        expected.sort_unstable();
+        assert_eq!(found, expected);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].path, path_str(&locked));
+    }
+
+    #[test]
+    fn files_changed_or_deleted_since_indexing_are_reported() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = DbConnection(Mutex::new(memory_db()));
+        let [same, grown, gone] = ["same.txt", "grown.txt", "gone.txt"].map(|n| dir.path().join(n));
+        for file in [&same, &grown, &gone] {
+            write_file(file, "12345");
+            index_path(&db.0.lock().unwrap(), file);
+        }
+        write_file(&grown, "1234567890");
+        fs::remove_file(&gone).unwrap();
+
+        let drift = index_drift(&db, Some(path_str(dir.path()))).unwrap();
+        let found: Vec<(&str, &str, Option<u64>)> =
+            drift.iter().map(|d| (d.path.as_str(), d.status, d.size)).collect();
+        let (grown, gone) = (path_str(&grown), path_str(&gone));
+        let expected = [(gone.as_str(), "deleted", None), (grown.as_str(), "modified", Some(10))];
+        assert_eq!(found, expected);
+        assert!(index_drift(&db, Some(native("/elsewhere"))).unwrap().is_empty());
+    }
+
+    #[test]
+    fn extensions_come_from_the_last_dot_of_the_name() {
+        let ext = |path: &str| get_extension(path.to_string());
+        assert_eq!(ext("archive.tar.gz").as_deref(), Some("gz"));
+        assert_eq!(ext("C:\\Users\\me\\Report.PDF").as_deref(), Some("PDF"));
+        assert_eq!(ext("/home/me/notes.md/").as_deref(), Some("md"));
+        assert_eq!(ext(".gitignore"), None);
+        assert_eq!(ext("C:\\repo\\.env"), None);
+        assert_eq!(ext("Makefile"), None);
+        assert_eq!(ext("name."), None);
+        // a dot in a parent folder is not the file's extension
+        assert_eq!(ext("C:\\v1.2\\README"), None);
+    }
+
+    fn limit_results(conn: &Connection, max_rows: usize, max_bytes: usize) {
+        let limits = ResultLimits { max_rows, max_bytes };
+        set_setting(conn, RESULT_LIMITS_SETTING, &serde_json::to_string(&limits).unwrap()).unwrap();
+    }
+
+    // Follows the cursors from an unpaged listing to the end: the names of
+    // each page and whether it said it was truncated.
+    fn follow_pages(conn: &Connection, sort: Option<&str>) -> Vec<(Vec<String>, bool)> {
+        let cache = ListingCache::default();
+        let key = ListingKey { sort: sort.map(str::to_string), ..listing_key("/idx", None, None) };
+        let mut pages = Vec::new();
+        let mut cursor = None;
+        loop {
+            let listing = children_listing(
+                conn,
+                &cache,
+                key.clone(),
+                cursor.take(),
+                None,
+                ListingFormat::Objects,
+            )
+            .unwrap();
+            let (entries, next_cursor, truncated) = match listing {
+                Listing::All(entries) => (entries, None, false),
+                Listing::Page { entries, next_cursor, truncated } => {
+                    (entries, next_cursor, truncated)
+                }
+            };
+            let Entries::Objects(entries) = entries else { panic!("objects were asked for") };
+            pages.push((entries.into_iter().map(|e| e.name).collect(), truncated));
+            match next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    fn five_children(conn: &Connection) {
+        insert_rows(
+            conn,
+            &[
+                ("/idx/a", 5, false),
+                ("/idx/b", 1, false),
+                ("/idx/c", 4, false),
+                ("/idx/d", 2, false),
+                ("/idx/e", 3, false),
+            ],
+        );
+    }
+
+    fn every_name() -> SearchFilters {
+        let (name, extension) = (String::new(), String::new());
+        SearchFilters { name, extension, doc_title: None, doc_author: None }
+    }
+
+    fn search_names(conn: &Connection, sort: Option<&str>) -> (Vec<String>, Option<String>) {
+        let sort = sort.map(str::to_string);
+        let listing =
+            search_listing(conn, &every_name(), sort, None, None, ListingFormat::Objects).unwrap();
+        let (entries, next_cursor) = match listing {
+            Listing::All(entries) => (entries, None),
+            Listing::Page { entries, next_cursor, .. } => (entries, next_cursor),
+        };
+        let Entries::Objects(entries) = entries else { panic!("objects were asked for") };
+        (entries.into_iter().map(|e| e.name).collect(), next_cursor)
+    }
+
+    #[test]
+    fn an_unpaged_search_is_ordered_by_its_sort_and_capped() {
+        let conn = memory_db();
+        five_children(&conn);
+        let (names, cursor) = search_names(&conn, Some("-size"));
+        assert_eq!(names, ["a", "c", "e", "d", "b"]);
+        assert!(cursor.is_none());
+        assert_eq!(search_names(&conn, None).0, ["a", "b", "c", "d", "e"]);
+
+        // over the cap it is the first page of the same order
+        limit_results(&conn, 2, usize::MAX);
+        let (names, cursor) = search_names(&conn, Some("size"));
+        assert_eq!(names, ["b", "d"]);
+        assert!(cursor.is_some());
+
+        let sort = Some("colour".to_string());
+        let bad = search_listing(&conn, &every_name(), sort, None, None, ListingFormat::Objects);
+        assert!(matches!(bad, Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn case_distinct_sibling_directories_list_apart_where_paths_keep_case() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/Foo", 0, true),
+                ("/idx/Foo/upper.txt", 1, false),
+                ("/idx/foo", 0, true),
+                ("/idx/foo/lower.txt", 1, false),
+            ],
+        );
+        let names = |dir: &str, page_size: Option<u32>| -> Vec<String> {
+            let key = listing_key(dir, None, None);
+            let cache = ListingCache::default();
+            let listing =
+                children_listing(&conn, &cache, key, None, page_size, ListingFormat::Objects)
+                    .unwrap();
+            let (Listing::All(entries) | Listing::Page { entries, .. }) = listing;
+            let Entries::Objects(entries) = entries else { panic!("objects were asked for") };
+            let mut names: Vec<String> = entries.into_iter().map(|e| e.name).collect();
+            names.sort();
+            names
+        };
+        for page_size in [None, Some(10)] {
+            if cfg!(target_os = "windows") {
+                assert_eq!(names("/idx/Foo", page_size), ["lower.txt", "upper.txt"]);
+            } else {
+                assert_eq!(names("/idx/Foo", page_size), ["upper.txt"]);
+                assert_eq!(names("/idx/foo", page_size), ["lower.txt"]);
+            }
+        }
+    }
+
+    #[test]
+    fn a_listing_over_the_row_cap_continues_from_its_last_row() {
+        let conn = memory_db();
+        five_children(&conn);
+        limit_results(&conn, 2, usize::MAX);
+        let pages = follow_pages(&conn, None);
+        let names: Vec<Vec<String>> = pages.iter().map(|(names, _)| names.clone()).collect();
+        assert_eq!(names, [vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+        // every page but the last stopped at the cap
+        assert!(pages[0].1 && pages[1].1);
+        assert!(!pages[2].1);
+
+        // under the cap the listing is whole and has no cursor
+        limit_results(&conn, 5, usize::MAX);
+        let whole = ["a", "b", "c", "d", "e"].map(String::from).to_vec();
+        assert_eq!(follow_pages(&conn, None), [(whole, false)]);
+    }
+
+    #[test]
+    fn a_listing_over_the_byte_budget_continues_in_its_sort_order() {
+        let conn = memory_db();
+        five_children(&conn);
+        let mut rows = query_children(&conn, &native("/idx")).unwrap();
+        FileKinds::load(&conn).unwrap().annotate(&mut rows);
+        let one_row = serde_json::to_vec(&rows[0]).unwrap().len();
+        limit_results(&conn, 100, one_row * 2 + 2);
+        let pages = follow_pages(&conn, Some("-size"));
+        let names: Vec<String> = pages.iter().flat_map(|(names, _)| names.clone()).collect();
+        assert_eq!(names, ["a", "c", "e", "d", "b"]);
+        assert_eq!(pages[0].0.len(), 2);
+        assert!(pages[0].1);
+    }
+
+    #[test]
+    fn type_composition_percentages_cover_the_subtree() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/a.txt", 100, false),
+                ("/idx/sub/b.TXT", 200, false),
+                ("/idx/photo.jpg", 300, false),
+                ("/idx/clip.mp4", 400, false),
+                ("/idx/sub", 0, true),
+                ("/other/big.mp4", 10_000, false),
+            ],
+        );
+        let shares = composition(&conn, &native("/idx")).unwrap();
+        let total_pct: f64 = shares.iter().map(|s| s.size_pct).sum();
+        assert!((total_pct - 100.0).abs() < 1e-9, "{}", total_pct);
+        assert_eq!(shares.iter().map(|s| s.count).sum::<u64>(), 4);
+        assert_eq!(shares.iter().map(|s| s.size).sum::<u64>(), 1_000);
+        // largest first; both text files land in one category
+        assert!(shares.windows(2).all(|w| w[0].size >= w[1].size));
+        let text = shares.iter().find(|s| s.count == 2).unwrap();
+        assert_eq!(text.size, 300);
+        assert!((text.size_pct - 30.0).abs() < 1e-9);
+
+        assert!(composition(&conn, &native("/empty")).unwrap().is_empty());
+    }
+
+    // A tempdir holding an approved `allowed` folder and an `outside` one,
+    // with restricted mode on.
+    fn restricted_db() -> (tempfile::TempDir, Connection) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("allowed")).unwrap();
+        fs::create_dir(dir.path().join("outside")).unwrap();
+        let conn = memory_db();
+        let approved = serde_json::to_string(&[path_str(&dir.path().join("allowed"))]).unwrap();
+        set_setting(&conn, APPROVED_DIRS_SETTING, &approved).unwrap();
+        set_setting(&conn, RESTRICTED_MODE_SETTING, "1").unwrap();
+        (dir, conn)
+    }
+
+    fn allowed(conn: &Connection, path: &Path) -> bool {
+        match ensure_allowed(conn, &[path_str(path)]) {
+            Ok(()) => true,
+            Err(Error::Forbidden(_)) => false,
+            Err(e) => panic!("unexpected {:?}", e),
+        }
+    }
+
+    #[test]
+    fn restricted_mode_allows_only_the_approved_roots() {
+        let (dir, conn) = restricted_db();
+        let allowed_dir = dir.path().join("allowed");
+        assert!(allowed(&conn, &allowed_dir.join("new.txt")));
+        assert!(allowed(&conn, &allowed_dir.join("deep/er/new.txt")));
+        assert!(!allowed(&conn, &dir.path().join("outside/x.txt")));
+        assert!(!allowed(&conn, &dir.path().join("allowed-not/x.txt")));
+
+        set_setting(&conn, RESTRICTED_MODE_SETTING, "0").unwrap();
+        assert!(allowed(&conn, &dir.path().join("outside/x.txt")));
+    }
+
+    #[test]
+    fn restricted_mode_sees_through_traversal() {
+        let (dir, conn) = restricted_db();
+        let allowed_dir = dir.path().join("allowed");
+        assert!(!allowed(&conn, &allowed_dir.join("..").join("outside").join("x.txt")));
+        // `..` past a folder that does not exist yet cannot be resolved
+        let past_missing = allowed_dir.join("missing").join("..").join("..").join("outside");
+        assert!(!allowed(&conn, &past_missing));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn restricted_mode_follows_symlinks_out_of_the_root() {
+        let (dir, conn) = restricted_db();
+        let link = dir.path().join("allowed/escape");
+        std::os::unix::fs::symlink(dir.path().join("outside"), &link).unwrap();
+        assert!(!allowed(&conn, &link.join("x.txt")));
+    }
+
+    #[test]
+    fn restricted_mode_refuses_relative_unc_and_drive_relative_paths() {
+        let (_dir, conn) = restricted_db();
+        for path in ["relative\\x.txt", "C:x.txt", "\\x.txt", "\\\\server\\share\\x.txt"] {
+            assert!(!allowed(&conn, Path::new(path)), "{}", path);
+        }
+    }
+
+    #[test]
+    fn max_depth_is_the_deepest_indexed_directory() {
+        let conn = memory_db();
+        assert_eq!(deepest_dir(&conn, None).unwrap(), None);
+        insert_rows(
+            &conn,
+            &[
+                ("/idx", 0, true),
+                ("/idx/a", 0, true),
+                ("/idx/a/b", 0, true),
+                ("/idx/a/b/c", 0, true),
+                // files do not count, however deep
+                ("/idx/a/b/c/d/e/f.txt", 1, false),
+                ("/other/x", 0, true),
+            ],
+        );
+        assert_eq!(deepest_dir(&conn, None).unwrap(), Some(4));
+        assert_eq!(deepest_dir(&conn, Some(&native("/other"))).unwrap(), Some(2));
+        assert_eq!(deepest_dir(&conn, Some(&native("/idx/a/b/c"))).unwrap(), None);
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn dropfiles_buffer_lists_wide_paths_after_the_header() {
+        let buffer = dropfiles_buffer(&["C:\\a.txt".to_string(), "D:\\b".to_string()]);
+        let header = std::mem::size_of::<windows_sys::Win32::UI::Shell::DROPFILES>();
+        let u32_at = |at: usize| u32::from_le_bytes(buffer[at..at + 4].try_into().unwrap());
+        assert_eq!(u32_at(0) as usize, header);
+        // fWide
+        assert_eq!(u32_at(header - 4), 1);
+        let units: Vec<u16> = buffer[header..]
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        let expected: Vec<u16> = "C:\\a.txt\0D:\\b\0\0".encode_utf16().collect();
+        assert_eq!(units, expected);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn file_uris_escape_everything_but_unreserved_bytes() {
+        assert_eq!(file_uri("/home/me/a-b_c.~txt"), "file:///home/me/a-b_c.~txt");
+        assert_eq!(file_uri("/tmp/50% off/é"), "file:///tmp/50%25%20off/%C3%A9");
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn a_cut_uses_the_gnome_format_and_a_copy_the_uri_list() {
+        let paths = ["/a/one".to_string(), "/b/two".to_string()];
+        assert_eq!(
+            clipboard_files_body(&paths, false),
+            ("text/uri-list", "file:///a/one\r\nfile:///b/two\r\n".to_string())
+        );
+        assert_eq!(
+            clipboard_files_body(&paths, true),
+            ("x-special/gnome-copied-files", "cut\nfile:///a/one\nfile:///b/two".to_string())
+        );
+    }
+
+    // xorshift64: good enough noise without a dependency
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn random_bytes_are_near_eight_bits_of_entropy_and_text_is_low() {
+        let dir = tempfile::tempdir().unwrap();
+        let measure = |name: &str, content: &[u8]| {
+            let file = dir.path().join(name);
+            fs::write(&file, content).unwrap();
+            sampled_entropy(&file, content.len() as u64).unwrap()
+        };
+        // small enough to read whole, and big enough to be sampled
+        assert!(measure("small.bin", &noise(64 * 1024)) > 7.9);
+        let big = (ENTROPY_BLOCK_LEN * ENTROPY_SAMPLE_BLOCKS * 4) as usize;
+        assert!(measure("big.bin", &noise(big)) > 7.9);
+        assert!(measure("text.txt", "hello hello hello ".repeat(5_000).as_bytes()) < 3.0);
+        assert_eq!(measure("one.txt", &[b'a'; 10_000]), 0.0);
+        assert_eq!(measure("empty.txt", b""), 0.0);
+    }
+
+    #[test]
+    fn globs_are_anchored_case_blind_and_backtrack() {
+        assert!(glob_matches("*.PDF", "report.pdf"));
+        assert!(glob_matches("invoice-??.txt", "Invoice-07.txt"));
+        assert!(!glob_matches("invoice-??.txt", "invoice-7.txt"));
+        // the whole name, not a substring
+        assert!(!glob_matches("report", "my report.doc"));
+        assert!(!glob_matches("*.doc", "a.docx"));
+        // the first `*` must give back what a later literal needs
+        assert!(glob_matches("*a*b", "xaxaxb"));
+        assert!(glob_matches("**", ""));
+        assert!(!glob_matches("?", ""));
+    }
+
+    #[test]
+    fn rule_criteria_require_every_set_field() {
+        let criteria = RuleCriteria {
+            extension: Some("pdf".to_string()),
+            name_glob: Some("scan*".to_string()),
+            min_age_secs: Some(60),
+            min_size: Some(100),
+        };
+        assert!(criteria.matches("Scan-01.PDF", 100, 60));
+        assert!(!criteria.matches("scan-01.txt", 100, 60));
+        assert!(!criteria.matches("photo.pdf", 100, 60));
+        assert!(!criteria.matches("scan-01.pdf", 99, 60));
+        assert!(!criteria.matches("scan-01.pdf", 100, 59));
+        // no extension at all never equals a wanted one
+        assert!(!criteria.matches("scan", 100, 60));
+        assert!(RuleCriteria::default().matches("anything", 0, 0));
+    }
+
+    fn rule_in(dir: &Path, action: RuleAction) -> Rule {
+        Rule {
+            id: 0,
+            watch_dir: path_str(dir),
+            priority: 0,
+            enabled: true,
+            criteria: RuleCriteria::default(),
+            action,
+            destination: None,
+            tag: None,
+        }
+    }
+
+    #[test]
+    fn a_tag_rule_needs_a_tag_and_keeps_no_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut rule = rule_in(dir.path(), RuleAction::Tag);
+        rule.tag = Some("  ".to_string());
+        assert!(matches!(rule.clone().validate(), Err(Error::InvalidInput(_))));
+
+        rule.tag = Some(" invoices ".to_string());
+        rule.destination = Some(path_str(dir.path()));
+        let rule = rule.validate().unwrap();
+        assert_eq!((rule.tag.as_deref(), rule.destination), (Some("invoices"), None));
+
+        let mut moving = rule_in(dir.path(), RuleAction::Move);
+        moving.destination = Some(path_str(&dir.path().join("out")));
+        moving.tag = Some("stray".to_string());
+        assert_eq!(moving.validate().unwrap().tag, None);
+    }
+
+    #[test]
+    fn a_tag_rule_tags_once_follows_renames_and_undoes() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("bill.pdf");
+        write_file(&file, "pdf");
+        index_path(&conn, &file);
+        let mut rule = rule_in(dir.path(), RuleAction::Tag);
+        rule.tag = Some("invoices".to_string());
+        let path = path_str(&file);
+
+        let (tagged, token) = apply_rule(&conn, &rule, &path, 3, 0).unwrap().unwrap();
+        assert_eq!(tagged, path);
+        assert!(apply_rule(&conn, &rule, &path, 3, 0).unwrap().is_none());
+        assert_eq!(file_tags(&conn, &path).unwrap(), ["invoices"]);
+
+        let renamed = rename_in_place(&conn, &path, "paid.pdf").unwrap();
+        assert!(file_tags(&conn, &path).unwrap().is_empty());
+        assert_eq!(file_tags(&conn, &renamed.entry.path).unwrap(), ["invoices"]);
+        undo_logged(&conn, &renamed.undo_token).unwrap();
+
+        let (untagged, from) = undo_logged(&conn, &token.unwrap()).unwrap();
+        assert_eq!((untagged.path, from), (path.clone(), path.clone()));
+        assert!(file_tags(&conn, &path).unwrap().is_empty());
+        assert!(file.is_file());
+    }
+
+    #[test]
+    fn undoing_a_rule_copy_deletes_it_only_while_unchanged() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("scan.pdf");
+        write_file(&file, "scan");
+        let meta = get_file_meta(&file).unwrap();
+        let mut rule = rule_in(dir.path(), RuleAction::Copy);
+        rule.destination = Some(path_str(&dir.path().join("copies")));
+        let path = path_str(&file);
+
+        let (copy, token) = apply_rule(&conn, &rule, &path, meta.size, meta.modified)
+            .unwrap()
+            .unwrap();
+        let (undone, from) = undo_logged(&conn, &token.unwrap()).unwrap();
+        assert_eq!((undone.path, from), (copy.clone(), copy.clone()));
+        assert!(!Path::new(&copy).exists() && !indexed(&conn, Path::new(&copy)));
+        assert!(file.is_file());
+
+        let (copy, token) = apply_rule(&conn, &rule, &path, meta.size, meta.modified)
+            .unwrap()
+            .unwrap();
+        write_file(Path::new(&copy), "edited since");
+        assert!(matches!(undo_logged(&conn, &token.unwrap()), Err(Error::InvalidInput(_))));
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "edited since");
+    }
+
+    #[test]
+    fn only_lnk_files_are_resolved_as_shortcuts() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        write_file(&file, "x");
+        let resolved = tauri::async_runtime::block_on(resolve_shortcut(path_str(&file)));
+        assert!(matches!(resolved, Err(Error::InvalidInput(_))));
+    }
+
+    #[cfg(target_os = "windows")]
+    #[test]
+    fn a_saved_shortcut_resolves_to_its_target_and_arguments() {
+        use windows::core::{ComInterface, HSTRING};
+        use windows::Win32::System::Com::{
+            CoCreateInstance, CoInitializeEx, IPersistFile, CLSCTX_INPROC_SERVER,
+            COINIT_APARTMENTTHREADED,
+        };
+        use windows::Win32::UI::Shell::{IShellLinkW, ShellLink};
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target.txt");
+        write_file(&target, "x");
+        let lnk = dir.path().join("target.lnk");
+        // SAFETY: COM is initialised for this thread and the strings outlive the calls
+        unsafe {
+            let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+            let link: IShellLinkW =
+                CoCreateInstance(&ShellLink, None, CLSCTX_INPROC_SERVER).unwrap();
+            link.SetPath(&HSTRING::from(target.as_os_str())).unwrap();
+            link.SetArguments(&HSTRING::from("--flag value")).unwrap();
+            link.SetWorkingDirectory(&HSTRING::from(dir.path().as_os_str())).unwrap();
+            let file: IPersistFile = link.cast().unwrap();
+            file.Save(&HSTRING::from(lnk.as_os_str()), true).unwrap();
+        }
+
+        let resolved = platform_resolve_shortcut(&lnk).unwrap();
+        assert_eq!(resolved.target, path_str(&target));
+        assert_eq!(resolved.arguments, "--flag value");
+        assert!(resolved.target_exists);
+        fs::remove_file(&target).unwrap();
+        assert!(!platform_resolve_shortcut(&lnk).unwrap().target_exists);
+    }
+
+    #[test]
+    fn open_all_attempts_direct_children_with_the_extension() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx", 0, true),
+                ("/idx/b.JPG", 1, false),
+                ("/idx/a.jpg", 1, false),
+                ("/idx/c.png", 1, false),
+                ("/idx/dir.jpg", 0, true),
+                ("/idx/dir.jpg/nested.jpg", 1, false),
+            ],
+        );
+        let dir = native("/idx");
+        let expected = vec![native("/idx/a.jpg"), native("/idx/b.JPG")];
+        assert_eq!(files_to_open_in_dir(&conn, &dir, ".jpg").unwrap(), expected);
+        assert!(matches!(files_to_open_in_dir(&conn, &dir, "."), Err(Error::InvalidInput(_))));
+
+        set_setting(&conn, OPEN_FILES_CONFIRM_SETTING, "1").unwrap();
+        assert!(matches!(files_to_open_in_dir(&conn, &dir, "jpg"), Err(Error::InvalidInput(_))));
+        assert_eq!(files_to_open_in_dir(&conn, &dir, "png").unwrap(), vec![native("/idx/c.png")]);
+    }
+
+    #[test]
+    fn a_copy_that_reads_back_wrong_is_retried_then_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("photo.raw");
+        fs::write(&from, noise(200_000)).unwrap();
+        let to = dir.path().join("copy.raw");
+        // damages the first `bad` copies before they are read back
+        let copy = |verification: &mut Verification, bad: u32| {
+            let mut damaged = 0;
+            verification
+                .copy_reporting(&from, &to, |to| {
+                    if damaged < bad {
+                        damaged += 1;
+                        fs::write(to, b"bit rot").unwrap();
+                    }
+                    Ok(())
+                })
+                .unwrap()
+        };
+
+        let mut verification = Verification::new(2);
+        assert!(copy(&mut verification, 0));
+        assert!(copy(&mut verification, 2));
+        assert_eq!(fs::read(&to).unwrap(), fs::read(&from).unwrap());
+        assert!(!copy(&mut verification, 3));
+        let attempts: Vec<(u32, bool)> =
+            verification.results.iter().map(|r| (r.attempts, r.verified)).collect();
+        assert_eq!(attempts, vec![(1, true), (3, true), (3, false)]);
+        let expected = checksum(&from, "sha256").unwrap();
+        assert!(verification.results.iter().all(|r| r.sha256 == expected));
+
+        let (mut failed, mut verified) = (Vec::new(), Vec::new());
+        verification.finish(&mut failed, &mut verified);
+        assert_eq!(failed.len(), 1);
+        assert_eq!(verified.len(), 3);
+    }
+
+    #[test]
+    fn the_setup_indexes_are_listed_with_their_columns() {
+        let conn = memory_db();
+        let indexes = db_indexes(&conn).unwrap();
+        let find = |name: &str| indexes.iter().find(|i| i.name == name).expect(name);
+        assert_eq!(find("idx_path").columns, ["dir_id", "name"]);
+        assert_eq!(find("idx_name").columns, ["name"]);
+        assert_eq!(find("idx_extension").columns, ["extension", "name"]);
+        for name in ["idx_path", "idx_name", "idx_extension"] {
+            let index = find(name);
+            assert_eq!((index.table.as_str(), index.origin.as_str()), ("main_table", "c"));
+        }
+        assert!(!indexes.iter().any(|i| i.name == "idx_extension_name"));
+    }
+
+    #[test]
+    fn an_older_extension_index_is_rebuilt_over_extension_and_name() {
+        let conn = memory_db();
+        conn.execute_batch(
+            "DROP INDEX idx_extension;
+             CREATE INDEX idx_extension ON main_table(extension);
+             CREATE INDEX idx_extension_name ON main_table(extension, name);",
+        )
+        .unwrap();
+        create_indexes(&conn).unwrap();
+        let indexes = db_indexes(&conn).unwrap();
+        let extension = indexes.iter().find(|i| i.name == "idx_extension").unwrap();
+        assert_eq!(extension.columns, ["extension", "name"]);
+        assert!(!indexes.iter().any(|i| i.name == "idx_extension_name"));
+    }
+
+    #[test]
+    fn directory_activity_reports_the_newest_file_below() {
+        let conn = memory_db();
+        let mut rows = vec![
+            file_row("/idx", 0, true),
+            file_row("/idx/old.txt", 1, false),
+            file_row("/idx/sub", 0, true),
+            file_row("/idx/sub/new.txt", 1, false),
+            file_row("/idx/sub/deep", 0, true),
+            file_row("/idx0/newer.txt", 1, false),
+        ];
+        for (row, modified) in rows.iter_mut().zip([10, 100, 20, 300, 500, 900]) {
+            row.modified = modified;
+        }
+        for row in &rows {
+            insert_file_meta(&conn, row).unwrap();
+        }
+
+        let activity = activity_below(&conn, &native("/idx")).unwrap();
+        // the newest entry is a directory, but only files count as newest_file
+        assert_eq!(activity.last_modified, 500);
+        assert_eq!(activity.newest_file.unwrap().path, native("/idx/sub/new.txt"));
+        assert_eq!(activity.file_count, 2);
+
+        let empty = activity_below(&conn, &native("/idx/sub/deep")).unwrap();
+        assert_eq!((empty.last_modified, empty.file_count), (500, 0));
+        assert!(empty.newest_file.is_none());
+        let unknown = activity_below(&conn, &native("/nowhere")).unwrap();
+        assert_eq!((unknown.last_modified, unknown.file_count), (0, 0));
+    }
+
+    #[test]
+    fn legacy_paths_find_their_std_path_form() {
+        assert!(has_bogus_drive("1:\\foo"));
+        assert!(!has_bogus_drive("C:\\foo"));
+        assert!(!has_bogus_drive("/"));
+        assert_eq!(normalized_legacy_path("/idx//a"), Some(native("/idx/a")));
+        assert_eq!(normalized_legacy_path("/idx/a/"), Some(native("/idx/a")));
+        assert_eq!(normalized_legacy_path("/idx/a"), None);
+        assert_eq!(normalized_legacy_path("/"), None);
+        // a UNC prefix is not a doubled separator
+        assert_eq!(normalized_legacy_path("\\\\server\\share"), None);
+    }
+
+    #[test]
+    fn the_legacy_path_audit_normalizes_merges_and_removes_once() {
+        let conn = memory_db();
+        // open_schema already audited the empty database
+        conn.execute("DELETE FROM settings WHERE key = ?1", [LEGACY_PATHS_AUDITED_KEY]).unwrap();
+        insert_rows(&conn, &[("/idx/b", 0, true), ("/idx/ok.txt", 1, false)]);
+        for raw in ["/idx//a.txt", "/idx/b/", "1:/x"] {
+            let mut row = file_row(raw, 1, false);
+            row.path = raw.to_string();
+            insert_file_meta(&conn, &row).unwrap();
+        }
+
+        normalize_legacy_paths(&conn).unwrap();
+        assert_eq!(
+            all_paths(&conn),
+            [native("/idx/a.txt"), native("/idx/b"), native("/idx/ok.txt")]
+        );
+        let mut stmt = conn
+            .prepare("SELECT kind, original, current FROM operation_log ORDER BY original")
+            .unwrap();
+        let logged: Vec<(String, String, String)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let entry = |kind: &str, original: &str, current: &str| {
+            (kind.to_string(), original.to_string(), current.to_string())
+        };
+        assert_eq!(
+            logged,
+            [
+                entry("normalize", "/idx//a.txt", &native("/idx/a.txt")),
+                entry("merge", "/idx/b/", &native("/idx/b")),
+                entry("remove", "1:/x", ""),
+            ]
+        );
+
+        // a second start leaves new oddities alone
+        let mut row = file_row("/idx//late", 1, false);
+        row.path = "/idx//late".to_string();
+        insert_file_meta(&conn, &row).unwrap();
+        normalize_legacy_paths(&conn).unwrap();
+        assert!(all_paths(&conn).contains(&"/idx//late".to_string()));
+    }
+
+    #[test]
+    fn frecency_decays_with_the_age_of_the_last_visit() {
+        let now = 1_000 * 86_400;
+        assert_eq!(frecency(3, now, now), 300.0);
+        assert_eq!(frecency(3, now - 10 * 86_400, now), 210.0);
+        assert_eq!(frecency(3, now - 200 * 86_400, now), 30.0);
+        // a clock that went backwards counts as a visit just now
+        assert_eq!(frecency(1, now + 86_400, now), 100.0);
+    }
+
+    #[test]
+    fn fuzzy_matches_prefer_runs_and_word_starts() {
+        let chars = |s: &str| s.chars().collect::<Vec<_>>();
+        let score = |query: &str, name: &str| fuzzy_match(&chars(query), &chars(name));
+        assert_eq!(score("doc", "my-documents"), Some((21, vec![(3, 6)])));
+        assert_eq!(score("md", "my-documents"), Some((18, vec![(0, 1), (3, 4)])));
+        assert_eq!(score("proj", "proj"), Some((37, vec![(0, 4)])));
+        assert_eq!(score("dco", "docs"), None);
+        assert!(score("doc", "docs").unwrap().0 > score("doc", "xdxoxc").unwrap().0);
+    }
+
+    #[test]
+    fn quick_jump_ranks_by_name_then_history_then_depth() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx/projects", 0, true),
+                ("/idx/deep/projects", 0, true),
+                ("/idx/old/proj-notes", 0, true),
+                ("/idx/photos", 0, true),
+            ],
+        );
+        let candidates = load_jump_candidates(&conn).unwrap();
+        let ranked = |scores: &std::collections::HashMap<String, f64>, query: &str| {
+            let found = rank_jump_candidates(&candidates, scores, query, 10);
+            found.into_iter().map(|m| m.path).collect::<Vec<_>>()
+        };
+
+        // equal name matches go shallowest first
+        let none = std::collections::HashMap::new();
+        let expected = ["/idx/projects", "/idx/deep/projects", "/idx/old/proj-notes"].map(native);
+        assert_eq!(ranked(&none, "proj"), expected);
+        assert!(ranked(&none, "").is_empty());
+
+        // history outranks depth on an equal name match, and alone fills an empty query
+        let visited = [(native("/idx/old/proj-notes"), 3_000.0)].into_iter().collect();
+        assert_eq!(ranked(&visited, "proj")[0], native("/idx/old/proj-notes"));
+        assert_eq!(ranked(&visited, ""), [native("/idx/old/proj-notes")]);
+        assert_eq!(rank_jump_candidates(&candidates, &none, "p", 2).len(), 2);
+    }
+
+    // Index rows as find_duplicate_directories reads them, sorted by path.
+    fn dir_rows(rows: &[(&str, u64, bool)]) -> Vec<(String, bool, u64)> {
+        let mut rows: Vec<_> =
+            rows.iter().map(|&(path, size, is_dir)| (native(path), is_dir, size)).collect();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn duplicate_trees_are_grouped_at_their_top() {
+        let nodes = build_dir_nodes(dir_rows(&[
+            ("/idx", 0, true),
+            ("/idx/a", 0, true),
+            ("/idx/a/x.jpg", 100, false),
+            ("/idx/a/sub", 0, true),
+            ("/idx/a/sub/y.txt", 50, false),
+            // names compare without case
+            ("/idx/b", 0, true),
+            ("/idx/b/X.JPG", 100, false),
+            ("/idx/b/sub", 0, true),
+            ("/idx/b/sub/y.txt", 50, false),
+            ("/idx/c", 0, true),
+            ("/idx/c/x.jpg", 100, false),
+            ("/idx/c/other.txt", 20, false),
+            ("/idx/empty1", 0, true),
+            ("/idx/empty2", 0, true),
+        ]));
+        let root = nodes.iter().find(|n| n.path == native("/idx")).unwrap();
+        assert_eq!((root.bytes, root.file_count), (420, 6));
+
+        // a/sub and b/sub only match because a and b do; empty folders never
+        let groups = duplicate_dir_groups(&nodes, 0, None);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].dirs, [native("/idx/a"), native("/idx/b")]);
+        assert_eq!((groups[0].bytes, groups[0].wasted_bytes, groups[0].files), (150, 150, 2));
+        assert!(duplicate_dir_groups(&nodes, 151, None).is_empty());
+
+        let near = near_duplicate_dirs(&nodes, 0, 60.0);
+        let pairs: Vec<(String, String, u64)> =
+            near.iter().map(|n| (n.larger.clone(), n.smaller.clone(), n.shared_bytes)).collect();
+        assert!(pairs.contains(&(native("/idx/a"), native("/idx/c"), 100)));
+        assert!(near.iter().all(|n| n.similarity_pct >= 60.0));
+        assert!(near_duplicate_dirs(&nodes, 0, 70.0).iter().all(|n| n.smaller != native("/idx/c")));
+    }
+
+    #[test]
+    fn verifying_a_duplicate_group_compares_file_contents() {
+        let dir = tempfile::tempdir().unwrap();
+        for (copy, content) in [("one", "same"), ("two", "same"), ("three", "diff")] {
+            write_file(&dir.path().join(copy).join("f.txt"), content);
+        }
+        let rows = |copies: &[&str]| {
+            let mut rows = vec![(path_str(dir.path()), true, 0)];
+            for copy in copies {
+                let copy = dir.path().join(copy);
+                rows.push((path_str(&copy), true, 0));
+                rows.push((path_str(&copy.join("f.txt")), false, 4));
+            }
+            rows.sort();
+            build_dir_nodes(rows)
+        };
+        let verified = |nodes: &[DirNode]| duplicate_dir_groups(nodes, 0, Some(10))[0].verified;
+        assert_eq!(verified(&rows(&["one", "two"])), Some(true));
+        assert_eq!(verified(&rows(&["one", "three"])), Some(false));
+    }
+
+    #[test]
+    fn tree_reports_filter_small_files_escape_names_and_cap_nodes() {
+        let conn = memory_db();
+        insert_rows(
+            &conn,
+            &[
+                ("/idx", 0, true),
+                ("/idx/docs", 0, true),
+                ("/idx/docs/a<b>_1.txt", 2_000, false),
+                ("/idx/z.txt", 10, false),
+                ("/idx/big.bin", 5_000, false),
+            ],
+        );
+        let root = native("/idx");
+        let children = report_children(&conn, &root, 5, 100).unwrap();
+        let render = |format, budget| {
+            let mut report = TreeReport {
+                conn: &conn,
+                children: &children,
+                format,
+                out: Vec::new(),
+                budget,
+                nodes: 0,
+                truncated: false,
+            };
+            report.write(&root).unwrap();
+            (String::from_utf8(report.out).unwrap(), report.nodes, report.truncated)
+        };
+        let date = format_timestamp(1_000, "%Y-%m-%d").unwrap();
+        let file = |size| format!("{}, {}", human_size(size), date);
+
+        let (text, nodes, truncated) = render(ReportFormat::Text, 10);
+        // the root total still counts the filtered-out z.txt
+        let expected = format!(
+            "{} ({})\n├── docs ({})\n│   └── a<b>_1.txt ({})\n└── big.bin ({})\n",
+            root,
+            human_size(7_010),
+            human_size(2_000),
+            file(2_000),
+            file(5_000)
+        );
+        assert_eq!(text, expected);
+        assert_eq!((nodes, truncated), (3, false));
+
+        let (markdown, ..) = render(ReportFormat::Markdown, 10);
+        assert!(markdown.contains(&format!("  - a\\<b\\>\\_1.txt ({})\n", file(2_000))));
+        assert!(markdown.contains(&format!("- **docs/** ({})\n", human_size(2_000))));
+        let (html, ..) = render(ReportFormat::Html, 10);
+        assert!(html.contains("<li>a&lt;b&gt;_1.txt"));
+        assert!(html.trim_end().ends_with("</body></html>"));
+
+        // docs uses the only node; each level then summarises what is left
+        let (capped, nodes, truncated) = render(ReportFormat::Text, 1);
+        assert_eq!((nodes, truncated), (1, true));
+        assert!(capped.ends_with("│   └── … 1 more items\n└── … 1 more items\n"));
+    }
+
+    #[test]
+    fn a_date_and_sequence_template_numbers_files_in_the_given_order() {
+        let conn = memory_db();
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = Vec::new();
+        // 2023-01-15 and 2023-03-02, midday UTC
+        let files = [("b.JPG", 1_673_784_000), ("a.jpg", 1_673_784_000), ("c.png", 1_677_758_400)];
+        for (name, modified) in files {
+            let file = dir.path().join(name);
+            write_file(&file, name);
+            let modified = filetime::FileTime::from_unix_time(modified, 0);
+            filetime::set_file_mtime(&file, modified).unwrap();
+            index_path(&conn, &file);
+            paths.push(path_str(&file));
+        }
+
+        let renamed = rename_by_template(&conn, &paths, "{date:%Y%m%d}_{n:3}.{ext}").unwrap();
+        let names: Vec<&str> = renamed.iter().map(|r| r.entry.name.as_str()).collect();
+        assert_eq!(names, ["20230115_001.JPG", "20230115_002.jpg", "20230302_003.png"]);
+        // the contents followed their names, and the index moved with them
+        assert_eq!(fs::read_to_string(dir.path().join("20230115_002.jpg")).unwrap(), "a.jpg");
+        assert!(!dir.path().join("b.JPG").exists());
+        assert!(indexed(&conn, &dir.path().join("20230302_003.png")));
+        assert!(!indexed(&conn, &dir.path().join("c.png")));
+
+        // renumbering from the other end swaps the outer two; the middle keeps its name
+        let current: Vec<String> = renamed.iter().rev().map(|r| r.entry.path.clone()).collect();
+        let renamed = rename_by_template(&conn, &current, "{date:%Y%m%d}_{n:3}.{ext}").unwrap();
+        let names: Vec<&str> = renamed.iter().map(|r| r.entry.name.as_str()).collect();
+        assert_eq!(names, ["20230302_001.png", "20230115_003.JPG"]);
+        assert_eq!(fs::read_to_string(dir.path().join("20230115_003.JPG")).unwrap(), "b.JPG");
+        assert!(matches!(
+            rename_by_template(&conn, &current, "{date:%Q}"),
+            Err(Error::InvalidInput(_))
+        ));
+    }
+
+    // Hands out the scripted idle times in turn, noting whether the run was
+    // on hold at each ask; the last time repeats.
+    fn scripted_idle(
+        state: &std::sync::Arc<IndexingState>,
+        script: &[u64],
+    ) -> (impl Fn() -> u64, std::rc::Rc<std::cell::RefCell<Vec<bool>>>) {
+        let asked = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let (state, script, log) = (state.clone(), script.to_vec(), asked.clone());
+        let source = move || {
+            let mut log = log.borrow_mut();
+            log.push(state.idle_hold.load(Ordering::SeqCst));
+            script[(log.len() - 1).min(script.len() - 1)]
+        };
+        (source, asked)
+    }
+
+    #[test]
+    fn an_idle_only_run_holds_while_the_user_is_active_and_resumes_once_idle() {
+        let state = std::sync::Arc::new(IndexingState::default());
+        let paused = AtomicBool::new(false);
+        let poll = std::time::Duration::from_millis(1);
+        let away = IDLE_THRESHOLD_SECS;
+        let (source, asked) = scripted_idle(&state, &[away + 5, 3, 40, away]);
+        let mut pacer = Pacer::with_idle_source(&IndexThrottle::default(), true, source, poll);
+
+        // the user is away: no hold
+        std::thread::sleep(poll * 2);
+        pacer.step(&state, &paused);
+        assert_eq!(*asked.borrow(), [false]);
+        assert!(!state.idle_hold.load(Ordering::SeqCst));
+
+        // back at the machine: held, polling, until idle again, then resumed
+        std::thread::sleep(poll * 2);
+        pacer.step(&state, &paused);
+        assert_eq!(*asked.borrow(), [false, false, true, true]);
+        assert!(!state.idle_hold.load(Ordering::SeqCst));
+
+        // checks are spaced out by the poll interval
+        pacer.step(&state, &paused);
+        assert_eq!(asked.borrow().len(), 4);
+    }
+
+    #[test]
+    fn a_held_run_lets_go_when_cancelled() {
+        let state = std::sync::Arc::new(IndexingState::default());
+        let (source, asked) = scripted_idle(&state, &[0]);
+        let poll = std::time::Duration::from_millis(1);
+        let mut pacer = Pacer::with_idle_source(&IndexThrottle::default(), true, source, poll);
+        let cancel = state.cancel.clone();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(20));
+            cancel.store(true, Ordering::SeqCst);
+        });
+        pacer.hold_until_idle(&state);
+        canceller.join().unwrap();
+        assert!(asked.borrow().len() > 1);
+        assert!(!state.idle_hold.load(Ordering::SeqCst));
+
+        // without only_when_idle the source is never asked
+        let (source, asked) = scripted_idle(&state, &[0]);
+        let mut pacer = Pacer::with_idle_source(&IndexThrottle::default(), false, source, poll);
+        std::thread::sleep(poll * 2);
+        pacer.step(&state, &AtomicBool::new(false));
+        assert!(asked.borrow().is_empty());
+    }
+
+    #[test]
+    fn idle_only_schedules_wait_for_the_threshold() {
+        let due = || {
+            let config = |only_when_idle| ScheduleConfig { interval_hours: 1, only_when_idle };
+            vec![("/a".to_string(), config(true)), ("/b".to_string(), config(false))]
+        };
+        let roots = |due: &[(String, ScheduleConfig)]| {
+            due.iter().map(|(root, _)| root.clone()).collect::<Vec<_>>()
+        };
+        let (ready, deferred) = split_for_idle(due(), IDLE_THRESHOLD_SECS - 1);
+        assert_eq!(roots(&ready), ["/b"]);
+        assert_eq!(roots(&deferred), ["/a"]);
+        let (ready, deferred) = split_for_idle(due(), IDLE_THRESHOLD_SECS);
+        assert_eq!(roots(&ready), ["/a", "/b"]);
+        assert!(deferred.is_empty());
+    }
+}
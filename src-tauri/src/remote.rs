@@ -0,0 +1,577 @@
+// SFTP remote locations. Each remote is a named row in the remotes table with
+// its password or key passphrase in the OS keyring. Paths handed to the
+// frontend look like sftp://name/dir/file, relative to the directory the
+// remote was added with. Nothing remote is indexed: every call opens its own
+// session and lists live.
+
+use std::fs;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::fs_meta::metadata_mtime;
+use crate::{CopyProgress, Error, FileMeta};
+
+pub(crate) const REMOTE_SCHEME: &str = "sftp://";
+const KEYRING_SERVICE: &str = "file-explorer-sftp";
+// for connecting and for each blocking call on the session after that
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(15);
+
+// LIBSSH2_ERROR_* codes meaning the connection itself failed
+const SOCKET_ERRORS: &[i32] = &[-7, -9, -13, -30, -43];
+
+#[derive(Clone, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub(crate) enum RemoteAuth {
+    Password { password: String },
+    Key { key_path: String, passphrase: Option<String> },
+    // whatever ssh-agent or Pageant holds
+    Agent,
+}
+
+impl RemoteAuth {
+    fn kind(&self) -> &'static str {
+        match self {
+            RemoteAuth::Password { .. } => "password",
+            RemoteAuth::Key { .. } => "key",
+            RemoteAuth::Agent => "agent",
+        }
+    }
+
+    // what goes into the keyring
+    fn secret(&self) -> Option<&str> {
+        match self {
+            RemoteAuth::Password { password } => Some(password),
+            RemoteAuth::Key { passphrase, .. } => passphrase.as_deref(),
+            RemoteAuth::Agent => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct Remote {
+    pub(crate) name: String,
+    pub(crate) user: String,
+    pub(crate) host: String,
+    pub(crate) port: u16,
+    // the server directory sftp://name/ stands for
+    pub(crate) base_path: String,
+    pub(crate) auth_kind: String,
+    pub(crate) key_path: Option<String>,
+    // hex SHA-256 of the server's host key, pinned when the remote was added
+    pub(crate) host_key: String,
+}
+
+// sftp://user@host[:port][/path]
+pub(crate) fn parse_sftp_url(url: &str) -> Result<(String, String, u16, String), Error> {
+    let invalid = |why: &str| Error::InvalidInput(format!("'{}': {}", url, why));
+    let rest = url.strip_prefix(REMOTE_SCHEME).ok_or_else(|| invalid("expected sftp://"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "."),
+    };
+    let (user, host_port) = authority.rsplit_once('@').ok_or_else(|| invalid("missing user@"))?;
+    let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+        // [v6 address]:port
+        let (host, after) = bracketed.split_once(']').ok_or_else(|| invalid("unclosed '['"))?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match host_port.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        }
+    };
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| invalid("bad port"))?,
+        None => 22,
+    };
+    if user.is_empty() || host.is_empty() {
+        return Err(invalid("missing user or host"));
+    }
+    Ok((user.to_string(), host.to_string(), port, path.to_string()))
+}
+
+pub(crate) fn validate_remote_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.contains(['/', '\\', ':', '@']) {
+        return Err(Error::InvalidInput(format!("'{}' is not a usable remote name", name)));
+    }
+    Ok(())
+}
+
+// sftp://name/a/b -> ("name", "a/b"). ".." is refused so every remote path
+// maps to exactly one place under the remote's base directory.
+pub(crate) fn split_remote_path(path: &str) -> Result<(&str, &str), Error> {
+    let rest = path
+        .strip_prefix(REMOTE_SCHEME)
+        .ok_or_else(|| Error::InvalidInput(format!("'{}' is not an sftp:// path", path)))?;
+    let (name, rel) = rest.split_once('/').unwrap_or((rest, ""));
+    let rel = rel.trim_matches('/');
+    if rel.split('/').any(|part| part == "..") {
+        return Err(Error::InvalidInput(format!("'{}' leaves the remote directory", path)));
+    }
+    Ok((name, rel))
+}
+
+pub(crate) fn join_remote(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent.trim_end_matches('/'), name)
+    }
+}
+
+pub(crate) fn remote_path(name: &str, rel: &str) -> String {
+    format!("{}{}/{}", REMOTE_SCHEME, name, rel)
+}
+
+fn remote_from_row(row: &rusqlite::Row) -> rusqlite::Result<Remote> {
+    Ok(Remote {
+        name: row.get(0)?,
+        user: row.get(1)?,
+        host: row.get(2)?,
+        port: row.get(3)?,
+        base_path: row.get(4)?,
+        auth_kind: row.get(5)?,
+        key_path: row.get(6)?,
+        host_key: row.get(7)?,
+    })
+}
+
+const REMOTE_COLUMNS: &str = "name, user, host, port, base_path, auth_kind, key_path, host_key";
+
+pub(crate) fn load_remote(conn: &Connection, name: &str) -> Result<Remote, Error> {
+    conn.query_row(
+        &format!("SELECT {} FROM remotes WHERE name = ?1", REMOTE_COLUMNS),
+        [name],
+        remote_from_row,
+    )
+    .optional()?
+    .ok_or_else(|| Error::InvalidInput(format!("no remote named '{}'", name)))
+}
+
+pub(crate) fn load_remotes(conn: &Connection) -> Result<Vec<Remote>, Error> {
+    let mut stmt =
+        conn.prepare(&format!("SELECT {} FROM remotes ORDER BY name", REMOTE_COLUMNS))?;
+    let remotes = stmt.query_map([], remote_from_row)?.collect::<rusqlite::Result<_>>()?;
+    Ok(remotes)
+}
+
+pub(crate) fn insert_remote(conn: &Connection, remote: &Remote) -> Result<(), Error> {
+    conn.execute(
+        &format!(
+            "INSERT INTO remotes ({}) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            REMOTE_COLUMNS
+        ),
+        rusqlite::params![
+            remote.name,
+            remote.user,
+            remote.host,
+            remote.port,
+            remote.base_path,
+            remote.auth_kind,
+            remote.key_path,
+            remote.host_key
+        ],
+    )?;
+    Ok(())
+}
+
+fn keyring_entry(name: &str) -> Result<keyring::Entry, Error> {
+    keyring::Entry::new(KEYRING_SERVICE, name).map_err(|e| Error::Keyring(e.to_string()))
+}
+
+pub(crate) fn store_secret(name: &str, secret: &str) -> Result<(), Error> {
+    keyring_entry(name)?
+        .set_password(secret)
+        .map_err(|e| Error::Keyring(e.to_string()))
+}
+
+fn load_secret(name: &str) -> Result<Option<String>, Error> {
+    match keyring_entry(name)?.get_password() {
+        Ok(secret) => Ok(Some(secret)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(Error::Keyring(e.to_string())),
+    }
+}
+
+pub(crate) fn forget_secret(name: &str) -> Result<(), Error> {
+    match keyring_entry(name)?.delete_password() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(Error::Keyring(e.to_string())),
+    }
+}
+
+fn network_error(name: &str, e: impl std::fmt::Display) -> Error {
+    Error::RemoteNetwork(name.to_string(), e.to_string())
+}
+
+fn auth_error(name: &str, e: impl std::fmt::Display) -> Error {
+    Error::RemoteAuth(name.to_string(), e.to_string())
+}
+
+// Errors from calls on an established session: a dropped or timed-out
+// connection is a network error, anything else is reported like local I/O.
+fn sftp_error(name: &str, e: ssh2::Error) -> Error {
+    match e.code() {
+        ssh2::ErrorCode::Session(code) if SOCKET_ERRORS.contains(&code) => network_error(name, e),
+        _ => Error::Io(e.into()),
+    }
+}
+
+// Connects and completes the key exchange, returning the session and the
+// fingerprint of the key the server presented. Nothing is trusted yet.
+pub(crate) fn handshake(
+    name: &str,
+    host: &str,
+    port: u16,
+) -> Result<(ssh2::Session, String), Error> {
+    let addr = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| network_error(name, e))?
+        .next()
+        .ok_or_else(|| network_error(name, format!("{} did not resolve", host)))?;
+    let tcp =
+        TcpStream::connect_timeout(&addr, REMOTE_TIMEOUT).map_err(|e| network_error(name, e))?;
+    let mut session = ssh2::Session::new().map_err(|e| network_error(name, e))?;
+    session.set_timeout(REMOTE_TIMEOUT.as_millis() as u32);
+    session.set_tcp_stream(tcp);
+    session.handshake().map_err(|e| network_error(name, e))?;
+    let key = session
+        .host_key_hash(ssh2::HashType::Sha256)
+        .ok_or_else(|| Error::RemoteHostKey(name.to_string(), "no host key offered".to_string()))?;
+    let fingerprint = key.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok((session, fingerprint))
+}
+
+pub(crate) fn authenticate(
+    session: &ssh2::Session,
+    name: &str,
+    user: &str,
+    auth_kind: &str,
+    key_path: Option<&str>,
+    secret: Option<&str>,
+) -> Result<(), Error> {
+    let result = match (auth_kind, key_path) {
+        ("password", _) => {
+            let password = secret.ok_or_else(|| auth_error(name, "no stored password"))?;
+            session.userauth_password(user, password)
+        }
+        ("key", Some(key_path)) => {
+            session.userauth_pubkey_file(user, None, Path::new(key_path), secret)
+        }
+        ("agent", _) => session.userauth_agent(user),
+        (other, _) => return Err(auth_error(name, format!("unusable auth method '{}'", other))),
+    };
+    result.map_err(|e| auth_error(name, e))?;
+    if !session.authenticated() {
+        return Err(auth_error(name, "server did not accept the credentials"));
+    }
+    Ok(())
+}
+
+// Checks a new remote end to end and pins its host key; the caller stores
+// the returned Remote and the secret only when this succeeds.
+pub(crate) fn probe_remote(name: &str, url: &str, auth: &RemoteAuth) -> Result<Remote, Error> {
+    let (user, host, port, base_path) = parse_sftp_url(url)?;
+    let (session, host_key) = handshake(name, &host, port)?;
+    let key_path = match auth {
+        RemoteAuth::Key { key_path, .. } => Some(key_path.clone()),
+        _ => None,
+    };
+    authenticate(&session, name, &user, auth.kind(), key_path.as_deref(), auth.secret())?;
+    let sftp = session.sftp().map_err(|e| sftp_error(name, e))?;
+    let stat = sftp.stat(Path::new(&base_path)).map_err(|e| sftp_error(name, e))?;
+    if !stat.is_dir() {
+        return Err(Error::InvalidInput(format!("'{}' is not a directory on {}", base_path, host)));
+    }
+    Ok(Remote {
+        name: name.to_string(),
+        user,
+        host,
+        port,
+        base_path,
+        auth_kind: auth.kind().to_string(),
+        key_path,
+        host_key,
+    })
+}
+
+pub(crate) fn store_new_secret(auth: &RemoteAuth, name: &str) -> Result<(), Error> {
+    match auth.secret() {
+        Some(secret) => store_secret(name, secret),
+        None => forget_secret(name),
+    }
+}
+
+pub(crate) struct RemoteSession {
+    remote: Remote,
+    sftp: ssh2::Sftp,
+    // the channel needs the session alive
+    _session: ssh2::Session,
+}
+
+impl Remote {
+    // A host key other than the pinned one is refused rather than re-pinned;
+    // remove and add the remote again if the server really changed keys.
+    pub(crate) fn connect(&self) -> Result<RemoteSession, Error> {
+        let (session, host_key) = handshake(&self.name, &self.host, self.port)?;
+        if host_key != self.host_key {
+            return Err(Error::RemoteHostKey(
+                self.name.clone(),
+                format!("server key {} does not match the pinned {}", host_key, self.host_key),
+            ));
+        }
+        let secret = load_secret(&self.name)?;
+        authenticate(
+            &session,
+            &self.name,
+            &self.user,
+            &self.auth_kind,
+            self.key_path.as_deref(),
+            secret.as_deref(),
+        )?;
+        let sftp = session.sftp().map_err(|e| sftp_error(&self.name, e))?;
+        Ok(RemoteSession {
+            remote: self.clone(),
+            sftp,
+            _session: session,
+        })
+    }
+}
+
+impl RemoteSession {
+    fn server_path(&self, rel: &str) -> String {
+        if rel.is_empty() {
+            self.remote.base_path.clone()
+        } else {
+            join_remote(&self.remote.base_path, rel)
+        }
+    }
+
+    fn error(&self, e: ssh2::Error) -> Error {
+        sftp_error(&self.remote.name, e)
+    }
+
+    fn stat(&self, rel: &str) -> Result<ssh2::FileStat, Error> {
+        self.sftp.stat(Path::new(&self.server_path(rel))).map_err(|e| self.error(e))
+    }
+
+    fn lstat(&self, rel: &str) -> Result<ssh2::FileStat, Error> {
+        self.sftp.lstat(Path::new(&self.server_path(rel))).map_err(|e| self.error(e))
+    }
+
+    // Symlinks are described by their target when it resolves, as local
+    // listings do.
+    pub(crate) fn list(&self, rel: &str) -> Result<Vec<FileMeta>, Error> {
+        let entries = self
+            .sftp
+            .readdir(Path::new(&self.server_path(rel)))
+            .map_err(|e| self.error(e))?;
+        let mut listing = Vec::with_capacity(entries.len());
+        for (path, link_stat) in entries {
+            let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+                continue;
+            };
+            let child = join_remote(rel, &name);
+            let is_symlink = link_stat.file_type().is_symlink();
+            let stat = if is_symlink {
+                self.stat(&child).unwrap_or(link_stat)
+            } else {
+                link_stat
+            };
+            listing.push(FileMeta {
+                extension: Path::new(&name)
+                    .extension()
+                    .map(|e| e.to_string_lossy().to_string()),
+                path: remote_path(&self.remote.name, &child),
+                name,
+                size: stat.size.unwrap_or(0),
+                modified: stat.mtime.unwrap_or(0),
+                accessed: stat.atime,
+                is_dir: stat.is_dir(),
+                created: None,
+                is_symlink,
+                link_count: None,
+                file_key: None,
+                kind: None,
+            });
+        }
+        Ok(listing)
+    }
+
+    // Copies a remote file or tree to `to`, keeping modification times, and
+    // returns how many files were written. A symlink to a file is copied as
+    // that file; a symlink to a directory is skipped, so a link back up the
+    // tree cannot recurse forever.
+    pub(crate) fn download(
+        &self,
+        rel: &str,
+        to: &Path,
+        progress: &mut CopyProgress,
+    ) -> Result<u64, Error> {
+        progress.step(Path::new(&remote_path(&self.remote.name, rel)))?;
+        let link_stat = self.lstat(rel)?;
+        let stat = if link_stat.file_type().is_symlink() {
+            let target = self.stat(rel)?;
+            if target.is_dir() {
+                return Ok(0);
+            }
+            target
+        } else {
+            link_stat
+        };
+        if stat.is_dir() {
+            fs::create_dir_all(to)?;
+            let entries = self
+                .sftp
+                .readdir(Path::new(&self.server_path(rel)))
+                .map_err(|e| self.error(e))?;
+            let mut count = 0;
+            for (path, _) in entries {
+                let Some(name) = path.file_name().map(|n| n.to_string_lossy().to_string()) else {
+                    continue;
+                };
+                count += self.download(&join_remote(rel, &name), &to.join(&name), progress)?;
+            }
+            Ok(count)
+        } else {
+            let mut source = self
+                .sftp
+                .open(Path::new(&self.server_path(rel)))
+                .map_err(|e| self.error(e))?;
+            let mut target = fs::File::create(to)?;
+            std::io::copy(&mut source, &mut target)?;
+            drop(target);
+            if let Some(mtime) = stat.mtime {
+                filetime::set_file_mtime(to, filetime::FileTime::from_unix_time(mtime as i64, 0))?;
+            }
+            Ok(1)
+        }
+    }
+
+    // The mirror of download: local file or tree to `rel` on the server.
+    pub(crate) fn upload(
+        &self,
+        from: &Path,
+        rel: &str,
+        progress: &mut CopyProgress,
+    ) -> Result<u64, Error> {
+        progress.step(from)?;
+        let target = self.server_path(rel);
+        if from.is_dir() {
+            if !self.stat(rel).map(|s| s.is_dir()).unwrap_or(false) {
+                self.sftp.mkdir(Path::new(&target), 0o755).map_err(|e| self.error(e))?;
+            }
+            let mut count = 0;
+            for entry in fs::read_dir(from)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                count += self.upload(&entry.path(), &join_remote(rel, &name), progress)?;
+            }
+            Ok(count)
+        } else {
+            let mut source = fs::File::open(from)?;
+            let mut file = self.sftp.create(Path::new(&target)).map_err(|e| self.error(e))?;
+            std::io::copy(&mut source, &mut file)?;
+            let mtime = metadata_mtime(&source.metadata()?);
+            // the protocol sets both times or neither
+            let times = ssh2::FileStat {
+                size: None,
+                uid: None,
+                gid: None,
+                perm: None,
+                atime: Some(mtime),
+                mtime: Some(mtime),
+            };
+            file.setstat(times).map_err(|e| self.error(e))?;
+            Ok(1)
+        }
+    }
+
+    pub(crate) fn exists(&self, rel: &str) -> bool {
+        self.lstat(rel).is_ok()
+    }
+
+    // Refuses to replace an existing entry; returns the new relative path.
+    pub(crate) fn rename(&self, rel: &str, new_name: &str) -> Result<String, Error> {
+        let parent = rel.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let renamed = join_remote(parent, new_name);
+        if self.exists(&renamed) {
+            return Err(Error::AlreadyExists(remote_path(&self.remote.name, &renamed)));
+        }
+        self.sftp
+            .rename(
+                Path::new(&self.server_path(rel)),
+                Path::new(&self.server_path(&renamed)),
+                Some(ssh2::RenameFlags::ATOMIC | ssh2::RenameFlags::NATIVE),
+            )
+            .map_err(|e| self.error(e))?;
+        Ok(renamed)
+    }
+
+    // Removes a file, or a directory and everything in it. Symlinks are
+    // removed themselves, never followed.
+    pub(crate) fn delete(&self, rel: &str) -> Result<(), Error> {
+        let path = self.server_path(rel);
+        let stat = self.lstat(rel)?;
+        if stat.is_dir() {
+            for (child, _) in self.sftp.readdir(Path::new(&path)).map_err(|e| self.error(e))? {
+                if let Some(name) = child.file_name() {
+                    self.delete(&join_remote(rel, &name.to_string_lossy()))?;
+                }
+            }
+            self.sftp.rmdir(Path::new(&path)).map_err(|e| self.error(e))
+        } else {
+            self.sftp.unlink(Path::new(&path)).map_err(|e| self.error(e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(url: &str) -> (String, String, u16, String) {
+        parse_sftp_url(url).unwrap()
+    }
+
+    #[test]
+    fn sftp_urls_split_into_user_host_port_and_path() {
+        let owned = |user: &str, host: &str, port, path: &str| {
+            (user.to_string(), host.to_string(), port, path.to_string())
+        };
+        assert_eq!(parsed("sftp://me@box"), owned("me", "box", 22, "."));
+        assert_eq!(parsed("sftp://me@box:2222/srv/data"), owned("me", "box", 2222, "/srv/data"));
+        assert_eq!(parsed("sftp://me@[::1]:2200/x"), owned("me", "::1", 2200, "/x"));
+        // only the last '@' separates the user from the host
+        assert_eq!(parsed("sftp://a@b@box"), owned("a@b", "box", 22, "."));
+        for bad in ["ftp://me@box", "sftp://box", "sftp://@box", "sftp://me@", "sftp://me@box:x"] {
+            assert!(matches!(parse_sftp_url(bad), Err(Error::InvalidInput(_))), "{}", bad);
+        }
+        assert!(matches!(parse_sftp_url("sftp://me@[::1"), Err(Error::InvalidInput(_))));
+    }
+
+    #[test]
+    fn remote_paths_round_trip_and_never_climb_out() {
+        assert_eq!(split_remote_path("sftp://nas").unwrap(), ("nas", ""));
+        assert_eq!(split_remote_path("sftp://nas/a/b/").unwrap(), ("nas", "a/b"));
+        assert_eq!(split_remote_path(&remote_path("nas", "a/b")).unwrap(), ("nas", "a/b"));
+        assert_eq!(join_remote("", "a"), "a");
+        assert_eq!(join_remote("a/", "b"), "a/b");
+        for bad in ["/local/path", "sftp://nas/a/../../etc", "sftp://nas/.."] {
+            assert!(matches!(split_remote_path(bad), Err(Error::InvalidInput(_))), "{}", bad);
+        }
+        // a name that merely starts with dots is an ordinary entry
+        assert_eq!(split_remote_path("sftp://nas/..hidden").unwrap(), ("nas", "..hidden"));
+    }
+
+    #[test]
+    fn remote_names_cannot_look_like_paths_or_urls() {
+        assert!(validate_remote_name("home-nas").is_ok());
+        for bad in ["", "a/b", "a\\b", "c:", "me@box"] {
+            assert!(validate_remote_name(bad).is_err(), "{}", bad);
+        }
+    }
+}
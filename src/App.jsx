@@ -1,8 +1,14 @@
 // src/App.jsx
 import { Box } from '@mui/material'
 import FileExplorer from './components/FileExplorer'
+import QuickSearch from './components/QuickSearch'
 
 function App() {
+  // The tray's quick-search window loads the same bundle at #quick-search.
+  if (window.location.hash === '#quick-search') {
+    return <QuickSearch />
+  }
+
   return (
     <Box sx={{ height: '100vh', width: '100vw', overflow: 'hidden' }}>
       <FileExplorer />
@@ -10,4 +16,4 @@ function App() {
   )
 }
 
-export default App
\ No newline at end of file
+export default App
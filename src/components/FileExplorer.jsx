@@ -1,6 +1,8 @@
 // src/components/FileExplorer.jsx
 import React, { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/tauri';
+import { listen } from '@tauri-apps/api/event';
+import { appWindow } from '@tauri-apps/api/window';
 import { open } from '@tauri-apps/api/shell';
 import {
   Table,
@@ -28,7 +30,10 @@ import {
   Refresh as RefreshIcon,
   Home as HomeIcon,
   ArrowUpward as UpIcon,
-  Storage as StorageIcon
+  ArrowBack as BackIcon,
+  ArrowForward as ForwardIcon,
+  Storage as StorageIcon,
+  OpenInNew as NewWindowIcon
 } from '@mui/icons-material';
 
 const FileExplorer = () => {
@@ -39,13 +44,32 @@ const FileExplorer = () => {
   const [searchQuery, setSearchQuery] = useState('');
   const [searchExtension, setSearchExtension] = useState('');
   const [breadcrumbs, setBreadcrumbs] = useState(['C:']);
+  const [indexing, setIndexing] = useState(false);
+  const [waitingForIdle, setWaitingForIdle] = useState(false);
+  const [history, setHistory] = useState({ can_go_back: false, can_go_forward: false });
 
   useEffect(() => {
     const initializeApp = async () => {
       setInitializing(true);
       try {
-        // Load the initial directory
-        await loadDirectory('C:\\');
+        // An encrypted index refuses every other command until unlocked.
+        while (await invoke('index_locked')) {
+          const passphrase = window.prompt('Index passphrase');
+          if (passphrase === null) break;
+          await invoke('unlock_index', { passphrase }).catch((error) => window.alert(error));
+        }
+        // Reopen where the last session left off
+        const session = await invoke('load_session').catch((error) => {
+          console.error('Error loading session:', error);
+          return { tabs: [], active_tab: 0 };
+        });
+        // Windows opened with create_new_window carry their directory in the hash
+        const requested = window.location.hash.startsWith('#open=')
+          ? decodeURIComponent(window.location.hash.slice('#open='.length))
+          : null;
+        const startDir = requested || session.tabs[session.active_tab] || 'C:\\';
+        setCurrentDir(startDir);
+        await loadDirectory(startDir);
       } catch (error) {
         console.error('Initialization error:', error);
       } finally {
@@ -60,9 +84,70 @@ const FileExplorer = () => {
     if (!initializing) {
       setBreadcrumbs(currentDir.split('\\').filter(Boolean));
       loadDirectory(currentDir);
+      invoke('set_active_directory', { path: currentDir });
+      invoke('push_history', { windowLabel: appWindow.label, path: currentDir })
+        .then(setHistory)
+        .catch((error) => console.error('Error recording history:', error));
+      invoke('save_session', {
+        stateJson: JSON.stringify({ tabs: [currentDir], active_tab: 0 }),
+      }).catch((error) => console.error('Error saving session:', error));
     }
   }, [currentDir, initializing]);
 
+  useEffect(() => {
+    // Scheduled runs start without any action here, so ask the backend.
+    invoke('is_indexing')
+      .then((status) => {
+        setIndexing(status.running);
+        setWaitingForIdle(status.waiting_for_idle);
+      })
+      .catch((error) => console.error('Error reading indexing status:', error));
+    const unlisten = listen('index-completed', () => {
+      setIndexing(false);
+      setWaitingForIdle(false);
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, []);
+
+  useEffect(() => {
+    // Another window, the watcher or a file operation changed the index.
+    const unlisten = listen('index-updated', (event) => {
+      if (event.payload.all || event.payload.dirs.includes(currentDir)) {
+        loadDirectory(currentDir);
+      }
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [currentDir]);
+
+  useEffect(() => {
+    const unlisten = listen('directory-invalidated', (event) => {
+      if (event.payload.dir === currentDir) {
+        loadDirectory(currentDir);
+      }
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [currentDir]);
+
+  useEffect(() => {
+    const unlisten = listen('drop-completed', (event) => {
+      if (event.payload.error) {
+        console.error('Error handling dropped files:', event.payload.error);
+      }
+      if (event.payload.dest === currentDir) {
+        loadDirectory(currentDir);
+      }
+    });
+    return () => {
+      unlisten.then((fn) => fn());
+    };
+  }, [currentDir]);
+
   const loadDirectory = async (path) => {
     setLoading(true);
     try {
@@ -82,7 +167,8 @@ const FileExplorer = () => {
         name: searchQuery,
         extension: searchExtension,
       });
-      setFiles(result);
+      // a search too large for the result limits comes back as a truncated page
+      setFiles(Array.isArray(result) ? result : result.entries);
     } catch (error) {
       console.error('Error searching files:', error);
     } finally {
@@ -91,7 +177,7 @@ const FileExplorer = () => {
   };
 
   const handleFileClick = async (file) => {
-    if (!file.extension) {
+    if (file.is_dir) {
       // It's a directory
       setCurrentDir(file.path);
     } else {
@@ -125,8 +211,27 @@ const FileExplorer = () => {
     loadDirectory(currentDir);
   };
 
+  const navigateHistory = async (direction) => {
+    try {
+      const result = await invoke('navigate_history', { windowLabel: appWindow.label, direction });
+      setHistory(result);
+      if (result.path) {
+        setCurrentDir(result.path);
+      }
+    } catch (error) {
+      console.error('Error navigating history:', error);
+    }
+  };
+
+  const openNewWindow = () => {
+    invoke('create_new_window', { initialPath: currentDir }).catch((error) =>
+      console.error('Error opening window:', error)
+    );
+  };
+
   const populateDatabase = async () => {
     setLoading(true);
+    setIndexing(true);
     try {
       await invoke('transfer_to_sqlite', { path: 'C:\\' });
       await loadDirectory(currentDir);
@@ -134,6 +239,7 @@ const FileExplorer = () => {
       console.error('Error populating database:', error);
     } finally {
       setLoading(false);
+      setIndexing(false);
     }
   };
 
@@ -200,6 +306,20 @@ const FileExplorer = () => {
             <HomeIcon />
           </IconButton>
         </Tooltip>
+        <Tooltip title="Back">
+          <span>
+            <IconButton onClick={() => navigateHistory('back')} disabled={!history.can_go_back}>
+              <BackIcon />
+            </IconButton>
+          </span>
+        </Tooltip>
+        <Tooltip title="Forward">
+          <span>
+            <IconButton onClick={() => navigateHistory('forward')} disabled={!history.can_go_forward}>
+              <ForwardIcon />
+            </IconButton>
+          </span>
+        </Tooltip>
         <Tooltip title="Up">
           <IconButton onClick={goUp} disabled={breadcrumbs.length <= 1}>
             <UpIcon />
@@ -210,11 +330,18 @@ const FileExplorer = () => {
             <RefreshIcon />
           </IconButton>
         </Tooltip>
-        <Tooltip title="Populate Database">
-          <IconButton onClick={populateDatabase}>
-            <StorageIcon />
+        <Tooltip title="New Window">
+          <IconButton onClick={openNewWindow}>
+            <NewWindowIcon />
           </IconButton>
         </Tooltip>
+        <Tooltip title={waitingForIdle ? 'Waiting for idle...' : indexing ? 'Indexing...' : 'Populate Database'}>
+          <span>
+            <IconButton onClick={populateDatabase} disabled={indexing}>
+              <StorageIcon />
+            </IconButton>
+          </span>
+        </Tooltip>
         <Breadcrumbs aria-label="breadcrumb" style={{ flexGrow: 1 }}>
           {breadcrumbs.map((crumb, index) => (
             <Link
@@ -264,14 +391,22 @@ const FileExplorer = () => {
                   key={file.path}
                   hover
                   onClick={() => handleFileClick(file)}
+                  draggable
+                  onDragStart={(e) => {
+                    // Hand the drag to the OS so it can leave the window.
+                    e.preventDefault();
+                    invoke('start_native_drag', { paths: [file.path] }).catch((error) =>
+                      console.error('Error starting drag:', error)
+                    );
+                  }}
                   style={{ cursor: 'pointer' }}
                 >
                   <TableCell>
                     <Box display="flex" alignItems="center">
-                      {file.extension ? (
-                        <FileIcon color="action" style={{ marginRight: '8px' }} />
-                      ) : (
+                      {file.is_dir ? (
                         <FolderIcon color="primary" style={{ marginRight: '8px' }} />
+                      ) : (
+                        <FileIcon color="action" style={{ marginRight: '8px' }} />
                       )}
                       {file.name}
                     </Box>
@@ -280,10 +415,10 @@ const FileExplorer = () => {
                     {file.path.replace(/\\/g, '\\').replace(/\\[^\\]+\\?$/, '')}
                   </TableCell>
                   <TableCell align="right">
-                    {file.extension ? formatFileSize(file.size) : '-'}
+                    {file.is_dir ? '-' : formatFileSize(file.size)}
                   </TableCell>
                   <TableCell>{formatDate(file.modified)}</TableCell>
-                  <TableCell>{file.extension || 'Folder'}</TableCell>
+                  <TableCell>{file.kind ? file.kind.label : file.is_dir ? 'Folder' : 'File'}</TableCell>
                 </TableRow>
               ))
             )}
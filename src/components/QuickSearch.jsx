@@ -0,0 +1,80 @@
+// src/components/QuickSearch.jsx
+import React, { useState, useEffect } from 'react';
+import { invoke } from '@tauri-apps/api/tauri';
+import {
+  List,
+  ListItemButton,
+  ListItemIcon,
+  ListItemText,
+  Paper,
+  TextField
+} from '@mui/material';
+import {
+  Folder as FolderIcon,
+  InsertDriveFile as FileIcon
+} from '@mui/icons-material';
+
+const QuickSearch = () => {
+  const [query, setQuery] = useState('');
+  const [results, setResults] = useState([]);
+
+  useEffect(() => {
+    if (!query) {
+      setResults([]);
+      return;
+    }
+    const timer = setTimeout(async () => {
+      try {
+        const found = await invoke('search_files', { name: query, extension: '' });
+        const entries = Array.isArray(found) ? found : found.entries;
+        setResults(entries.slice(0, 50));
+      } catch (error) {
+        console.error('Error searching files:', error);
+      }
+    }, 150);
+    return () => clearTimeout(timer);
+  }, [query]);
+
+  const openResult = async (file) => {
+    try {
+      await invoke('open_file', { path: file.path });
+      await invoke('hide_quick_search');
+    } catch (error) {
+      console.error('Error opening file:', error);
+    }
+  };
+
+  const handleKeyDown = (e) => {
+    if (e.key === 'Escape') {
+      invoke('hide_quick_search');
+    } else if (e.key === 'Enter' && results.length > 0) {
+      openResult(results[0]);
+    }
+  };
+
+  return (
+    <Paper square style={{ height: '100vh', display: 'flex', flexDirection: 'column', padding: '8px' }}>
+      <TextField
+        autoFocus
+        fullWidth
+        size="small"
+        placeholder="Search the index..."
+        value={query}
+        onChange={(e) => setQuery(e.target.value)}
+        onKeyDown={handleKeyDown}
+      />
+      <List dense style={{ flexGrow: 1, overflow: 'auto' }}>
+        {results.map((file) => (
+          <ListItemButton key={file.path} onClick={() => openResult(file)}>
+            <ListItemIcon>
+              {file.is_dir ? <FolderIcon color="primary" /> : <FileIcon color="action" />}
+            </ListItemIcon>
+            <ListItemText primary={file.name} secondary={file.path} />
+          </ListItemButton>
+        ))}
+      </List>
+    </Paper>
+  );
+};
+
+export default QuickSearch;